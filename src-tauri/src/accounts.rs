@@ -0,0 +1,551 @@
+//! Signed in accounts, so users stay signed in between launches. The list of
+//! accounts is kept in `accounts.json` in the launcher dir, their tokens in
+//! the [`CredentialStore`].
+//!
+//! Offline accounts are just a name, for playing without signing in. They
+//! can only join servers in offline mode.
+
+use std::path::PathBuf;
+
+use log::info;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::sync::Notify;
+
+use crate::{
+    auth::AuthError,
+    command_result,
+    errors::{coded, CommandError, ErrorCode},
+    instance, settings,
+    storage::CredentialStore,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Account {
+    /// Xbox user hash, which stays the same for a Microsoft account, or
+    /// `offline-` and the UUID for offline accounts
+    pub id: String,
+    pub kind: AccountKind,
+    pub msa_access_token: String,
+    pub msa_refresh_token: String,
+    /// Unix timestamp
+    pub msa_expires_at: i64,
+    pub minecraft_access_token: String,
+    /// Unix timestamp
+    pub minecraft_expires_at: i64,
+    /// `None` if the account doesn't own Minecraft
+    pub profile: Option<Profile>,
+    pub ownership: Ownership,
+    /// `None` for offline accounts, or if it couldn't be fetched
+    pub xbox_profile: Option<XboxProfile>,
+    /// Azure application the tokens were issued to, which refreshing them
+    /// needs. `None` for offline accounts and ones saved before this was
+    /// kept, which use the settings.
+    pub msa_client_id: Option<String>,
+    /// Azure tenant the tokens came from, like `msa_client_id`
+    pub msa_tenant: Option<String>,
+}
+
+impl Account {
+    /// Fails for accounts that can't play the full game.
+    pub fn ensure_owns_game(&self) -> anyhow::Result<()> {
+        if self.kind == AccountKind::Microsoft && self.ownership == Ownership::NotOwned {
+            return Err(AuthError::NotEntitled.into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AccountKind {
+    #[default]
+    Microsoft,
+    /// Only a name, without tokens
+    Offline,
+}
+
+/// How an account owns Minecraft, from its entitlements.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Ownership {
+    Purchased,
+    /// Through Xbox Game Pass, only lasts as long as the subscription
+    GamePass,
+    /// Can only play the demo
+    #[default]
+    NotOwned,
+}
+
+/// The Xbox Live identity of a Microsoft account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct XboxProfile {
+    pub xuid: String,
+    pub gamertag: String,
+}
+
+/// The player behind an account, from the Minecraft profile API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    /// UUID without hyphens
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub skins: Vec<Skin>,
+    #[serde(default)]
+    pub capes: Vec<Cape>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Skin {
+    pub id: String,
+    /// `ACTIVE` or `INACTIVE`
+    pub state: String,
+    pub url: String,
+    /// `CLASSIC` or `SLIM`
+    pub variant: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cape {
+    pub id: String,
+    /// `ACTIVE` or `INACTIVE`
+    pub state: String,
+    pub url: String,
+    pub alias: String,
+}
+
+/// The parts of an [`Account`] that go into the credential store
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountTokens {
+    msa_access_token: String,
+    msa_refresh_token: String,
+    minecraft_access_token: String,
+}
+
+/// The parts of an [`Account`] that go into `accounts.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredAccount {
+    id: String,
+    #[serde(default)]
+    kind: AccountKind,
+    msa_expires_at: i64,
+    minecraft_expires_at: i64,
+    #[serde(default)]
+    profile: Option<Profile>,
+    #[serde(default)]
+    ownership: Ownership,
+    #[serde(default)]
+    xbox_profile: Option<XboxProfile>,
+    #[serde(default)]
+    msa_client_id: Option<String>,
+    #[serde(default)]
+    msa_tenant: Option<String>,
+    /// Tokens written by versions that kept them in this file, moved to the
+    /// credential store on the next save
+    #[serde(flatten, skip_serializing)]
+    legacy_tokens: Option<AccountTokens>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct AccountsFile {
+    active: Option<String>,
+    accounts: Vec<StoredAccount>,
+}
+
+/// What the frontend gets to see of an account, without tokens.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountInfo {
+    pub id: String,
+    pub kind: AccountKind,
+    pub active: bool,
+    pub minecraft_expires_at: i64,
+    pub profile: Option<Profile>,
+    pub ownership: Ownership,
+    pub xbox_profile: Option<XboxProfile>,
+}
+
+/// The active account, kept in memory so using it doesn't wait on the
+/// keychain, and a signal for when the accounts change.
+#[derive(Default)]
+pub struct AccountsState {
+    active: tokio::sync::Mutex<Option<Account>>,
+    changed: Notify,
+}
+
+lazy_static::lazy_static! {
+    static ref LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+fn accounts_path(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    Ok(instance::launcher_dir(app_handle)?.join("accounts.json"))
+}
+
+async fn read_accounts(app_handle: &tauri::AppHandle) -> anyhow::Result<AccountsFile> {
+    match tokio::fs::read(accounts_path(app_handle)?).await {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(AccountsFile::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn write_accounts(app_handle: &tauri::AppHandle, file: &AccountsFile) -> anyhow::Result<()> {
+    let path = accounts_path(app_handle)?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(file)?).await?;
+    // Older versions kept tokens in here, keep other users out
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600)).await?;
+    }
+    tokio::fs::rename(&tmp, &path).await?;
+    let state = app_handle.state::<AccountsState>();
+    *state.active.lock().await = None;
+    state.changed.notify_one();
+    Ok(())
+}
+
+fn credential_key(id: &str) -> String {
+    format!("account:{}", id)
+}
+
+/// The account with `id`, or the active one.
+pub async fn get(app_handle: &tauri::AppHandle, id: Option<&str>) -> anyhow::Result<Account> {
+    let file = read_accounts(app_handle).await?;
+    let id = id
+        .or(file.active.as_deref())
+        .ok_or_else(|| coded(ErrorCode::NotFound, "No account is signed in"))?;
+    let stored = file
+        .accounts
+        .into_iter()
+        .find(|account| account.id == id)
+        .ok_or_else(|| coded(ErrorCode::NotFound, format!("No account {}", id)))?;
+    let tokens = match stored.legacy_tokens {
+        Some(tokens) => tokens,
+        None if stored.kind == AccountKind::Offline => AccountTokens::default(),
+        None => {
+            let credentials = CredentialStore::open(app_handle)
+                .await?
+                .get(&credential_key(id))
+                .await?
+                .ok_or_else(|| {
+                    anyhow::Error::from(AuthError::SignInAgain)
+                        .context(format!("The tokens of account {} are missing", id))
+                })?;
+            serde_json::from_str(&credentials)?
+        }
+    };
+    Ok(Account {
+        id: stored.id,
+        kind: stored.kind,
+        msa_access_token: tokens.msa_access_token,
+        msa_refresh_token: tokens.msa_refresh_token,
+        msa_expires_at: stored.msa_expires_at,
+        minecraft_access_token: tokens.minecraft_access_token,
+        minecraft_expires_at: stored.minecraft_expires_at,
+        profile: stored.profile,
+        ownership: stored.ownership,
+        xbox_profile: stored.xbox_profile,
+        msa_client_id: stored.msa_client_id,
+        msa_tenant: stored.msa_tenant,
+    })
+}
+
+/// The active account.
+pub async fn active(app_handle: &tauri::AppHandle) -> anyhow::Result<Account> {
+    let state = app_handle.state::<AccountsState>();
+    let mut active = state.active.lock().await;
+    if let Some(account) = &*active {
+        return Ok(account.clone());
+    }
+    let account = get(app_handle, None).await?;
+    *active = Some(account.clone());
+    Ok(account)
+}
+
+/// Waits until an account is saved or another one becomes active.
+pub async fn changed(app_handle: &tauri::AppHandle) {
+    app_handle.state::<AccountsState>().changed.notified().await
+}
+
+/// Adds or replaces an account. New accounts become the active one.
+pub async fn save(app_handle: &tauri::AppHandle, account: Account) -> anyhow::Result<()> {
+    let _lock = LOCK.lock().await;
+    let store = CredentialStore::open(app_handle).await?;
+    let mut file = read_accounts(app_handle).await?;
+    // Writing the file drops any tokens still in it, move them out first
+    for stored in &mut file.accounts {
+        if let Some(tokens) = stored.legacy_tokens.take() {
+            store
+                .set(
+                    &credential_key(&stored.id),
+                    &serde_json::to_string(&tokens)?,
+                )
+                .await?;
+        }
+    }
+    if account.kind == AccountKind::Microsoft {
+        let tokens = AccountTokens {
+            msa_access_token: account.msa_access_token,
+            msa_refresh_token: account.msa_refresh_token,
+            minecraft_access_token: account.minecraft_access_token,
+        };
+        store
+            .set(
+                &credential_key(&account.id),
+                &serde_json::to_string(&tokens)?,
+            )
+            .await?;
+    }
+    // Signing into an account imported from another machine is what it
+    // was waiting for
+    if let Some(profile) = account
+        .profile
+        .as_ref()
+        .filter(|_| account.kind == AccountKind::Microsoft)
+    {
+        settings::remove_pending_account(app_handle, &profile.id)?;
+    }
+    let stored = StoredAccount {
+        id: account.id,
+        kind: account.kind,
+        msa_expires_at: account.msa_expires_at,
+        minecraft_expires_at: account.minecraft_expires_at,
+        profile: account.profile,
+        ownership: account.ownership,
+        xbox_profile: account.xbox_profile,
+        msa_client_id: account.msa_client_id,
+        msa_tenant: account.msa_tenant,
+        legacy_tokens: None,
+    };
+    match file
+        .accounts
+        .iter_mut()
+        .find(|existing| existing.id == stored.id)
+    {
+        Some(existing) => *existing = stored,
+        None => {
+            file.active = Some(stored.id.clone());
+            file.accounts.push(stored);
+        }
+    }
+    write_accounts(app_handle, &file).await
+}
+
+/// Replaces the stored profile of the account with `id`, after it was
+/// changed through the profile API.
+pub async fn update_profile(
+    app_handle: &tauri::AppHandle,
+    id: &str,
+    profile: Profile,
+) -> anyhow::Result<()> {
+    let _lock = LOCK.lock().await;
+    let mut file = read_accounts(app_handle).await?;
+    let account = file
+        .accounts
+        .iter_mut()
+        .find(|account| account.id == id)
+        .ok_or_else(|| coded(ErrorCode::NotFound, format!("No account {}", id)))?;
+    account.profile = Some(profile);
+    write_accounts(app_handle, &file).await
+}
+
+/// [`read_accounts`] for callers that can't wait.
+fn read_accounts_blocking(app_handle: &tauri::AppHandle) -> anyhow::Result<AccountsFile> {
+    match std::fs::read(accounts_path(app_handle)?) {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(AccountsFile::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The profiles of all Microsoft accounts that own Minecraft.
+pub fn profiles(app_handle: &tauri::AppHandle) -> anyhow::Result<Vec<Profile>> {
+    Ok(read_accounts_blocking(app_handle)?
+        .accounts
+        .into_iter()
+        .filter(|account| account.kind == AccountKind::Microsoft)
+        .filter_map(|account| account.profile)
+        .collect())
+}
+
+/// The Minecraft and Xbox profiles of every account, offline ones included.
+pub fn identities(
+    app_handle: &tauri::AppHandle,
+) -> anyhow::Result<Vec<(Option<Profile>, Option<XboxProfile>)>> {
+    Ok(read_accounts_blocking(app_handle)?
+        .accounts
+        .into_iter()
+        .map(|account| (account.profile, account.xbox_profile))
+        .collect())
+}
+
+/// The UUID the game gives a player in offline mode, like Java's
+/// `UUID.nameUUIDFromBytes` of `OfflinePlayer:<name>`.
+pub fn offline_uuid(username: &str) -> uuid::Uuid {
+    let hash = Md5::digest(format!("OfflinePlayer:{}", username));
+    uuid::Builder::from_md5_bytes(hash.into()).into_uuid()
+}
+
+pub async fn add_offline_account_inner(
+    app_handle: &tauri::AppHandle,
+    username: &str,
+) -> anyhow::Result<String> {
+    if username.is_empty()
+        || username.len() > 16
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(coded(
+            ErrorCode::InvalidData,
+            "Names have to be 1 to 16 letters, digits or underscores",
+        ));
+    }
+    let uuid = offline_uuid(username).simple().to_string();
+    let id = format!("offline-{}", uuid);
+    save(
+        app_handle,
+        Account {
+            id: id.clone(),
+            kind: AccountKind::Offline,
+            msa_access_token: String::new(),
+            msa_refresh_token: String::new(),
+            msa_expires_at: 0,
+            minecraft_access_token: String::new(),
+            minecraft_expires_at: 0,
+            profile: Some(Profile {
+                id: uuid,
+                name: username.to_string(),
+                skins: vec![],
+                capes: vec![],
+            }),
+            ownership: Ownership::NotOwned,
+            xbox_profile: None,
+            msa_client_id: None,
+            msa_tenant: None,
+        },
+    )
+    .await?;
+    Ok(id)
+}
+
+/// Adds an offline account with `username`, returning its id.
+#[tauri::command]
+pub async fn add_offline_account(
+    app_handle: tauri::AppHandle,
+    username: String,
+) -> Result<String, CommandError> {
+    command_result(add_offline_account_inner(&app_handle, &username).await)
+}
+
+#[tauri::command]
+pub async fn get_accounts(app_handle: tauri::AppHandle) -> Result<Vec<AccountInfo>, CommandError> {
+    command_result(
+        async {
+            let file = read_accounts(&app_handle).await?;
+            Ok(file
+                .accounts
+                .iter()
+                .map(|account| AccountInfo {
+                    id: account.id.clone(),
+                    kind: account.kind,
+                    active: file.active.as_ref() == Some(&account.id),
+                    minecraft_expires_at: account.minecraft_expires_at,
+                    profile: account.profile.clone(),
+                    ownership: account.ownership,
+                    xbox_profile: account.xbox_profile.clone(),
+                })
+                .collect())
+        }
+        .await,
+    )
+}
+
+#[tauri::command]
+pub async fn set_active_account(
+    app_handle: tauri::AppHandle,
+    id: String,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            let _lock = LOCK.lock().await;
+            let mut file = read_accounts(&app_handle).await?;
+            if !file.accounts.iter().any(|account| account.id == id) {
+                return Err(coded(ErrorCode::NotFound, format!("No account {}", id)));
+            }
+            file.active = Some(id);
+            write_accounts(&app_handle, &file).await
+        }
+        .await,
+    )
+}
+
+/// Signs out of the account with `id`, forgetting it and its tokens. The
+/// next account becomes the active one if it was.
+///
+/// Microsoft has no endpoint to revoke a refresh token on its own, so the
+/// token is only dropped here. Users can revoke the launcher's access
+/// entirely from their Microsoft account's app permissions.
+#[tauri::command]
+pub async fn logout(app_handle: tauri::AppHandle, id: String) -> Result<(), CommandError> {
+    command_result(
+        async {
+            let _lock = LOCK.lock().await;
+            let mut file = read_accounts(&app_handle).await?;
+            let len = file.accounts.len();
+            file.accounts.retain(|account| account.id != id);
+            if file.accounts.len() == len {
+                return Err(coded(ErrorCode::NotFound, format!("No account {}", id)));
+            }
+            if file.active.as_ref() == Some(&id) {
+                file.active = file.accounts.first().map(|account| account.id.clone());
+            }
+            CredentialStore::open(&app_handle)
+                .await?
+                .delete(&credential_key(&id))
+                .await?;
+            write_accounts(&app_handle, &file).await?;
+            info!("Signed out of {}", id);
+            Ok(())
+        }
+        .await,
+    )
+}
+
+/// The player profile of the account with `id`, or the active one. `None` if
+/// the account doesn't own Minecraft.
+#[tauri::command]
+pub async fn get_profile(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+) -> Result<Option<Profile>, CommandError> {
+    command_result(
+        read_accounts(&app_handle)
+            .await
+            .and_then(|file| {
+                let id = id
+                    .or(file.active)
+                    .ok_or_else(|| coded(ErrorCode::NotFound, "No account is signed in"))?;
+                file.accounts
+                    .into_iter()
+                    .find(|account| account.id == id)
+                    .ok_or_else(|| coded(ErrorCode::NotFound, format!("No account {}", id)))
+            })
+            .map(|account| account.profile),
+    )
+}
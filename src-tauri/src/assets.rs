@@ -0,0 +1,276 @@
+//! The game's assets, like sounds and translations, shared by all instances
+//! in the `assets` directory.
+//!
+//! Each Minecraft version names an asset index, which lists the objects by
+//! hash. Indexes are kept in `assets/indexes/<id>.json` and the objects in
+//! `assets/objects/<first two hex digits>/<hash>`. Once all objects of an
+//! index are there, `assets/indexes/<id>.complete` records it, so the
+//! objects aren't checked again for every launch.
+//!
+//! Versions from before 1.6 look for the assets by name instead. Their
+//! indexes are `virtual`, to be copied to `assets/virtual/<id>`, or even
+//! `map_to_resources`, to be copied to `resources` in the game dir.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use log::info;
+use serde::Deserialize;
+
+use crate::{
+    command_result,
+    components::{self, Component, Patches},
+    downloads::{self, DownloadJob},
+    errors::{coded, CommandError, ErrorCode},
+    install_progress::{InstallPhase, InstallTracker},
+    instance,
+    messages::Message,
+    prism_meta::{self, AssetIndex},
+    storage::{self, Expected},
+    tasks::{self, TaskKind},
+};
+
+const ASSETS_URL_BASE: &str = "https://resources.download.minecraft.net/";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AssetObject {
+    pub hash: String,
+    pub size: u64,
+}
+
+impl AssetObject {
+    /// Where the object is stored below `assets_dir`.
+    pub fn path(&self, assets_dir: &Path) -> PathBuf {
+        assets_dir
+            .join("objects")
+            .join(&self.hash[..2])
+            .join(&self.hash)
+    }
+
+    fn url(&self) -> String {
+        format!("{}{}/{}", ASSETS_URL_BASE, &self.hash[..2], self.hash)
+    }
+}
+
+/// An asset index file, with the objects by their name in the game.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AssetObjects {
+    pub objects: BTreeMap<String, AssetObject>,
+    /// The game wants the objects by name in `assets/virtual/<id>`
+    #[serde(default, rename = "virtual")]
+    pub is_virtual: bool,
+    /// The game wants the objects by name in `resources` in the game dir
+    #[serde(default)]
+    pub map_to_resources: bool,
+}
+
+impl AssetObjects {
+    /// Fails if an object has a hash that can't be used as a path.
+    fn validate(&self) -> anyhow::Result<()> {
+        for (name, object) in &self.objects {
+            if object.hash.len() != 40 || !object.hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(coded(
+                    ErrorCode::InvalidData,
+                    format!("Asset {} has an invalid hash {:?}", name, object.hash),
+                ));
+            }
+            // Names become paths for legacy versions
+            if self.is_virtual || self.map_to_resources {
+                instance::safe_join(Path::new(""), name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn assets_dir(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    Ok(instance::launcher_dir(app_handle)?.join("assets"))
+}
+
+/// The id of `index`, which comes from the meta and is used as a file name.
+fn index_id(index: &AssetIndex) -> anyhow::Result<&str> {
+    instance::check_name(index.id()).map_err(|_| {
+        coded(
+            ErrorCode::InvalidData,
+            format!("Invalid asset index {:?}", index.id()),
+        )
+    })?;
+    Ok(index.id())
+}
+
+/// The file of `index` in `assets/indexes` with `extension`.
+fn index_file(assets_dir: &Path, index: &AssetIndex, extension: &str) -> anyhow::Result<PathBuf> {
+    instance::safe_join(
+        &assets_dir.join("indexes"),
+        &format!("{}.{}", index_id(index)?, extension),
+    )
+}
+
+/// Downloads the asset index, unless it's there already.
+pub async fn fetch_index(assets_dir: &Path, index: &AssetIndex) -> anyhow::Result<AssetObjects> {
+    let path = index_file(assets_dir, index, "json")?;
+    storage::get_file(&path, index.url(), false, &index.expected()).await?;
+    let objects: AssetObjects = serde_json::from_slice(&tokio::fs::read(&path).await?)?;
+    objects.validate()?;
+    Ok(objects)
+}
+
+/// Makes sure all objects of `index` are downloaded, checking them again
+/// even if they were complete before if `full_verify`. How far it is goes to
+/// `progress`.
+pub async fn install(
+    app_handle: &tauri::AppHandle,
+    index: &AssetIndex,
+    progress: Option<&InstallTracker>,
+    full_verify: bool,
+) -> anyhow::Result<AssetObjects> {
+    let assets_dir = assets_dir(app_handle)?;
+    if let Some(progress) = progress {
+        progress.phase(InstallPhase::Meta, 1, 0);
+    }
+    let objects = fetch_index(&assets_dir, index).await?;
+    // Holds the hash of the index, so a changed index is checked again
+    let marker = index_file(&assets_dir, index, "complete")?;
+    if !full_verify {
+        if let Ok(sha1) = tokio::fs::read_to_string(&marker).await {
+            if sha1 == index.sha1() {
+                return Ok(objects);
+            }
+        }
+    }
+
+    // Objects with the same content are stored once
+    let mut seen = HashSet::new();
+    let jobs: Vec<_> = objects
+        .objects
+        .values()
+        .filter(|object| seen.insert(&object.hash))
+        .map(|object| {
+            DownloadJob::new(object.url(), object.path(&assets_dir))
+                .with_expected(Expected::sha1(Some(&object.hash)).with_size(Some(object.size)))
+                .with_full_verify(full_verify)
+                .with_task(progress.map(|progress| progress.task().id()))
+        })
+        .collect();
+    info!(
+        "Installing {} asset objects of index {}",
+        jobs.len(),
+        index.id()
+    );
+    if let Some(progress) = progress {
+        let bytes = jobs.iter().map(|job| job.expected.size.unwrap_or(0)).sum();
+        progress.phase(InstallPhase::Assets, jobs.len(), bytes);
+    }
+    downloads::download_pool(app_handle, &jobs, |pool| {
+        if let Some(progress) = progress {
+            progress.set(pool.files_done, pool.bytes_done);
+        }
+    })
+    .await?;
+    tokio::fs::write(&marker, index.sha1()).await?;
+    Ok(objects)
+}
+
+/// Copies each object to `dir` by its name, unless there's a file of the
+/// same size already, returning how many were copied.
+fn copy_by_name(assets_dir: &Path, objects: &AssetObjects, dir: &Path) -> anyhow::Result<usize> {
+    let mut copied = 0;
+    for (name, object) in &objects.objects {
+        let target = instance::safe_join(dir, name)?;
+        if std::fs::metadata(&target).is_ok_and(|meta| meta.len() == object.size) {
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(object.path(assets_dir), &target)?;
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+/// Lays out the objects of a legacy index where the game looks for them,
+/// returning the directory to pass as its assets dir. That's `None` for
+/// indexes of 1.6 and later, which use the objects as they are.
+///
+/// Objects mapped to resources go into the game dir of `instance_id`,
+/// without one there's nowhere to put them.
+pub async fn lay_out(
+    app_handle: &tauri::AppHandle,
+    index: &AssetIndex,
+    objects: &AssetObjects,
+    instance_id: Option<&str>,
+) -> anyhow::Result<Option<PathBuf>> {
+    let assets_dir = assets_dir(app_handle)?;
+    let dir = if objects.map_to_resources {
+        let instance_id =
+            instance_id.ok_or(anyhow!("Index {} needs an instance to copy to", index.id()))?;
+        instance::game_dir(app_handle, instance_id)?.join("resources")
+    } else if objects.is_virtual {
+        assets_dir.join("virtual").join(index_id(index)?)
+    } else {
+        return Ok(None);
+    };
+    let copied = {
+        let (objects, dir) = (objects.clone(), dir.clone());
+        tokio::task::spawn_blocking(move || copy_by_name(&assets_dir, &objects, &dir)).await??
+    };
+    info!(
+        "Copied {} assets of index {} to {:?}",
+        copied,
+        index.id(),
+        dir
+    );
+    Ok(Some(dir))
+}
+
+/// Downloads the assets of the Minecraft version among `components`, and
+/// for versions from before 1.6 copies them where the game looks for them.
+#[tauri::command]
+pub async fn install_assets(
+    app_handle: tauri::AppHandle,
+    components: Vec<Component>,
+    instance_id: Option<String>,
+    full_verify: Option<bool>,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            let meta = prism_meta::fetch_meta(&app_handle).await?;
+            let patches = match &instance_id {
+                Some(instance_id) => Patches::of_instance(&app_handle, instance_id).await?,
+                None => Patches::default(),
+            };
+            let profile = components::resolve_profile(&meta, &patches, &components).await?;
+            let index = profile
+                .asset_index
+                .ok_or(anyhow!("No component has an asset index"))?;
+            let app_handle = &app_handle;
+            let (kind, title) = match full_verify {
+                Some(true) => (TaskKind::Verify, "task.verifyAssets"),
+                _ => (TaskKind::Download, "task.installAssets"),
+            };
+            tasks::run(
+                app_handle,
+                kind,
+                Message::new(title).with("index", index.id()),
+                |task| async move {
+                    let progress = InstallTracker::new(app_handle, &task);
+                    let objects = install(
+                        app_handle,
+                        &index,
+                        Some(&progress),
+                        full_verify.unwrap_or(false),
+                    )
+                    .await?;
+                    lay_out(app_handle, &index, &objects, instance_id.as_deref()).await?;
+                    Ok(())
+                },
+            )
+            .await
+        }
+        .await,
+    )
+}
@@ -0,0 +1,1147 @@
+//! Signing in with a Microsoft account through the device code flow, and
+//! keeping the tokens fresh afterwards.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use log::{error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tauri::Manager;
+use tokio::{sync::Notify, time::sleep};
+
+use crate::{
+    accounts::{self, Account, AccountKind, Ownership, Profile, XboxProfile},
+    errors::{coded, coded_message, CommandError, ErrorCode},
+    messages::Message,
+    network, proxy, settings,
+    tasks::Cancelled,
+};
+
+const LOGIN_URL: &str = "https://login.microsoftonline.com";
+/// The launcher's own Azure application, used unless the settings name
+/// another
+pub const DEFAULT_CLIENT_ID: &str = "7872a85a-1d8c-415c-a4f4-1a243f40c354";
+pub const DEFAULT_TENANT: &str = "consumers";
+const SCOPES: &str = "XboxLive.signin offline_access";
+const XBL_AUTH_URL: &str = "https://user.auth.xboxlive.com/user/authenticate";
+const XSTS_AUTH_URL: &str = "https://xsts.auth.xboxlive.com/xsts/authorize";
+const LAUNCHER_AUTH_URL: &str = "https://api.minecraftservices.com/launcher/login";
+pub const PROFILE_URL: &str = "https://api.minecraftservices.com/minecraft/profile";
+const ENTITLEMENT_URL: &str = "https://api.minecraftservices.com/entitlements/license?requestId=";
+
+/// The background refresh happens this long before tokens expire, well
+/// ahead of [`EXPIRY_MARGIN`]
+const REFRESH_AHEAD: i64 = 15 * 60;
+const REFRESH_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Passed to the game for offline accounts
+const OFFLINE_ACCESS_TOKEN: &str = "0";
+/// Added to the polling interval when asked to slow down
+const SLOW_DOWN_INCREMENT: Duration = Duration::from_secs(5);
+/// Refresh this long before tokens expire, so they don't run out mid-use
+const EXPIRY_MARGIN: i64 = 5 * 60;
+
+/// Waited before the first retry, doubling for each one after
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// Rate limits asking to wait longer than this fail instead
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+lazy_static::lazy_static! {
+    /// Until when each host asked not to be sent requests, shared by all
+    /// clients so a rate limit holds back every request to the host
+    static ref RATE_LIMITS: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+}
+
+/// Waits out the rate limit of `host`, if there is one.
+async fn wait_for_rate_limit(host: &str) -> anyhow::Result<()> {
+    let until = RATE_LIMITS.lock().unwrap().get(host).copied();
+    if let Some(wait) = until.and_then(|until| until.checked_duration_since(Instant::now())) {
+        if wait > MAX_RATE_LIMIT_WAIT {
+            return Err(rate_limited(wait));
+        }
+        info!("Waiting {:?} for the rate limit of {}", wait, host);
+        sleep(wait).await;
+    }
+    Ok(())
+}
+
+fn rate_limited(wait: Duration) -> anyhow::Error {
+    coded_message(
+        ErrorCode::Network,
+        Message::new("auth.rateLimited").with("seconds", wait.as_secs().max(1)),
+    )
+}
+
+/// How long a 429 response asks to wait. Only the seconds form of
+/// `Retry-After` is sent by these servers.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// A response of the auth and profile servers, read in full.
+pub(crate) struct AuthResponse {
+    pub status: u16,
+    /// The body as JSON, as a string if it isn't and `Null` if it's empty
+    pub data: serde_json::Value,
+    retry_after: Option<Duration>,
+}
+
+impl AuthResponse {
+    async fn read(response: reqwest::Response) -> reqwest::Result<AuthResponse> {
+        let status = response.status().as_u16();
+        let retry_after = retry_after(&response);
+        let body = response.bytes().await?;
+        let data = match serde_json::from_slice(&body) {
+            Ok(data) => data,
+            Err(_) if body.is_empty() => serde_json::Value::Null,
+            Err(_) => serde_json::Value::String(String::from_utf8_lossy(&body).into_owned()),
+        };
+        Ok(AuthResponse {
+            status,
+            data,
+            retry_after,
+        })
+    }
+}
+
+/// HTTP client for the auth and profile servers, with the timeout and
+/// retries from the settings.
+pub(crate) struct AuthClient {
+    client: reqwest::Client,
+    timeout: Duration,
+    retries: u32,
+    client_id: String,
+    tenant: String,
+}
+
+impl AuthClient {
+    pub(crate) fn new(app_handle: &tauri::AppHandle) -> anyhow::Result<AuthClient> {
+        let settings = settings::current(app_handle);
+        Ok(AuthClient {
+            client: proxy::client(),
+            timeout: Duration::from_secs(settings.auth_timeout_secs),
+            retries: settings.auth_retries,
+            client_id: settings
+                .msa_client_id
+                .unwrap_or_else(|| DEFAULT_CLIENT_ID.to_string()),
+            tenant: settings
+                .msa_tenant
+                .unwrap_or_else(|| DEFAULT_TENANT.to_string()),
+        })
+    }
+
+    /// A client for refreshing the tokens of `account`, which only works
+    /// with the client id and tenant they were issued through.
+    fn for_account(app_handle: &tauri::AppHandle, account: &Account) -> anyhow::Result<AuthClient> {
+        let mut client = AuthClient::new(app_handle)?;
+        if let Some(client_id) = &account.msa_client_id {
+            client.client_id = client_id.clone();
+        }
+        if let Some(tenant) = &account.msa_tenant {
+            client.tenant = tenant.clone();
+        }
+        Ok(client)
+    }
+
+    fn flow_url(&self) -> String {
+        format!("{}/{}/oauth2/v2.0/devicecode", LOGIN_URL, self.tenant)
+    }
+
+    fn token_url(&self) -> String {
+        format!("{}/{}/oauth2/v2.0/token", LOGIN_URL, self.tenant)
+    }
+
+    /// Sends the request `request` builds on the client, building it again
+    /// for retries. Connection failures, server errors and rate limiting
+    /// are retried with backoff. Connection failures that outlast the
+    /// retries become [`AuthError::Network`].
+    ///
+    /// A 429 response holds back all requests to the host for as long as its
+    /// `Retry-After` asks, and fails with a rate limit error once the retries
+    /// are used up.
+    pub(crate) async fn send(
+        &self,
+        request: impl Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    ) -> anyhow::Result<AuthResponse> {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let request = request(&self.client).timeout(self.timeout).build()?;
+            let host = request.url().host_str().unwrap_or_default().to_string();
+            wait_for_rate_limit(&host).await?;
+            let result = match self.client.execute(request).await {
+                Ok(response) => AuthResponse::read(response).await,
+                Err(e) => Err(e),
+            };
+            let mut wait = backoff;
+            let transient = match &result {
+                Ok(response) if response.status == 429 => {
+                    wait = response.retry_after.unwrap_or(backoff);
+                    let until = Instant::now() + wait;
+                    let mut limits = RATE_LIMITS.lock().unwrap();
+                    let limit = limits.entry(host.clone()).or_insert(until);
+                    *limit = (*limit).max(until);
+                    true
+                }
+                Ok(response) => response.status >= 500,
+                Err(_) => true,
+            };
+            if !transient || attempt >= self.retries || wait > MAX_RATE_LIMIT_WAIT {
+                return match result {
+                    Ok(response) if response.status == 429 => Err(rate_limited(wait)),
+                    result => result.map_err(|e| {
+                        anyhow::Error::from(AuthError::Network).context(format!("{:#}", e))
+                    }),
+                };
+            }
+            attempt += 1;
+            warn!(
+                "Request to {} failed, retrying in {:?} ({}/{})",
+                host, wait, attempt, self.retries
+            );
+            // Rate limits are waited out at the start of the next attempt
+            if !result.is_ok_and(|response| response.status == 429) {
+                sleep(wait).await;
+            }
+            backoff *= 2;
+        }
+    }
+}
+
+/// The client id tokens are requested for. Refresh tokens only work with
+/// the client id they were issued to.
+pub fn client_id(app_handle: &tauri::AppHandle) -> String {
+    settings::current(app_handle)
+        .msa_client_id
+        .unwrap_or_else(|| DEFAULT_CLIENT_ID.to_string())
+}
+
+fn now() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// Why signing in or using an account failed, so the frontend can show
+/// guidance for each case. Auth commands return this instead of a
+/// [`CommandError`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum AuthError {
+    /// The auth servers couldn't be reached
+    Network,
+    Cancelled,
+    /// The user declined signing in
+    Declined,
+    /// The device code expired before the user entered it
+    Expired,
+    /// The stored tokens can't be used or refreshed anymore
+    SignInAgain,
+    NoXboxProfile,
+    /// Xbox Live isn't available in the user's country
+    XboxUnavailable,
+    /// The account needs adult verification on the Xbox website
+    AdultVerification,
+    /// The account is underage and isn't part of a family
+    Underage,
+    /// Another Xbox Live error
+    Xbox {
+        code: u32,
+    },
+    /// The account doesn't own the game
+    NotEntitled,
+    /// Anything else, as it would be reported for other commands
+    Other(CommandError),
+}
+
+impl AuthError {
+    fn from_x_err(x_err: u32) -> AuthError {
+        match x_err {
+            2148916233 => AuthError::NoXboxProfile,
+            2148916235 => AuthError::XboxUnavailable,
+            2148916236 | 2148916237 => AuthError::AdultVerification,
+            2148916238 => AuthError::Underage,
+            code => AuthError::Xbox { code },
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AuthError::Network => ErrorCode::Network,
+            AuthError::Cancelled => ErrorCode::Cancelled,
+            AuthError::Declined => ErrorCode::AuthDeclined,
+            AuthError::Expired | AuthError::SignInAgain => ErrorCode::AuthExpired,
+            AuthError::NoXboxProfile
+            | AuthError::XboxUnavailable
+            | AuthError::AdultVerification
+            | AuthError::Underage
+            | AuthError::Xbox { .. }
+            | AuthError::NotEntitled => ErrorCode::AuthFailed,
+            AuthError::Other(e) => e.code,
+        }
+    }
+
+    pub fn message(&self) -> Message {
+        match self {
+            AuthError::Network => Message::new("error.network"),
+            AuthError::Cancelled => Message::new("error.cancelled"),
+            AuthError::Declined => Message::new("auth.declined"),
+            AuthError::Expired => Message::new("auth.expired"),
+            AuthError::SignInAgain => Message::new("auth.signInAgain"),
+            AuthError::NoXboxProfile => Message::new("auth.xbox.noProfile"),
+            AuthError::XboxUnavailable => Message::new("auth.xbox.unavailable"),
+            AuthError::AdultVerification => Message::new("auth.xbox.adultVerification"),
+            AuthError::Underage => Message::new("auth.xbox.underage"),
+            AuthError::Xbox { code } => Message::new("auth.xbox.unknown").with("code", *code),
+            AuthError::NotEntitled => Message::new("auth.notEntitled"),
+            AuthError::Other(e) => e.localized.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::Other(e) => write!(f, "{}", e.message),
+            _ => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<anyhow::Error> for AuthError {
+    fn from(e: anyhow::Error) -> Self {
+        if let Some(auth) = e
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<AuthError>())
+        {
+            return auth.clone();
+        }
+        match ErrorCode::of(&e) {
+            ErrorCode::Network => AuthError::Network,
+            ErrorCode::Cancelled => AuthError::Cancelled,
+            // The HTTP client only fails like this when it can't connect
+            _ if e.chain().any(|cause| cause.is::<reqwest::Error>()) => AuthError::Network,
+            _ => AuthError::Other(e.into()),
+        }
+    }
+}
+
+/// Like [`command_result`](crate::command_result), for auth commands.
+fn auth_result<T>(result: anyhow::Result<T>) -> Result<T, AuthError> {
+    result.map_err(|e| {
+        error!("{:#?}", e);
+        e.into()
+    })
+}
+
+#[tauri::command]
+pub async fn login_msa(app_handle: tauri::AppHandle) -> Result<(), AuthError> {
+    auth_result(login_msa_inner(app_handle).await)
+}
+
+/// The sign in that is in progress, so it can be cancelled
+#[derive(Default)]
+pub struct LoginState {
+    cancel: Mutex<Option<Arc<Notify>>>,
+}
+
+/// Signs in through the device code flow. Returns [`Cancelled`] if
+/// `cancel_login` is called before it finishes.
+pub async fn login_msa_inner(app_handle: tauri::AppHandle) -> anyhow::Result<()> {
+    let cancel = Arc::new(Notify::new());
+    let state = app_handle.state::<LoginState>();
+    // Only one sign in at a time, a new one replaces the old
+    if let Some(previous) = state.cancel.lock().unwrap().replace(cancel.clone()) {
+        previous.notify_one();
+    }
+    let result = tokio::select! {
+        result = device_code_login(&app_handle) => result,
+        _ = cancel.notified() => Err(Cancelled.into()),
+    };
+    let mut current = state.cancel.lock().unwrap();
+    if current
+        .as_ref()
+        .is_some_and(|current| Arc::ptr_eq(current, &cancel))
+    {
+        *current = None;
+    }
+    result
+}
+
+/// Stops the sign in that is in progress. Returns whether there was one.
+#[tauri::command]
+pub fn cancel_login(app_handle: tauri::AppHandle) -> bool {
+    match app_handle
+        .state::<LoginState>()
+        .cancel
+        .lock()
+        .unwrap()
+        .take()
+    {
+        Some(cancel) => {
+            info!("Cancelling sign in");
+            cancel.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+async fn device_code_login(app_handle: &tauri::AppHandle) -> anyhow::Result<()> {
+    let client = AuthClient::new(app_handle)?;
+    let flow_resp = client
+        .send(|http| {
+            http.post(client.flow_url())
+                .form(&[("client_id", client.client_id.as_str()), ("scope", SCOPES)])
+        })
+        .await?;
+    if flow_resp.status != 200 {
+        return Err(anyhow!(
+            "Server returned error response: {}",
+            flow_resp.data.to_string()
+        ));
+    }
+    let flow_resp: DeviceCodeResponse = serde_json::from_value(flow_resp.data)?;
+    app_handle.emit_all("auth:msa:login_message", &flow_resp.message)?;
+    // The CLI has no window to receive the event above
+    app_handle.trigger_global(
+        "auth:msa:login_message",
+        Some(serde_json::to_string(&flow_resp.message)?),
+    );
+    // The same in pieces, so frontends can show a copyable code, a link
+    // and a timer, in the user's language
+    let expires_at = now() + i64::from(flow_resp.expires_in);
+    app_handle.emit_all(
+        "auth:msa:device_code",
+        DeviceCodeEvent {
+            verification_uri: flow_resp.verification_uri.clone(),
+            user_code: flow_resp.user_code.clone(),
+            expires_in: flow_resp.expires_in,
+            expires_at,
+            message: Message::new("auth.deviceCode")
+                .with("verificationUri", flow_resp.verification_uri.as_str())
+                .with("userCode", flow_resp.user_code.as_str()),
+        },
+    )?;
+    trace!("Got device code, expires in {}s", flow_resp.expires_in);
+    let token = tokio::select! {
+        token = poll_token(&client, &flow_resp) => token?,
+        result = countdown(app_handle, expires_at) => {
+            result?;
+            return Err(AuthError::Expired.into());
+        }
+    };
+    trace!("Got MSA token, expires at {}", token.expires_at);
+    app_handle.emit_all("auth:msa:msa_token", Message::new("auth.step.msaToken"))?;
+
+    let account = sign_in(app_handle, &client, token, true).await?;
+    accounts::save(app_handle, account).await?;
+    Ok(())
+}
+
+/// Polls until the user entered the code and signing in finished.
+async fn poll_token(client: &AuthClient, flow_resp: &DeviceCodeResponse) -> anyhow::Result<Token> {
+    let mut interval = Duration::from_secs(flow_resp.interval.into());
+    sleep(interval).await;
+    loop {
+        let token_resp = client
+            .send(|http| {
+                http.post(client.token_url()).form(&[
+                    ("client_id", client.client_id.as_str()),
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                    ("device_code", flow_resp.device_code.as_str()),
+                ])
+            })
+            .await?;
+        let token_resp: TokenResponse = serde_json::from_value(token_resp.data)?;
+        match token_resp {
+            TokenResponse::Ok {
+                access_token,
+                refresh_token,
+                expires_in,
+            } => {
+                return Ok(Token {
+                    access: access_token,
+                    refresh: refresh_token,
+                    expires_at: now() + expires_in,
+                });
+            }
+            TokenResponse::Err { error } => match error {
+                TokenResponseErrorKind::AuthorizationPending => {
+                    sleep(interval).await;
+                }
+                TokenResponseErrorKind::SlowDown => {
+                    // RFC 8628 asks for 5 more seconds between polls from
+                    // now on
+                    interval += SLOW_DOWN_INCREMENT;
+                    sleep(interval).await;
+                }
+                TokenResponseErrorKind::AuthorizationDeclined => {
+                    return Err(AuthError::Declined.into())
+                }
+                TokenResponseErrorKind::BadVerificationCode => {
+                    return Err(anyhow!("Server claims bad verification code?"))
+                }
+                TokenResponseErrorKind::ExpiredToken => return Err(AuthError::Expired.into()),
+                error => return Err(anyhow!("Polling for the token failed: {:?}", error)),
+            },
+        }
+    }
+}
+
+/// Emits `auth:msa:device_code_tick` with the seconds left every second,
+/// returning once the code expired.
+async fn countdown(app_handle: &tauri::AppHandle, expires_at: i64) -> anyhow::Result<()> {
+    loop {
+        let remaining = (expires_at - now()).max(0);
+        app_handle.emit_all("auth:msa:device_code_tick", remaining)?;
+        if remaining == 0 {
+            return Ok(());
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Trades a Microsoft token for Xbox Live and Minecraft tokens. The step
+/// events are only emitted if `interactive`.
+async fn sign_in(
+    app_handle: &tauri::AppHandle,
+    client: &AuthClient,
+    msa_token: Token,
+    interactive: bool,
+) -> anyhow::Result<Account> {
+    let xbl_resp = client
+        .send(|http| {
+            http.post(XBL_AUTH_URL).json(&json!({
+                "Properties": {
+                    "AuthMethod": "RPS",
+                    "SiteName": "user.auth.xboxlive.com",
+                    "RpsTicket": format!("d={}", msa_token.access)
+                },
+                "RelyingParty": "http://auth.xboxlive.com",
+                "TokenType": "JWT"
+            }))
+        })
+        .await?;
+    let xbl_resp: XblAuthResponse = serde_json::from_value(xbl_resp.data)?;
+    let (token, userhash) = match xbl_resp {
+        XblAuthResponse::Ok {
+            issue_instant,
+            not_after,
+            token,
+            display_claims,
+        } => (token, display_claims.xui[0].uhs.clone()),
+        XblAuthResponse::Err { x_err } => return Err(AuthError::from_x_err(x_err).into()),
+    };
+    if interactive {
+        app_handle.emit_all("auth:msa:xbl_token", Message::new("auth.step.xblToken"))?;
+    }
+
+    let xsts_resp = client
+        .send(|http| {
+            http.post(XSTS_AUTH_URL).json(&json!({
+                "Properties": {
+                    "SandboxId": "RETAIL",
+                    "UserTokens": [token]
+                },
+                "RelyingParty": "rp://api.minecraftservices.com/",
+                "TokenType": "JWT"
+            }))
+        })
+        .await?;
+    let xsts_resp: XblAuthResponse = serde_json::from_value(xsts_resp.data)?;
+    if interactive {
+        app_handle.emit_all("auth:msa:xsts_token", Message::new("auth.step.xstsToken"))?;
+    }
+
+    let xsts_token = match xsts_resp {
+        XblAuthResponse::Ok {
+            issue_instant,
+            not_after,
+            token,
+            display_claims,
+        } => token,
+        // Where missing profiles, age limits and the like are reported
+        XblAuthResponse::Err { x_err } => return Err(AuthError::from_x_err(x_err).into()),
+    };
+    // Not needed to play, so failing to get it doesn't fail signing in
+    let xbox_profile = fetch_xbox_profile(client, &token)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Couldn't get the Xbox profile: {:#}", e);
+            None
+        });
+
+    let launcher_resp = client
+        .send(|http| {
+            http.post(LAUNCHER_AUTH_URL).json(&json!({
+                "xtoken": format!("XBL3.0 x={};{}", userhash, xsts_token),
+                "platform": "PC_LAUNCHER"
+            }))
+        })
+        .await?;
+    if interactive {
+        app_handle.emit_all("auth:msa:mc_token", Message::new("auth.step.mcToken"))?;
+    }
+
+    let launcher_token: LauncherToken = serde_json::from_value(launcher_resp.data)?;
+
+    trace!(
+        "Got Minecraft token, expires in {}s",
+        launcher_token.expires_in
+    );
+    let profile = fetch_profile(client, &launcher_token.access_token).await?;
+    // Checked on every refresh too, since Game Pass can run out
+    let ownership = fetch_ownership(client, &launcher_token.access_token).await?;
+    Ok(Account {
+        id: userhash,
+        kind: AccountKind::Microsoft,
+        msa_access_token: msa_token.access,
+        msa_refresh_token: msa_token.refresh,
+        msa_expires_at: msa_token.expires_at,
+        minecraft_access_token: launcher_token.access_token,
+        minecraft_expires_at: now() + launcher_token.expires_in,
+        profile,
+        ownership,
+        xbox_profile,
+        msa_client_id: Some(client.client_id.clone()),
+        msa_tenant: Some(client.tenant.clone()),
+    })
+}
+
+/// The gamertag and XUID, which only come with an XSTS token for Xbox Live
+/// itself rather than for Minecraft.
+async fn fetch_xbox_profile(
+    client: &AuthClient,
+    user_token: &str,
+) -> anyhow::Result<Option<XboxProfile>> {
+    let xsts_resp = client
+        .send(|http| {
+            http.post(XSTS_AUTH_URL).json(&json!({
+                "Properties": {
+                    "SandboxId": "RETAIL",
+                    "UserTokens": [user_token]
+                },
+                "RelyingParty": "http://xboxlive.com",
+                "TokenType": "JWT"
+            }))
+        })
+        .await?;
+    match serde_json::from_value(xsts_resp.data)? {
+        XblAuthResponse::Ok { display_claims, .. } => Ok(display_claims
+            .xui
+            .into_iter()
+            .next()
+            .and_then(|xui| match (xui.xid, xui.gtg) {
+                (Some(xuid), Some(gamertag)) => Some(XboxProfile { xuid, gamertag }),
+                _ => None,
+            })),
+        XblAuthResponse::Err { x_err } => Err(anyhow!(
+            "Error {} while getting the Xbox Live XSTS token",
+            x_err
+        )),
+    }
+}
+
+/// Whether the account owns Minecraft, from its entitlements.
+async fn fetch_ownership(client: &AuthClient, access_token: &str) -> anyhow::Result<Ownership> {
+    let entitlement_resp = client
+        .send(|http| {
+            http.get(format!("{}{}", ENTITLEMENT_URL, uuid::Uuid::new_v4()))
+                .bearer_auth(access_token)
+        })
+        .await?;
+    trace!("got entitlement data: {}", entitlement_resp.data);
+    if entitlement_resp.status != 200 {
+        return Err(coded(
+            ErrorCode::AuthFailed,
+            format!(
+                "Fetching entitlements failed with {}",
+                entitlement_resp.status
+            ),
+        ));
+    }
+    let entitlements: EntitlementsResponse = serde_json::from_value(entitlement_resp.data)?;
+    let signed = signed_entitlements(&entitlements.signature)?;
+    // Only trust items that the signed token lists as well
+    let items: Vec<_> = entitlements
+        .items
+        .iter()
+        .filter(|item| signed.contains(&item.name))
+        .collect();
+    let game = items
+        .iter()
+        .find(|item| item.name == "product_minecraft" || item.name == "game_minecraft");
+    Ok(match game {
+        Some(item) if item.source.as_deref() != Some("GAMEPASS") => Ownership::Purchased,
+        Some(_) => Ownership::GamePass,
+        None if items
+            .iter()
+            .any(|item| item.name.starts_with("product_game_pass")) =>
+        {
+            Ownership::GamePass
+        }
+        None => Ownership::NotOwned,
+    })
+}
+
+/// The entitlement names in the payload of the signed token that comes with
+/// the entitlements.
+///
+/// The launcher doesn't ship Mojang's signing key, so the signature itself
+/// isn't checked. The response comes over TLS from Mojang either way, this
+/// just makes sure the plain list agrees with the signed one.
+fn signed_entitlements(token: &str) -> anyhow::Result<Vec<String>> {
+    let payload = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| coded(ErrorCode::InvalidData, "Malformed entitlements signature"))?;
+    let payload: SignedEntitlements =
+        serde_json::from_slice(&base64::decode_config(payload, base64::URL_SAFE_NO_PAD)?)?;
+    Ok(payload
+        .entitlements
+        .into_iter()
+        .map(|entitlement| entitlement.name)
+        .collect())
+}
+
+/// The player's profile, or `None` if the account doesn't own Minecraft.
+async fn fetch_profile(client: &AuthClient, access_token: &str) -> anyhow::Result<Option<Profile>> {
+    let profile_resp = client
+        .send(|http| http.get(PROFILE_URL).bearer_auth(access_token))
+        .await?;
+    trace!("got profile response: {}", profile_resp.data);
+    match profile_resp.status {
+        200 => Ok(Some(serde_json::from_value(profile_resp.data)?)),
+        404 => Ok(None),
+        status => Err(coded(
+            ErrorCode::AuthFailed,
+            format!("Fetching the Minecraft profile failed with {}", status),
+        )),
+    }
+}
+
+/// Gets new tokens for an account with its refresh token, without any
+/// interaction.
+pub async fn refresh(app_handle: &tauri::AppHandle, account: &Account) -> anyhow::Result<Account> {
+    let client = AuthClient::for_account(app_handle, account)?;
+    let token_resp = client
+        .send(|http| {
+            http.post(client.token_url()).form(&[
+                ("client_id", client.client_id.as_str()),
+                ("scope", SCOPES),
+                ("grant_type", "refresh_token"),
+                ("refresh_token", account.msa_refresh_token.as_str()),
+            ])
+        })
+        .await?;
+    let token = match serde_json::from_value(token_resp.data)? {
+        TokenResponse::Ok {
+            access_token,
+            refresh_token,
+            expires_in,
+        } => Token {
+            access: access_token,
+            refresh: refresh_token,
+            expires_at: now() + expires_in,
+        },
+        TokenResponse::Err { error } => {
+            return Err(anyhow::Error::from(AuthError::SignInAgain).context(format!(
+                "Refreshing the Microsoft token failed: {:?}",
+                error
+            )))
+        }
+    };
+    let refreshed = sign_in(app_handle, &client, token, false).await?;
+    accounts::save(app_handle, refreshed.clone()).await?;
+    Ok(refreshed)
+}
+
+/// A Minecraft access token for the account with `id`, or the active one,
+/// refreshing the tokens first if they are about to expire.
+pub async fn minecraft_access_token(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+) -> anyhow::Result<String> {
+    let account = match id {
+        Some(id) => accounts::get(app_handle, Some(id)).await?,
+        None => accounts::active(app_handle).await?,
+    };
+    if account.kind == AccountKind::Offline {
+        // The game wants one, but nothing checks it
+        return Ok(OFFLINE_ACCESS_TOKEN.to_string());
+    }
+    if account.minecraft_expires_at - EXPIRY_MARGIN > now() {
+        return Ok(account.minecraft_access_token);
+    }
+    Ok(refresh(app_handle, &account).await?.minecraft_access_token)
+}
+
+/// Who the game is launched as.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchSession {
+    pub username: String,
+    /// UUID without hyphens
+    pub uuid: String,
+    pub access_token: String,
+    /// `msa`, or `legacy` for offline accounts
+    pub user_type: &'static str,
+    pub xuid: Option<String>,
+    /// Launch with `--demo`
+    pub demo: bool,
+}
+
+impl LaunchSession {
+    /// Values for the `${...}` placeholders in the game arguments of a
+    /// version that are about who plays.
+    pub fn placeholders(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("auth_player_name", self.username.clone()),
+            ("auth_uuid", self.uuid.clone()),
+            ("auth_access_token", self.access_token.clone()),
+            // Versions from before 1.6 take the token and UUID together
+            (
+                "auth_session",
+                format!("token:{}:{}", self.access_token, self.uuid),
+            ),
+            ("user_type", self.user_type.to_string()),
+            ("user_properties", "{}".to_string()),
+        ]
+    }
+
+    /// The game arguments for the session on top of the ones of the
+    /// version.
+    pub fn game_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(xuid) = &self.xuid {
+            args.extend(["--xuid".to_string(), xuid.clone()]);
+        }
+        if self.demo {
+            args.push("--demo".to_string());
+        }
+        args
+    }
+}
+
+/// The session to launch the game with for the account with `id`, or the
+/// active one. Accounts that don't own the game can only launch with `demo`
+/// set.
+pub async fn launch_session(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+    demo: bool,
+) -> anyhow::Result<LaunchSession> {
+    let account = match id {
+        Some(id) => accounts::get(app_handle, Some(id)).await?,
+        None => accounts::active(app_handle).await?,
+    };
+    if !demo {
+        account.ensure_owns_game()?;
+    }
+    let access_token = minecraft_access_token(app_handle, Some(&account.id)).await?;
+    let (username, uuid) = match &account.profile {
+        Some(profile) => (profile.name.clone(), profile.id.clone()),
+        // Accounts without the game have no profile, the demo still needs
+        // a name
+        None => {
+            let username = account
+                .xbox_profile
+                .as_ref()
+                .map_or("Player", |xbox| xbox.gamertag.as_str())
+                .to_string();
+            let uuid = accounts::offline_uuid(&username).simple().to_string();
+            (username, uuid)
+        }
+    };
+    Ok(LaunchSession {
+        username,
+        uuid,
+        access_token,
+        user_type: match account.kind {
+            AccountKind::Microsoft => "msa",
+            AccountKind::Offline => "legacy",
+        },
+        xuid: account.xbox_profile.map(|xbox| xbox.xuid),
+        demo,
+    })
+}
+
+/// Refreshes the active account in the background shortly before its
+/// tokens expire, so launching never has to wait for it. Called once during
+/// setup.
+pub fn start_refresh_scheduler(app_handle: &tauri::AppHandle) {
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            // `None` waits until the accounts change
+            let delay = match accounts::active(&app_handle).await {
+                Ok(account) if account.kind == AccountKind::Microsoft => {
+                    let due = account.minecraft_expires_at - REFRESH_AHEAD - now();
+                    if due > 0 {
+                        Some(Duration::from_secs(due as u64))
+                    } else if !network::is_online(&app_handle) {
+                        Some(REFRESH_RETRY_INTERVAL)
+                    } else {
+                        match refresh(&app_handle, &account).await {
+                            Ok(_) => {
+                                info!("Refreshed account {} in the background", account.id);
+                                // Saving it woke us up already
+                                None
+                            }
+                            Err(e) => {
+                                warn!("Refreshing account {} failed: {:#}", account.id, e);
+                                Some(REFRESH_RETRY_INTERVAL)
+                            }
+                        }
+                    }
+                }
+                _ => None,
+            };
+            match delay {
+                Some(delay) => {
+                    tokio::select! {
+                        _ = sleep(delay) => {}
+                        _ = accounts::changed(&app_handle) => {}
+                    }
+                }
+                None => accounts::changed(&app_handle).await,
+            }
+        }
+    });
+}
+
+/// Gets new tokens for the account with `id`, or the active one.
+#[tauri::command]
+pub async fn refresh_account(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+) -> Result<(), AuthError> {
+    auth_result(
+        async {
+            let account = accounts::get(&app_handle, id.as_deref()).await?;
+            if account.kind == AccountKind::Offline {
+                return Ok(());
+            }
+            refresh(&app_handle, &account).await?;
+            Ok(())
+        }
+        .await,
+    )
+}
+
+/// What an account needs before it can be used, from `validate_session`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SessionStatus {
+    Valid,
+    /// The Minecraft token expired or was revoked, but can be refreshed
+    /// without the user
+    NeedsRefresh,
+    /// The user has to sign in again
+    NeedsLogin,
+}
+
+/// Payload of the `auth:expired` event
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionExpired {
+    id: String,
+    status: SessionStatus,
+}
+
+async fn validate_session_inner(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+) -> anyhow::Result<SessionStatus> {
+    let account = match accounts::get(app_handle, id).await {
+        Ok(account) => account,
+        // The tokens are gone
+        Err(e) if ErrorCode::of(&e) == ErrorCode::AuthExpired => {
+            if let Some(id) = id {
+                emit_expired(app_handle, id, SessionStatus::NeedsLogin)?;
+            }
+            return Ok(SessionStatus::NeedsLogin);
+        }
+        Err(e) => return Err(e),
+    };
+    if account.kind == AccountKind::Offline {
+        return Ok(SessionStatus::Valid);
+    }
+    let mut status = if account.minecraft_expires_at - EXPIRY_MARGIN > now() {
+        SessionStatus::Valid
+    } else {
+        SessionStatus::NeedsRefresh
+    };
+    // Tokens can be revoked before they expire, ask whether it still works
+    if status == SessionStatus::Valid && network::is_online(app_handle) {
+        let profile_resp = AuthClient::new(app_handle)?
+            .send(|http| {
+                http.get(PROFILE_URL)
+                    .bearer_auth(&account.minecraft_access_token)
+            })
+            .await?;
+        if profile_resp.status == 401 {
+            status = SessionStatus::NeedsRefresh;
+        }
+    }
+    if status != SessionStatus::Valid {
+        emit_expired(app_handle, &account.id, status)?;
+    }
+    Ok(status)
+}
+
+fn emit_expired(
+    app_handle: &tauri::AppHandle,
+    id: &str,
+    status: SessionStatus,
+) -> anyhow::Result<()> {
+    app_handle.emit_all(
+        "auth:expired",
+        SessionExpired {
+            id: id.to_string(),
+            status,
+        },
+    )?;
+    Ok(())
+}
+
+/// Checks whether the account with `id`, or the active one, can be used
+/// as is. Emits `auth:expired` if it can't.
+#[tauri::command]
+pub async fn validate_session(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+) -> Result<SessionStatus, AuthError> {
+    auth_result(validate_session_inner(&app_handle, id.as_deref()).await)
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    expires_in: u32,
+    interval: u32,
+    message: String,
+}
+
+/// Payload of the `auth:msa:device_code` event
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeviceCodeEvent {
+    verification_uri: String,
+    user_code: String,
+    /// Seconds
+    expires_in: u32,
+    /// Unix timestamp
+    expires_at: i64,
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TokenResponse {
+    Ok {
+        access_token: String,
+        refresh_token: String,
+        /// Seconds
+        expires_in: i64,
+    },
+    Err {
+        error: TokenResponseErrorKind,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TokenResponseErrorKind {
+    AuthorizationPending,
+    SlowDown,
+    AuthorizationDeclined,
+    BadVerificationCode,
+    ExpiredToken,
+    /// The refresh token was revoked or ran out
+    InvalidGrant,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug)]
+struct Token {
+    access: String,
+    refresh: String,
+    /// Unix timestamp
+    expires_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+#[serde(rename_all = "PascalCase")]
+enum XblAuthResponse {
+    #[serde(rename_all = "PascalCase")]
+    Ok {
+        issue_instant: String,
+        not_after: String,
+        token: String,
+        display_claims: XblDisplayClaims,
+    },
+    #[serde(rename_all = "PascalCase")]
+    Err { x_err: u32 },
+}
+
+#[derive(Debug, Deserialize)]
+struct XblDisplayClaims {
+    xui: Vec<XblXui>,
+}
+
+#[derive(Debug, Deserialize)]
+struct XblXui {
+    uhs: String,
+    /// Only for the Xbox Live relying party
+    xid: Option<String>,
+    gtg: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EntitlementsResponse {
+    #[serde(default)]
+    items: Vec<EntitlementItem>,
+    /// JWT with the same entitlements as its payload
+    signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EntitlementItem {
+    name: String,
+    /// E.g. `PURCHASE` or `GAMEPASS`
+    source: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedEntitlements {
+    #[serde(default)]
+    entitlements: Vec<SignedEntitlement>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedEntitlement {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LauncherToken {
+    access_token: String,
+    /// Seconds
+    expires_in: i64,
+}
@@ -0,0 +1,184 @@
+//! Command line subcommands that run without opening the launcher window.
+
+use std::{path::PathBuf, process::Stdio};
+
+use anyhow::anyhow;
+use tauri::Manager;
+
+use crate::{
+    client_instance,
+    errors::{coded, ErrorCode},
+    instance::{self, InstanceConfig, InstanceKind},
+    launch_hooks,
+    process::{self, ProcessManager},
+    server_instance, server_pack,
+};
+
+const USAGE: &str = "\
+Usage: untitled-minecraft-launcher [COMMAND]
+
+Without a command the launcher window opens.
+
+Commands:
+  --launch <instance>               Run an instance in this terminal, clients
+                                    as the active account. With the launcher
+                                    open, it runs there instead
+  --list-instances                  List instance ids, names and versions
+  --login                           Sign in with a Microsoft account
+  --import <pack> [--into <id>]     Install a server pack, into a new server
+                                    instance unless --into is given
+  --help                            Show this message";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliCommand {
+    Launch(String),
+    ListInstances,
+    Login,
+    Import { pack: PathBuf, into: Option<String> },
+    Help,
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> anyhow::Result<String> {
+    args.next()
+        .ok_or(anyhow!("{} needs a value\n\n{}", flag, USAGE))
+}
+
+/// Parses the arguments after the executable name. Returns `None` if the
+/// launcher should start normally, which includes arguments meant for the
+/// GUI such as deep links.
+pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Option<CliCommand>> {
+    let mut args = args.into_iter();
+    let command = match args.next().as_deref() {
+        Some("--launch") => CliCommand::Launch(value(&mut args, "--launch")?),
+        Some("--list-instances") => CliCommand::ListInstances,
+        Some("--login") => CliCommand::Login,
+        Some("--import") => {
+            let pack = PathBuf::from(value(&mut args, "--import")?);
+            let into = match args.next().as_deref() {
+                Some("--into") => Some(value(&mut args, "--into")?),
+                Some(other) => return Err(anyhow!("Unexpected argument {}\n\n{}", other, USAGE)),
+                None => None,
+            };
+            CliCommand::Import { pack, into }
+        }
+        Some("--help" | "-h") => CliCommand::Help,
+        _ => return Ok(None),
+    };
+    if let Some(extra) = args.next() {
+        return Err(anyhow!("Unexpected argument {}\n\n{}", extra, USAGE));
+    }
+    Ok(Some(command))
+}
+
+/// Runs the instance attached to this terminal, so a server's console can
+/// be used directly. Only reached when no launcher window is open, since
+/// the single instance plugin hands `--launch` to that one otherwise.
+async fn launch(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<i32> {
+    if app_handle.state::<ProcessManager>().is_running(instance_id) {
+        return Err(coded(
+            ErrorCode::InstanceRunning,
+            format!("{} is already running", instance_id),
+        ));
+    }
+    let config = instance::read_config(app_handle, instance_id).await?;
+    let mut command = match config.kind {
+        InstanceKind::Server => server_instance::server_command(app_handle, instance_id).await?,
+        InstanceKind::Client => {
+            client_instance::client_command(app_handle, instance_id, None, false).await?
+        }
+    };
+    launch_hooks::pre_launch(app_handle, instance_id).await?;
+    let mut child = command
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+    let started = process::record_start(app_handle, instance_id).await;
+    let code = child.wait().await?.code();
+    process::record_exit(app_handle, instance_id, started, code).await;
+    Ok(code.unwrap_or(1))
+}
+
+async fn list_instances(app_handle: &tauri::AppHandle) -> anyhow::Result<i32> {
+    for entry in instance::list_instances(app_handle).await? {
+        println!(
+            "{}\t{}\t{:?}\t{}",
+            entry.id,
+            entry.config.name,
+            entry.config.kind,
+            entry.config.minecraft_version.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(0)
+}
+
+async fn login(app_handle: &tauri::AppHandle) -> anyhow::Result<i32> {
+    let handler = app_handle.listen_global("auth:msa:login_message", |event| {
+        if let Some(message) = event
+            .payload()
+            .and_then(|payload| serde_json::from_str::<String>(payload).ok())
+        {
+            println!("{}", message);
+        }
+    });
+    let result = crate::auth::login_msa_inner(app_handle.clone()).await;
+    app_handle.unlisten(handler);
+    result?;
+    println!("Signed in");
+    Ok(0)
+}
+
+async fn import(
+    app_handle: &tauri::AppHandle,
+    pack: PathBuf,
+    into: Option<String>,
+) -> anyhow::Result<i32> {
+    let instance_id = match into {
+        Some(instance_id) => instance_id,
+        None => {
+            let name = pack
+                .file_stem()
+                .ok_or(anyhow!("{:?} is not a file", pack))?
+                .to_string_lossy()
+                .to_string();
+            let config = InstanceConfig::new(name, InstanceKind::Server);
+            instance::create_instance(app_handle, &config).await?
+        }
+    };
+    let result = server_pack::install_server_pack_inner(app_handle, &instance_id, pack).await?;
+    println!(
+        "Installed {} files into {}",
+        result.files_installed, instance_id
+    );
+    for skipped in result.skipped {
+        println!("Skipped client-only {}", skipped);
+    }
+    Ok(0)
+}
+
+async fn run_inner(app_handle: &tauri::AppHandle, command: CliCommand) -> anyhow::Result<i32> {
+    match command {
+        CliCommand::Launch(instance_id) => launch(app_handle, &instance_id).await,
+        CliCommand::ListInstances => list_instances(app_handle).await,
+        CliCommand::Login => login(app_handle).await,
+        CliCommand::Import { pack, into } => import(app_handle, pack, into).await,
+        CliCommand::Help => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+    }
+}
+
+/// Runs `command` in the background and exits the app with its status.
+pub fn run(app_handle: tauri::AppHandle, command: CliCommand) {
+    tauri::async_runtime::spawn(async move {
+        let code = match run_inner(&app_handle, command).await {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("{:#}", e);
+                1
+            }
+        };
+        app_handle.exit(code);
+    });
+}
@@ -0,0 +1,377 @@
+//! Client instances: installing what their components need and launching
+//! the game through the process manager.
+//!
+//! Everything is checked again before each launch, which costs little once
+//! it's there, so changes to the patches or the meta are picked up.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use log::info;
+use regex::{Captures, Regex};
+use tauri::Manager;
+use tokio::process::Command;
+
+use crate::{
+    assets,
+    auth::{self, LaunchSession},
+    command_result,
+    components::{self, Component, ComponentRequest, LaunchTarget, Patches, ResolvedProfile},
+    downloads::{self, DownloadJob},
+    errors::{coded, CommandError, ErrorCode},
+    install_progress::{InstallPhase, InstallTracker},
+    instance::{self, InstanceConfig, InstanceKind},
+    jar_mods, java, launch_hooks, libraries,
+    messages::Message,
+    prism_meta::{self, Library, MINECRAFT_UID},
+    process::ProcessManager,
+    settings,
+    tasks::{self, TaskKind},
+};
+
+lazy_static::lazy_static! {
+    static ref PLACEHOLDER_REGEX: Regex = Regex::new(r"\$\{(\w+)\}").unwrap();
+}
+
+/// What an installed client instance is launched with.
+struct Installed {
+    profile: ResolvedProfile,
+    target: LaunchTarget,
+    /// In order, ending with the client jar with the jar mods applied
+    classpath: Vec<PathBuf>,
+    assets_dir: PathBuf,
+    /// Where versions from before 1.6 look for the assets, `assets_dir`
+    /// for later ones
+    game_assets: PathBuf,
+    asset_index: String,
+}
+
+/// Minecraft in the version of the instance, and every component it has a
+/// patch for.
+fn requested_components(minecraft_version: &str, patches: &Patches) -> Vec<ComponentRequest> {
+    std::iter::once(ComponentRequest {
+        uid: MINECRAFT_UID.to_string(),
+        version: Some(minecraft_version.to_string()),
+    })
+    .chain(
+        patches
+            .uids()
+            .filter(|uid| *uid != MINECRAFT_UID)
+            .map(|uid| ComponentRequest {
+                uid: uid.to_string(),
+                version: None,
+            }),
+    )
+    .collect()
+}
+
+/// The components the instance is made of with the current meta, as it
+/// would be installed.
+pub(crate) async fn resolved_components(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    config: &InstanceConfig,
+) -> anyhow::Result<Vec<Component>> {
+    let minecraft_version = config
+        .minecraft_version
+        .as_deref()
+        .ok_or(anyhow!("Instance has no Minecraft version"))?;
+    let meta = prism_meta::fetch_meta(app_handle).await?;
+    let patches = Patches::of_instance(app_handle, instance_id).await?;
+    components::resolve(
+        &meta,
+        &patches,
+        &requested_components(minecraft_version, &patches),
+    )
+    .await
+}
+
+/// The downloads of `library`, as part of the install `progress` is for.
+async fn library_jobs(
+    libraries_dir: &Path,
+    library: &Library,
+    progress: &InstallTracker,
+) -> anyhow::Result<Vec<DownloadJob>> {
+    Ok(prism_meta::library_jobs(libraries_dir, library)
+        .await?
+        .into_iter()
+        .map(|job| job.with_task(Some(progress.task().id())))
+        .collect())
+}
+
+/// Downloads the libraries, client jar and assets of the instance, unless
+/// they're there already, extracts the natives and applies the jar mods.
+async fn install(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    config: &InstanceConfig,
+    progress: &InstallTracker,
+) -> anyhow::Result<Installed> {
+    let minecraft_version = config
+        .minecraft_version
+        .as_deref()
+        .ok_or(anyhow!("Instance has no Minecraft version"))?;
+    progress.phase(InstallPhase::Meta, 1, 0);
+    let meta = prism_meta::fetch_meta(app_handle).await?;
+    let patches = Patches::of_instance(app_handle, instance_id).await?;
+    let components = components::resolve(
+        &meta,
+        &patches,
+        &requested_components(minecraft_version, &patches),
+    )
+    .await?;
+    let profile = components::resolve_profile(&meta, &patches, &components).await?;
+    let target = profile.launch_target()?;
+    progress.add(1, 0);
+
+    let libraries_dir = libraries::libraries_dir(app_handle)?;
+    let main_jar = profile
+        .main_jar
+        .as_ref()
+        .ok_or_else(|| coded(ErrorCode::InvalidData, "No component has a client jar"))?;
+    let mut jobs = vec![];
+    let mut classpath = vec![];
+    let mut applicable = vec![];
+    for library in profile.libraries.iter().chain(&target.extra_libraries) {
+        if !library.applies().await {
+            continue;
+        }
+        jobs.extend(library_jobs(&libraries_dir, library, progress).await?);
+        classpath.extend(library.classpath_entry(&libraries_dir)?);
+        applicable.push(library.clone());
+    }
+    jobs.extend(library_jobs(&libraries_dir, main_jar, progress).await?);
+    let main_jar_path = main_jar
+        .classpath_entry(&libraries_dir)?
+        .ok_or_else(|| coded(ErrorCode::InvalidData, "The client jar has no file"))?;
+    // Added by the user in the instance's `jarmods`, or downloaded with
+    // the libraries
+    let jar_mods_dir = instance::instance_dir(app_handle, instance_id)?.join("jarmods");
+    let mut jar_mods = vec![];
+    for jar_mod in &profile.jar_mods {
+        match jar_mod.local_file_name() {
+            Some(file_name) => {
+                let path = instance::safe_join(&jar_mods_dir, &file_name)?;
+                if !path.is_file() {
+                    return Err(coded(
+                        ErrorCode::NotFound,
+                        format!("Jar mod {} is missing", file_name),
+                    ));
+                }
+                jar_mods.push(path);
+            }
+            None => {
+                jobs.extend(library_jobs(&libraries_dir, jar_mod, progress).await?);
+                jar_mods.extend(jar_mod.classpath_entry(&libraries_dir)?);
+            }
+        }
+    }
+    let bytes = jobs.iter().map(|job| job.expected.size.unwrap_or(0)).sum();
+    progress.phase(InstallPhase::Libraries, jobs.len(), bytes);
+    downloads::download_pool(app_handle, &jobs, |pool| {
+        progress.set(pool.files_done, pool.bytes_done)
+    })
+    .await?;
+    // Not on the classpath, but where Forge's install processors look
+    let maven_files =
+        prism_meta::download_maven_files(app_handle, &libraries_dir, &profile.maven_files).await?;
+    // Keeps them when unused libraries are cleaned up
+    let used: Vec<PathBuf> = jobs
+        .iter()
+        .map(|job| job.path.clone())
+        .chain(maven_files)
+        .collect();
+    libraries::record_references(app_handle, instance_id, &used).await?;
+    // The game runs from the patched jar if there are jar mods
+    classpath.push(
+        jar_mods::build_patched_jar(
+            main_jar_path,
+            jar_mods,
+            jar_mods::jar_mods_cache_dir(app_handle)?,
+        )
+        .await?,
+    );
+    let natives = prism_meta::extract_natives(
+        libraries_dir.clone(),
+        applicable,
+        instance::natives_dir(app_handle, instance_id)?,
+    )
+    .await?;
+
+    let index = profile
+        .asset_index
+        .as_ref()
+        .ok_or_else(|| coded(ErrorCode::InvalidData, "No component has an asset index"))?;
+    let objects = assets::install(app_handle, index, Some(progress), false).await?;
+    let assets_dir = assets::assets_dir(app_handle)?;
+    let game_assets = assets::lay_out(app_handle, index, &objects, Some(instance_id))
+        .await?
+        .unwrap_or_else(|| assets_dir.clone());
+    let asset_index = index.id().to_string();
+
+    info!(
+        "Installed {} {} with {} libraries and {} native files",
+        instance_id,
+        minecraft_version,
+        classpath.len(),
+        natives
+    );
+    Ok(Installed {
+        profile,
+        target,
+        classpath,
+        assets_dir,
+        game_assets,
+        asset_index,
+    })
+}
+
+/// Fills in the `${...}` placeholders in `arg` that `values` has, leaving
+/// others as they are.
+fn substitute(arg: &str, values: &HashMap<&str, String>) -> String {
+    PLACEHOLDER_REGEX
+        .replace_all(arg, |caps: &Captures| {
+            values
+                .get(&caps[1])
+                .cloned()
+                .unwrap_or_else(|| caps[0].to_string())
+        })
+        .into_owned()
+}
+
+/// The arguments of the version with the placeholders filled in, followed
+/// by the ones for the tweakers and the session.
+fn game_args(
+    installed: &Installed,
+    minecraft_version: &str,
+    game_dir: &Path,
+    session: &LaunchSession,
+) -> Vec<String> {
+    let mut values: HashMap<&str, String> = session.placeholders().into_iter().collect();
+    values.extend([
+        ("version_name", minecraft_version.to_string()),
+        ("version_type", "release".to_string()),
+        ("game_directory", game_dir.to_string_lossy().to_string()),
+        (
+            "assets_root",
+            installed.assets_dir.to_string_lossy().to_string(),
+        ),
+        (
+            "game_assets",
+            installed.game_assets.to_string_lossy().to_string(),
+        ),
+        ("assets_index_name", installed.asset_index.clone()),
+    ]);
+    let mut args: Vec<String> = installed
+        .profile
+        .minecraft_arguments
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|arg| substitute(arg, &values))
+        .collect();
+    args.extend(installed.target.game_args());
+    args.extend(session.game_args());
+    args
+}
+
+/// Installs what's missing and builds the command that runs a client
+/// instance as the account with `account_id`, or the active one.
+pub(crate) async fn client_command(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    account_id: Option<&str>,
+    demo: bool,
+) -> anyhow::Result<Command> {
+    let config = instance::read_config(app_handle, instance_id).await?;
+    if config.kind != InstanceKind::Client {
+        return Err(anyhow!("{} is not a client instance", instance_id));
+    }
+    let config = &config;
+    let installed = tasks::run(
+        app_handle,
+        TaskKind::Install,
+        Message::new("task.installClient").with("instance", instance_id),
+        |task| async move {
+            let progress = InstallTracker::new(app_handle, &task);
+            install(app_handle, instance_id, config, &progress).await
+        },
+    )
+    .await?;
+    let session = auth::launch_session(app_handle, account_id, demo).await?;
+    let java = java::select_for_instance(
+        app_handle,
+        config,
+        &installed.profile.compatible_java_majors,
+    )
+    .await?;
+
+    let game_dir = instance::game_dir(app_handle, instance_id)?;
+    // Older versions don't create it themselves, and packs can be dropped
+    // in before the first launch
+    tokio::fs::create_dir_all(game_dir.join(installed.profile.resource_packs_dir())).await?;
+    let mut library_path = OsString::from("-Djava.library.path=");
+    library_path.push(instance::natives_dir(app_handle, instance_id)?);
+    let mut command = Command::new(&java.path);
+    command
+        .arg(library_path)
+        .args(installed.profile.launch_jvm_args())
+        .args(settings::current(app_handle).jvm_args)
+        .args(&config.jvm_args)
+        .arg("-cp")
+        .arg(std::env::join_paths(&installed.classpath)?)
+        .arg(&installed.target.main_class)
+        .args(game_args(
+            &installed,
+            config.minecraft_version.as_deref().unwrap_or_default(),
+            &game_dir,
+            &session,
+        ))
+        .current_dir(&game_dir);
+    Ok(command)
+}
+
+/// Installs what's missing and starts a client instance as the account
+/// with `account_id`, or the active one. Accounts without the game can only
+/// launch the `demo`.
+pub(crate) async fn launch_client_inner(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    account_id: Option<&str>,
+    demo: bool,
+) -> anyhow::Result<()> {
+    // Before installing, which can take a while
+    if app_handle.state::<ProcessManager>().is_running(instance_id) {
+        return Err(coded(
+            ErrorCode::InstanceRunning,
+            format!("{} is already running", instance_id),
+        ));
+    }
+    let command = client_command(app_handle, instance_id, account_id, demo).await?;
+    launch_hooks::pre_launch(app_handle, instance_id).await?;
+    app_handle
+        .state::<ProcessManager>()
+        .spawn(app_handle, instance_id, command)
+}
+
+#[tauri::command]
+pub async fn launch_client(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    account_id: Option<String>,
+    demo: Option<bool>,
+) -> Result<(), CommandError> {
+    command_result(
+        launch_client_inner(
+            &app_handle,
+            &instance_id,
+            account_id.as_deref(),
+            demo.unwrap_or(false),
+        )
+        .await,
+    )
+}
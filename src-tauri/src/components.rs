@@ -0,0 +1,501 @@
+//! Picking the components of a client instance: Minecraft itself, a mod
+//! loader and the internal pieces they need like LWJGL and intermediary
+//! mappings, following the `requires` and `conflicts` in the Prism meta.
+//!
+//! The version files of the components are then layered by their `order`
+//! into the [`ResolvedProfile`] the game is launched with.
+//!
+//! Instances can override components with Prism-style `patches/<uid>.json`
+//! files, which are layered on top of the version from the meta.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+};
+
+use anyhow::Context;
+use log::trace;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command_result,
+    errors::{coded, CommandError, ErrorCode},
+    instance,
+    prism_meta::{
+        self, AssetIndex, Dependency, DownloadedMetaIndex, Library, PackageIndex, PackageVersion,
+        Version,
+    },
+};
+
+/// A component the user asked for, with the latest recommended version
+/// picked if `version` is `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComponentRequest {
+    pub uid: String,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Component {
+    pub uid: String,
+    pub version: String,
+    /// Picked because another component requires it, not by the user
+    pub dependency_only: bool,
+}
+
+/// What the `+traits` of the components change about launching.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchTraits {
+    /// LWJGL 2 needs the main thread on macOS, `-XstartOnFirstThread`
+    pub first_thread_on_macos: bool,
+    /// Launched through the legacy launcher wrapper, for versions from
+    /// before the current launcher
+    pub legacy_launch: bool,
+    /// Uses `texturepacks` instead of `resourcepacks`
+    pub texture_packs: bool,
+    /// Never started as an applet, even with an applet class
+    pub no_applet: bool,
+}
+
+impl LaunchTraits {
+    pub fn from_traits(traits: &[String]) -> LaunchTraits {
+        let mut launch_traits = LaunchTraits::default();
+        for name in traits {
+            match name.as_str() {
+                "FirstThreadOnMacOS" => launch_traits.first_thread_on_macos = true,
+                "legacyLaunch" => launch_traits.legacy_launch = true,
+                "texturepacks" => launch_traits.texture_packs = true,
+                "noapplet" => launch_traits.no_applet = true,
+                _ => trace!("Ignoring trait {}", name),
+            }
+        }
+        launch_traits
+    }
+}
+
+/// Everything needed to launch, merged from the version files of all
+/// components.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedProfile {
+    pub main_class: Option<String>,
+    pub applet_class: Option<String>,
+    pub minecraft_arguments: Option<String>,
+    pub tweakers: Vec<String>,
+    pub traits: Vec<String>,
+    pub jvm_args: Vec<String>,
+    pub main_jar: Option<Library>,
+    pub libraries: Vec<Library>,
+    pub maven_files: Vec<Library>,
+    pub jar_mods: Vec<Library>,
+    pub asset_index: Option<AssetIndex>,
+    pub compatible_java_majors: Vec<u32>,
+    /// From `traits`
+    pub launch_traits: LaunchTraits,
+}
+
+/// Mojang's LaunchWrapper, which starts applets in a frame with the stub
+/// they expect, and runs the tweakers of legacy mod loaders
+const LAUNCH_WRAPPER_MAIN_CLASS: &str = "net.minecraft.launchwrapper.Launch";
+const LAUNCH_WRAPPER_LIBRARIES: &[&str] = &[
+    "net.minecraft:launchwrapper:1.12",
+    "net.sf.jopt-simple:jopt-simple:5.0.3",
+    "org.ow2.asm:asm-all:5.2",
+];
+/// Starts `net.minecraft.client.MinecraftApplet`
+const APPLET_TWEAKER: &str = "net.minecraft.launchwrapper.AlphaVanillaTweaker";
+
+/// The entry point of the game.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchTarget {
+    pub main_class: String,
+    pub tweakers: Vec<String>,
+    /// Needed on top of the libraries of the components
+    pub extra_libraries: Vec<Library>,
+}
+
+impl LaunchTarget {
+    /// The game arguments for the tweakers.
+    pub fn game_args(&self) -> Vec<String> {
+        self.tweakers
+            .iter()
+            .flat_map(|tweaker| ["--tweakClass".to_string(), tweaker.clone()])
+            .collect()
+    }
+}
+
+impl ResolvedProfile {
+    /// What to launch. Versions from before 1.6 are applets without a
+    /// usable main class, those are started through LaunchWrapper.
+    pub fn launch_target(&self) -> anyhow::Result<LaunchTarget> {
+        let applet = self.launch_applet_class().is_some() || self.launch_traits.legacy_launch;
+        let main_class = self.main_class.as_deref();
+        if !applet || main_class == Some(LAUNCH_WRAPPER_MAIN_CLASS) {
+            return Ok(LaunchTarget {
+                main_class: main_class
+                    .ok_or_else(|| coded(ErrorCode::InvalidData, "No main class to launch"))?
+                    .to_string(),
+                tweakers: self.tweakers.clone(),
+                extra_libraries: vec![],
+            });
+        }
+        let mut tweakers = self.tweakers.clone();
+        // Last, so the tweakers of mods run before the game is started
+        tweakers.push(APPLET_TWEAKER.to_string());
+        let extra_libraries = LAUNCH_WRAPPER_LIBRARIES
+            .iter()
+            .map(|name| Library::from_name(name))
+            .filter(|library| {
+                self.libraries
+                    .iter()
+                    .all(|existing| existing.artifact_id() != library.artifact_id())
+            })
+            .collect();
+        Ok(LaunchTarget {
+            main_class: LAUNCH_WRAPPER_MAIN_CLASS.to_string(),
+            tweakers,
+            extra_libraries,
+        })
+    }
+
+    /// The JVM arguments of the components and the ones their traits need.
+    pub fn launch_jvm_args(&self) -> Vec<String> {
+        let mut args = self.jvm_args.clone();
+        if self.launch_traits.first_thread_on_macos && cfg!(target_os = "macos") {
+            args.push("-XstartOnFirstThread".to_string());
+        }
+        args
+    }
+
+    /// The applet class to start the game through, unless `noapplet` says
+    /// otherwise.
+    pub fn launch_applet_class(&self) -> Option<&str> {
+        self.applet_class
+            .as_deref()
+            .filter(|_| !self.launch_traits.no_applet)
+    }
+
+    /// The directory in the game dir resource packs go in.
+    pub fn resource_packs_dir(&self) -> &'static str {
+        if self.launch_traits.texture_packs {
+            "texturepacks"
+        } else {
+            "resourcepacks"
+        }
+    }
+}
+
+/// Adds `library`, replacing any other version of it.
+fn add_library(libraries: &mut Vec<Library>, library: Library) {
+    let artifact_id = library.artifact_id();
+    match libraries
+        .iter_mut()
+        .find(|existing| existing.artifact_id() == artifact_id)
+    {
+        Some(existing) => *existing = library,
+        None => libraries.push(library),
+    }
+}
+
+fn add_unique(list: &mut Vec<String>, items: Option<Vec<String>>) {
+    for item in items.into_iter().flatten() {
+        if !list.contains(&item) {
+            list.push(item);
+        }
+    }
+}
+
+/// Layers `versions` by their `order`. Later layers replace single values
+/// like the main class and add to lists, with a newer version of a library
+/// replacing the older one.
+pub fn merge_components(mut versions: Vec<Version>) -> ResolvedProfile {
+    versions.sort_by_key(|version| version.order);
+    let mut profile = ResolvedProfile::default();
+    for version in versions {
+        if version.main_class.is_some() {
+            profile.main_class = version.main_class;
+        }
+        if version.applet_class.is_some() {
+            profile.applet_class = version.applet_class;
+        }
+        if version.minecraft_arguments.is_some() {
+            profile.minecraft_arguments = version.minecraft_arguments;
+        }
+        if version.main_jar.is_some() {
+            profile.main_jar = version.main_jar;
+        }
+        if version.asset_index.is_some() {
+            profile.asset_index = version.asset_index;
+        }
+        if !version.compatible_java_majors.is_empty() {
+            profile.compatible_java_majors = version.compatible_java_majors;
+        }
+        add_unique(&mut profile.tweakers, version.tweakers);
+        add_unique(&mut profile.traits, version.traits);
+        profile
+            .jvm_args
+            .extend(version.jvm_args.into_iter().flatten());
+        for library in version
+            .libraries
+            .into_iter()
+            .chain(version.add_libraries)
+            .flatten()
+        {
+            add_library(&mut profile.libraries, library);
+        }
+        for library in version.maven_files.into_iter().flatten() {
+            add_library(&mut profile.maven_files, library);
+        }
+        profile
+            .jar_mods
+            .extend(version.jar_mods.into_iter().flatten());
+    }
+    profile.launch_traits = LaunchTraits::from_traits(&profile.traits);
+    profile
+}
+
+/// Component files an instance overrides the meta with, by uid.
+#[derive(Debug, Clone, Default)]
+pub struct Patches(HashMap<String, Version>);
+
+impl Patches {
+    /// The `patches/<uid>.json` files of the instance in `instance_dir`.
+    pub async fn load(instance_dir: &Path) -> anyhow::Result<Patches> {
+        let mut patches = HashMap::new();
+        let mut entries = match tokio::fs::read_dir(instance_dir.join("patches")).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Patches::default()),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let uid = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(uid) if path.extension().is_some_and(|ext| ext == "json") => uid.to_string(),
+                _ => continue,
+            };
+            let version: Version = prism_meta::parse_meta(&tokio::fs::read(&path).await?)
+                .with_context(|| format!("Invalid patch {}", path.display()))?;
+            patches.insert(uid, version);
+        }
+        Ok(Patches(patches))
+    }
+
+    /// The uids of the components with a patch.
+    pub fn uids(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    /// The patches of instance `instance_id`.
+    pub async fn of_instance(
+        app_handle: &tauri::AppHandle,
+        instance_id: &str,
+    ) -> anyhow::Result<Patches> {
+        Patches::load(&instance::instance_dir(app_handle, instance_id)?).await
+    }
+}
+
+/// The version files making up `uid`: the one from the meta, followed by
+/// the instance's patch for it. Components only in a patch have no file
+/// from the meta.
+async fn component_versions(
+    meta: &DownloadedMetaIndex,
+    patches: &Patches,
+    uid: &str,
+    version: &str,
+) -> anyhow::Result<Vec<Version>> {
+    let patch = patches.0.get(uid);
+    let mut versions = match meta.fetch_version(uid, version).await {
+        Ok(remote) => vec![remote],
+        Err(e) if patch.is_some() && ErrorCode::of(&e) == ErrorCode::NotFound => vec![],
+        Err(e) => return Err(e),
+    };
+    versions.extend(patch.cloned());
+    Ok(versions)
+}
+
+/// Fetches the version files of `components` and merges them, with the
+/// instance's patches on top.
+pub async fn resolve_profile(
+    meta: &DownloadedMetaIndex,
+    patches: &Patches,
+    components: &[Component],
+) -> anyhow::Result<ResolvedProfile> {
+    let mut versions = vec![];
+    for component in components {
+        versions
+            .extend(component_versions(meta, patches, &component.uid, &component.version).await?);
+    }
+    Ok(merge_components(versions))
+}
+
+/// Whether `dependency` allows `version` of its package.
+fn allows(dependency: &Dependency, version: &str) -> bool {
+    dependency
+        .equals
+        .as_deref()
+        .is_none_or(|equals| equals == version)
+}
+
+/// Whether the requirements listed for `candidate` agree with what's picked
+/// already.
+fn compatible(candidate: &PackageVersion, picked: &[Component]) -> bool {
+    candidate.requires.iter().all(|dependency| {
+        picked
+            .iter()
+            .find(|component| component.uid == dependency.uid)
+            .is_none_or(|component| allows(dependency, &component.version))
+    })
+}
+
+/// The version of `package` to use: the one `dependency` pins or suggests,
+/// or else the newest recommended one that fits with what's picked, or else
+/// the newest that does.
+fn pick_version(
+    package: &PackageIndex,
+    dependency: Option<&Dependency>,
+    picked: &[Component],
+) -> anyhow::Result<String> {
+    if let Some(version) = dependency
+        .and_then(|dependency| dependency.equals.as_ref().or(dependency.suggests.as_ref()))
+    {
+        return Ok(version.clone());
+    }
+    let candidates = || {
+        package
+            .versions
+            .iter()
+            .filter(|candidate| compatible(candidate, picked))
+    };
+    candidates()
+        .filter(|candidate| candidate.recommended)
+        .max_by_key(|candidate| candidate.release_time)
+        .or_else(|| candidates().max_by_key(|candidate| candidate.release_time))
+        .map(|candidate| candidate.version.clone())
+        .ok_or_else(|| {
+            coded(
+                ErrorCode::NotFound,
+                format!("No version of {} fits the other components", package.name),
+            )
+        })
+}
+
+/// Picks versions for `requested` and everything they require, in the order
+/// the game loads them.
+///
+/// Requested components win over requirements: a requirement on a
+/// requested component has to allow its version, and everything that's
+/// required but not requested gets the version it's pinned or suggested
+/// to. Conflicts between the picked components fail.
+///
+/// Requirements and conflicts in `patches` count like the ones from the
+/// meta, and a requested component without a version takes the one of its
+/// patch if it has one.
+pub async fn resolve(
+    meta: &DownloadedMetaIndex,
+    patches: &Patches,
+    requested: &[ComponentRequest],
+) -> anyhow::Result<Vec<Component>> {
+    let mut picked: Vec<Component> = vec![];
+    for request in requested {
+        let version = match (&request.version, patches.0.get(&request.uid)) {
+            (Some(version), _) => version.clone(),
+            (None, Some(patch)) => patch.version.clone(),
+            (None, None) => pick_version(&*meta.package(&request.uid).await?, None, &picked)?,
+        };
+        picked.push(Component {
+            uid: request.uid.clone(),
+            version,
+            dependency_only: false,
+        });
+    }
+
+    let mut versions = vec![];
+    let mut queue: VecDeque<usize> = (0..picked.len()).collect();
+    while let Some(i) = queue.pop_front() {
+        let layers = component_versions(meta, patches, &picked[i].uid, &picked[i].version).await?;
+        let Some(version) = layers.last() else {
+            continue;
+        };
+        for dependency in layers.iter().flat_map(|layer| &layer.requires) {
+            match picked
+                .iter()
+                .find(|component| component.uid == dependency.uid)
+            {
+                Some(component) if !allows(dependency, &component.version) => {
+                    return Err(coded(
+                        ErrorCode::ComponentConflict,
+                        format!(
+                            "{} {} needs {} {}, not {}",
+                            version.name,
+                            version.version,
+                            dependency.uid,
+                            dependency.equals.as_deref().unwrap_or_default(),
+                            component.version
+                        ),
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    let package = meta.package(&dependency.uid).await?;
+                    let version = pick_version(&package, Some(dependency), &picked)?;
+                    picked.push(Component {
+                        uid: dependency.uid.clone(),
+                        version,
+                        dependency_only: true,
+                    });
+                    queue.push_back(picked.len() - 1);
+                }
+            }
+        }
+        versions.push((i, layers));
+    }
+
+    for version in versions.iter().flat_map(|(_, layers)| layers) {
+        for conflict in &version.conflicts {
+            if let Some(component) = picked
+                .iter()
+                .find(|component| component.uid == conflict.uid)
+                .filter(|component| allows(conflict, &component.version))
+            {
+                return Err(coded(
+                    ErrorCode::ComponentConflict,
+                    format!(
+                        "{} {} conflicts with {} {}",
+                        version.name, version.version, component.uid, component.version
+                    ),
+                ));
+            }
+        }
+    }
+
+    versions.sort_by_key(|(_, layers)| layers.first().map_or(0, |version| version.order));
+    Ok(versions
+        .into_iter()
+        .map(|(i, _)| picked[i].clone())
+        .collect())
+}
+
+/// Fills in versions and required components for the requested ones, with
+/// the patches of `instance_id` if given.
+#[tauri::command]
+pub async fn resolve_components(
+    app_handle: tauri::AppHandle,
+    requested: Vec<ComponentRequest>,
+    instance_id: Option<String>,
+) -> Result<Vec<Component>, CommandError> {
+    command_result(
+        async {
+            let meta = prism_meta::fetch_meta(&app_handle).await?;
+            let patches = match &instance_id {
+                Some(instance_id) => Patches::of_instance(&app_handle, instance_id).await?,
+                None => Patches::default(),
+            };
+            resolve(&meta, &patches, &requested).await
+        }
+        .await,
+    )
+}
@@ -0,0 +1,145 @@
+//! `untitledmc://` links, used by websites and Discord to talk to the
+//! launcher.
+//!
+//! - `untitledmc://launch/<instance>`
+//! - `untitledmc://join/<server address>`
+//! - `untitledmc://import?url=<pack url>`
+
+use anyhow::anyhow;
+use log::{error, info};
+use serde::Serialize;
+use tauri::Manager;
+
+use crate::{tray, usage_stats};
+
+pub const SCHEME: &str = "untitledmc";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeepLink {
+    Launch(String),
+    Join(String),
+    Import(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinRequested {
+    pub address: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRequested {
+    pub url: String,
+}
+
+fn percent_decode(text: &str) -> anyhow::Result<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = text
+                    .get(i + 1..i + 3)
+                    .ok_or(anyhow!("Truncated escape in {:?}", text))?;
+                decoded.push(u8::from_str_radix(hex, 16)?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    Ok(String::from_utf8(decoded)?)
+}
+
+pub fn parse(link: &str) -> anyhow::Result<DeepLink> {
+    let rest = link
+        .strip_prefix(SCHEME)
+        .and_then(|rest| rest.strip_prefix("://"))
+        .ok_or(anyhow!("Not a {} link: {}", SCHEME, link))?;
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let path = path.trim_end_matches('/');
+    let (action, argument) = path.split_once('/').unwrap_or((path, ""));
+    let argument = percent_decode(argument)?;
+    match action {
+        "launch" if !argument.is_empty() => Ok(DeepLink::Launch(argument)),
+        "join" if !argument.is_empty() => Ok(DeepLink::Join(argument)),
+        "import" => {
+            let url = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("url="))
+                .ok_or(anyhow!("Import link without a url"))?;
+            let url = percent_decode(url)?;
+            if !url.starts_with("https://") {
+                return Err(anyhow!("Only https pack urls can be imported"));
+            }
+            Ok(DeepLink::Import(url))
+        }
+        _ => Err(anyhow!("Unknown link {}", link)),
+    }
+}
+
+async fn confirm(app_handle: &tauri::AppHandle, message: String) -> anyhow::Result<bool> {
+    let window = app_handle.get_window("main");
+    Ok(tokio::task::spawn_blocking(move || {
+        tauri::api::dialog::blocking::ask(window.as_ref(), "Open link", message)
+    })
+    .await?)
+}
+
+async fn dispatch(app_handle: &tauri::AppHandle, link: DeepLink) -> anyhow::Result<()> {
+    info!("Handling link {:?}", link);
+    usage_stats::record_feature(app_handle, "deepLink");
+    tray::show_main_window(app_handle);
+    match link {
+        // Links can come from any website, so launching needs a confirmation
+        DeepLink::Launch(instance_id) => {
+            let config = crate::instance::read_config(app_handle, &instance_id).await?;
+            if confirm(app_handle, format!("Launch {}?", config.name)).await? {
+                tray::quick_launch(app_handle, &instance_id).await?;
+            }
+        }
+        // Picking the instance and account happens in the frontend
+        DeepLink::Join(address) => {
+            app_handle.emit_all("instance:join-requested", JoinRequested { address })?;
+        }
+        DeepLink::Import(url) => {
+            app_handle.emit_all("instance:import-requested", ImportRequested { url })?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses and handles a link in the background.
+pub fn handle(app_handle: &tauri::AppHandle, link: String) {
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let result = match parse(&link) {
+            Ok(link) => dispatch(&app_handle, link).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Failed to handle {}: {:#}", link, e);
+        }
+    });
+}
+
+/// Handles any links among the launcher's command line arguments, which is
+/// how they arrive on Windows and Linux.
+pub fn handle_args(app_handle: &tauri::AppHandle, args: &[String]) -> bool {
+    let mut handled = false;
+    for arg in args {
+        if arg.starts_with(&format!("{}://", SCHEME)) {
+            handle(app_handle, arg.clone());
+            handled = true;
+        }
+    }
+    handled
+}
@@ -0,0 +1,246 @@
+//! A Markdown report of the system, the launcher and optionally one
+//! instance, meant to be pasted into issue reports.
+
+use std::fmt::Write;
+
+use tokio::process::Command;
+
+use crate::{
+    client_instance, command_result,
+    errors::CommandError,
+    instance::{self, InstanceKind},
+    java, mclogs, usage_stats,
+};
+
+/// How many of the latest launcher errors go into a report
+const MAX_ERRORS: usize = 30;
+
+async fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().await.ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+async fn os_version() -> Option<String> {
+    if cfg!(target_os = "linux") {
+        let os_release = tokio::fs::read_to_string("/etc/os-release").await.ok()?;
+        os_release.lines().find_map(|line| {
+            Some(
+                line.strip_prefix("PRETTY_NAME=")?
+                    .trim_matches('"')
+                    .to_string(),
+            )
+        })
+    } else if cfg!(target_os = "macos") {
+        command_output("sw_vers", &["-productVersion"]).await
+    } else if cfg!(windows) {
+        command_output("cmd", &["/C", "ver"]).await
+    } else {
+        None
+    }
+}
+
+async fn gpus() -> Vec<String> {
+    let output = if cfg!(target_os = "linux") {
+        command_output("lspci", &[]).await.map(|output| {
+            output
+                .lines()
+                .filter(|line| line.contains("VGA") || line.contains("3D controller"))
+                .filter_map(|line| Some(line.split_once(": ")?.1))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+    } else if cfg!(target_os = "macos") {
+        command_output("system_profiler", &["SPDisplaysDataType"])
+            .await
+            .map(|output| {
+                output
+                    .lines()
+                    .filter_map(|line| line.trim().strip_prefix("Chipset Model: "))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+    } else if cfg!(windows) {
+        command_output(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "(Get-CimInstance Win32_VideoController).Name",
+            ],
+        )
+        .await
+    } else {
+        None
+    };
+    output
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Error lines from the most recently written launcher log.
+async fn recent_errors(app_handle: &tauri::AppHandle) -> Vec<String> {
+    let log_dir = match app_handle.path_resolver().app_log_dir() {
+        Some(log_dir) => log_dir,
+        None => return vec![],
+    };
+    let newest = std::fs::read_dir(log_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().and_then(|e| e.to_str()) == Some("log"))
+        .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok());
+    let newest = match newest {
+        Some(newest) => newest,
+        None => return vec![],
+    };
+    let content = tokio::fs::read(newest.path()).await.unwrap_or_default();
+    let errors: Vec<_> = String::from_utf8_lossy(&content)
+        .lines()
+        .filter(|line| line.contains("[ERROR]"))
+        .map(str::to_string)
+        .collect();
+    errors[errors.len().saturating_sub(MAX_ERRORS)..].to_vec()
+}
+
+async fn write_instance(
+    report: &mut String,
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<()> {
+    let config = instance::read_config(app_handle, instance_id).await?;
+    writeln!(report, "## Instance {}", instance_id)?;
+    writeln!(report, "Kind: {:?}", config.kind)?;
+    writeln!(
+        report,
+        "Minecraft: {}",
+        config.minecraft_version.as_deref().unwrap_or("unknown")
+    )?;
+    if let Some(loader) = &config.server_loader {
+        writeln!(report, "Loader: {}", serde_json::to_string(loader)?)?;
+    }
+    if let Some(java_path) = &config.java_path {
+        writeln!(report, "Java: {}", java_path)?;
+    }
+    if !config.jvm_args.is_empty() {
+        writeln!(report, "JVM arguments: {}", config.jvm_args.join(" "))?;
+    }
+    if config.kind == InstanceKind::Client {
+        match client_instance::resolved_components(app_handle, instance_id, &config).await {
+            Ok(components) => {
+                writeln!(report, "\n### Components ({})", components.len())?;
+                for component in components {
+                    let dependency = if component.dependency_only {
+                        " (dependency)"
+                    } else {
+                        ""
+                    };
+                    writeln!(
+                        report,
+                        "- {} {}{}",
+                        component.uid, component.version, dependency
+                    )?;
+                }
+            }
+            Err(e) => writeln!(report, "Components: unknown ({:#})", e)?,
+        }
+    }
+    let game_dir = instance::game_dir(app_handle, instance_id)?;
+    for dir in ["mods", "resourcepacks", "shaderpacks"] {
+        let mut files: Vec<_> = std::fs::read_dir(game_dir.join(dir))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        if files.is_empty() {
+            continue;
+        }
+        files.sort();
+        writeln!(report, "\n### {} ({})", dir, files.len())?;
+        for file in files {
+            writeln!(report, "- {}", file)?;
+        }
+    }
+    writeln!(report)?;
+    Ok(())
+}
+
+async fn generate_diagnostics_inner(
+    app_handle: &tauri::AppHandle,
+    instance_id: Option<&str>,
+) -> anyhow::Result<String> {
+    let mut report = String::new();
+    writeln!(report, "# untitled-minecraft-launcher diagnostics\n")?;
+    writeln!(report, "Launcher: {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(
+        report,
+        "OS: {} ({}, {})",
+        os_version().await.as_deref().unwrap_or("unknown"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )?;
+    let gpus = gpus().await;
+    if gpus.is_empty() {
+        writeln!(report, "GPU: unknown")?;
+    }
+    for gpu in gpus {
+        writeln!(report, "GPU: {}", gpu)?;
+    }
+
+    writeln!(report, "\n## Java")?;
+    let javas = java::detect(app_handle).await;
+    if javas.is_empty() {
+        writeln!(report, "None found")?;
+    }
+    for java in javas {
+        writeln!(
+            report,
+            "- {} ({}, {}) at {}",
+            java.version,
+            java.vendor.as_deref().unwrap_or("unknown vendor"),
+            java.arch.as_deref().unwrap_or("unknown arch"),
+            java.path.display()
+        )?;
+    }
+    writeln!(report)?;
+
+    if let Some(instance_id) = instance_id {
+        if let Err(e) = write_instance(&mut report, app_handle, instance_id).await {
+            writeln!(report, "## Instance {}\nCan't read: {:#}\n", instance_id, e)?;
+        }
+    }
+
+    writeln!(report, "## Recent errors")?;
+    let errors = recent_errors(app_handle).await;
+    if errors.is_empty() {
+        writeln!(report, "None")?;
+    } else {
+        writeln!(report, "```")?;
+        for error in errors {
+            writeln!(report, "{}", error)?;
+        }
+        writeln!(report, "```")?;
+    }
+    Ok(mclogs::redact(
+        &report,
+        &mclogs::KnownAccounts::load(app_handle),
+    ))
+}
+
+/// Builds a Markdown report of the system, Java runtimes, recent launcher
+/// errors and, if given, the instance that is having trouble. User names and
+/// tokens are redacted like uploaded logs.
+#[tauri::command]
+pub async fn generate_diagnostics(
+    app_handle: tauri::AppHandle,
+    instance_id: Option<String>,
+) -> Result<String, CommandError> {
+    usage_stats::record_feature(&app_handle, "diagnostics");
+    command_result(generate_diagnostics_inner(&app_handle, instance_id.as_deref()).await)
+}
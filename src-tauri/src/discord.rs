@@ -0,0 +1,204 @@
+//! Discord Rich Presence over Discord's local IPC socket.
+
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use log::{info, warn};
+use serde_json::json;
+use tauri::Manager;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::Mutex,
+};
+
+use crate::{
+    instance::{self, InstanceKind},
+    settings,
+};
+
+/// Id of the launcher's Discord application, set at build time and
+/// overridable with the same variable at runtime. Without one Rich Presence
+/// is unavailable and the frontend hides its setting.
+fn client_id() -> Option<String> {
+    std::env::var("UML_DISCORD_CLIENT_ID")
+        .ok()
+        .or_else(|| option_env!("UML_DISCORD_CLIENT_ID").map(str::to_string))
+        .filter(|client_id| !client_id.is_empty())
+}
+
+const OP_HANDSHAKE: u32 = 0;
+const OP_FRAME: u32 = 1;
+
+trait IpcStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> IpcStream for T {}
+
+struct DiscordIpc {
+    stream: Box<dyn IpcStream>,
+    nonce: u64,
+}
+
+impl DiscordIpc {
+    #[cfg(unix)]
+    async fn open_socket() -> anyhow::Result<Box<dyn IpcStream>> {
+        let base = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+            .into_iter()
+            .find_map(std::env::var_os)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/tmp"));
+        // Flatpak and Snap builds of Discord put the socket in a subdirectory
+        for dir in ["", "app/com.discordapp.Discord", "snap.discord"] {
+            for i in 0..10 {
+                let path = base.join(dir).join(format!("discord-ipc-{}", i));
+                if let Ok(stream) = tokio::net::UnixStream::connect(&path).await {
+                    return Ok(Box::new(stream));
+                }
+            }
+        }
+        Err(anyhow!("Discord doesn't seem to be running"))
+    }
+
+    #[cfg(windows)]
+    async fn open_socket() -> anyhow::Result<Box<dyn IpcStream>> {
+        for i in 0..10 {
+            let path = format!(r"\\.\pipe\discord-ipc-{}", i);
+            if let Ok(pipe) = tokio::net::windows::named_pipe::ClientOptions::new().open(&path) {
+                return Ok(Box::new(pipe));
+            }
+        }
+        Err(anyhow!("Discord doesn't seem to be running"))
+    }
+
+    async fn connect(client_id: &str) -> anyhow::Result<Self> {
+        let mut ipc = DiscordIpc {
+            stream: Self::open_socket().await?,
+            nonce: 0,
+        };
+        ipc.send(OP_HANDSHAKE, &json!({ "v": 1, "client_id": client_id }))
+            .await?;
+        let (op, ready) = ipc.receive().await?;
+        if op != OP_FRAME || ready["evt"] != "READY" {
+            return Err(anyhow!("Discord refused the handshake: {}", ready));
+        }
+        Ok(ipc)
+    }
+
+    async fn send(&mut self, op: u32, payload: &serde_json::Value) -> anyhow::Result<()> {
+        let payload = serde_json::to_vec(payload)?;
+        let mut frame = Vec::with_capacity(payload.len() + 8);
+        frame.extend_from_slice(&op.to_le_bytes());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        self.stream.write_all(&frame).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> anyhow::Result<(u32, serde_json::Value)> {
+        let op = self.stream.read_u32_le().await?;
+        let len = self.stream.read_u32_le().await?;
+        let mut payload = vec![0; len as usize];
+        self.stream.read_exact(&mut payload).await?;
+        Ok((op, serde_json::from_slice(&payload)?))
+    }
+
+    /// Sets the activity, or clears it when `activity` is `None`.
+    async fn set_activity(&mut self, activity: Option<serde_json::Value>) -> anyhow::Result<()> {
+        self.nonce += 1;
+        self.send(
+            OP_FRAME,
+            &json!({
+                "cmd": "SET_ACTIVITY",
+                "args": { "pid": std::process::id(), "activity": activity },
+                "nonce": self.nonce.to_string(),
+            }),
+        )
+        .await?;
+        let (_, response) = self.receive().await?;
+        if response["evt"] == "ERROR" {
+            return Err(anyhow!(
+                "Discord rejected the activity: {}",
+                response["data"]
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The Discord connection, opened lazily the first time a game starts.
+#[derive(Default)]
+pub struct DiscordPresence {
+    ipc: Mutex<Option<DiscordIpc>>,
+    /// The instance currently shown, so exits of other instances don't
+    /// clear it
+    playing: Mutex<Option<String>>,
+}
+
+async fn set_activity(
+    app_handle: &tauri::AppHandle,
+    activity: Option<serde_json::Value>,
+) -> anyhow::Result<()> {
+    let client_id = client_id().ok_or(anyhow!("Built without a Discord client id"))?;
+    let presence = app_handle.state::<DiscordPresence>();
+    let mut ipc = presence.ipc.lock().await;
+    if ipc.is_none() {
+        *ipc = Some(DiscordIpc::connect(&client_id).await?);
+    }
+    let result = ipc.as_mut().unwrap().set_activity(activity).await;
+    if result.is_err() {
+        // Discord was probably restarted, reconnect next time
+        *ipc = None;
+    }
+    result
+}
+
+async fn game_started_inner(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<()> {
+    if !settings::current(app_handle).discord_rich_presence || client_id().is_none() {
+        return Ok(());
+    }
+    let config = instance::read_config(app_handle, instance_id).await?;
+    if config.kind != InstanceKind::Client || !config.discord_rich_presence {
+        return Ok(());
+    }
+    let details = match &config.minecraft_version {
+        Some(version) => format!("Playing {} ({})", config.name, version),
+        None => format!("Playing {}", config.name),
+    };
+    let activity = json!({
+        "details": details,
+        "timestamps": { "start": time::OffsetDateTime::now_utc().unix_timestamp() },
+    });
+    set_activity(app_handle, Some(activity)).await?;
+    *app_handle.state::<DiscordPresence>().playing.lock().await = Some(instance_id.to_string());
+    info!("Set Discord presence for {}", instance_id);
+    Ok(())
+}
+
+/// Shows the instance as being played, if Rich Presence is enabled globally
+/// and for the instance.
+pub async fn game_started(app_handle: &tauri::AppHandle, instance_id: &str) {
+    if let Err(e) = game_started_inner(app_handle, instance_id).await {
+        warn!("Can't set Discord presence: {:#}", e);
+    }
+}
+
+/// Clears the presence if it is showing `instance_id`.
+pub async fn game_exited(app_handle: &tauri::AppHandle, instance_id: &str) {
+    let presence = app_handle.state::<DiscordPresence>();
+    let mut playing = presence.playing.lock().await;
+    if playing.as_deref() != Some(instance_id) {
+        return;
+    }
+    *playing = None;
+    if let Err(e) = set_activity(app_handle, None).await {
+        warn!("Can't clear Discord presence: {:#}", e);
+    }
+}
+
+/// Whether this build can show Rich Presence, so the frontend only offers
+/// the setting then.
+#[tauri::command]
+pub fn discord_rich_presence_available() -> bool {
+    client_id().is_some()
+}
@@ -0,0 +1,819 @@
+//! Downloads of game files like libraries and assets, queued and run a few
+//! at a time, as many as `max_concurrent_downloads` in the settings.
+//!
+//! While anything is queued, `download:progress` is emitted with a
+//! [`DownloadProgress`] whenever a download starts or ends, and every so
+//! often while bytes arrive.
+//!
+//! Downloads can be paused, resumed and cancelled one by one or for a whole
+//! task. Pausing keeps what was downloaded, cancelling deletes it.
+//!
+//! All downloads together are kept below `download_speed_limit` from the
+//! settings, if it's set.
+//!
+//! Connection failures and server errors are retried `download_retries`
+//! times with jittered backoff before a download fails. A file that arrives
+//! with the wrong hash or size is downloaded once more right away.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{watch, Notify},
+};
+
+use crate::{
+    errors::{coded, ErrorCode},
+    hash_cache, mirrors, plugins, proxy, settings,
+    storage::{self, Expected, StatusError},
+    tasks::{Cancelled, TaskId},
+};
+
+/// How often progress is emitted at most while bytes arrive
+const EMIT_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the speed is averaged over
+const SPEED_WINDOW: Duration = Duration::from_secs(1);
+/// Wait before the first retry, doubled for every one after
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// How many files [`download_pool`] checks for at once
+const PRESENCE_CHECKS: usize = 32;
+/// How many failed URLs an error lists at most
+const MAX_LISTED_FAILURES: usize = 10;
+
+pub type JobId = u64;
+
+/// A file to download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadJob {
+    pub url: String,
+    pub path: PathBuf,
+    /// Hash and size the file should have, if they're known
+    pub expected: Expected,
+    /// Download even if the file is there already
+    pub redownload: bool,
+    /// Hash the file that's there already even if its hash is cached
+    pub full_verify: bool,
+    /// The task the download is part of, to pause or cancel it with
+    pub task: Option<TaskId>,
+}
+
+impl DownloadJob {
+    pub fn new(url: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        DownloadJob {
+            url: url.into(),
+            path: path.into(),
+            expected: Expected::default(),
+            redownload: false,
+            full_verify: false,
+            task: None,
+        }
+    }
+
+    pub fn with_expected(mut self, expected: Expected) -> Self {
+        self.expected = expected;
+        self
+    }
+
+    pub fn with_redownload(mut self, redownload: bool) -> Self {
+        self.redownload = redownload;
+        self
+    }
+
+    pub fn with_full_verify(mut self, full_verify: bool) -> Self {
+        self.full_verify = full_verify;
+        self
+    }
+
+    pub fn with_task(mut self, task: Option<TaskId>) -> Self {
+        self.task = task;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    /// Waiting for a free slot
+    Queued,
+    Downloading,
+    Paused,
+}
+
+/// What the user wants a download to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    Run,
+    Pause,
+    Cancel,
+}
+
+/// Which downloads a command is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum DownloadSelector {
+    Job {
+        id: JobId,
+    },
+    /// Every download of a task
+    Task {
+        id: TaskId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobProgress {
+    pub id: JobId,
+    pub url: String,
+    pub task: Option<TaskId>,
+    pub status: JobStatus,
+    pub downloaded: u64,
+    /// Bytes, once they're known
+    pub total: Option<u64>,
+}
+
+/// The payload of `download:progress`. Counts start over once everything
+/// queued is done.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadProgress {
+    /// Queued and running downloads
+    pub jobs: Vec<JobProgress>,
+    pub completed: usize,
+    pub failed: usize,
+    /// URLs of the downloads that failed after all retries
+    pub failed_urls: Vec<String>,
+    pub downloaded_bytes: u64,
+    /// Of the downloads with a known size
+    pub total_bytes: u64,
+    pub bytes_per_second: u64,
+}
+
+#[derive(Default)]
+struct Queue {
+    next_id: JobId,
+    jobs: BTreeMap<JobId, JobProgress>,
+    controls: HashMap<JobId, watch::Sender<Control>>,
+    /// Downloads holding a slot
+    active: usize,
+    completed: usize,
+    failed: usize,
+    failed_urls: Vec<String>,
+    downloaded_bytes: u64,
+    total_bytes: u64,
+    bytes_per_second: u64,
+    /// When the speed was last worked out, and the bytes by then
+    speed_sample: Option<(Instant, u64)>,
+    last_emit: Option<Instant>,
+}
+
+impl Queue {
+    fn progress(&self) -> DownloadProgress {
+        DownloadProgress {
+            jobs: self.jobs.values().cloned().collect(),
+            completed: self.completed,
+            failed: self.failed,
+            failed_urls: self.failed_urls.clone(),
+            downloaded_bytes: self.downloaded_bytes,
+            total_bytes: self.total_bytes,
+            bytes_per_second: self.bytes_per_second,
+        }
+    }
+
+    fn update_speed(&mut self, now: Instant) {
+        match self.speed_sample {
+            Some((at, bytes)) if now.duration_since(at) >= SPEED_WINDOW => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                self.bytes_per_second =
+                    (self.downloaded_bytes.saturating_sub(bytes) as f64 / elapsed) as u64;
+                self.speed_sample = Some((now, self.downloaded_bytes));
+            }
+            Some(_) => {}
+            None => self.speed_sample = Some((now, self.downloaded_bytes)),
+        }
+    }
+}
+
+/// Spreads the bytes of all downloads over time so they stay below a speed
+/// limit, allowing bursts of up to a second's worth.
+#[derive(Default)]
+struct Limiter {
+    /// Bytes that may arrive right away, negative when downloads are ahead
+    /// of the limit
+    allowance: f64,
+    last: Option<Instant>,
+}
+
+impl Limiter {
+    /// Takes `bytes` that arrived, returning how long to wait before
+    /// reading more to stay below `limit` bytes per second.
+    fn take(&mut self, bytes: u64, limit: u64) -> Duration {
+        let now = Instant::now();
+        let limit = limit as f64;
+        let elapsed = self
+            .last
+            .map_or(0.0, |last| now.duration_since(last).as_secs_f64());
+        self.last = Some(now);
+        self.allowance = (self.allowance + elapsed * limit).min(limit) - bytes as f64;
+        if self.allowance < 0.0 {
+            Duration::from_secs_f64(-self.allowance / limit)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DownloadManager {
+    queue: Mutex<Queue>,
+    slot_freed: Notify,
+    limiter: Mutex<Limiter>,
+}
+
+impl DownloadManager {
+    pub fn progress(&self) -> DownloadProgress {
+        self.queue.lock().unwrap().progress()
+    }
+
+    /// Changes the queue and emits the progress, unless it was emitted less
+    /// than [`EMIT_INTERVAL`] ago and not `force`.
+    fn update(&self, app_handle: &tauri::AppHandle, force: bool, f: impl FnOnce(&mut Queue)) {
+        let mut queue = self.queue.lock().unwrap();
+        f(&mut queue);
+        let now = Instant::now();
+        queue.update_speed(now);
+        let due = queue
+            .last_emit
+            .is_none_or(|last| now.duration_since(last) >= EMIT_INTERVAL);
+        if force || due {
+            queue.last_emit = Some(now);
+            if let Err(e) = app_handle.emit_all("download:progress", queue.progress()) {
+                warn!("Failed to emit download progress: {}", e);
+            }
+        }
+        if queue.jobs.is_empty() {
+            *queue = Queue {
+                next_id: queue.next_id,
+                ..Queue::default()
+            };
+        }
+    }
+
+    fn enqueue(
+        &self,
+        app_handle: &tauri::AppHandle,
+        job: &DownloadJob,
+    ) -> (JobId, watch::Receiver<Control>) {
+        let mut id = 0;
+        let (control, receiver) = watch::channel(Control::Run);
+        self.update(app_handle, true, |queue| {
+            id = queue.next_id;
+            queue.next_id += 1;
+            queue.controls.insert(id, control);
+            queue.total_bytes += job.expected.size.unwrap_or(0);
+            queue.jobs.insert(
+                id,
+                JobProgress {
+                    id,
+                    url: job.url.clone(),
+                    task: job.task,
+                    status: JobStatus::Queued,
+                    downloaded: 0,
+                    total: job.expected.size,
+                },
+            );
+        });
+        (id, receiver)
+    }
+
+    /// Tells the selected downloads to `control`, returning how many there
+    /// were.
+    fn control(&self, selector: DownloadSelector, control: Control) -> usize {
+        let queue = self.queue.lock().unwrap();
+        let mut count = 0;
+        for job in queue.jobs.values() {
+            let selected = match selector {
+                DownloadSelector::Job { id } => job.id == id,
+                DownloadSelector::Task { id } => job.task == Some(id),
+            };
+            if let Some(sender) = queue.controls.get(&job.id).filter(|_| selected) {
+                sender.send_replace(control);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn update_job(
+        &self,
+        app_handle: &tauri::AppHandle,
+        id: JobId,
+        force: bool,
+        f: impl FnOnce(&mut JobProgress, &mut u64, &mut u64),
+    ) {
+        self.update(app_handle, force, |queue| {
+            let Queue {
+                jobs,
+                downloaded_bytes,
+                total_bytes,
+                ..
+            } = queue;
+            if let Some(job) = jobs.get_mut(&id) {
+                f(job, downloaded_bytes, total_bytes);
+            }
+        });
+    }
+}
+
+/// Takes the job out of the queue when it ends, however it ends.
+struct JobGuard<'a> {
+    app_handle: &'a tauri::AppHandle,
+    id: JobId,
+    succeeded: bool,
+    /// Neither completed nor failed
+    cancelled: bool,
+}
+
+impl Drop for JobGuard<'_> {
+    fn drop(&mut self) {
+        let manager = self.app_handle.state::<DownloadManager>();
+        manager.update(self.app_handle, true, |queue| {
+            queue.controls.remove(&self.id);
+            let job = match queue.jobs.remove(&self.id) {
+                Some(job) => job,
+                None => return,
+            };
+            if self.succeeded {
+                queue.completed += 1;
+            } else if !self.cancelled {
+                queue.failed += 1;
+                queue.failed_urls.push(job.url);
+            }
+        });
+    }
+}
+
+/// One of the `max_concurrent_downloads` slots, freed when dropped.
+struct Slot<'a> {
+    app_handle: &'a tauri::AppHandle,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let manager = self.app_handle.state::<DownloadManager>();
+        manager.queue.lock().unwrap().active -= 1;
+        manager.slot_freed.notify_waiters();
+    }
+}
+
+/// Waits for a free slot. The limit is read every time, so changes to the
+/// settings apply to what's queued already.
+async fn acquire_slot(app_handle: &tauri::AppHandle) -> Slot<'_> {
+    let manager = app_handle.state::<DownloadManager>();
+    loop {
+        // Registered before checking, so a slot freed in between isn't
+        // missed
+        let freed = manager.slot_freed.notified();
+        {
+            let mut queue = manager.queue.lock().unwrap();
+            if queue.active < settings::current(app_handle).max_concurrent_downloads {
+                queue.active += 1;
+                return Slot { app_handle };
+            }
+        }
+        freed.await;
+    }
+}
+
+/// Whether the file for `job` is there already and doesn't need to be
+/// downloaded.
+async fn is_present(job: &DownloadJob) -> bool {
+    !job.redownload && job.expected.matches_file(&job.path, job.full_verify).await
+}
+
+/// Whether trying `e` again might work: lost connections, timeouts and
+/// server errors.
+fn is_transient(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<StatusError>() {
+        Some(StatusError { status, .. }) => *status >= 500 || *status == 408 || *status == 429,
+        None => e.chain().any(|cause| cause.is::<reqwest::Error>()),
+    }
+}
+
+/// `backoff` give or take half of it, so downloads that failed together
+/// don't all retry at once.
+fn jittered(backoff: Duration) -> Duration {
+    // Random enough for spreading out retries
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.subsec_nanos());
+    backoff.mul_f64(0.5 + f64::from(nanos % 1000) / 1000.0)
+}
+
+/// Downloads `url` to `path`, reporting the bytes as they arrive. The file
+/// only appears at `path` once it's complete and as `expected`. If it can be
+/// verified, an earlier attempt that was interrupted is resumed.
+async fn fetch(
+    app_handle: &tauri::AppHandle,
+    id: JobId,
+    url: &str,
+    path: &Path,
+    expected: &Expected,
+) -> anyhow::Result<()> {
+    let manager = app_handle.state::<DownloadManager>();
+    let part = storage::part_path(path);
+    let download =
+        storage::start_download(&proxy::client(), url, &part, expected.hash.is_some()).await?;
+    let (offset, total) = (download.offset, download.total());
+    // Only what's in the part file counts, not what earlier attempts got
+    manager.update_job(
+        app_handle,
+        id,
+        false,
+        |job, downloaded_bytes, total_bytes| {
+            *downloaded_bytes = *downloaded_bytes - job.downloaded + offset;
+            job.downloaded = offset;
+            if let (None, Some(total)) = (job.total, total) {
+                job.total = Some(total);
+                *total_bytes += total;
+            }
+        },
+    );
+    let storage::PartDownload {
+        mut resp, mut file, ..
+    } = download;
+    // Read once per file, not for every chunk
+    let limit = settings::current(app_handle).download_speed_limit;
+    while let Some(chunk) = resp.chunk().await? {
+        file.write_all(&chunk).await?;
+        let len = chunk.len() as u64;
+        manager.update_job(app_handle, id, false, |job, downloaded_bytes, _| {
+            job.downloaded += len;
+            *downloaded_bytes += len;
+        });
+        if let Some(limit) = limit {
+            let wait = manager.limiter.lock().unwrap().take(len, limit);
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+    file.sync_all().await?;
+    drop(file);
+    if let Err(e) = expected.verify(url, &part).await {
+        storage::remove_part(&part).await;
+        return Err(e);
+    }
+    tokio::fs::rename(&part, path).await?;
+    expected.remember(path).await;
+    Ok(())
+}
+
+/// Waits until `control` says to do something other than `current`.
+async fn changed_from(control: &mut watch::Receiver<Control>, current: Control) -> Control {
+    match control.wait_for(|control| *control != current).await {
+        Ok(control) => *control,
+        // The sender is only dropped with the job
+        Err(_) => current,
+    }
+}
+
+/// Downloads `job`, from the mirror and then the original server, and again
+/// after a while if that failed in a way that might not last.
+async fn fetch_with_retries(
+    app_handle: &tauri::AppHandle,
+    id: JobId,
+    job: &DownloadJob,
+) -> anyhow::Result<()> {
+    let retries = settings::current(app_handle).download_retries;
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let result = storage::retry_on_mismatch(&job.url, || {
+            mirrors::with_fallback(&job.url, |url| async move {
+                fetch(app_handle, id, &url, &job.path, &job.expected).await
+            })
+        })
+        .await;
+        match result {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                let wait = jittered(backoff);
+                warn!(
+                    "Downloading {} failed, retrying in {:?} ({}/{}): {:#}",
+                    job.url, wait, attempt, retries, e
+                );
+                tokio::time::sleep(wait).await;
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Deletes what a cancelled download got so far.
+async fn cancelled(job: &DownloadJob) -> anyhow::Error {
+    storage::remove_part(&storage::part_path(&job.path)).await;
+    Cancelled.into()
+}
+
+/// Downloads `job` through the queue, unless the file is there already.
+///
+/// A paused download gives up its slot and picks up where it stopped once
+/// it's resumed, a cancelled one fails with [`Cancelled`] and deletes the
+/// partial file.
+pub async fn download(app_handle: &tauri::AppHandle, job: &DownloadJob) -> anyhow::Result<()> {
+    let manager = app_handle.state::<DownloadManager>();
+    let (id, mut control) = manager.enqueue(app_handle, job);
+    let mut guard = JobGuard {
+        app_handle,
+        id,
+        succeeded: false,
+        cancelled: false,
+    };
+    if is_present(job).await {
+        guard.succeeded = true;
+        return Ok(());
+    }
+    if let Some(parent) = job.path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    loop {
+        if *control.borrow() == Control::Pause {
+            manager.update_job(app_handle, id, true, |progress, _, _| {
+                progress.status = JobStatus::Paused;
+            });
+            changed_from(&mut control, Control::Pause).await;
+        }
+        if *control.borrow() == Control::Cancel {
+            guard.cancelled = true;
+            return Err(cancelled(job).await);
+        }
+        manager.update_job(app_handle, id, true, |progress, _, _| {
+            progress.status = JobStatus::Queued;
+        });
+        let _slot = tokio::select! {
+            slot = acquire_slot(app_handle) => slot,
+            _ = changed_from(&mut control, Control::Run) => continue,
+        };
+        manager.update_job(app_handle, id, true, |progress, _, _| {
+            progress.status = JobStatus::Downloading;
+        });
+        let fetched = fetch_with_retries(app_handle, id, job);
+        tokio::select! {
+            result = fetched => {
+                result?;
+                break;
+            }
+            interrupted = changed_from(&mut control, Control::Run) => {
+                if interrupted == Control::Cancel {
+                    guard.cancelled = true;
+                    return Err(cancelled(job).await);
+                }
+            }
+        }
+    }
+    plugins::run_hook(
+        plugins::Hook::Download,
+        serde_json::json!({ "url": job.url, "path": job.path }),
+    );
+    guard.succeeded = true;
+    Ok(())
+}
+
+/// Fails if the files of `jobs` that aren't there yet won't fit on the
+/// disk. Only sizes known beforehand count.
+pub async fn ensure_space_for<'a>(
+    jobs: impl IntoIterator<Item = &'a DownloadJob>,
+) -> anyhow::Result<()> {
+    let mut needed = 0;
+    let mut dir = None;
+    for job in jobs {
+        dir = dir.or(job.path.parent());
+        if job.redownload || !job.path.exists() {
+            needed += job.expected.size.unwrap_or(0);
+        }
+    }
+    match dir {
+        Some(dir) => storage::ensure_free_space(dir, needed).await,
+        None => Ok(()),
+    }
+}
+
+/// One error for all the downloads that `failed`, listing the first few.
+fn failure(mut failed: Vec<(&DownloadJob, anyhow::Error)>) -> anyhow::Result<()> {
+    match failed.len() {
+        0 => Ok(()),
+        // Keeps the code of the error
+        1 => Err(failed.pop().unwrap().1),
+        _ => {
+            let listed = failed
+                .iter()
+                .take(MAX_LISTED_FAILURES)
+                .map(|(job, _)| job.url.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let more = match failed.len().saturating_sub(MAX_LISTED_FAILURES) {
+                0 => String::new(),
+                more => format!(" and {} more", more),
+            };
+            Err(coded(
+                ErrorCode::DownloadFailed,
+                format!("{} downloads failed: {}{}", failed.len(), listed, more),
+            ))
+        }
+    }
+}
+
+/// Saves the hashes checked in a batch, logging if that fails.
+pub(crate) async fn save_hash_cache() {
+    if let Err(e) = hash_cache::save().await {
+        warn!("Failed to save the hash cache: {:#}", e);
+    }
+}
+
+/// Downloads all of `jobs` through the queue, after checking there's room
+/// for them. If any fail, the others still finish and the error lists all
+/// that failed.
+pub async fn download_all(
+    app_handle: &tauri::AppHandle,
+    jobs: &[DownloadJob],
+) -> anyhow::Result<()> {
+    ensure_space_for(jobs).await?;
+    let results = futures::future::join_all(jobs.iter().map(|job| download(app_handle, job))).await;
+    save_hash_cache().await;
+    let mut failed = vec![];
+    for (job, result) in jobs.iter().zip(results) {
+        match result {
+            Err(e) if e.is::<Cancelled>() => return Err(e),
+            Err(e) => {
+                warn!("Failed to download {}: {:#}", job.url, e);
+                failed.push((job, e));
+            }
+            Ok(()) => {}
+        }
+    }
+    failure(failed)
+}
+
+/// How far [`download_pool`] is, by files and by their sizes where known.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+impl PoolProgress {
+    fn add(&mut self, job: &DownloadJob) {
+        self.files_done += 1;
+        self.bytes_done += job.expected.size.unwrap_or(0);
+    }
+}
+
+/// Calls `on_progress` at most every [`EMIT_INTERVAL`], and when `done`.
+struct Reporter<F> {
+    on_progress: F,
+    last: Option<Instant>,
+}
+
+impl<F: FnMut(&PoolProgress)> Reporter<F> {
+    fn report(&mut self, progress: &PoolProgress, done: bool) {
+        let now = Instant::now();
+        if done
+            || self
+                .last
+                .is_none_or(|last| now.duration_since(last) >= EMIT_INTERVAL)
+        {
+            self.last = Some(now);
+            (self.on_progress)(progress);
+        }
+    }
+}
+
+async fn check(job: &DownloadJob) -> (&DownloadJob, bool) {
+    (job, is_present(job).await)
+}
+
+async fn download_job<'a>(
+    app_handle: &tauri::AppHandle,
+    job: &'a DownloadJob,
+) -> (&'a DownloadJob, anyhow::Result<()>) {
+    (job, download(app_handle, job).await)
+}
+
+/// Downloads lots of small files, like asset objects, reporting how many
+/// are done to `on_progress`. Unlike [`download_all`], the files that are
+/// there already are sorted out first, many at once and without going
+/// through the queue, and only as many downloads are queued at a time as
+/// can run, so the queue and its progress events stay small.
+pub async fn download_pool(
+    app_handle: &tauri::AppHandle,
+    jobs: &[DownloadJob],
+    on_progress: impl FnMut(&PoolProgress),
+) -> anyhow::Result<()> {
+    let mut progress = PoolProgress {
+        files_total: jobs.len(),
+        bytes_total: jobs.iter().map(|job| job.expected.size.unwrap_or(0)).sum(),
+        ..PoolProgress::default()
+    };
+    let mut reporter = Reporter {
+        on_progress,
+        last: None,
+    };
+    reporter.report(&progress, false);
+
+    // Not a buffered stream with closures, futures from those aren't
+    // general enough for the callers to be spawned
+    let mut missing = vec![];
+    let mut pending = jobs.iter();
+    let mut checks = FuturesUnordered::new();
+    loop {
+        checks.extend(
+            pending
+                .by_ref()
+                .take(PRESENCE_CHECKS - checks.len())
+                .map(check),
+        );
+        let (job, present) = match checks.next().await {
+            Some(checked) => checked,
+            None => break,
+        };
+        if present {
+            progress.add(job);
+            reporter.report(&progress, false);
+        } else {
+            missing.push(job);
+        }
+    }
+    save_hash_cache().await;
+    ensure_space_for(missing.iter().copied()).await?;
+
+    let workers = settings::current(app_handle).max_concurrent_downloads;
+    let mut pending = missing.into_iter();
+    let mut downloads = FuturesUnordered::new();
+    let mut failed = vec![];
+    loop {
+        downloads.extend(
+            pending
+                .by_ref()
+                .take(workers.saturating_sub(downloads.len()))
+                .map(|job| download_job(app_handle, job)),
+        );
+        let (job, result) = match downloads.next().await {
+            Some(downloaded) => downloaded,
+            None => break,
+        };
+        match result {
+            Err(e) if e.is::<Cancelled>() => return Err(e),
+            Err(e) => {
+                warn!("Failed to download {}: {:#}", job.url, e);
+                failed.push((job, e));
+            }
+            Ok(()) => {}
+        }
+        progress.add(job);
+        reporter.report(&progress, false);
+    }
+    save_hash_cache().await;
+    reporter.report(&progress, true);
+    failure(failed)
+}
+
+#[tauri::command]
+pub fn get_download_progress(app_handle: tauri::AppHandle) -> DownloadProgress {
+    app_handle.state::<DownloadManager>().progress()
+}
+
+/// Pauses the selected downloads, returning how many there were.
+#[tauri::command]
+pub fn pause_downloads(app_handle: tauri::AppHandle, selector: DownloadSelector) -> usize {
+    app_handle
+        .state::<DownloadManager>()
+        .control(selector, Control::Pause)
+}
+
+#[tauri::command]
+pub fn resume_downloads(app_handle: tauri::AppHandle, selector: DownloadSelector) -> usize {
+    app_handle
+        .state::<DownloadManager>()
+        .control(selector, Control::Run)
+}
+
+#[tauri::command]
+pub fn cancel_downloads(app_handle: tauri::AppHandle, selector: DownloadSelector) -> usize {
+    app_handle
+        .state::<DownloadManager>()
+        .control(selector, Control::Cancel)
+}
@@ -0,0 +1,200 @@
+//! Stable error codes for command failures, so the frontend and support docs
+//! can tell failure modes apart without matching on messages.
+//!
+//! Code that knows exactly what went wrong returns a [`CodedError`] through
+//! [`coded`]; anything else is classified by [`ErrorCode::of`] from the
+//! errors in its chain.
+//!
+//! Alongside the English message, errors carry a [`Message`] the frontend
+//! can translate: the one given to [`coded_message`], or the generic one for
+//! their code.
+
+use serde::Serialize;
+
+use crate::{
+    auth::AuthError,
+    messages::Message,
+    storage::{IntegrityError, StatusError},
+    tasks::Cancelled,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    Unknown,
+    Cancelled,
+    /// The user declined signing in
+    AuthDeclined,
+    /// A login or token expired before it could be used
+    AuthExpired,
+    /// Signing in failed because of the account, e.g. it has no Xbox profile
+    AuthFailed,
+    Network,
+    /// The server answered a download with an error status
+    DownloadFailed,
+    DownloadHashMismatch,
+    JavaNotFound,
+    JavaIncompatible,
+    DiskFull,
+    PermissionDenied,
+    NotFound,
+    /// A file or response couldn't be parsed
+    InvalidData,
+    /// The instance has to be stopped first
+    InstanceRunning,
+    InstanceNotRunning,
+    /// The instance is locked against changes
+    InstanceLocked,
+    EulaNotAccepted,
+    Unsupported,
+    /// The picked components of an instance don't work together
+    ComponentConflict,
+    /// Something is too new for this version of the launcher
+    UpdateRequired,
+}
+
+/// An error that knows its [`ErrorCode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodedError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub localized: Option<Message>,
+}
+
+impl std::fmt::Display for CodedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+pub fn coded(code: ErrorCode, message: impl std::fmt::Display) -> anyhow::Error {
+    CodedError {
+        code,
+        message: message.to_string(),
+        localized: None,
+    }
+    .into()
+}
+
+/// Like [`coded`], with a message from the catalog.
+pub fn coded_message(code: ErrorCode, message: Message) -> anyhow::Error {
+    CodedError {
+        code,
+        message: message.render(),
+        localized: Some(message),
+    }
+    .into()
+}
+
+fn io_error_code(e: &std::io::Error) -> Option<ErrorCode> {
+    use std::io::ErrorKind;
+
+    // ENOSPC, and ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL on Windows
+    #[cfg(unix)]
+    const DISK_FULL: &[i32] = &[28];
+    #[cfg(windows)]
+    const DISK_FULL: &[i32] = &[39, 112];
+    if e.raw_os_error()
+        .is_some_and(|code| DISK_FULL.contains(&code))
+    {
+        return Some(ErrorCode::DiskFull);
+    }
+    match e.kind() {
+        ErrorKind::NotFound => Some(ErrorCode::NotFound),
+        ErrorKind::PermissionDenied => Some(ErrorCode::PermissionDenied),
+        ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::TimedOut => Some(ErrorCode::Network),
+        ErrorKind::InvalidData | ErrorKind::UnexpectedEof => Some(ErrorCode::InvalidData),
+        _ => None,
+    }
+}
+
+impl ErrorCode {
+    /// Id of the generic message for this code
+    pub fn message_id(self) -> &'static str {
+        match self {
+            ErrorCode::Unknown => "error.unknown",
+            ErrorCode::Cancelled => "error.cancelled",
+            ErrorCode::AuthDeclined => "error.authDeclined",
+            ErrorCode::AuthExpired => "error.authExpired",
+            ErrorCode::AuthFailed => "error.authFailed",
+            ErrorCode::Network => "error.network",
+            ErrorCode::DownloadFailed => "error.downloadFailed",
+            ErrorCode::DownloadHashMismatch => "error.downloadHashMismatch",
+            ErrorCode::JavaNotFound => "error.javaNotFound",
+            ErrorCode::JavaIncompatible => "error.javaIncompatible",
+            ErrorCode::DiskFull => "error.diskFull",
+            ErrorCode::PermissionDenied => "error.permissionDenied",
+            ErrorCode::NotFound => "error.notFound",
+            ErrorCode::InvalidData => "error.invalidData",
+            ErrorCode::InstanceRunning => "error.instanceRunning",
+            ErrorCode::InstanceNotRunning => "error.instanceNotRunning",
+            ErrorCode::InstanceLocked => "error.instanceLocked",
+            ErrorCode::EulaNotAccepted => "error.eulaNotAccepted",
+            ErrorCode::Unsupported => "error.unsupported",
+            ErrorCode::ComponentConflict => "error.componentConflict",
+            ErrorCode::UpdateRequired => "error.updateRequired",
+        }
+    }
+
+    /// The code of the outermost error in the chain that has one.
+    pub fn of(e: &anyhow::Error) -> ErrorCode {
+        e.chain()
+            .find_map(|cause| {
+                if let Some(coded) = cause.downcast_ref::<CodedError>() {
+                    Some(coded.code)
+                } else if let Some(auth) = cause.downcast_ref::<AuthError>() {
+                    Some(auth.code())
+                } else if cause.is::<Cancelled>() {
+                    Some(ErrorCode::Cancelled)
+                } else if cause.is::<IntegrityError>() {
+                    Some(ErrorCode::DownloadHashMismatch)
+                } else if cause.is::<StatusError>() {
+                    Some(ErrorCode::DownloadFailed)
+                } else if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                    io_error_code(e)
+                } else if cause.is::<reqwest::Error>() {
+                    Some(ErrorCode::Network)
+                } else if cause.is::<serde_json::Error>() || cause.is::<zip::result::ZipError>() {
+                    Some(ErrorCode::InvalidData)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(ErrorCode::Unknown)
+    }
+}
+
+/// What a failed command returns to the frontend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandError {
+    pub code: ErrorCode,
+    /// English, with the details of what went wrong
+    pub message: String,
+    pub localized: Message,
+}
+
+impl From<anyhow::Error> for CommandError {
+    fn from(e: anyhow::Error) -> Self {
+        let code = ErrorCode::of(&e);
+        let localized = e
+            .chain()
+            .find_map(|cause| {
+                if let Some(auth) = cause.downcast_ref::<AuthError>() {
+                    return Some(auth.message());
+                }
+                cause.downcast_ref::<CodedError>()?.localized.clone()
+            })
+            .unwrap_or_else(|| Message::new(code.message_id()));
+        CommandError {
+            code,
+            message: format!("{:#}", e),
+            localized,
+        }
+    }
+}
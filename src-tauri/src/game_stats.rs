@@ -0,0 +1,189 @@
+//! Reads the game's own per-world statistics from `stats/<uuid>.json`.
+
+use std::{collections::HashMap, path::Path};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{command_result, errors::CommandError, instance};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameStats {
+    /// In game ticks, 20 per second
+    pub play_time_ticks: u64,
+    pub deaths: u64,
+    pub mob_kills: u64,
+    pub player_kills: u64,
+    pub jumps: u64,
+    /// Summed over every movement type, in centimeters
+    pub distance_cm: u64,
+    pub blocks_mined: u64,
+    pub items_crafted: u64,
+}
+
+impl GameStats {
+    fn add(&mut self, other: &GameStats) {
+        self.play_time_ticks += other.play_time_ticks;
+        self.deaths += other.deaths;
+        self.mob_kills += other.mob_kills;
+        self.player_kills += other.player_kills;
+        self.jumps += other.jumps;
+        self.distance_cm += other.distance_cm;
+        self.blocks_mined += other.blocks_mined;
+        self.items_crafted += other.items_crafted;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorldStats {
+    pub world: String,
+    pub player_uuid: String,
+    pub stats: GameStats,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceStats {
+    pub total: GameStats,
+    pub worlds: Vec<WorldStats>,
+}
+
+fn sum(values: Option<&Value>) -> u64 {
+    values
+        .and_then(Value::as_object)
+        .map_or(0, |map| map.values().filter_map(Value::as_u64).sum())
+}
+
+/// Parses a stats file in either the 1.13+ nested format or the older flat
+/// `stat.*` format.
+pub fn parse_stats(json: &Value) -> GameStats {
+    if let Some(stats) = json.get("stats") {
+        let custom: HashMap<&str, u64> = stats
+            .get("minecraft:custom")
+            .and_then(Value::as_object)
+            .map(|map| {
+                map.iter()
+                    .filter_map(|(k, v)| Some((k.as_str(), v.as_u64()?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let get = |key: &str| custom.get(key).copied().unwrap_or(0);
+        GameStats {
+            // Renamed from play_one_minute in 1.17
+            play_time_ticks: get("minecraft:play_time") + get("minecraft:play_one_minute"),
+            deaths: get("minecraft:deaths"),
+            mob_kills: get("minecraft:mob_kills"),
+            player_kills: get("minecraft:player_kills"),
+            jumps: get("minecraft:jump"),
+            distance_cm: custom
+                .iter()
+                .filter(|(k, _)| k.ends_with("_one_cm"))
+                .map(|(_, v)| v)
+                .sum(),
+            blocks_mined: sum(stats.get("minecraft:mined")),
+            items_crafted: sum(stats.get("minecraft:crafted")),
+        }
+    } else {
+        let flat: HashMap<&str, u64> = json
+            .as_object()
+            .map(|map| {
+                map.iter()
+                    .filter_map(|(k, v)| Some((k.as_str(), v.as_u64()?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let get = |key: &str| flat.get(key).copied().unwrap_or(0);
+        let sum_prefixed = |prefix: &str| -> u64 {
+            flat.iter()
+                .filter(|(k, _)| k.starts_with(prefix))
+                .map(|(_, v)| v)
+                .sum()
+        };
+        GameStats {
+            play_time_ticks: get("stat.playOneMinute"),
+            deaths: get("stat.deaths"),
+            mob_kills: get("stat.mobKills"),
+            player_kills: get("stat.playerKills"),
+            jumps: get("stat.jump"),
+            distance_cm: flat
+                .iter()
+                .filter(|(k, _)| k.starts_with("stat.") && k.ends_with("OneCm"))
+                .map(|(_, v)| v)
+                .sum(),
+            blocks_mined: sum_prefixed("stat.mineBlock."),
+            items_crafted: sum_prefixed("stat.craftItem."),
+        }
+    }
+}
+
+async fn read_world_stats(
+    world_dir: &Path,
+    player_uuid: Option<&str>,
+) -> anyhow::Result<Vec<(String, GameStats)>> {
+    let mut results = vec![];
+    let mut entries = match tokio::fs::read_dir(world_dir.join("stats")).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(results),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let uuid = match path.file_stem() {
+            Some(stem) if path.extension() == Some("json".as_ref()) => {
+                stem.to_string_lossy().to_string()
+            }
+            _ => continue,
+        };
+        if let Some(wanted) = player_uuid {
+            if !uuid.eq_ignore_ascii_case(wanted) {
+                continue;
+            }
+        }
+        let json: Value = serde_json::from_slice(&tokio::fs::read(&path).await?)?;
+        results.push((uuid, parse_stats(&json)));
+    }
+    Ok(results)
+}
+
+/// Collects stats from every world of an instance, optionally only for one
+/// player.
+pub async fn instance_stats(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    player_uuid: Option<&str>,
+) -> anyhow::Result<InstanceStats> {
+    let saves = instance::game_dir(app_handle, instance_id)?.join("saves");
+    let mut result = InstanceStats::default();
+    let mut entries = match tokio::fs::read_dir(&saves).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(result),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let world = entry.file_name().to_string_lossy().to_string();
+        for (uuid, stats) in read_world_stats(&entry.path(), player_uuid).await? {
+            result.total.add(&stats);
+            result.worlds.push(WorldStats {
+                world: world.clone(),
+                player_uuid: uuid,
+                stats,
+            });
+        }
+    }
+    result.worlds.sort_by(|a, b| a.world.cmp(&b.world));
+    Ok(result)
+}
+
+#[tauri::command]
+pub async fn get_instance_stats(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    player_uuid: Option<String>,
+) -> Result<InstanceStats, CommandError> {
+    command_result(instance_stats(&app_handle, &instance_id, player_uuid.as_deref()).await)
+}
@@ -0,0 +1,140 @@
+//! Hashes of files that were checked before, so checking libraries and
+//! assets again doesn't mean reading all of them. A hash is reused as long
+//! as the file has the same size and modification time.
+//!
+//! The cache is kept in `hash_cache.json` in the launcher dir, loaded on
+//! startup and written after a batch of files was checked.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{instance, storage::FileHash};
+
+lazy_static::lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache::default());
+    /// Held while saving, so an older snapshot can't be renamed over a
+    /// newer one
+    static ref SAVING: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    /// Nanoseconds since the epoch
+    modified: u64,
+    sha1: Option<String>,
+    sha256: Option<String>,
+}
+
+#[derive(Default)]
+struct Cache {
+    /// Where it's saved, once loaded
+    path: Option<PathBuf>,
+    entries: HashMap<String, Entry>,
+    /// Changed since it was saved
+    dirty: bool,
+}
+
+/// Size and modification time of `metadata`, or `None` if the platform has
+/// no modification times.
+fn stamp(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_nanos() as u64))
+}
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+/// Loads the cache from the launcher dir. Without it, nothing is cached.
+pub fn load(app_handle: &tauri::AppHandle) -> anyhow::Result<()> {
+    let path = instance::launcher_dir(app_handle)?.join("hash_cache.json");
+    let entries = match std::fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => return Err(e.into()),
+    };
+    info!("Loaded {} cached file hashes", entries.len());
+    *CACHE.lock().unwrap() = Cache {
+        path: Some(path),
+        entries,
+        dirty: false,
+    };
+    Ok(())
+}
+
+/// Writes the cache if it changed. Meant to be called once a batch of files
+/// was checked, not after every file.
+pub async fn save() -> anyhow::Result<()> {
+    let _saving = SAVING.lock().await;
+    let (path, data) = {
+        let mut cache = CACHE.lock().unwrap();
+        let path = match &cache.path {
+            Some(path) if cache.dirty => path.clone(),
+            _ => return Ok(()),
+        };
+        cache.dirty = false;
+        (path, serde_json::to_vec(&cache.entries)?)
+    };
+    // Another launcher process could be saving at the same time
+    let tmp = path.with_extension(format!("json.{}.tmp", uuid::Uuid::new_v4().simple()));
+    let result = match tokio::fs::write(&tmp, data).await {
+        Ok(()) => tokio::fs::rename(&tmp, &path).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        // So the next batch tries again
+        CACHE.lock().unwrap().dirty = true;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// The cached hash of the same kind as `like` for the file at `path`, if
+/// the file hasn't changed since.
+pub fn lookup(path: &Path, metadata: &std::fs::Metadata, like: &FileHash) -> Option<FileHash> {
+    let (size, modified) = stamp(metadata)?;
+    let cache = CACHE.lock().unwrap();
+    let entry = cache
+        .entries
+        .get(&key(path))
+        .filter(|entry| entry.size == size && entry.modified == modified)?;
+    match like {
+        FileHash::Sha1(_) => entry.sha1.clone().map(FileHash::Sha1),
+        FileHash::Sha256(_) => entry.sha256.clone().map(FileHash::Sha256),
+    }
+}
+
+/// Remembers `hash` for the file at `path` as it is now.
+pub fn store(path: &Path, metadata: &std::fs::Metadata, hash: &FileHash) {
+    let (size, modified) = match stamp(metadata) {
+        Some(stamp) => stamp,
+        None => return,
+    };
+    let mut cache = CACHE.lock().unwrap();
+    if cache.path.is_none() {
+        return;
+    }
+    let entry = cache.entries.entry(key(path)).or_default();
+    // Hashes of an older version of the file are of no use anymore
+    if entry.size != size || entry.modified != modified {
+        *entry = Entry {
+            size,
+            modified,
+            ..Entry::default()
+        };
+    }
+    match hash {
+        FileHash::Sha1(hex) => entry.sha1 = Some(hex.clone()),
+        FileHash::Sha256(hex) => entry.sha256 = Some(hex.clone()),
+    }
+    cache.dirty = true;
+}
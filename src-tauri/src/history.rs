@@ -0,0 +1,389 @@
+//! Instance history, kept in a git repository in `<instance>/history`.
+//!
+//! A snapshot copies the instance config, the list of installed mods and
+//! the instance's `history_files` into the repository's work tree and
+//! commits them. Mods themselves aren't stored, rolling back disables the
+//! ones that weren't installed at the time and re-enables disabled ones
+//! that were.
+
+use std::path::{Path, PathBuf};
+
+use git2::{build::CheckoutBuilder, IndexAddOption, Oid, Repository, Signature};
+use log::info;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use tauri::Manager;
+
+use crate::{
+    command_result,
+    errors::{coded, CommandError, ErrorCode},
+    instance::{self, InstanceConfig},
+    messages::Message,
+    process::ProcessManager,
+    sync::{self, ModFile},
+    tasks::{self, TaskKind},
+};
+
+const CONFIG_FILE: &str = "instance.json";
+const MODS_FILE: &str = "mods.json";
+/// Where `history_files` go in the work tree
+const FILES_DIR: &str = "files";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// Commit hash
+    pub id: String,
+    pub message: String,
+    /// Unix timestamp
+    pub time: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RollbackResult {
+    /// Mods that were installed after the snapshot and got disabled
+    pub disabled_mods: Vec<String>,
+    /// Mods from the snapshot that aren't installed anymore
+    pub missing_mods: Vec<String>,
+}
+
+lazy_static::lazy_static! {
+    /// Snapshots and rollbacks share the work tree
+    static ref LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+fn open_or_init(path: &Path) -> anyhow::Result<Repository> {
+    match Repository::open(path) {
+        Ok(repo) => Ok(repo),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => {
+            std::fs::create_dir_all(path)?;
+            Ok(Repository::init(path)?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn remove_path(path: &Path) -> anyhow::Result<()> {
+    let result = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Commits the current state, returning `None` if nothing changed since the
+/// last snapshot.
+fn snapshot_blocking(
+    history_dir: &Path,
+    game_dir: &Path,
+    config: &InstanceConfig,
+    message: &str,
+) -> anyhow::Result<Option<Oid>> {
+    let repo = open_or_init(history_dir)?;
+
+    // Start from an empty work tree so deleted files show up as deleted
+    for entry in std::fs::read_dir(history_dir)? {
+        let entry = entry?;
+        if entry.file_name() != ".git" {
+            remove_path(&entry.path())?;
+        }
+    }
+    // The launch time changes all the time and isn't worth a snapshot
+    let config = InstanceConfig {
+        last_played: None,
+        ..config.clone()
+    };
+    std::fs::write(
+        history_dir.join(CONFIG_FILE),
+        serde_json::to_vec_pretty(&config)?,
+    )?;
+    std::fs::write(
+        history_dir.join(MODS_FILE),
+        serde_json::to_vec_pretty(&sync::mod_list(game_dir)?)?,
+    )?;
+    for file in &config.history_files {
+        instance::copy_recursive(
+            &instance::safe_join(game_dir, file)?,
+            &instance::safe_join(&history_dir.join(FILES_DIR), file)?,
+        )?;
+    }
+
+    let mut index = repo.index()?;
+    index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"], None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    if parent
+        .as_ref()
+        .is_some_and(|parent| parent.tree_id() == tree.id())
+    {
+        return Ok(None);
+    }
+    let signature = Signature::now("Launcher", "launcher@localhost")?;
+    let parents: Vec<_> = parent.iter().collect();
+    let id = repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )?;
+    Ok(Some(id))
+}
+
+/// Snapshots an instance into its history.
+pub async fn snapshot(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    message: &str,
+) -> anyhow::Result<Option<String>> {
+    let id = tasks::run(
+        app_handle,
+        TaskKind::Backup,
+        Message::new("task.snapshot").with("instance", instance_id),
+        |_| async move {
+            let _lock = LOCK.lock().await;
+            let config = instance::read_config(app_handle, instance_id).await?;
+            let history_dir = history_dir(app_handle, instance_id)?;
+            let game_dir = instance::game_dir(app_handle, instance_id)?;
+            let message = message.to_string();
+            tokio::task::spawn_blocking(move || {
+                snapshot_blocking(&history_dir, &game_dir, &config, &message)
+            })
+            .await?
+        },
+    )
+    .await?;
+    if let Some(id) = id {
+        info!("Snapshotted {} as {}", instance_id, id);
+    }
+    Ok(id.map(|id| id.to_string()))
+}
+
+/// Snapshots the instance if it has `track_history` enabled, for use before
+/// changes that could break it.
+pub async fn auto_snapshot(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    if instance::read_config(app_handle, instance_id)
+        .await?
+        .track_history
+    {
+        snapshot(app_handle, instance_id, message).await?;
+    }
+    Ok(())
+}
+
+fn history_blocking(history_dir: &Path) -> anyhow::Result<Vec<HistoryEntry>> {
+    if !history_dir.join(".git").exists() {
+        return Ok(vec![]);
+    }
+    let repo = Repository::open(history_dir)?;
+    let mut walk = repo.revwalk()?;
+    if walk.push_head().is_err() {
+        // No commits yet
+        return Ok(vec![]);
+    }
+    walk.map(|id| {
+        let commit = repo.find_commit(id?)?;
+        Ok(HistoryEntry {
+            id: commit.id().to_string(),
+            message: commit.message().unwrap_or_default().trim().to_string(),
+            time: commit.time().seconds(),
+        })
+    })
+    .collect()
+}
+
+fn set_mod_enabled(mods_dir: &Path, file_name: &str, enabled: bool) -> anyhow::Result<()> {
+    let enabled_path = instance::safe_join(mods_dir, file_name)?;
+    let disabled_path = instance::safe_join(mods_dir, &format!("{}.disabled", file_name))?;
+    if enabled {
+        std::fs::rename(disabled_path, enabled_path)?;
+    } else {
+        std::fs::rename(enabled_path, disabled_path)?;
+    }
+    Ok(())
+}
+
+/// Restores the work tree to commit `id` and copies it back into the
+/// instance.
+fn rollback_blocking(
+    history_dir: &Path,
+    instance_dir: &Path,
+    game_dir: &Path,
+    current: &InstanceConfig,
+    id: &str,
+) -> anyhow::Result<RollbackResult> {
+    let repo = Repository::open(history_dir)?;
+    let commit = repo
+        .find_commit(Oid::from_str(id)?)
+        .map_err(|e| coded(ErrorCode::NotFound, format!("No snapshot {}: {}", id, e)))?;
+    repo.checkout_tree(
+        commit.as_object(),
+        Some(CheckoutBuilder::new().force().remove_untracked(true)),
+    )?;
+
+    let mut config: InstanceConfig =
+        serde_json::from_slice(&std::fs::read(history_dir.join(CONFIG_FILE))?)?;
+    config.last_played = current.last_played;
+    config.locked = current.locked;
+    let tmp = instance_dir.join("instance.json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(&config)?)?;
+    std::fs::rename(&tmp, instance_dir.join("instance.json"))?;
+
+    // Files that are tracked now but weren't back then are removed too
+    for file in current.history_files.iter().chain(&config.history_files) {
+        remove_path(&instance::safe_join(game_dir, file)?)?;
+    }
+    for file in &config.history_files {
+        instance::copy_recursive(
+            &instance::safe_join(&history_dir.join(FILES_DIR), file)?,
+            &instance::safe_join(game_dir, file)?,
+        )?;
+    }
+
+    let snapshot_mods: Vec<ModFile> =
+        serde_json::from_slice(&std::fs::read(history_dir.join(MODS_FILE))?)?;
+    let mods_dir = game_dir.join("mods");
+    let mut result = RollbackResult::default();
+    for installed in sync::mod_list(game_dir)? {
+        if installed.file_name.ends_with(".disabled") {
+            continue;
+        }
+        if !snapshot_mods.contains(&installed) {
+            set_mod_enabled(&mods_dir, &installed.file_name, false)?;
+            result.disabled_mods.push(installed.file_name);
+        }
+    }
+    for file in snapshot_mods {
+        if file.file_name.ends_with(".disabled") || mods_dir.join(&file.file_name).exists() {
+            continue;
+        }
+        let disabled = mods_dir.join(format!("{}.disabled", file.file_name));
+        if disabled.exists() && hex::encode(Sha1::digest(std::fs::read(&disabled)?)) == file.sha1 {
+            set_mod_enabled(&mods_dir, &file.file_name, true)?;
+        } else {
+            result.missing_mods.push(file.file_name);
+        }
+    }
+    Ok(result)
+}
+
+fn history_dir(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<PathBuf> {
+    Ok(instance::instance_dir(app_handle, instance_id)?.join("history"))
+}
+
+/// Returns the id of the new snapshot, or `None` if nothing changed.
+#[tauri::command]
+pub async fn snapshot_instance(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    message: Option<String>,
+) -> Result<Option<String>, CommandError> {
+    command_result(
+        snapshot(
+            &app_handle,
+            &instance_id,
+            message.as_deref().unwrap_or("Manual snapshot"),
+        )
+        .await,
+    )
+}
+
+/// Newest first.
+#[tauri::command]
+pub async fn get_instance_history(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+) -> Result<Vec<HistoryEntry>, CommandError> {
+    command_result(
+        async {
+            let history_dir = history_dir(&app_handle, &instance_id)?;
+            tokio::task::spawn_blocking(move || history_blocking(&history_dir)).await?
+        }
+        .await,
+    )
+}
+
+/// Puts the instance back into the state of snapshot `id`. The current
+/// state is snapshotted first, so the rollback can be undone.
+#[tauri::command]
+pub async fn rollback_instance(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    id: String,
+) -> Result<RollbackResult, CommandError> {
+    command_result(
+        async {
+            if app_handle
+                .state::<ProcessManager>()
+                .is_running(&instance_id)
+            {
+                return Err(coded(
+                    ErrorCode::InstanceRunning,
+                    "Stop the instance before rolling it back",
+                ));
+            }
+            instance::ensure_unlocked(&app_handle, &instance_id).await?;
+            snapshot(&app_handle, &instance_id, "Before rollback").await?;
+            let result = tasks::run(
+                &app_handle,
+                TaskKind::Backup,
+                Message::new("task.rollback").with("instance", instance_id.as_str()),
+                |_| async {
+                    let _lock = LOCK.lock().await;
+                    let current = instance::read_config(&app_handle, &instance_id).await?;
+                    let history_dir = history_dir(&app_handle, &instance_id)?;
+                    let instance_dir = instance::instance_dir(&app_handle, &instance_id)?;
+                    let game_dir = instance::game_dir(&app_handle, &instance_id)?;
+                    let id = id.clone();
+                    tokio::task::spawn_blocking(move || {
+                        rollback_blocking(&history_dir, &instance_dir, &game_dir, &current, &id)
+                    })
+                    .await?
+                },
+            )
+            .await?;
+            snapshot(
+                &app_handle,
+                &instance_id,
+                &format!("Rolled back to {}", &id[..id.len().min(7)]),
+            )
+            .await?;
+            crate::tray::refresh(&app_handle);
+            Ok(result)
+        }
+        .await,
+    )
+}
+
+#[tauri::command]
+pub async fn set_instance_track_history(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    track_history: bool,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            instance::ensure_unlocked(&app_handle, &instance_id).await?;
+            let mut config = instance::read_config(&app_handle, &instance_id).await?;
+            config.track_history = track_history;
+            instance::write_config(&app_handle, &instance_id, &config).await?;
+            if track_history {
+                snapshot(&app_handle, &instance_id, "Started tracking history").await?;
+            }
+            Ok(())
+        }
+        .await,
+    )
+}
@@ -0,0 +1,162 @@
+//! One progress stream per install task, so the frontend can show a single
+//! bar for it. Installs go through phases, and report the files and bytes
+//! done in each as `install:progress` with an [`InstallProgress`].
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use serde::Serialize;
+use tauri::Manager;
+
+use crate::tasks::{TaskHandle, TaskId};
+
+/// How often progress is emitted at most
+const EMIT_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the speed is averaged over
+const SPEED_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InstallPhase {
+    /// Version lists and manifests
+    Meta,
+    Libraries,
+    Assets,
+    Mods,
+    /// The server jar or loader installer
+    Server,
+}
+
+/// The payload of `install:progress`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstallProgress {
+    pub task: TaskId,
+    pub phase: InstallPhase,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    /// Of the files with a known size
+    pub bytes_total: u64,
+    pub bytes_per_second: u64,
+    /// Seconds until the phase is done, once there's a speed to go by
+    pub eta_seconds: Option<u64>,
+}
+
+struct State {
+    progress: InstallProgress,
+    /// When the speed was last worked out, and the bytes by then
+    speed_sample: (Instant, u64),
+    last_emit: Option<Instant>,
+}
+
+/// Tracks the progress of an install task, emitting it and setting the
+/// progress of the task along the way.
+pub struct InstallTracker {
+    app_handle: tauri::AppHandle,
+    task: TaskHandle,
+    state: Mutex<State>,
+}
+
+impl InstallTracker {
+    pub fn new(app_handle: &tauri::AppHandle, task: &TaskHandle) -> InstallTracker {
+        InstallTracker {
+            app_handle: app_handle.clone(),
+            task: task.clone(),
+            state: Mutex::new(State {
+                progress: InstallProgress {
+                    task: task.id(),
+                    phase: InstallPhase::Meta,
+                    files_done: 0,
+                    files_total: 0,
+                    bytes_done: 0,
+                    bytes_total: 0,
+                    bytes_per_second: 0,
+                    eta_seconds: None,
+                },
+                speed_sample: (Instant::now(), 0),
+                last_emit: None,
+            }),
+        }
+    }
+
+    pub fn task(&self) -> &TaskHandle {
+        &self.task
+    }
+
+    /// Starts `phase`, with `files_total` files of `bytes_total` bytes to go.
+    pub fn phase(&self, phase: InstallPhase, files_total: usize, bytes_total: u64) {
+        self.state.lock().unwrap().speed_sample = (Instant::now(), 0);
+        self.update(true, |progress| {
+            *progress = InstallProgress {
+                task: progress.task,
+                phase,
+                files_done: 0,
+                files_total,
+                bytes_done: 0,
+                bytes_total,
+                bytes_per_second: 0,
+                eta_seconds: None,
+            };
+        });
+    }
+
+    /// Sets how far the current phase is.
+    pub fn set(&self, files_done: usize, bytes_done: u64) {
+        self.update(false, |progress| {
+            progress.files_done = files_done;
+            progress.bytes_done = bytes_done;
+        });
+    }
+
+    /// Counts `files` more files of `bytes` bytes as done.
+    pub fn add(&self, files: usize, bytes: u64) {
+        self.update(false, |progress| {
+            progress.files_done += files;
+            progress.bytes_done += bytes;
+        });
+    }
+
+    /// Changes the progress and emits it, unless it was emitted less than
+    /// [`EMIT_INTERVAL`] ago and not `force`.
+    fn update(&self, force: bool, f: impl FnOnce(&mut InstallProgress)) {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state.progress);
+        let now = Instant::now();
+        let (at, bytes) = state.speed_sample;
+        if now.duration_since(at) >= SPEED_WINDOW {
+            let elapsed = now.duration_since(at).as_secs_f64();
+            let done = state.progress.bytes_done;
+            state.progress.bytes_per_second = (done.saturating_sub(bytes) as f64 / elapsed) as u64;
+            state.speed_sample = (now, done);
+        }
+        let progress = &mut state.progress;
+        let remaining = progress.bytes_total.saturating_sub(progress.bytes_done);
+        progress.eta_seconds =
+            (progress.bytes_per_second > 0).then(|| remaining / progress.bytes_per_second);
+
+        let done = progress.files_done >= progress.files_total;
+        let due = state
+            .last_emit
+            .is_none_or(|last| now.duration_since(last) >= EMIT_INTERVAL);
+        if !(force || done || due) {
+            return;
+        }
+        state.last_emit = Some(now);
+        let progress = state.progress.clone();
+        drop(state);
+        if progress.bytes_total > 0 {
+            self.task
+                .set_progress(progress.bytes_done, progress.bytes_total);
+        } else {
+            self.task
+                .set_progress(progress.files_done as u64, progress.files_total as u64);
+        }
+        if let Err(e) = self.app_handle.emit_all("install:progress", progress) {
+            warn!("Failed to emit install progress: {}", e);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    command_result,
+    errors::{coded_message, CommandError, ErrorCode},
+    messages::Message,
+    plugins::{self, Hook},
+    server_instance::ServerLoader,
+    settings,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InstanceKind {
+    #[default]
+    Client,
+    Server,
+}
+
+/// The per-instance `instance.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceConfig {
+    pub name: String,
+    #[serde(default)]
+    pub kind: InstanceKind,
+    pub minecraft_version: Option<String>,
+    #[serde(default)]
+    pub server_loader: Option<ServerLoader>,
+    /// Java executable to use instead of the one on `PATH`
+    #[serde(default)]
+    pub java_path: Option<String>,
+    /// Launch with `java_path` even if the version needs another Java
+    #[serde(default)]
+    pub ignore_java_compatibility: bool,
+    #[serde(default)]
+    pub jvm_args: Vec<String>,
+    /// Lets the instance opt out of Discord Rich Presence
+    #[serde(default = "default_true")]
+    pub discord_rich_presence: bool,
+    /// Always listed in the tray menu
+    #[serde(default)]
+    pub pinned: bool,
+    /// Unix timestamp of the last launch
+    #[serde(default)]
+    pub last_played: Option<i64>,
+    /// Snapshot the instance into its history automatically, see `history.rs`
+    #[serde(default)]
+    pub track_history: bool,
+    /// Files and directories in the game dir that are kept in the history
+    #[serde(default = "default_history_files")]
+    pub history_files: Vec<String>,
+    /// Group the instance is listed under
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Icon file in the instance dir
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// Blocks changes to mods, the version and the config until unlocked
+    #[serde(default)]
+    pub locked: bool,
+}
+
+impl InstanceConfig {
+    /// A config with everything but the name and kind left as for a
+    /// config file without them.
+    pub fn new(name: String, kind: InstanceKind) -> InstanceConfig {
+        InstanceConfig {
+            name,
+            kind,
+            minecraft_version: None,
+            server_loader: None,
+            java_path: None,
+            ignore_java_compatibility: false,
+            jvm_args: vec![],
+            discord_rich_presence: default_true(),
+            pinned: false,
+            last_played: None,
+            track_history: false,
+            history_files: default_history_files(),
+            group: None,
+            icon: None,
+            locked: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceEntry {
+    pub id: String,
+    #[serde(flatten)]
+    pub config: InstanceConfig,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub fn default_history_files() -> Vec<String> {
+    vec!["options.txt".to_string(), "config".to_string()]
+}
+
+pub fn launcher_dir(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    if let Some(data_dir) = crate::settings::current(app_handle).data_dir {
+        return Ok(data_dir);
+    }
+    app_handle
+        .path_resolver()
+        .app_data_dir()
+        .ok_or(anyhow!("Can't get app data dir"))
+}
+
+pub fn instances_dir(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    Ok(launcher_dir(app_handle)?.join("instances"))
+}
+
+pub fn trash_dir(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    Ok(launcher_dir(app_handle)?.join("trash"))
+}
+
+pub fn instance_dir(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<PathBuf> {
+    check_name(instance_id)?;
+    Ok(instances_dir(app_handle)?.join(instance_id))
+}
+
+/// The `.minecraft` directory the game runs in
+pub fn game_dir(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<PathBuf> {
+    Ok(instance_dir(app_handle, instance_id)?.join(".minecraft"))
+}
+
+/// Where the native libraries of a client instance are extracted to
+pub fn natives_dir(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<PathBuf> {
+    Ok(instance_dir(app_handle, instance_id)?.join("natives"))
+}
+
+/// Makes sure a name coming from the frontend is a single path component,
+/// so it can't be used to escape the directory it is joined onto.
+pub fn check_name(name: &str) -> anyhow::Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(anyhow!("Invalid name: {:?}", name)),
+    }
+}
+
+/// Joins a relative path from an untrusted source (modpack manifests,
+/// archives) onto `base`, refusing anything that would end up outside it.
+pub fn safe_join(base: &Path, relative: &str) -> anyhow::Result<PathBuf> {
+    let relative = Path::new(relative);
+    if relative.components().count() == 0
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(anyhow!("Unsafe path {:?}", relative));
+    }
+    Ok(base.join(relative))
+}
+
+/// Copies a file or directory tree, doing nothing if `from` doesn't exist.
+pub fn copy_recursive(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if from.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else if from.is_file() {
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Moves `path` into the launcher trash instead of deleting it outright.
+pub async fn move_to_trash(
+    app_handle: &tauri::AppHandle,
+    path: &Path,
+    label: &str,
+) -> anyhow::Result<PathBuf> {
+    let trash = trash_dir(app_handle)?;
+    tokio::fs::create_dir_all(&trash).await?;
+    let timestamp = time::OffsetDateTime::now_utc().unix_timestamp();
+    let file_name = path
+        .file_name()
+        .ok_or(anyhow!("Can't trash a path without a file name"))?
+        .to_string_lossy();
+    let target = trash.join(format!("{}-{}-{}", timestamp, label, file_name));
+    tokio::fs::rename(path, &target).await?;
+    Ok(target)
+}
+
+pub async fn read_config(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<InstanceConfig> {
+    let path = instance_dir(app_handle, instance_id)?.join("instance.json");
+    let data = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("Can't read config of instance {}", instance_id))?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+pub async fn write_config(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    config: &InstanceConfig,
+) -> anyhow::Result<()> {
+    let dir = instance_dir(app_handle, instance_id)?;
+    tokio::fs::create_dir_all(&dir).await?;
+    let tmp = dir.join("instance.json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(config)?).await?;
+    tokio::fs::rename(&tmp, dir.join("instance.json")).await?;
+    Ok(())
+}
+
+/// Creates a new instance directory named after `config.name`, returning
+/// the id of the new instance.
+pub async fn create_instance(
+    app_handle: &tauri::AppHandle,
+    config: &InstanceConfig,
+) -> anyhow::Result<String> {
+    let base: String = config
+        .name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let base = base.trim_start_matches('.');
+    let base = if base.is_empty() { "instance" } else { base };
+    let mut instance_id = base.to_string();
+    let mut n = 1;
+    while tokio::fs::metadata(instance_dir(app_handle, &instance_id)?)
+        .await
+        .is_ok()
+    {
+        n += 1;
+        instance_id = format!("{}-{}", base, n);
+    }
+    tokio::fs::create_dir_all(game_dir(app_handle, &instance_id)?).await?;
+    write_config(app_handle, &instance_id, config).await?;
+    plugins::run_hook(
+        Hook::InstanceCreated,
+        json!({ "instanceId": instance_id, "config": config }),
+    );
+    Ok(instance_id)
+}
+
+/// Reads the config of every instance, skipping ones that can't be read.
+pub async fn list_instances(app_handle: &tauri::AppHandle) -> anyhow::Result<Vec<InstanceEntry>> {
+    let mut instances = vec![];
+    let mut entries = match tokio::fs::read_dir(instances_dir(app_handle)?).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(instances),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let id = entry.file_name().to_string_lossy().to_string();
+        match read_config(app_handle, &id).await {
+            Ok(config) => instances.push(InstanceEntry { id, config }),
+            Err(e) => warn!("Skipping instance {}: {:#}", id, e),
+        }
+    }
+    instances.sort_by(|a, b| a.config.name.cmp(&b.config.name));
+    Ok(instances)
+}
+
+/// Stamps the instance's `last_played` with the current time, returning the
+/// updated config.
+pub async fn record_launch(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<InstanceConfig> {
+    let mut config = read_config(app_handle, instance_id).await?;
+    config.last_played = Some(time::OffsetDateTime::now_utc().unix_timestamp());
+    write_config(app_handle, instance_id, &config).await?;
+    Ok(config)
+}
+
+/// Fails if the instance is locked, for anything that changes its mods,
+/// version or config.
+pub async fn ensure_unlocked(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<()> {
+    if read_config(app_handle, instance_id).await?.locked {
+        return Err(coded_message(
+            ErrorCode::InstanceLocked,
+            Message::new("instanceLock.locked").with("instance", instance_id),
+        ));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_instances(
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<InstanceEntry>, CommandError> {
+    command_result(list_instances(&app_handle).await)
+}
+
+#[tauri::command]
+pub async fn set_instance_pinned(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    pinned: bool,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            let mut config = read_config(&app_handle, &instance_id).await?;
+            config.pinned = pinned;
+            write_config(&app_handle, &instance_id, &config).await?;
+            crate::tray::refresh(&app_handle);
+            Ok(())
+        }
+        .await,
+    )
+}
+
+/// Locks or unlocks an instance. Unlocking needs the PIN if one is set in
+/// the settings.
+#[tauri::command]
+pub async fn set_instance_locked(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    locked: bool,
+    pin: Option<String>,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            if !locked && !settings::check_lock_pin(&app_handle, pin.as_deref()) {
+                return Err(coded_message(
+                    ErrorCode::PermissionDenied,
+                    Message::new("instanceLock.wrongPin"),
+                ));
+            }
+            let mut config = read_config(&app_handle, &instance_id).await?;
+            config.locked = locked;
+            write_config(&app_handle, &instance_id, &config).await?;
+            Ok(())
+        }
+        .await,
+    )
+}
@@ -0,0 +1,93 @@
+//! Jar mods, patched right into the client jar like mods were before mod
+//! loaders, and like legacy Forge and OptiFine still can be. The patched
+//! jars are cached by what went into them.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+use zip::{ZipArchive, ZipWriter};
+
+use crate::instance;
+
+/// Where patched jars are cached
+pub fn jar_mods_cache_dir(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    Ok(instance::launcher_dir(app_handle)?.join("jarmods"))
+}
+
+/// Signatures of the original jar don't hold for the patched one
+fn is_signature(name: &str) -> bool {
+    name.starts_with("META-INF/")
+}
+
+/// Identifies the patched jar for `main_jar` with `jar_mods`, so it's only
+/// built once.
+fn patched_jar_id(main_jar: &Path, jar_mods: &[PathBuf]) -> anyhow::Result<String> {
+    let mut hasher = Sha1::new();
+    for jar in std::iter::once(main_jar).chain(jar_mods.iter().map(PathBuf::as_path)) {
+        hasher.update(Sha1::digest(std::fs::read(jar)?));
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Copies the entries of the jar at `from` that aren't in `seen` yet.
+fn copy_entries(
+    zip: &mut ZipWriter<File>,
+    from: &Path,
+    seen: &mut HashSet<String>,
+) -> anyhow::Result<()> {
+    let mut archive = ZipArchive::new(File::open(from)?)?;
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        if is_signature(entry.name()) || !seen.insert(entry.name().to_string()) {
+            continue;
+        }
+        zip.raw_copy_file(entry)?;
+    }
+    Ok(())
+}
+
+fn build_patched_jar_blocking(
+    main_jar: &Path,
+    jar_mods: &[PathBuf],
+    cache_dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    let path = cache_dir.join(format!("{}.jar", patched_jar_id(main_jar, jar_mods)?));
+    if path.exists() {
+        return Ok(path);
+    }
+    std::fs::create_dir_all(cache_dir)?;
+    let tmp = path.with_extension("jar.tmp");
+    let mut zip = ZipWriter::new(File::create(&tmp)?);
+    let mut seen = HashSet::new();
+    // Later jar mods win over earlier ones, and all of them over the game
+    for jar_mod in jar_mods.iter().rev() {
+        copy_entries(&mut zip, jar_mod, &mut seen)
+            .with_context(|| format!("Failed to apply jar mod {}", jar_mod.display()))?;
+    }
+    copy_entries(&mut zip, main_jar, &mut seen)?;
+    zip.finish()?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
+/// The client jar at `main_jar` with `jar_mods` overlaid in order and the
+/// signatures stripped, built in `cache_dir` unless it was already. Returns
+/// `main_jar` itself if there are no jar mods.
+pub async fn build_patched_jar(
+    main_jar: PathBuf,
+    jar_mods: Vec<PathBuf>,
+    cache_dir: PathBuf,
+) -> anyhow::Result<PathBuf> {
+    if jar_mods.is_empty() {
+        return Ok(main_jar);
+    }
+    tokio::task::spawn_blocking(move || {
+        build_patched_jar_blocking(&main_jar, &jar_mods, &cache_dir)
+    })
+    .await?
+}
@@ -0,0 +1,264 @@
+//! Finding Java installations and working out their versions.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use serde::Serialize;
+use tokio::process::Command;
+
+use crate::{
+    errors::{coded_message, ErrorCode},
+    instance::{self, InstanceConfig},
+    messages::Message,
+    settings,
+};
+
+#[cfg(windows)]
+const JAVA_EXECUTABLE: &str = "java.exe";
+#[cfg(not(windows))]
+const JAVA_EXECUTABLE: &str = "java";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JavaInstall {
+    pub path: PathBuf,
+    /// `java.version`, e.g. `17.0.5` or `1.8.0_352`
+    pub version: String,
+    pub major: u32,
+    pub vendor: Option<String>,
+    /// `os.arch` of the runtime, which may differ from the launcher's
+    pub arch: Option<String>,
+}
+
+impl JavaInstall {
+    /// Whether this runtime can load the natives installed for the
+    /// launcher's architecture. Unknown ones are given the benefit of the
+    /// doubt.
+    pub fn matches_arch(&self) -> bool {
+        let Some(arch) = &self.arch else {
+            return true;
+        };
+        let names: &[&str] = match std::env::consts::ARCH {
+            "x86_64" => &["amd64", "x86_64"],
+            "x86" => &["x86", "i386", "i686"],
+            "aarch64" => &["aarch64", "arm64"],
+            "arm" => &["arm", "aarch32"],
+            other => return arch == other,
+        };
+        names.contains(&arch.as_str())
+    }
+}
+
+/// `1.8.0_352` is Java 8, `17.0.5` is Java 17.
+pub fn major_version(version: &str) -> Option<u32> {
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    match parts.next()?.parse().ok()? {
+        1 => parts.next()?.parse().ok(),
+        major => Some(major),
+    }
+}
+
+/// Runs `java` to read its system properties.
+pub async fn probe(java: &Path) -> anyhow::Result<JavaInstall> {
+    let output = Command::new(java)
+        .arg("-XshowSettings:properties")
+        .arg("-version")
+        .output()
+        .await?;
+    // The properties go to stderr
+    let output = String::from_utf8_lossy(&output.stderr);
+    let property = |name: &str| {
+        output.lines().find_map(|line| {
+            let (key, value) = line.trim().split_once(" = ")?;
+            (key == name).then(|| value.to_string())
+        })
+    };
+    let version = property("java.version").ok_or(anyhow!("{:?} is not a Java runtime", java))?;
+    Ok(JavaInstall {
+        path: java.to_path_buf(),
+        major: major_version(&version).ok_or(anyhow!(
+            "Unknown Java version {} at {:?}",
+            version,
+            java
+        ))?,
+        version,
+        vendor: property("java.vendor"),
+        arch: property("os.arch"),
+    })
+}
+
+/// `<dir>/*/<suffix>/java` for every subdirectory of `dir`.
+fn java_homes_in(dir: &Path, suffix: &str) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join(suffix).join(JAVA_EXECUTABLE))
+        .filter(|java| java.is_file())
+        .collect()
+}
+
+fn candidates(app_handle: &tauri::AppHandle) -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from(JAVA_EXECUTABLE)];
+    if let Some(java_path) = settings::current(app_handle).java_path {
+        candidates.push(PathBuf::from(java_path));
+    }
+    if let Some(java_home) = std::env::var_os("JAVA_HOME") {
+        candidates.push(PathBuf::from(java_home).join("bin").join(JAVA_EXECUTABLE));
+    }
+    if let Ok(dir) = instance::launcher_dir(app_handle) {
+        candidates.extend(java_homes_in(&dir.join("java"), "bin"));
+    }
+    #[cfg(target_os = "linux")]
+    for dir in ["/usr/lib/jvm", "/usr/lib64/jvm", "/usr/java", "/opt/java"] {
+        candidates.extend(java_homes_in(Path::new(dir), "bin"));
+    }
+    #[cfg(target_os = "macos")]
+    candidates.extend(java_homes_in(
+        Path::new("/Library/Java/JavaVirtualMachines"),
+        "Contents/Home/bin",
+    ));
+    #[cfg(windows)]
+    for program_files in ["ProgramFiles", "ProgramFiles(x86)"] {
+        if let Some(program_files) = std::env::var_os(program_files) {
+            for vendor in ["Java", "Eclipse Adoptium", "Zulu", "Microsoft"] {
+                candidates.extend(java_homes_in(
+                    &PathBuf::from(&program_files).join(vendor),
+                    "bin",
+                ));
+            }
+        }
+    }
+    candidates
+}
+
+/// Probes the Java on `PATH`, the configured one, `JAVA_HOME` and the usual
+/// install locations, skipping anything that doesn't run.
+pub async fn detect(app_handle: &tauri::AppHandle) -> Vec<JavaInstall> {
+    let mut seen = HashSet::new();
+    let mut installs = vec![];
+    for candidate in candidates(app_handle) {
+        // Distros tend to link the same runtime from several places
+        let key = std::fs::canonicalize(&candidate).unwrap_or_else(|_| candidate.clone());
+        if !seen.insert(key) {
+            continue;
+        }
+        match probe(&candidate).await {
+            Ok(install) => installs.push(install),
+            Err(e) => warn!("Skipping Java {:?}: {:#}", candidate, e),
+        }
+    }
+    installs
+}
+
+/// `majors` for messages, e.g. `17` or `8 or 11`.
+fn describe_majors(majors: &[u32]) -> String {
+    majors
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+/// The Java to launch a version with that runs on one of
+/// `compatible_majors`, any if empty, and matches the launcher's
+/// architecture.
+///
+/// A `forced` Java, the one configured for the instance, has to be
+/// compatible unless `allow_incompatible` is set. If none is forced, the
+/// `preferred` one from the settings is used if it's compatible, and
+/// otherwise the first compatible one found, preferring the majors in the
+/// order they are listed.
+pub async fn select(
+    app_handle: &tauri::AppHandle,
+    forced: Option<&Path>,
+    preferred: Option<&Path>,
+    compatible_majors: &[u32],
+    allow_incompatible: bool,
+) -> anyhow::Result<JavaInstall> {
+    let compatible = |install: &JavaInstall| {
+        (compatible_majors.is_empty() || compatible_majors.contains(&install.major))
+            && install.matches_arch()
+    };
+    if let Some(forced) = forced {
+        let install = probe(forced).await?;
+        if !install.matches_arch() && !allow_incompatible {
+            return Err(coded_message(
+                ErrorCode::JavaIncompatible,
+                Message::new("java.wrongArch")
+                    .with("arch", install.arch.clone())
+                    .with("required", std::env::consts::ARCH),
+            ));
+        }
+        if !compatible(&install) {
+            if !allow_incompatible {
+                return Err(coded_message(
+                    ErrorCode::JavaIncompatible,
+                    Message::new("java.incompatible")
+                        .with("major", install.major)
+                        .with("required", describe_majors(compatible_majors)),
+                ));
+            }
+            warn!(
+                "Launching with Java {} ({:?}) at {:?}, which isn't one of {:?}",
+                install.major, install.arch, install.path, compatible_majors
+            );
+        }
+        return Ok(install);
+    }
+    if let Some(preferred) = preferred {
+        match probe(preferred).await {
+            Ok(install) if compatible(&install) => return Ok(install),
+            Ok(install) => info!(
+                "Not using Java {} ({:?}) from the settings for {:?}",
+                install.major, install.arch, compatible_majors
+            ),
+            Err(e) => warn!("Skipping Java {:?} from the settings: {:#}", preferred, e),
+        }
+    }
+    let installs = detect(app_handle).await;
+    let preferred = compatible_majors.iter().find_map(|major| {
+        installs
+            .iter()
+            .find(|install| install.major == *major && install.matches_arch())
+    });
+    preferred
+        .or_else(|| installs.iter().find(|install| compatible(install)))
+        .cloned()
+        .ok_or_else(|| {
+            coded_message(
+                ErrorCode::JavaNotFound,
+                Message::new("java.notFound").with("required", describe_majors(compatible_majors)),
+            )
+        })
+}
+
+/// [`select`] for an instance, forcing the Java set for it and preferring
+/// the one from the settings.
+pub async fn select_for_instance(
+    app_handle: &tauri::AppHandle,
+    config: &InstanceConfig,
+    compatible_majors: &[u32],
+) -> anyhow::Result<JavaInstall> {
+    let forced = config.java_path.as_ref().map(PathBuf::from);
+    let preferred = settings::current(app_handle).java_path.map(PathBuf::from);
+    select(
+        app_handle,
+        forced.as_deref(),
+        preferred.as_deref(),
+        compatible_majors,
+        config.ignore_java_compatibility,
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn detect_javas(app_handle: tauri::AppHandle) -> Vec<JavaInstall> {
+    detect(&app_handle).await
+}
@@ -0,0 +1,125 @@
+//! Per-instance Rhai scripts run around a launch:
+//! `<instance>/hooks/pre-launch.rhai` before the game starts and
+//! `<instance>/hooks/post-exit.rhai` after it exits.
+//!
+//! Scripts see an `instance` map (`id`, `name`, `kind`, `minecraftVersion`,
+//! `dir`, `gameDir`), plus `exit_code` after exiting. Throwing from the
+//! pre-launch script cancels the launch. Besides `log`, scripts can call
+//! `env(name)`, `path_exists(path)`, `link_dir(target, link)`,
+//! `unlink(path)` and `run(program, args)`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use serde_json::json;
+
+use crate::instance;
+
+type ScriptResult<T> = Result<T, Box<rhai::EvalAltResult>>;
+
+fn link_dir(target: &str, link: &str) -> ScriptResult<()> {
+    let link = Path::new(link);
+    // Relinking is fine, replacing real files is not
+    if let Ok(metadata) = std::fs::symlink_metadata(link) {
+        if !metadata.file_type().is_symlink() {
+            return Err(format!("{:?} exists and is not a link", link).into());
+        }
+        remove_link(link)?;
+    }
+    #[cfg(unix)]
+    let result = std::os::unix::fs::symlink(target, link);
+    #[cfg(windows)]
+    let result = std::os::windows::fs::symlink_dir(target, link);
+    result.map_err(|e| format!("Can't link {:?} to {}: {}", link, target, e).into())
+}
+
+fn remove_link(link: &Path) -> ScriptResult<()> {
+    match std::fs::symlink_metadata(link) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            // Directory links are directories on Windows
+            std::fs::remove_file(link)
+                .or_else(|_| std::fs::remove_dir(link))
+                .map_err(|e| format!("Can't remove {:?}: {}", link, e).into())
+        }
+        Ok(_) => Err(format!("{:?} is not a link", link).into()),
+        Err(_) => Ok(()),
+    }
+}
+
+fn run(program: &str, args: rhai::Array) -> ScriptResult<i64> {
+    let args: Vec<String> = args.into_iter().map(|arg| arg.to_string()).collect();
+    let status = std::process::Command::new(program)
+        .args(&args)
+        .status()
+        .map_err(|e| format!("Can't run {}: {}", program, e))?;
+    Ok(status.code().unwrap_or(-1) as i64)
+}
+
+fn create_engine() -> rhai::Engine {
+    let mut engine = rhai::Engine::new();
+    engine.register_fn("log", |message: &str| info!("[hook] {}", message));
+    engine.register_fn("env", |name: &str| std::env::var(name).unwrap_or_default());
+    engine.register_fn("path_exists", |path: &str| Path::new(path).exists());
+    engine.register_fn("link_dir", link_dir);
+    engine.register_fn("unlink", |path: &str| remove_link(Path::new(path)));
+    engine.register_fn("run", run);
+    engine
+}
+
+lazy_static::lazy_static! {
+    static ref ENGINE: rhai::Engine = create_engine();
+}
+
+async fn run_script(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    name: &str,
+    exit_code: Option<i32>,
+) -> anyhow::Result<()> {
+    let dir = instance::instance_dir(app_handle, instance_id)?;
+    let script: PathBuf = dir.join("hooks").join(name);
+    if !tokio::fs::try_exists(&script).await? {
+        return Ok(());
+    }
+    let config = instance::read_config(app_handle, instance_id).await?;
+    let metadata = json!({
+        "id": instance_id,
+        "name": config.name,
+        "kind": config.kind,
+        "minecraftVersion": config.minecraft_version,
+        "dir": dir,
+        "gameDir": instance::game_dir(app_handle, instance_id)?,
+    });
+    info!("Running {} for {}", name, instance_id);
+    tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        let mut scope = rhai::Scope::new();
+        scope.push_dynamic(
+            "instance",
+            rhai::serde::to_dynamic(metadata).map_err(|e| anyhow!("{}", e))?,
+        );
+        if let Some(code) = exit_code {
+            scope.push("exit_code", code as i64);
+        }
+        let ast = ENGINE.compile_file(script).map_err(|e| anyhow!("{}", e))?;
+        ENGINE
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| anyhow!("{}", e))
+    })
+    .await?
+}
+
+/// Runs the pre-launch script, failing if the script throws.
+pub async fn pre_launch(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<()> {
+    run_script(app_handle, instance_id, "pre-launch.rhai", None)
+        .await
+        .map_err(|e| anyhow!("Pre-launch hook failed: {:#}", e))
+}
+
+/// Runs the post-exit script. Failures are only logged since the game has
+/// already exited.
+pub async fn post_exit(app_handle: &tauri::AppHandle, instance_id: &str, exit_code: Option<i32>) {
+    if let Err(e) = run_script(app_handle, instance_id, "post-exit.rhai", exit_code).await {
+        warn!("Post-exit hook of {} failed: {:#}", instance_id, e);
+    }
+}
@@ -0,0 +1,183 @@
+//! The `libraries` directory shared by all instances, laid out like a Maven
+//! repository so each version of a library is stored once.
+//!
+//! Which instance uses which files is kept in `references.json` in there,
+//! so files no instance uses anymore can be deleted.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    command_result,
+    errors::{coded, CommandError, ErrorCode},
+    instance::{self, InstanceKind},
+};
+
+lazy_static::lazy_static! {
+    static ref LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+/// Paths relative to the libraries dir, by instance
+type References = HashMap<String, HashSet<String>>;
+
+pub fn libraries_dir(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    Ok(instance::launcher_dir(app_handle)?.join("libraries"))
+}
+
+const REFERENCES_FILE: &str = "references.json";
+
+fn references_path(libraries_dir: &Path) -> PathBuf {
+    libraries_dir.join(REFERENCES_FILE)
+}
+
+async fn read_references(libraries_dir: &Path) -> anyhow::Result<References> {
+    match tokio::fs::read(references_path(libraries_dir)).await {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(References::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn write_references(libraries_dir: &Path, references: &References) -> anyhow::Result<()> {
+    tokio::fs::create_dir_all(libraries_dir).await?;
+    let path = references_path(libraries_dir);
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec(references)?).await?;
+    tokio::fs::rename(&tmp, &path).await?;
+    Ok(())
+}
+
+/// Records that instance `instance_id` uses the library files at `paths`,
+/// replacing what it used before. Paths outside the libraries dir are
+/// ignored.
+pub async fn record_references(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    paths: &[PathBuf],
+) -> anyhow::Result<()> {
+    let libraries_dir = libraries_dir(app_handle)?;
+    let relative = paths
+        .iter()
+        .filter_map(|path| path.strip_prefix(&libraries_dir).ok())
+        .map(|path| path.to_string_lossy().replace('\\', "/"))
+        .collect();
+    let _lock = LOCK.lock().await;
+    let mut references = read_references(&libraries_dir).await?;
+    references.insert(instance_id.to_string(), relative);
+    write_references(&libraries_dir, &references).await
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibrariesCleanup {
+    pub files_removed: usize,
+    pub bytes_freed: u64,
+}
+
+/// Every file below `dir`, relative to `base`.
+async fn list_files(base: &Path, dir: &Path, files: &mut Vec<String>) -> anyhow::Result<()> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if entry.file_type().await?.is_dir() {
+            Box::pin(list_files(base, &path, files)).await?;
+        } else if let Ok(relative) = path.strip_prefix(base) {
+            files.push(relative.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(())
+}
+
+/// Deletes library files no existing instance uses, and forgets about
+/// instances that were deleted.
+///
+/// Refuses to while a client instance hasn't recorded what it uses, since
+/// its libraries would go too. Downloads in progress are left alone.
+pub async fn collect_garbage(app_handle: &tauri::AppHandle) -> anyhow::Result<LibrariesCleanup> {
+    let libraries_dir = libraries_dir(app_handle)?;
+    if !libraries_dir.exists() {
+        return Ok(LibrariesCleanup::default());
+    }
+    // Not from the configs, an instance with a broken one still uses its
+    // libraries
+    let mut instances = HashSet::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(instance::instances_dir(app_handle)?).await {
+        while let Some(entry) = entries.next_entry().await? {
+            instances.insert(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    let _lock = LOCK.lock().await;
+    let mut references = read_references(&libraries_dir).await?;
+    references.retain(|instance_id, _| instances.contains(instance_id));
+    let mut unrecorded = vec![];
+    for instance_id in instances
+        .iter()
+        .filter(|instance_id| !references.contains_key(*instance_id))
+    {
+        // Servers don't use the libraries
+        match instance::read_config(app_handle, instance_id).await {
+            Ok(config) if config.kind == InstanceKind::Server => {}
+            _ => unrecorded.push(instance_id.as_str()),
+        }
+    }
+    if !unrecorded.is_empty() {
+        unrecorded.sort();
+        return Err(coded(
+            ErrorCode::Unsupported,
+            format!(
+                "Launch {} once first, which libraries they use isn't known yet",
+                unrecorded.join(", ")
+            ),
+        ));
+    }
+    if references.is_empty() {
+        return Err(coded(
+            ErrorCode::Unsupported,
+            "No instance has recorded which libraries it uses yet",
+        ));
+    }
+    write_references(&libraries_dir, &references).await?;
+    let used: HashSet<&String> = references.values().flatten().collect();
+
+    let mut files = vec![];
+    list_files(&libraries_dir, &libraries_dir, &mut files).await?;
+    let mut cleanup = LibrariesCleanup::default();
+    for file in files {
+        let in_use = used.contains(&file)
+            || file.starts_with(REFERENCES_FILE)
+            // Being downloaded right now, or resumed by the next download
+            || file.ends_with(".part");
+        if in_use {
+            continue;
+        }
+        let path = libraries_dir.join(&file);
+        let size = tokio::fs::metadata(&path)
+            .await
+            .map_or(0, |meta| meta.len());
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {
+                cleanup.files_removed += 1;
+                cleanup.bytes_freed += size;
+            }
+            Err(e) => warn!("Failed to remove unused library {}: {}", file, e),
+        }
+    }
+    info!(
+        "Removed {} unused library files, {} bytes",
+        cleanup.files_removed, cleanup.bytes_freed
+    );
+    Ok(cleanup)
+}
+
+/// Deletes the library files no instance uses anymore.
+#[tauri::command]
+pub async fn clean_libraries(
+    app_handle: tauri::AppHandle,
+) -> Result<LibrariesCleanup, CommandError> {
+    command_result(collect_garbage(&app_handle).await)
+}
@@ -0,0 +1,176 @@
+//! Log filtering driven by the settings, and log bundles for bug reports.
+//!
+//! The log plugin lets everything through to [`enabled`], so changing the
+//! levels in the settings takes effect without a restart.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use log::{info, LevelFilter, Metadata};
+
+use crate::{
+    command_result,
+    errors::CommandError,
+    instance,
+    mclogs::{self, KnownAccounts},
+    settings::Settings,
+    usage_stats,
+};
+
+/// Log files are rotated once they reach this size
+pub const MAX_LOG_FILE_SIZE: u128 = 5 * 1024 * 1024;
+/// How far back logs are included in a bundle
+const BUNDLE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+struct LogLevels {
+    default: LevelFilter,
+    /// Keyed by module path, e.g. `untitled_minecraft_launcher::rpc`
+    modules: HashMap<String, LevelFilter>,
+}
+
+lazy_static::lazy_static! {
+    static ref LEVELS: RwLock<LogLevels> = RwLock::new(LogLevels {
+        default: LevelFilter::Info,
+        modules: HashMap::new(),
+    });
+}
+
+/// Picks up the log levels from `settings`.
+pub fn apply(settings: &Settings) {
+    *LEVELS.write().unwrap() = LogLevels {
+        default: settings.log_level,
+        modules: settings.module_log_levels.clone(),
+    };
+}
+
+/// Whether a record should be logged. The most specific module level that
+/// matches the record's target wins.
+pub fn enabled(metadata: &Metadata) -> bool {
+    let levels = LEVELS.read().unwrap();
+    let target = metadata.target();
+    let level = levels
+        .modules
+        .iter()
+        .filter(|(module, _)| {
+            target == module.as_str()
+                || target
+                    .strip_prefix(module.as_str())
+                    .is_some_and(|rest| rest.starts_with("::"))
+        })
+        .max_by_key(|(module, _)| module.len())
+        .map_or(levels.default, |(_, level)| *level);
+    metadata.level() <= level
+}
+
+fn is_recent(path: &Path) -> bool {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age <= BUNDLE_MAX_AGE)
+}
+
+/// Recently modified files directly inside `dir` with the given extension.
+fn recent_files(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path.extension().and_then(|e| e.to_str()) == Some(extension)
+                && is_recent(path)
+        })
+        .collect()
+}
+
+/// Adds the log at `path` with tokens and names scrubbed, as the bundle is
+/// meant to be shared.
+fn add_file(
+    zip: &mut zip::ZipWriter<std::fs::File>,
+    path: &Path,
+    name: &str,
+    known: &KnownAccounts,
+) -> anyhow::Result<()> {
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let content = mclogs::redact(&String::from_utf8_lossy(&std::fs::read(path)?), known);
+    zip.start_file(name, options)?;
+    zip.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+fn write_bundle(
+    bundle: &Path,
+    launcher_logs: Vec<PathBuf>,
+    game_logs: Vec<(String, PathBuf)>,
+    known: &KnownAccounts,
+) -> anyhow::Result<()> {
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(bundle)?);
+    for path in launcher_logs {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        add_file(&mut zip, &path, &format!("launcher/{}", name), known)?;
+    }
+    for (instance_id, path) in game_logs {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        add_file(
+            &mut zip,
+            &path,
+            &format!("instances/{}/{}", instance_id, name),
+            known,
+        )?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+async fn export_logs_bundle_inner(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    let log_dir = app_handle
+        .path_resolver()
+        .app_log_dir()
+        .ok_or(anyhow!("Can't get app log dir"))?;
+    let launcher_logs = recent_files(&log_dir, "log");
+    let mut game_logs = vec![];
+    for entry in instance::list_instances(app_handle).await? {
+        let game_dir = instance::game_dir(app_handle, &entry.id)?;
+        let latest = game_dir.join("logs").join("latest.log");
+        if is_recent(&latest) {
+            game_logs.push((entry.id.clone(), latest));
+        }
+        for crash_report in recent_files(&game_dir.join("crash-reports"), "txt") {
+            game_logs.push((entry.id.clone(), crash_report));
+        }
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    let bundle = tauri::api::path::download_dir()
+        .unwrap_or(log_dir)
+        .join(format!(
+            "untitled-minecraft-launcher-logs-{}.zip",
+            timestamp
+        ));
+    let result_path = bundle.clone();
+    let known = KnownAccounts::load(app_handle);
+    tokio::task::spawn_blocking(move || write_bundle(&bundle, launcher_logs, game_logs, &known))
+        .await??;
+    info!("Exported logs to {:?}", result_path);
+    Ok(result_path)
+}
+
+/// Zips the last week of launcher logs along with every instance's latest
+/// game log and crash reports into the downloads folder, returning the path
+/// of the zip. Everything in it is redacted like logs shared on mclo.gs.
+#[tauri::command]
+pub async fn export_logs_bundle(app_handle: tauri::AppHandle) -> Result<PathBuf, CommandError> {
+    usage_stats::record_feature(&app_handle, "logBundleExport");
+    command_result(export_logs_bundle_inner(&app_handle).await)
+}
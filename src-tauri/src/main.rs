@@ -3,18 +3,9 @@
     windows_subsystem = "windows"
 )]
 
-use std::{collections::HashMap, time::Duration};
-
-use anyhow::anyhow;
-use log::{error, trace};
-use serde::Deserialize;
-use serde_json::json;
-use tauri::{
-    api::http::{Body, ClientBuilder, FormBody, FormPart, HttpRequestBuilder, ResponseType},
-    Manager,
-};
-use tauri_plugin_log::LogTarget;
-use tokio::time::sleep;
+use log::error;
+use tauri::Manager;
+use tauri_plugin_log::{LogTarget, RotationStrategy};
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
@@ -22,303 +13,254 @@ fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+pub mod accounts;
+pub mod assets;
+pub mod auth;
+pub mod cli;
+pub mod client_instance;
+pub mod components;
+pub mod deep_link;
+pub mod diagnostics;
+pub mod discord;
+pub mod downloads;
+pub mod errors;
+pub mod game_stats;
+pub mod hash_cache;
+pub mod history;
+pub mod install_progress;
+pub mod instance;
+pub mod jar_mods;
+pub mod java;
+pub mod launch_hooks;
+pub mod libraries;
+pub mod logging;
+pub mod mclogs;
+pub mod messages;
+pub mod migration;
+pub mod mirrors;
+pub mod nbt;
+pub mod network;
+pub mod news;
+pub mod options;
+pub mod piston_meta;
+pub mod playtime;
+pub mod plugins;
 pub mod prism_meta;
+pub mod process;
+pub mod proxy;
+pub mod rcon;
+pub mod rpc;
+pub mod screenshots;
+pub mod self_update;
+pub mod server_instance;
+pub mod server_pack;
+pub mod server_ping;
+pub mod servers;
+pub mod settings;
+pub mod single_instance;
+pub mod skins;
 pub mod storage;
+pub mod sync;
+pub mod tasks;
+pub mod tray;
+pub mod usage_stats;
+pub mod worlds;
 
-const FLOW_URL: &str = "https://login.microsoftonline.com/consumers/oauth2/v2.0/devicecode";
-const TOKEN_URL: &str = "https://login.microsoftonline.com/consumers/oauth2/v2.0/token";
-const CLIENT_ID: &str = "7872a85a-1d8c-415c-a4f4-1a243f40c354";
-const SCOPES: &str = "XboxLive.signin offline_access";
-const XBL_AUTH_URL: &str = "https://user.auth.xboxlive.com/user/authenticate";
-const XSTS_AUTH_URL: &str = "https://xsts.auth.xboxlive.com/xsts/authorize";
-const LAUNCHER_AUTH_URL: &str = "https://api.minecraftservices.com/launcher/login";
-const ENTITLEMENT_URL: &str = "https://api.minecraftservices.com/entitlements/license?requestId=";
-
-#[tauri::command]
-async fn login_msa(app_handle: tauri::AppHandle) -> Option<String> {
-    if let Err(e) = login_msa_inner(app_handle).await {
+/// Logs a failed command and turns the error into something the frontend
+/// can display.
+pub(crate) fn command_result<T>(result: anyhow::Result<T>) -> Result<T, errors::CommandError> {
+    result.map_err(|e| {
         error!("{:#?}", e);
-        Some(format!("{:?}", e))
-    } else {
-        None
-    }
+        e.into()
+    })
 }
 
-async fn login_msa_inner(app_handle: tauri::AppHandle) -> anyhow::Result<()> {
-    let client = ClientBuilder::new().build()?;
-    let flow_resp = client
-        .send(
-            HttpRequestBuilder::new("POST", FLOW_URL)?
-                .body(Body::Form(FormBody::new(HashMap::from([
-                    (
-                        "client_id".to_string(),
-                        FormPart::Text(CLIENT_ID.to_string()),
-                    ),
-                    ("scope".to_string(), FormPart::Text(SCOPES.to_string())),
-                ]))))
-                .response_type(ResponseType::Json),
-        )
-        .await?
-        .read()
-        .await?;
-    if flow_resp.status != 200 {
-        return Err(anyhow!(
-            "Server returned error response: {}",
-            flow_resp.data.to_string()
-        ));
-    }
-    let flow_resp: DeviceCodeResponse = serde_json::from_value(flow_resp.data)?;
-    app_handle.emit_all("auth:msa:login_message", &flow_resp.message)?;
-    trace!("Got response {:?}", &flow_resp);
-    sleep(Duration::from_secs(flow_resp.interval.into())).await;
-    let token = loop {
-        let token_resp = client
-            .send(
-                HttpRequestBuilder::new("POST", TOKEN_URL)?
-                    .body(Body::Form(FormBody::new(HashMap::from([
-                        (
-                            "client_id".to_string(),
-                            FormPart::Text(CLIENT_ID.to_string()),
-                        ),
-                        (
-                            "grant_type".to_string(),
-                            FormPart::Text(
-                                "urn:ietf:params:oauth:grant-type:device_code".to_string(),
-                            ),
-                        ),
-                        (
-                            "device_code".to_string(),
-                            FormPart::Text(flow_resp.device_code.clone()),
-                        ),
-                    ]))))
-                    .response_type(ResponseType::Json),
-            )
-            .await?
-            .read()
-            .await?;
-        let token_resp: TokenResponse = serde_json::from_value(token_resp.data)?;
-        println!("Got token response {:?}", token_resp);
-        match token_resp {
-            TokenResponse::Ok {
-                access_token,
-                refresh_token,
-            } => {
-                break Token {
-                    access: access_token,
-                    refresh: refresh_token,
-                };
-            }
-            TokenResponse::Err { error } => match error {
-                TokenResponseErrorKind::AuthorizationPending => {
-                    sleep(Duration::from_secs(flow_resp.interval.into())).await;
-                }
-                TokenResponseErrorKind::AuthorizationDeclined => {
-                    return Err(anyhow!("Authentication Declined."))
-                }
-                TokenResponseErrorKind::BadVerificationCode => {
-                    return Err(anyhow!("Server claims bad verification code?"))
-                }
-                TokenResponseErrorKind::ExpiredToken => {
-                    return Err(anyhow!("Authentication time excedded"))
-                }
-            },
-        }
-    };
-    trace!("Got MSA Token: {:?}", token);
-    app_handle.emit_all("auth:msa:msa_token", ())?;
-
-    let xbl_resp = client
-        .send(
-            HttpRequestBuilder::new("POST", XBL_AUTH_URL)?
-                .body(Body::Json(json!({
-                    "Properties": {
-                        "AuthMethod": "RPS",
-                        "SiteName": "user.auth.xboxlive.com",
-                        "RpsTicket": format!("d={}", token.access)
-                    },
-                    "RelyingParty": "http://auth.xboxlive.com",
-                    "TokenType": "JWT"
-                })))
-                .response_type(ResponseType::Json),
-        )
-        .await?
-        .read()
-        .await?;
-    let xbl_resp: XblAuthResponse = serde_json::from_value(xbl_resp.data)?;
-    trace!("got XBL response: {:?}", xbl_resp);
-    let (token, userhash) = match xbl_resp {
-        XblAuthResponse::Ok {
-            issue_instant,
-            not_after,
-            token,
-            display_claims,
-        } => (token, display_claims.xui[0].uhs.clone()),
-        XblAuthResponse::Err { x_err } => {
-            return Err(anyhow!(
-                "Error {}: {}",
-                x_err,
-                match x_err {
-                    2148916233 => "This Microsoft account does not have an XBox Live profile.",
-                    2148916235 => "XBox Live is not available in your country.",
-                    2148916236 =>
-                        "The account needs adult verification on Xbox page. (South Korea)",
-                    2148916237 =>
-                        "The account needs adult verification on Xbox page. (South Korea)",
-                    2148916238 =>
-                        "This Microsoft account is underaged and is not linked to a family.",
-                    _ => "Unknown error.",
-                }
-            ))
-        }
-    };
-    app_handle.emit_all("auth:msa:xbl_token", ())?;
-
-    let xsts_resp = client
-        .send(
-            HttpRequestBuilder::new("POST", XSTS_AUTH_URL)?
-                .body(Body::Json(json!({
-                    "Properties": {
-                        "SandboxId": "RETAIL",
-                        "UserTokens": [token]
-                    },
-                    "RelyingParty": "rp://api.minecraftservices.com/",
-                    "TokenType": "JWT"
-                })))
-                .response_type(ResponseType::Json),
-        )
-        .await?
-        .read()
-        .await?;
-    let xsts_resp: XblAuthResponse = serde_json::from_value(xsts_resp.data)?;
-    trace!("got XSTS response: {:?}", xsts_resp);
-    app_handle.emit_all("auth:msa:xsts_token", ())?;
-
-    let xsts_token = match xsts_resp {
-        XblAuthResponse::Ok {
-            issue_instant,
-            not_after,
-            token,
-            display_claims,
-        } => token,
-        XblAuthResponse::Err { x_err } => {
-            return Err(anyhow!("Error {} while getting XSTS token", x_err))
+fn main() {
+    let cli_command = match cli::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
         }
     };
-
-    let launcher_resp = client
-        .send(
-            HttpRequestBuilder::new("POST", LAUNCHER_AUTH_URL)?
-                .body(Body::Json(json!({
-                    "xtoken": format!("XBL3.0 x={};{}", userhash, xsts_token),
-                    "platform": "PC_LAUNCHER"
-                })))
-                .response_type(ResponseType::Json),
-        )
-        .await?
-        .read()
-        .await?;
-    app_handle.emit_all("auth:msa:mc_token", ())?;
-
-    let launcher_token: LauncherToken = serde_json::from_value(launcher_resp.data)?;
-
-    trace!("got launcher response: {:?}", launcher_token.access_token);
-
-    let entitlement_resp = client
-        .send(
-            HttpRequestBuilder::new(
-                "GET",
-                format!("{}{}", ENTITLEMENT_URL, uuid::Uuid::new_v4()),
-            )?
-            .header(
-                "Authorization",
-                format!("Bearer {}", launcher_token.access_token),
-            )?
-            .response_type(ResponseType::Json),
-        )
-        .await?
-        .read()
-        .await?;
-    trace!("got entitlement data: {}", entitlement_resp.data);
-    Ok(())
-}
-
-const ASSETS_URL_BASE: &str = "https://resources.download.minecraft.net/";
-
-#[derive(Debug, Deserialize)]
-struct DeviceCodeResponse {
-    device_code: String,
-    user_code: String,
-    verification_uri: String,
-    expires_in: u32,
-    interval: u32,
-    message: String,
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum TokenResponse {
-    Ok {
-        access_token: String,
-        refresh_token: String,
-    },
-    Err {
-        error: TokenResponseErrorKind,
-    },
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "snake_case")]
-enum TokenResponseErrorKind {
-    AuthorizationPending,
-    AuthorizationDeclined,
-    BadVerificationCode,
-    ExpiredToken,
-}
-
-#[derive(Debug)]
-struct Token {
-    access: String,
-    refresh: String,
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-#[serde(rename_all = "PascalCase")]
-enum XblAuthResponse {
-    #[serde(rename_all = "PascalCase")]
-    Ok {
-        issue_instant: String,
-        not_after: String,
-        token: String,
-        display_claims: XblDisplayClaims,
-    },
-    #[serde(rename_all = "PascalCase")]
-    Err { x_err: u32 },
-}
-
-#[derive(Debug, Deserialize)]
-struct XblDisplayClaims {
-    xui: Vec<XblXui>,
-}
-
-#[derive(Debug, Deserialize)]
-struct XblXui {
-    uhs: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct LauncherToken {
-    access_token: String,
-}
-
-fn main() {
-    tauri::Builder::default()
+    let mut builder = tauri::Builder::default();
+    // Launches go to a running launcher so it tracks the game, other CLI
+    // runs shouldn't hand it their arguments. Neither adds a tray icon.
+    if matches!(cli_command, None | Some(cli::CliCommand::Launch(_))) {
+        builder = builder.plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
+            single_instance::on_second_instance(app, argv, cwd)
+        }));
+    }
+    if cli_command.is_none() {
+        tauri_plugin_deep_link::prepare("vg.skye.uml");
+        builder = builder
+            .system_tray(tray::build())
+            .on_system_tray_event(tray::handle_event);
+    }
+    builder
         .plugin(
             tauri_plugin_log::Builder::default()
                 .targets([LogTarget::LogDir, LogTarget::Stdout, LogTarget::Webview])
+                // Levels are filtered by the settings instead, so they can
+                // change at runtime
+                .level(log::LevelFilter::Trace)
+                .filter(logging::enabled)
+                .max_file_size(logging::MAX_LOG_FILE_SIZE)
+                .rotation_strategy(RotationStrategy::KeepAll)
                 .build(),
         )
-        .invoke_handler(tauri::generate_handler![greet, login_msa])
+        .setup(|app| {
+            let settings = settings::load(&app.handle())?;
+            app.manage(settings);
+            let handle = app.handle();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = settings::migrate_secrets(&handle).await {
+                    error!("Failed to move the sync secret: {:#}", e);
+                }
+            });
+            if let Err(e) = plugins::load(&app.handle()) {
+                error!("Failed to load plugins: {:#}", e);
+            }
+            if let Err(e) = hash_cache::load(&app.handle()) {
+                error!("Failed to load the hash cache: {:#}", e);
+            }
+            if let Some(command) = cli_command {
+                cli::run(app.handle(), command);
+            } else {
+                tray::refresh(&app.handle());
+                rpc::start(&app.handle());
+                network::start(&app.handle());
+                auth::start_refresh_scheduler(&app.handle());
+                tauri::WindowBuilder::new(app, "main", tauri::WindowUrl::default())
+                    .title("untitled-minecraft-launcher")
+                    .inner_size(800.0, 600.0)
+                    .resizable(true)
+                    .build()?;
+                let handle = app.handle();
+                tauri_plugin_deep_link::register(deep_link::SCHEME, move |link| {
+                    deep_link::handle(&handle, link)
+                })?;
+                let args: Vec<String> = std::env::args().skip(1).collect();
+                deep_link::handle_args(&app.handle(), &args);
+            }
+            Ok(())
+        })
+        .manage(process::ProcessManager::default())
+        .manage(rcon::RconSessions::default())
+        .manage(discord::DiscordPresence::default())
+        .manage(tasks::TaskManager::default())
+        .manage(downloads::DownloadManager::default())
+        .manage(network::NetworkState::default())
+        .manage(auth::LoginState::default())
+        .manage(accounts::AccountsState::default())
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            auth::login_msa,
+            worlds::delete_world,
+            servers::list_servers,
+            servers::add_server,
+            servers::edit_server,
+            servers::move_server,
+            servers::remove_server,
+            servers::sync_servers,
+            server_ping::ping_server,
+            server_ping::ping_instance_servers,
+            screenshots::list_screenshots,
+            screenshots::open_screenshot,
+            screenshots::delete_screenshot,
+            screenshots::copy_screenshot,
+            screenshots::upload_screenshot,
+            game_stats::get_instance_stats,
+            options::get_options,
+            options::set_options,
+            options::sync_options,
+            rcon::list_rcon_profiles,
+            rcon::save_rcon_profile,
+            rcon::remove_rcon_profile,
+            rcon::rcon_connect,
+            rcon::rcon_command,
+            rcon::rcon_disconnect,
+            client_instance::launch_client,
+            server_instance::create_server_instance,
+            server_instance::get_eula_accepted,
+            server_instance::accept_eula,
+            server_instance::start_server,
+            server_instance::stop_server,
+            server_instance::kill_server,
+            server_instance::send_server_command,
+            server_pack::install_server_pack,
+            settings::get_settings,
+            settings::set_settings,
+            self_update::check_for_update,
+            self_update::install_update,
+            discord::discord_rich_presence_available,
+            news::get_news,
+            news::get_patch_notes,
+            news::get_launcher_changelog,
+            instance::get_instances,
+            instance::set_instance_pinned,
+            plugins::list_plugins,
+            plugins::reload_plugins,
+            plugins::run_plugin_command,
+            logging::export_logs_bundle,
+            mclogs::upload_log,
+            java::detect_javas,
+            diagnostics::generate_diagnostics,
+            tasks::get_tasks,
+            tasks::cancel_task,
+            tasks::clear_finished_tasks,
+            downloads::get_download_progress,
+            downloads::pause_downloads,
+            downloads::resume_downloads,
+            downloads::cancel_downloads,
+            usage_stats::get_usage_stats,
+            usage_stats::export_usage_stats,
+            usage_stats::delete_usage_stats,
+            playtime::get_playtime_summary,
+            sync::sync_push,
+            sync::sync_pull,
+            history::snapshot_instance,
+            history::get_instance_history,
+            history::rollback_instance,
+            history::set_instance_track_history,
+            settings::export_settings,
+            settings::import_settings,
+            settings::get_pending_accounts,
+            settings::dismiss_pending_account,
+            migration::find_launchers_to_migrate,
+            migration::migrate_from_launcher,
+            migration::find_accounts_to_import,
+            migration::import_accounts,
+            messages::get_message_catalog,
+            instance::set_instance_locked,
+            settings::set_instance_lock_pin,
+            network::get_network_status,
+            network::check_network,
+            auth::refresh_account,
+            auth::cancel_login,
+            auth::validate_session,
+            accounts::get_accounts,
+            accounts::set_active_account,
+            accounts::get_profile,
+            accounts::add_offline_account,
+            accounts::logout,
+            skins::upload_skin,
+            skins::set_skin_variant,
+            skins::reset_skin,
+            skins::get_capes,
+            skins::equip_cape,
+            skins::disable_cape,
+            skins::get_player_head,
+            skins::check_name_available,
+            skins::get_name_change_info,
+            skins::change_name,
+            components::resolve_components,
+            prism_meta::check_meta_url,
+            prism_meta::list_versions,
+            prism_meta::stream_versions,
+            libraries::clean_libraries,
+            assets::install_assets,
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -0,0 +1,207 @@
+//! Sharing logs through mclo.gs, with anything identifying scrubbed first.
+
+use std::{borrow::Cow, path::PathBuf};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use regex::Regex;
+use serde::Deserialize;
+use tauri::Manager;
+
+use crate::{accounts, command_result, errors::CommandError, instance, proxy, usage_stats};
+
+const MCLOGS_URL: &str = "https://api.mclo.gs/1/log";
+
+lazy_static::lazy_static! {
+    /// Minecraft and Xbox tokens are JWTs
+    static ref JWT_REGEX: Regex = Regex::new(r"eyJ[\w-]+\.[\w-]+\.[\w-]*").unwrap();
+    static ref TOKEN_ARG_REGEX: Regex =
+        Regex::new(r"(?i)(--accessToken[ =]|accessToken[=:] ?|Session ID is token:)\S+").unwrap();
+    /// Token fields of structs in launcher logs, e.g. `refresh: "M.C5…"`, as
+    /// Microsoft's tokens aren't JWTs
+    static ref TOKEN_FIELD_REGEX: Regex = Regex::new(
+        r#"(?i)(\b(?:access|refresh|device_code|\w*token)"?\s*[:=]\s*)"[^"]*""#
+    )
+    .unwrap();
+    static ref PLAYER_REGEX: Regex =
+        Regex::new(r"(?:Setting user: |--username[ =])(?P<name>\w+)").unwrap();
+    static ref UUID_ARG_REGEX: Regex = Regex::new(r"--uuid[ =](?P<uuid>[0-9a-fA-F-]{32,36})").unwrap();
+}
+
+/// A log the user picked to share.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum LogSource {
+    /// A file in the launcher's log dir
+    #[serde(rename_all = "camelCase")]
+    Launcher { file_name: String },
+    /// A file in an instance's `logs` or `crash-reports` dir, e.g.
+    /// `logs/latest.log`
+    #[serde(rename_all = "camelCase")]
+    Game { instance_id: String, path: String },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MclogsResponse {
+    Ok { url: String },
+    Err { error: String },
+}
+
+fn log_path(app_handle: &tauri::AppHandle, source: &LogSource) -> anyhow::Result<PathBuf> {
+    match source {
+        LogSource::Launcher { file_name } => {
+            instance::check_name(file_name)?;
+            Ok(app_handle
+                .path_resolver()
+                .app_log_dir()
+                .ok_or(anyhow!("Can't get app log dir"))?
+                .join(file_name))
+        }
+        LogSource::Game { instance_id, path } => {
+            if !(path.starts_with("logs/") || path.starts_with("crash-reports/")) {
+                return Err(anyhow!("{} is not a log", path));
+            }
+            instance::safe_join(&instance::game_dir(app_handle, instance_id)?, path)
+        }
+    }
+}
+
+fn replace_word<'a>(text: Cow<'a, str>, word: &str, replacement: &str) -> Cow<'a, str> {
+    let regex = Regex::new(&format!(r"\b{}\b", regex::escape(word))).unwrap();
+    match regex.replace_all(&text, replacement) {
+        Cow::Borrowed(_) => text,
+        Cow::Owned(replaced) => Cow::Owned(replaced),
+    }
+}
+
+/// The names and ids of the launcher's accounts, which [`redact`] removes
+/// even where a log doesn't mention them as the player.
+#[derive(Debug, Clone, Default)]
+pub struct KnownAccounts {
+    names: Vec<String>,
+    uuids: Vec<uuid::Uuid>,
+    xuids: Vec<String>,
+}
+
+impl KnownAccounts {
+    /// Every stored account's name, UUID, gamertag and XUID.
+    pub fn load(app_handle: &tauri::AppHandle) -> KnownAccounts {
+        let mut known = KnownAccounts::default();
+        let identities = match accounts::identities(app_handle) {
+            Ok(identities) => identities,
+            Err(e) => {
+                warn!("Can't read the accounts to redact: {:#}", e);
+                return known;
+            }
+        };
+        for (profile, xbox_profile) in identities {
+            if let Some(profile) = profile {
+                known.uuids.extend(uuid::Uuid::parse_str(&profile.id).ok());
+                known.names.push(profile.name);
+            }
+            if let Some(xbox_profile) = xbox_profile {
+                known.names.push(xbox_profile.gamertag);
+                known.xuids.push(xbox_profile.xuid);
+            }
+        }
+        known
+    }
+}
+
+/// Removes access tokens, the player's name and UUID, those of `known`
+/// accounts and the system user name from a log.
+pub fn redact(content: &str, known: &KnownAccounts) -> String {
+    let mut players = known.names.clone();
+    let mut uuids = known.uuids.clone();
+    for captures in PLAYER_REGEX.captures_iter(content) {
+        players.push(captures["name"].to_string());
+    }
+    for captures in UUID_ARG_REGEX.captures_iter(content) {
+        if let Ok(uuid) = uuid::Uuid::parse_str(&captures["uuid"]) {
+            uuids.push(uuid);
+        }
+    }
+
+    let mut content = TOKEN_ARG_REGEX.replace_all(content, "${1}<token>");
+    content = Cow::Owned(
+        TOKEN_FIELD_REGEX
+            .replace_all(&content, "${1}\"<token>\"")
+            .into_owned(),
+    );
+    content = Cow::Owned(JWT_REGEX.replace_all(&content, "<token>").into_owned());
+    for uuid in uuids {
+        for form in [uuid.hyphenated().to_string(), uuid.simple().to_string()] {
+            content = Cow::Owned(content.replace(&form, "<uuid>"));
+        }
+    }
+    for player in players.iter().filter(|player| !player.is_empty()) {
+        content = replace_word(content, player, "<player>");
+    }
+    for xuid in known.xuids.iter().filter(|xuid| !xuid.is_empty()) {
+        content = replace_word(content, xuid, "<xuid>");
+    }
+    if let Some(home) = tauri::api::path::home_dir() {
+        content = Cow::Owned(content.replace(&*home.to_string_lossy(), "~"));
+    }
+    // Also catches paths that don't go through the home dir, like temp dirs
+    if let Ok(user) = std::env::var("USER").or_else(|_| std::env::var("USERNAME")) {
+        if !user.is_empty() {
+            content = replace_word(content, &user, "<user>");
+        }
+    }
+    content.into_owned()
+}
+
+async fn upload_log_inner(
+    app_handle: &tauri::AppHandle,
+    source: &LogSource,
+) -> anyhow::Result<Option<String>> {
+    let path = log_path(app_handle, source)?;
+    if path.extension().and_then(|e| e.to_str()) == Some("gz") {
+        return Err(anyhow!("Compressed logs can't be uploaded"));
+    }
+
+    let window = app_handle.get_window("main");
+    let message = format!(
+        "{} will be uploaded publicly to mclo.gs. Access tokens and user names are removed \
+         first. Continue?",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let confirmed = tokio::task::spawn_blocking(move || {
+        tauri::api::dialog::blocking::ask(window.as_ref(), "Upload log", message)
+    })
+    .await?;
+    if !confirmed {
+        return Ok(None);
+    }
+
+    let content = redact(
+        &String::from_utf8_lossy(&tokio::fs::read(&path).await?),
+        &KnownAccounts::load(app_handle),
+    );
+    let resp: MclogsResponse = proxy::client()
+        .post(MCLOGS_URL)
+        .form(&[("content", content)])
+        .send()
+        .await?
+        .json()
+        .await?;
+    let url = match resp {
+        MclogsResponse::Ok { url } => url,
+        MclogsResponse::Err { error } => return Err(anyhow!("mclo.gs: {}", error)),
+    };
+    info!("Uploaded log {:?} to {}", path, url);
+    Ok(Some(url))
+}
+
+/// Uploads a log to mclo.gs after redacting it and asking the user to
+/// confirm. Returns the URL of the log, or `None` if the user declined.
+#[tauri::command]
+pub async fn upload_log(
+    app_handle: tauri::AppHandle,
+    source: LogSource,
+) -> Result<Option<String>, CommandError> {
+    usage_stats::record_feature(&app_handle, "logUpload");
+    command_result(upload_log_inner(&app_handle, &source).await)
+}
@@ -0,0 +1,199 @@
+//! User-facing text sent to the frontend as a message id plus parameters,
+//! so it can be translated there. [`CATALOG`] has the English text for
+//! every id, which the frontend falls back to and which is used for logs.
+//!
+//! Parameters are substituted for `{name}` placeholders.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// English text for every message id
+pub const CATALOG: &[(&str, &str)] = &[
+    // Sign in steps, emitted as the payload of the `auth:msa:*` events
+    (
+        "auth.deviceCode",
+        "Open {verificationUri} and enter the code {userCode}",
+    ),
+    ("auth.step.msaToken", "Signed in with Microsoft"),
+    ("auth.step.xblToken", "Signed in to Xbox Live"),
+    ("auth.step.xstsToken", "Authorized for Minecraft"),
+    ("auth.step.mcToken", "Signed in to Minecraft"),
+    ("auth.declined", "Signing in was declined"),
+    (
+        "auth.expired",
+        "The code expired before signing in finished",
+    ),
+    (
+        "auth.signInAgain",
+        "The account was signed out, please sign in again",
+    ),
+    (
+        "auth.xbox.noProfile",
+        "This Microsoft account doesn't have an Xbox Live profile",
+    ),
+    (
+        "auth.xbox.unavailable",
+        "Xbox Live isn't available in your country",
+    ),
+    (
+        "auth.xbox.adultVerification",
+        "The account needs adult verification on the Xbox website",
+    ),
+    (
+        "auth.xbox.underage",
+        "This Microsoft account is underage and isn't part of a family",
+    ),
+    ("auth.xbox.unknown", "Xbox Live returned error {code}"),
+    (
+        "auth.rateLimited",
+        "Too many requests to Minecraft, try again in {seconds} seconds",
+    ),
+    (
+        "auth.notEntitled",
+        "This account doesn't own Minecraft, only the demo can be played",
+    ),
+    (
+        "disk.notEnoughSpace",
+        "This needs {needed} MB of free disk space, but only {available} MB are free",
+    ),
+    (
+        "instanceLock.locked",
+        "{instance} is locked, unlock it to make changes",
+    ),
+    ("instanceLock.wrongPin", "The PIN is wrong"),
+    (
+        "java.incompatible",
+        "Java {major} can't run this version, it needs Java {required}",
+    ),
+    (
+        "java.wrongArch",
+        "This Java is built for {arch}, but natives are installed for {required}",
+    ),
+    (
+        "java.notFound",
+        "This version needs Java {required}, which isn't installed",
+    ),
+    (
+        "meta.formatTooNew",
+        "The version lists are in a newer format, please update the launcher",
+    ),
+    (
+        "network.queued",
+        "You're offline, this will run once the connection is back",
+    ),
+    // Task titles
+    ("task.metaSync", "Updating version lists"),
+    ("task.installAssets", "Downloading assets for {index}"),
+    ("task.verifyAssets", "Verifying assets for {index}"),
+    ("task.installClient", "Installing {instance}"),
+    ("task.installServer", "Installing server for {instance}"),
+    (
+        "task.installServerPack",
+        "Installing {pack} into {instance}",
+    ),
+    ("task.syncPush", "Uploading {instance}"),
+    ("task.syncPull", "Downloading {instance}"),
+    ("task.migrate", "Migrating from another launcher"),
+    ("task.snapshot", "Saving a snapshot of {instance}"),
+    ("task.rollback", "Rolling back {instance}"),
+    // Fallbacks for errors without a more specific message, by error code
+    ("error.unknown", "Something went wrong"),
+    ("error.cancelled", "Cancelled"),
+    ("error.authDeclined", "Signing in was declined"),
+    (
+        "error.authExpired",
+        "Signing in took too long, please try again",
+    ),
+    ("error.authFailed", "Signing in failed"),
+    ("error.network", "Couldn't connect to the server"),
+    ("error.downloadFailed", "A download failed"),
+    (
+        "error.downloadHashMismatch",
+        "A downloaded file is corrupted",
+    ),
+    (
+        "error.javaNotFound",
+        "No suitable Java installation was found",
+    ),
+    (
+        "error.javaIncompatible",
+        "The selected Java version can't run this instance",
+    ),
+    ("error.diskFull", "There is not enough disk space"),
+    (
+        "error.permissionDenied",
+        "The launcher isn't allowed to access a file",
+    ),
+    ("error.notFound", "Something that was needed doesn't exist"),
+    ("error.invalidData", "A file or response is damaged"),
+    ("error.instanceRunning", "Stop the instance first"),
+    ("error.instanceNotRunning", "The instance isn't running"),
+    ("error.instanceLocked", "The instance is locked"),
+    (
+        "error.eulaNotAccepted",
+        "The Minecraft EULA has to be accepted first",
+    ),
+    ("error.unsupported", "This isn't supported"),
+    (
+        "error.componentConflict",
+        "The selected versions don't work together",
+    ),
+    ("error.updateRequired", "Please update the launcher"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Message {
+    pub id: &'static str,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub params: Map<String, Value>,
+}
+
+impl Message {
+    pub fn new(id: &'static str) -> Self {
+        debug_assert!(
+            CATALOG.iter().any(|(known, _)| *known == id),
+            "{} is not in the message catalog",
+            id
+        );
+        Message {
+            id,
+            params: Map::new(),
+        }
+    }
+
+    pub fn with(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.params.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// The English text, with the parameters filled in.
+    pub fn render(&self) -> String {
+        let mut text = CATALOG
+            .iter()
+            .find(|(id, _)| *id == self.id)
+            .map_or(self.id, |(_, text)| text)
+            .to_string();
+        for (name, value) in &self.params {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            text = text.replace(&format!("{{{}}}", name), &value);
+        }
+        text
+    }
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render())
+    }
+}
+
+/// Every message id with its English text.
+#[tauri::command]
+pub fn get_message_catalog() -> BTreeMap<&'static str, &'static str> {
+    CATALOG.iter().copied().collect()
+}
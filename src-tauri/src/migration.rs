@@ -0,0 +1,687 @@
+//! Moving everything over from a Prism Launcher or MultiMC installation in
+//! one go: instances with their components, icons and groups, the global
+//! Java settings, the library and asset caches and the accounts.
+//!
+//! Accounts can also be imported on their own, from Prism Launcher's
+//! `accounts.json` or the official launcher's `launcher_accounts.json`.
+//! Microsoft tokens only work with the app they were issued to, so only
+//! accounts of launchers sharing our client id can be carried over as they
+//! are. The others are remembered as pending accounts to sign into again.
+//!
+//! Nothing in the other launcher's directory is changed, so it keeps
+//! working afterwards.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    accounts::{self, Account, AccountKind, Ownership},
+    auth, command_result,
+    errors::{coded, CommandError, ErrorCode},
+    instance::{self, InstanceConfig, InstanceKind},
+    messages::Message,
+    prism_meta::MINECRAFT_UID,
+    settings::{self, AccountPlaceholder},
+    tasks::{self, TaskHandle, TaskKind},
+};
+
+/// Config file names of the supported launchers, newest first
+const LAUNCHER_CONFIGS: &[&str] = &["prismlauncher.cfg", "polymc.cfg", "multimc.cfg"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MigrationItemKind {
+    Instance,
+    /// A mod loader or other component of an instance
+    Component,
+    Icon,
+    Group,
+    JavaSettings,
+    Cache,
+    Account,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationItem {
+    pub kind: MigrationItemKind,
+    pub name: String,
+    /// Set if the item was left alone on purpose, e.g. because it exists
+    /// already
+    pub skipped: Option<String>,
+    pub error: Option<CommandError>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationReport {
+    pub items: Vec<MigrationItem>,
+}
+
+impl MigrationReport {
+    fn push(
+        &mut self,
+        kind: MigrationItemKind,
+        name: impl Into<String>,
+        result: anyhow::Result<Option<String>>,
+    ) {
+        let name = name.into();
+        let (skipped, error) = match result {
+            Ok(skipped) => (skipped, None),
+            Err(e) => {
+                warn!("Failed to migrate {}: {:#}", name, e);
+                (None, Some(e.into()))
+            }
+        };
+        self.items.push(MigrationItem {
+            kind,
+            name,
+            skipped,
+            error,
+        });
+    }
+}
+
+/// `instgroups.json`
+#[derive(Debug, Deserialize)]
+struct InstanceGroups {
+    #[serde(default)]
+    groups: HashMap<String, InstanceGroup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InstanceGroup {
+    #[serde(default)]
+    instances: Vec<String>,
+}
+
+/// `mmc-pack.json`
+#[derive(Debug, Deserialize)]
+struct PackProfile {
+    #[serde(default)]
+    components: Vec<PackComponent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PackComponent {
+    uid: String,
+    version: Option<String>,
+    cached_name: Option<String>,
+    /// Only there because another component requires it
+    #[serde(default)]
+    dependency_only: bool,
+}
+
+/// Prism Launcher's `accounts.json`
+#[derive(Debug, Deserialize)]
+struct PrismAccounts {
+    #[serde(default)]
+    accounts: Vec<PrismAccount>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrismAccount {
+    /// `MSA`, `Offline`, or `Mojang` for long dead Mojang accounts
+    #[serde(rename = "type")]
+    kind: String,
+    msa: Option<PrismMsaToken>,
+    #[serde(rename = "msa-client-id")]
+    msa_client_id: Option<String>,
+    profile: Option<PrismProfile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrismMsaToken {
+    refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrismProfile {
+    id: String,
+    name: String,
+}
+
+/// The official launcher's `launcher_accounts.json`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VanillaAccounts {
+    #[serde(default)]
+    accounts: HashMap<String, VanillaAccount>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VanillaAccount {
+    minecraft_profile: Option<PrismProfile>,
+}
+
+/// Reads the `key=value` lines of a Qt settings file, ignoring sections.
+fn read_cfg(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            (key.trim().to_string(), value.replace("\\\\", "\\"))
+        })
+        .collect())
+}
+
+fn is_true(cfg: &HashMap<String, String>, key: &str) -> bool {
+    cfg.get(key).is_some_and(|value| value == "true")
+}
+
+fn non_empty<'a>(cfg: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+    cfg.get(key)
+        .map(String::as_str)
+        .filter(|value| !value.is_empty())
+}
+
+/// JVM arguments from a config, including the memory settings.
+fn jvm_args(cfg: &HashMap<String, String>) -> Vec<String> {
+    let mut args = vec![];
+    if let Some(max) = non_empty(cfg, "MaxMemAlloc") {
+        args.push(format!("-Xmx{}m", max));
+    }
+    if let Some(min) = non_empty(cfg, "MinMemAlloc") {
+        args.push(format!("-Xms{}m", min));
+    }
+    if let Some(extra) = non_empty(cfg, "JvmArgs") {
+        args.extend(extra.split_whitespace().map(str::to_string));
+    }
+    args
+}
+
+/// A directory from the launcher config, relative to the data dir unless
+/// it is absolute.
+fn configured_dir(root: &Path, cfg: &HashMap<String, String>, key: &str, default: &str) -> PathBuf {
+    root.join(non_empty(cfg, key).unwrap_or(default))
+}
+
+/// Installations of supported launchers in their default locations.
+fn default_locations() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Some(data_dir) = tauri::api::path::data_dir() {
+        for name in ["PrismLauncher", "PolyMC", "multimc"] {
+            dirs.push(data_dir.join(name));
+        }
+    }
+    if let Some(home) = tauri::api::path::home_dir() {
+        dirs.push(home.join(".var/app/org.prismlauncher.PrismLauncher/data/PrismLauncher"));
+    }
+    dirs.into_iter()
+        .filter(|dir| LAUNCHER_CONFIGS.iter().any(|cfg| dir.join(cfg).is_file()))
+        .collect()
+}
+
+/// Signs into a Microsoft account with a refresh token issued to us.
+async fn import_refresh_token(
+    app_handle: &tauri::AppHandle,
+    refresh_token: String,
+) -> anyhow::Result<()> {
+    // Only the refresh token matters to `refresh`, it fills in the rest
+    let account = Account {
+        id: String::new(),
+        kind: AccountKind::Microsoft,
+        msa_access_token: String::new(),
+        msa_refresh_token: refresh_token,
+        msa_expires_at: 0,
+        minecraft_access_token: String::new(),
+        minecraft_expires_at: 0,
+        profile: None,
+        ownership: Ownership::NotOwned,
+        xbox_profile: None,
+        msa_client_id: None,
+        msa_tenant: None,
+    };
+    auth::refresh(app_handle, &account).await?;
+    Ok(())
+}
+
+async fn import_prism_account(
+    app_handle: &tauri::AppHandle,
+    account: PrismAccount,
+    pending: &mut Vec<AccountPlaceholder>,
+) -> anyhow::Result<Option<String>> {
+    let profile = match account.profile {
+        Some(profile) => profile,
+        None => return Ok(Some("Doesn't own Minecraft".to_string())),
+    };
+    match account.kind.as_str() {
+        "Offline" => {
+            accounts::add_offline_account_inner(app_handle, &profile.name).await?;
+            Ok(None)
+        }
+        "MSA" => {
+            let client_id = auth::client_id(app_handle);
+            let refresh_token = account
+                .msa
+                .and_then(|msa| msa.refresh_token)
+                .filter(|_| account.msa_client_id.as_deref() == Some(client_id.as_str()));
+            if let Some(refresh_token) = refresh_token {
+                if import_refresh_token(app_handle, refresh_token)
+                    .await
+                    .is_ok()
+                {
+                    return Ok(None);
+                }
+            }
+            pending.push(AccountPlaceholder {
+                username: profile.name,
+                uuid: profile.id,
+            });
+            Ok(Some("Has to be signed into again".to_string()))
+        }
+        kind => Ok(Some(format!("{} accounts aren't supported", kind))),
+    }
+}
+
+/// Imports the accounts in a Prism Launcher `accounts.json` or an official
+/// launcher `launcher_accounts.json`.
+async fn import_accounts_file(
+    app_handle: &tauri::AppHandle,
+    path: &Path,
+    report: &mut MigrationReport,
+) -> anyhow::Result<()> {
+    let data = tokio::fs::read(path).await?;
+    let mut pending = vec![];
+    if let Ok(vanilla) = serde_json::from_slice::<VanillaAccounts>(&data) {
+        // The official launcher only keeps tokens for its own client id, so
+        // every account has to be signed into again
+        for profile in vanilla
+            .accounts
+            .into_values()
+            .filter_map(|account| account.minecraft_profile)
+        {
+            report.push(
+                MigrationItemKind::Account,
+                &profile.name,
+                Ok(Some("Has to be signed into again".to_string())),
+            );
+            pending.push(AccountPlaceholder {
+                username: profile.name,
+                uuid: profile.id,
+            });
+        }
+    } else {
+        let prism: PrismAccounts = serde_json::from_slice(&data)?;
+        for account in prism.accounts {
+            let name = account
+                .profile
+                .as_ref()
+                .map_or_else(|| account.kind.clone(), |profile| profile.name.clone());
+            let result = import_prism_account(app_handle, account, &mut pending).await;
+            report.push(MigrationItemKind::Account, name, result);
+        }
+    }
+    settings::add_pending_accounts(app_handle, pending)?;
+    Ok(())
+}
+
+/// The official launcher's `.minecraft` folder.
+fn official_minecraft_dir() -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        Some(tauri::api::path::data_dir()?.join(".minecraft"))
+    } else if cfg!(target_os = "macos") {
+        Some(tauri::api::path::data_dir()?.join("minecraft"))
+    } else {
+        Some(tauri::api::path::home_dir()?.join(".minecraft"))
+    }
+}
+
+/// Copies the files below `from` that don't exist below `to` yet.
+fn copy_missing(from: &Path, to: &Path) -> anyhow::Result<u64> {
+    let mut copied = 0;
+    if !from.is_dir() {
+        return Ok(copied);
+    }
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copied += copy_missing(&entry.path(), &target)?;
+        } else if !target.exists() {
+            std::fs::create_dir_all(to)?;
+            std::fs::copy(entry.path(), &target)?;
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
+
+fn find_icon(icons_dir: &Path, key: &str) -> Option<PathBuf> {
+    std::fs::read_dir(icons_dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| path.file_stem().is_some_and(|stem| stem == key))
+}
+
+/// Imports one instance directory, returning why it was skipped, if it was.
+async fn migrate_instance(
+    app_handle: &tauri::AppHandle,
+    dir: &Path,
+    group: Option<String>,
+    icons_dir: &Path,
+    report: &mut MigrationReport,
+) -> anyhow::Result<Option<String>> {
+    let cfg = read_cfg(&dir.join("instance.cfg"))?;
+    let name = non_empty(&cfg, "name")
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+            dir.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+    if instance::list_instances(app_handle)
+        .await?
+        .iter()
+        .any(|entry| entry.config.name == name)
+    {
+        return Ok(Some(
+            "An instance with this name exists already".to_string(),
+        ));
+    }
+
+    let components = match std::fs::read(dir.join("mmc-pack.json")) {
+        Ok(data) => serde_json::from_slice::<PackProfile>(&data)?.components,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e.into()),
+    };
+    let minecraft_version = components
+        .iter()
+        .find(|component| component.uid == MINECRAFT_UID)
+        .and_then(|component| component.version.clone());
+    let config = InstanceConfig {
+        minecraft_version,
+        java_path: is_true(&cfg, "OverrideJavaLocation")
+            .then(|| non_empty(&cfg, "JavaPath").map(str::to_string))
+            .flatten(),
+        ignore_java_compatibility: false,
+        jvm_args: if is_true(&cfg, "OverrideJavaArgs") || is_true(&cfg, "OverrideMemory") {
+            jvm_args(&cfg)
+        } else {
+            vec![]
+        },
+        last_played: cfg
+            .get("lastLaunchTime")
+            .and_then(|time| time.parse::<i64>().ok())
+            .filter(|time| *time > 0)
+            .map(|millis| millis / 1000),
+        group: group.clone(),
+        ..InstanceConfig::new(name.clone(), InstanceKind::Client)
+    };
+    let instance_id = instance::create_instance(app_handle, &config).await?;
+
+    let game_dir = instance::game_dir(app_handle, &instance_id)?;
+    let source = [".minecraft", "minecraft"]
+        .into_iter()
+        .map(|name| dir.join(name))
+        .find(|dir| dir.is_dir());
+    if let Some(source) = source {
+        tokio::task::spawn_blocking(move || instance::copy_recursive(&source, &game_dir)).await??;
+    }
+    // Customized components and the jar mods they add, laid out the same
+    let instance_dir = instance::instance_dir(app_handle, &instance_id)?;
+    for name in ["patches", "jarmods"] {
+        let (from, to) = (dir.join(name), instance_dir.join(name));
+        tokio::task::spawn_blocking(move || instance::copy_recursive(&from, &to)).await??;
+    }
+    for component in components
+        .iter()
+        .filter(|component| component.uid != MINECRAFT_UID && !component.dependency_only)
+    {
+        let result = migrate_component(&instance_dir, component).await;
+        report.push(
+            MigrationItemKind::Component,
+            format!("{}: {}", name, component.uid),
+            result,
+        );
+    }
+
+    if let Some(group) = group {
+        report.push(
+            MigrationItemKind::Group,
+            format!("{}: {}", group, name),
+            Ok(None),
+        );
+    }
+    if let Some(key) = non_empty(&cfg, "iconKey") {
+        // Built-in icons have no file and are left at the default
+        if let Some(icon) = find_icon(icons_dir, key) {
+            let result = async {
+                let file_name = format!(
+                    "icon.{}",
+                    icon.extension().unwrap_or_default().to_string_lossy()
+                );
+                let instance_dir = instance::instance_dir(app_handle, &instance_id)?;
+                tokio::fs::copy(&icon, instance_dir.join(&file_name)).await?;
+                let mut config = instance::read_config(app_handle, &instance_id).await?;
+                config.icon = Some(file_name);
+                instance::write_config(app_handle, &instance_id, &config).await?;
+                Ok(None)
+            }
+            .await;
+            report.push(MigrationItemKind::Icon, key, result);
+        }
+    }
+    info!("Migrated {:?} as {}", dir, instance_id);
+    Ok(None)
+}
+
+/// Makes the instance in `instance_dir` use `component`. Instances pick
+/// their components by patch, so one that only pins the version is written
+/// unless the instance brought its own. Components only there as a
+/// dependency are left to be resolved again.
+async fn migrate_component(
+    instance_dir: &Path,
+    component: &PackComponent,
+) -> anyhow::Result<Option<String>> {
+    let path = instance::safe_join(
+        &instance_dir.join("patches"),
+        &format!("{}.json", component.uid),
+    )?;
+    if path.exists() {
+        return Ok(None);
+    }
+    let version = component.version.as_ref().ok_or_else(|| {
+        coded(
+            ErrorCode::InvalidData,
+            format!("{} has no version to install", component.uid),
+        )
+    })?;
+    let patch = serde_json::json!({
+        "formatVersion": 1,
+        "uid": component.uid,
+        "name": component.cached_name.as_deref().unwrap_or(&component.uid),
+        "version": version,
+        "order": 0,
+    });
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&path, serde_json::to_vec_pretty(&patch)?).await?;
+    Ok(None)
+}
+
+fn migrate_java_settings(
+    app_handle: &tauri::AppHandle,
+    cfg: &HashMap<String, String>,
+) -> anyhow::Result<Option<String>> {
+    let mut settings = settings::current(app_handle);
+    let mut changed = false;
+    if settings.java_path.is_none() {
+        if let Some(java_path) = non_empty(cfg, "JavaPath") {
+            settings.java_path = Some(java_path.to_string());
+            changed = true;
+        }
+    }
+    if settings.jvm_args.is_empty() {
+        settings.jvm_args = jvm_args(cfg);
+        changed |= !settings.jvm_args.is_empty();
+    }
+    if !changed {
+        return Ok(Some("Java settings are set up already".to_string()));
+    }
+    settings::set_settings_inner(app_handle, settings)?;
+    Ok(None)
+}
+
+async fn migrate(
+    app_handle: &tauri::AppHandle,
+    task: TaskHandle,
+    root: PathBuf,
+) -> anyhow::Result<MigrationReport> {
+    let cfg_path = LAUNCHER_CONFIGS
+        .iter()
+        .map(|name| root.join(name))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            coded(
+                ErrorCode::NotFound,
+                format!("{:?} is not a Prism Launcher or MultiMC folder", root),
+            )
+        })?;
+    let cfg = read_cfg(&cfg_path)?;
+    let instances_dir = configured_dir(&root, &cfg, "InstanceDir", "instances");
+    let icons_dir = configured_dir(&root, &cfg, "IconsDir", "icons");
+    let mut report = MigrationReport::default();
+
+    let groups: HashMap<String, String> = match std::fs::read(instances_dir.join("instgroups.json"))
+    {
+        Ok(data) => serde_json::from_slice::<InstanceGroups>(&data)?
+            .groups
+            .into_iter()
+            .flat_map(|(group, entry)| {
+                entry
+                    .instances
+                    .into_iter()
+                    .map(move |instance| (instance, group.clone()))
+            })
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut dirs = vec![];
+    for entry in std::fs::read_dir(&instances_dir)? {
+        let path = entry?.path();
+        if path.join("instance.cfg").is_file() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    // The settings, caches and accounts are the last three steps
+    let total = dirs.len() as u64 + 3;
+    for (i, dir) in dirs.iter().enumerate() {
+        let dir_name = dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        task.set_progress(i as u64, total);
+        task.set_message(&dir_name);
+        let group = groups.get(&dir_name).cloned();
+        let result = migrate_instance(app_handle, dir, group, &icons_dir, &mut report).await;
+        report.push(MigrationItemKind::Instance, dir_name, result);
+    }
+
+    task.set_progress(total - 3, total);
+    task.set_message("Java settings");
+    report.push(
+        MigrationItemKind::JavaSettings,
+        "Java",
+        migrate_java_settings(app_handle, &cfg),
+    );
+
+    task.set_progress(total - 2, total);
+    task.set_message("Caches");
+    let launcher_dir = instance::launcher_dir(app_handle)?;
+    for cache in ["libraries", "assets"] {
+        let from = root.join(cache);
+        let to = launcher_dir.join(cache);
+        let result = tokio::task::spawn_blocking(move || copy_missing(&from, &to))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result)
+            .map(|copied| (copied == 0).then(|| "Nothing new to copy".to_string()));
+        report.push(MigrationItemKind::Cache, cache, result);
+    }
+
+    task.set_progress(total - 1, total);
+    task.set_message("Accounts");
+    let accounts_path = root.join("accounts.json");
+    if accounts_path.is_file() {
+        if let Err(e) = import_accounts_file(app_handle, &accounts_path, &mut report).await {
+            report.push(MigrationItemKind::Account, "accounts.json", Err(e));
+        }
+    }
+    Ok(report)
+}
+
+/// Prism Launcher, PolyMC and MultiMC installations found in their default
+/// locations. MultiMC is usually portable and has to be picked by hand.
+#[tauri::command]
+pub fn find_launchers_to_migrate() -> Vec<PathBuf> {
+    default_locations()
+}
+
+/// Account files of other launchers found in their default locations.
+#[tauri::command]
+pub fn find_accounts_to_import() -> Vec<PathBuf> {
+    let mut files: Vec<_> = default_locations()
+        .into_iter()
+        .map(|dir| dir.join("accounts.json"))
+        .collect();
+    if let Some(minecraft_dir) = official_minecraft_dir() {
+        files.push(minecraft_dir.join("launcher_accounts.json"));
+    }
+    files.retain(|file| file.is_file());
+    files
+}
+
+/// Imports the accounts in the account file of another launcher at `path`.
+#[tauri::command]
+pub async fn import_accounts(
+    app_handle: tauri::AppHandle,
+    path: PathBuf,
+) -> Result<MigrationReport, CommandError> {
+    command_result(
+        async {
+            let mut report = MigrationReport::default();
+            import_accounts_file(&app_handle, &path, &mut report).await?;
+            Ok(report)
+        }
+        .await,
+    )
+}
+
+/// Imports everything from the installation at `path`. Failures of single
+/// items don't stop the migration, they are listed in the report.
+#[tauri::command]
+pub async fn migrate_from_launcher(
+    app_handle: tauri::AppHandle,
+    path: PathBuf,
+) -> Result<MigrationReport, CommandError> {
+    command_result(
+        tasks::run(
+            &app_handle,
+            TaskKind::Install,
+            Message::new("task.migrate"),
+            |task| migrate(&app_handle, task, path),
+        )
+        .await,
+    )
+}
@@ -0,0 +1,108 @@
+//! Download mirrors, for users who can't reach Mojang's and the loaders'
+//! servers well, e.g. BMCLAPI in China. URLs are rewritten to the mirror
+//! configured in the settings, and the original is tried when the mirror
+//! fails.
+
+use std::{future::Future, sync::RwLock};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+
+const BMCLAPI_URL: &str = "https://bmclapi2.bangbang93.com/";
+
+/// Origins BMCLAPI mirrors, with where below it they are
+const BMCLAPI_REWRITES: &[(&str, &str)] = &[
+    ("https://piston-meta.mojang.com/", ""),
+    ("https://launchermeta.mojang.com/", ""),
+    ("https://piston-data.mojang.com/", ""),
+    ("https://launcher.mojang.com/", ""),
+    ("https://libraries.minecraft.net/", "maven/"),
+    ("https://resources.download.minecraft.net/", "assets/"),
+    ("https://maven.minecraftforge.net/", "maven/"),
+    ("https://maven.fabricmc.net/", "maven/"),
+    ("https://meta.fabricmc.net/", "fabric-meta/"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rewrite {
+    /// URL prefix to replace
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Mirror {
+    Bmclapi,
+    /// A mirror set up by the user, e.g. for the meta server
+    Custom {
+        rewrites: Vec<Rewrite>,
+    },
+}
+
+impl Mirror {
+    /// `url` on the mirror, or `None` if the mirror doesn't have it.
+    pub fn rewrite(&self, url: &str) -> Option<String> {
+        match self {
+            Mirror::Bmclapi => BMCLAPI_REWRITES.iter().find_map(|(from, to)| {
+                url.strip_prefix(from)
+                    .map(|rest| format!("{}{}{}", BMCLAPI_URL, to, rest))
+            }),
+            Mirror::Custom { rewrites } => rewrites
+                .iter()
+                .filter(|rewrite| !rewrite.from.is_empty())
+                .find_map(|rewrite| {
+                    url.strip_prefix(&rewrite.from)
+                        .map(|rest| format!("{}{}", rewrite.to, rest))
+                }),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref MIRROR: RwLock<Option<Mirror>> = RwLock::new(None);
+}
+
+/// Picks up the mirror from `settings`.
+pub fn apply(settings: &Settings) {
+    *MIRROR.write().unwrap() = settings.mirror.clone();
+}
+
+/// The URLs to try for `url`, the mirrored one first.
+pub fn candidates(url: &str) -> Vec<String> {
+    let mirrored = MIRROR
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|mirror| mirror.rewrite(url));
+    mirrored
+        .into_iter()
+        .chain(std::iter::once(url.to_string()))
+        .collect()
+}
+
+/// Runs `download` with the mirrored URL for `url`, and with `url` itself
+/// if that fails.
+pub async fn with_fallback<T, F, Fut>(url: &str, download: F) -> anyhow::Result<T>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut candidates = candidates(url).into_iter().peekable();
+    loop {
+        // There's always the original
+        let candidate = candidates.next().unwrap();
+        match download(candidate.clone()).await {
+            Err(e) if candidates.peek().is_some() => {
+                warn!(
+                    "Mirror failed for {}, using the original: {:#}",
+                    candidate, e
+                );
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! Minimal reader/writer for uncompressed, big-endian NBT as used by
+//! `servers.dat`.
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
+
+use anyhow::anyhow;
+
+pub type Compound = HashMap<String, Tag>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(Compound),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_byte(&self) -> Option<i8> {
+        match self {
+            Tag::Byte(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&Compound> {
+        match self {
+            Tag::Compound(compound) => Some(compound),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a named root tag, returning its name and value.
+pub fn read(reader: &mut impl Read) -> anyhow::Result<(String, Tag)> {
+    let id = read_u8(reader)?;
+    if id == 0 {
+        return Err(anyhow!("Root tag is TAG_End"));
+    }
+    let name = read_string(reader)?;
+    Ok((name, read_payload(reader, id)?))
+}
+
+/// Writes a named root tag.
+pub fn write(writer: &mut impl Write, name: &str, tag: &Tag) -> anyhow::Result<()> {
+    writer.write_all(&[tag.id()])?;
+    write_string(writer, name)?;
+    write_payload(writer, tag)
+}
+
+fn read_u8(reader: &mut impl Read) -> anyhow::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+macro_rules! read_be {
+    ($reader:expr, $ty:ty) => {{
+        let mut buf = [0; std::mem::size_of::<$ty>()];
+        $reader.read_exact(&mut buf)?;
+        <$ty>::from_be_bytes(buf)
+    }};
+}
+
+fn read_len(reader: &mut impl Read) -> anyhow::Result<usize> {
+    let len = read_be!(reader, i32);
+    usize::try_from(len).map_err(|_| anyhow!("Negative length {}", len))
+}
+
+fn read_string(reader: &mut impl Read) -> anyhow::Result<String> {
+    let len = read_be!(reader, u16);
+    let mut buf = vec![0; len.into()];
+    reader.read_exact(&mut buf)?;
+    decode_mutf8(&buf)
+}
+
+fn read_payload(reader: &mut impl Read, id: u8) -> anyhow::Result<Tag> {
+    Ok(match id {
+        1 => Tag::Byte(read_be!(reader, i8)),
+        2 => Tag::Short(read_be!(reader, i16)),
+        3 => Tag::Int(read_be!(reader, i32)),
+        4 => Tag::Long(read_be!(reader, i64)),
+        5 => Tag::Float(read_be!(reader, f32)),
+        6 => Tag::Double(read_be!(reader, f64)),
+        7 => {
+            let len = read_len(reader)?;
+            let mut buf = vec![0; len];
+            reader.read_exact(&mut buf)?;
+            Tag::ByteArray(buf.into_iter().map(|b| b as i8).collect())
+        }
+        8 => Tag::String(read_string(reader)?),
+        9 => {
+            let element_id = read_u8(reader)?;
+            let len = read_len(reader)?;
+            let mut list = Vec::with_capacity(len.min(4096));
+            for _ in 0..len {
+                list.push(read_payload(reader, element_id)?);
+            }
+            Tag::List(list)
+        }
+        10 => {
+            let mut compound = Compound::new();
+            loop {
+                let id = read_u8(reader)?;
+                if id == 0 {
+                    break;
+                }
+                let name = read_string(reader)?;
+                compound.insert(name, read_payload(reader, id)?);
+            }
+            Tag::Compound(compound)
+        }
+        11 => {
+            let len = read_len(reader)?;
+            let mut array = Vec::with_capacity(len.min(4096));
+            for _ in 0..len {
+                array.push(read_be!(reader, i32));
+            }
+            Tag::IntArray(array)
+        }
+        12 => {
+            let len = read_len(reader)?;
+            let mut array = Vec::with_capacity(len.min(4096));
+            for _ in 0..len {
+                array.push(read_be!(reader, i64));
+            }
+            Tag::LongArray(array)
+        }
+        _ => return Err(anyhow!("Unknown tag id {}", id)),
+    })
+}
+
+fn write_len(writer: &mut impl Write, len: usize) -> anyhow::Result<()> {
+    let len = i32::try_from(len).map_err(|_| anyhow!("Length {} too large", len))?;
+    writer.write_all(&len.to_be_bytes())?;
+    Ok(())
+}
+
+fn write_string(writer: &mut impl Write, string: &str) -> anyhow::Result<()> {
+    let encoded = encode_mutf8(string);
+    let len = u16::try_from(encoded.len()).map_err(|_| anyhow!("String too long"))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(&encoded)?;
+    Ok(())
+}
+
+fn write_payload(writer: &mut impl Write, tag: &Tag) -> anyhow::Result<()> {
+    match tag {
+        Tag::Byte(v) => writer.write_all(&v.to_be_bytes())?,
+        Tag::Short(v) => writer.write_all(&v.to_be_bytes())?,
+        Tag::Int(v) => writer.write_all(&v.to_be_bytes())?,
+        Tag::Long(v) => writer.write_all(&v.to_be_bytes())?,
+        Tag::Float(v) => writer.write_all(&v.to_be_bytes())?,
+        Tag::Double(v) => writer.write_all(&v.to_be_bytes())?,
+        Tag::ByteArray(array) => {
+            write_len(writer, array.len())?;
+            let bytes: Vec<u8> = array.iter().map(|b| *b as u8).collect();
+            writer.write_all(&bytes)?;
+        }
+        Tag::String(string) => write_string(writer, string)?,
+        Tag::List(list) => {
+            let element_id = list.first().map_or(0, Tag::id);
+            if list.iter().any(|tag| tag.id() != element_id) {
+                return Err(anyhow!("List elements must all have the same type"));
+            }
+            writer.write_all(&[element_id])?;
+            write_len(writer, list.len())?;
+            for tag in list {
+                write_payload(writer, tag)?;
+            }
+        }
+        Tag::Compound(compound) => {
+            for (name, tag) in compound {
+                writer.write_all(&[tag.id()])?;
+                write_string(writer, name)?;
+                write_payload(writer, tag)?;
+            }
+            writer.write_all(&[0])?;
+        }
+        Tag::IntArray(array) => {
+            write_len(writer, array.len())?;
+            for v in array {
+                writer.write_all(&v.to_be_bytes())?;
+            }
+        }
+        Tag::LongArray(array) => {
+            write_len(writer, array.len())?;
+            for v in array {
+                writer.write_all(&v.to_be_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Java's "modified UTF-8": NUL is encoded as two bytes and characters
+/// outside the BMP are encoded as surrogate pairs.
+fn decode_mutf8(bytes: &[u8]) -> anyhow::Result<String> {
+    if let Ok(string) = std::str::from_utf8(bytes) {
+        if !bytes.contains(&0xed) {
+            return Ok(string.to_string());
+        }
+    }
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i] as u16;
+        let cont = |offset: usize| -> anyhow::Result<u16> {
+            bytes
+                .get(i + offset)
+                .map(|b| (*b & 0x3f) as u16)
+                .ok_or(anyhow!("Truncated modified UTF-8"))
+        };
+        if b & 0x80 == 0 {
+            units.push(b);
+            i += 1;
+        } else if b & 0xe0 == 0xc0 {
+            units.push(((b & 0x1f) << 6) | cont(1)?);
+            i += 2;
+        } else if b & 0xf0 == 0xe0 {
+            units.push(((b & 0x0f) << 12) | (cont(1)? << 6) | cont(2)?);
+            i += 3;
+        } else {
+            return Err(anyhow!("Invalid modified UTF-8"));
+        }
+    }
+    Ok(String::from_utf16_lossy(&units))
+}
+
+fn encode_mutf8(string: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(string.len());
+    for unit in string.encode_utf16() {
+        match unit {
+            0x01..=0x7f => bytes.push(unit as u8),
+            0x00 | 0x80..=0x7ff => {
+                bytes.push(0xc0 | (unit >> 6) as u8);
+                bytes.push(0x80 | (unit & 0x3f) as u8);
+            }
+            _ => {
+                bytes.push(0xe0 | (unit >> 12) as u8);
+                bytes.push(0x80 | ((unit >> 6) & 0x3f) as u8);
+                bytes.push(0x80 | (unit & 0x3f) as u8);
+            }
+        }
+    }
+    bytes
+}
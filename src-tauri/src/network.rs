@@ -0,0 +1,200 @@
+//! Connectivity tracking. A background loop checks whether the services the
+//! launcher talks to are reachable and switches to offline mode when they
+//! aren't, so network work is skipped instead of failing slowly.
+//!
+//! Work that can wait is queued while offline and replayed once the
+//! connection is back. Changes are emitted as `network:status` with a
+//! [`NetworkStatus`], and every replayed operation as `network:replayed`.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use log::{info, warn};
+use serde::Serialize;
+use tauri::Manager;
+
+use crate::{
+    errors::{coded_message, CommandError, ErrorCode},
+    messages::Message,
+    mirrors, prism_meta, proxy, sync,
+};
+
+/// Checked along with the meta server, reaching any of them counts as
+/// online
+const PROBE_URLS: &[&str] = &[
+    "https://api.minecraftservices.com/",
+    "https://login.microsoftonline.com/",
+];
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const ONLINE_INTERVAL: Duration = Duration::from_secs(60);
+/// Shorter, so coming back online is noticed quickly
+const OFFLINE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum QueuedOperation {
+    MetaSync,
+    #[serde(rename_all = "camelCase")]
+    SyncPush {
+        instance_id: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkStatus {
+    pub online: bool,
+    pub queued: Vec<QueuedOperation>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayedOperation {
+    pub operation: QueuedOperation,
+    pub error: Option<CommandError>,
+}
+
+#[derive(Default)]
+pub struct NetworkState {
+    offline: AtomicBool,
+    queue: Mutex<Vec<QueuedOperation>>,
+}
+
+impl NetworkState {
+    fn status(&self) -> NetworkStatus {
+        NetworkStatus {
+            online: !self.offline.load(Ordering::Relaxed),
+            queued: self.queue.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Whether the launcher is online. Assumed to be until a check fails.
+pub fn is_online(app_handle: &tauri::AppHandle) -> bool {
+    app_handle
+        .try_state::<NetworkState>()
+        .is_none_or(|state| !state.offline.load(Ordering::Relaxed))
+}
+
+fn emit_status(app_handle: &tauri::AppHandle) {
+    let status = app_handle.state::<NetworkState>().status();
+    if let Err(e) = app_handle.emit_all("network:status", status) {
+        warn!("Failed to emit network status: {}", e);
+    }
+}
+
+/// Defers `operation` until the connection is back, returning the error to
+/// report for it in the meantime.
+pub fn defer(app_handle: &tauri::AppHandle, operation: QueuedOperation) -> anyhow::Error {
+    let state = app_handle.state::<NetworkState>();
+    let mut queue = state.queue.lock().unwrap();
+    if !queue.contains(&operation) {
+        info!("Offline, queued {:?}", operation);
+        queue.push(operation);
+    }
+    drop(queue);
+    emit_status(app_handle);
+    coded_message(ErrorCode::Network, Message::new("network.queued"))
+}
+
+/// Asks the configured meta server (and its mirror) and the auth servers
+/// in parallel, through the proxy. Any answer counts, even an error status.
+async fn probe(app_handle: &tauri::AppHandle) -> bool {
+    let urls: Vec<String> = mirrors::candidates(&prism_meta::meta_url(app_handle))
+        .into_iter()
+        .chain(PROBE_URLS.iter().map(|url| url.to_string()))
+        .collect();
+    let (tx, mut rx) = tokio::sync::mpsc::channel(urls.len());
+    let client = proxy::client();
+    for url in urls {
+        let (tx, client) = (tx.clone(), client.clone());
+        tauri::async_runtime::spawn(async move {
+            let connected = client
+                .head(&url)
+                .timeout(PROBE_TIMEOUT)
+                .send()
+                .await
+                .is_ok();
+            let _ = tx.send(connected).await;
+        });
+    }
+    drop(tx);
+    while let Some(connected) = rx.recv().await {
+        if connected {
+            return true;
+        }
+    }
+    false
+}
+
+async fn replay(app_handle: &tauri::AppHandle) {
+    let queued = std::mem::take(&mut *app_handle.state::<NetworkState>().queue.lock().unwrap());
+    emit_status(app_handle);
+    for operation in queued {
+        info!("Back online, running {:?}", operation);
+        let result = match &operation {
+            QueuedOperation::MetaSync => prism_meta::fetch_meta(app_handle).await.map(|_| ()),
+            QueuedOperation::SyncPush { instance_id } => {
+                sync::push_instance(app_handle, instance_id, false)
+                    .await
+                    .map(|_| ())
+            }
+        };
+        let event = ReplayedOperation {
+            operation,
+            error: result.err().map(CommandError::from),
+        };
+        if let Err(e) = app_handle.emit_all("network:replayed", event) {
+            warn!("Failed to emit replayed operation: {}", e);
+        }
+    }
+}
+
+/// Checks the connection now, updating the state. Returns whether the
+/// launcher is online.
+async fn check(app_handle: &tauri::AppHandle) -> bool {
+    let online = probe(app_handle).await;
+    let state = app_handle.state::<NetworkState>();
+    let was_online = !state.offline.swap(!online, Ordering::Relaxed);
+    if online != was_online {
+        info!("Network is {}", if online { "back" } else { "down" });
+        emit_status(app_handle);
+        if online {
+            replay(app_handle).await;
+        }
+    }
+    online
+}
+
+/// Starts checking the connection in the background. Called once during
+/// setup.
+pub fn start(app_handle: &tauri::AppHandle) {
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let interval = if check(&app_handle).await {
+                ONLINE_INTERVAL
+            } else {
+                OFFLINE_INTERVAL
+            };
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub fn get_network_status(app_handle: tauri::AppHandle) -> NetworkStatus {
+    app_handle.state::<NetworkState>().status()
+}
+
+/// Checks the connection right away, e.g. after the user fixed it. Returns
+/// whether the launcher is online.
+#[tauri::command]
+pub async fn check_network(app_handle: tauri::AppHandle) -> bool {
+    check(&app_handle).await
+}
@@ -0,0 +1,237 @@
+//! Minecraft news, Java Edition patch notes and the launcher's own release
+//! notes, cached on disk so the home screen works offline.
+
+use std::{future::Future, path::PathBuf, time::Duration};
+
+use anyhow::anyhow;
+use log::warn;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{command_result, errors::CommandError, proxy, self_update};
+
+const LAUNCHER_CONTENT_URL: &str = "https://launchercontent.mojang.com";
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Deserialize)]
+struct MojangFeed<T> {
+    entries: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MojangImage {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MojangArticle {
+    id: String,
+    title: String,
+    category: String,
+    tag: Option<String>,
+    date: String,
+    text: String,
+    play_page_image: Option<MojangImage>,
+    read_more_link: Option<String>,
+    #[serde(default)]
+    news_type: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MojangPatchNote {
+    id: String,
+    title: String,
+    version: String,
+    #[serde(rename = "type")]
+    kind: String,
+    image: Option<MojangImage>,
+    body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewsArticle {
+    pub id: String,
+    pub title: String,
+    pub category: String,
+    pub tag: Option<String>,
+    pub date: String,
+    pub text: String,
+    pub image_url: Option<String>,
+    pub read_more_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchNote {
+    pub id: String,
+    pub title: String,
+    pub version: String,
+    /// `release` or `snapshot`
+    pub kind: String,
+    pub image_url: Option<String>,
+    /// HTML
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LauncherRelease {
+    pub version: String,
+    pub name: Option<String>,
+    /// Markdown
+    pub notes: Option<String>,
+    pub url: String,
+    pub date: Option<String>,
+    pub prerelease: bool,
+}
+
+fn content_url(path: &str) -> String {
+    if path.starts_with("http") {
+        path.to_string()
+    } else {
+        format!("{}{}", LAUNCHER_CONTENT_URL, path)
+    }
+}
+
+async fn get_feed<T: DeserializeOwned>(path: &str) -> anyhow::Result<Vec<T>> {
+    let url = content_url(path);
+    let resp = proxy::client().get(&url).send().await?;
+    if resp.status() != reqwest::StatusCode::OK {
+        return Err(anyhow!("Got status {} fetching {}", resp.status(), url));
+    }
+    Ok(resp.json::<MojangFeed<T>>().await?.entries)
+}
+
+async fn fetch_news() -> anyhow::Result<Vec<NewsArticle>> {
+    Ok(get_feed::<MojangArticle>("/news.json")
+        .await?
+        .into_iter()
+        .filter(|article| {
+            article.news_type.is_empty() || article.news_type.iter().any(|kind| kind == "Java")
+        })
+        .map(|article| NewsArticle {
+            id: article.id,
+            title: article.title,
+            category: article.category,
+            tag: article.tag,
+            date: article.date,
+            text: article.text,
+            image_url: article.play_page_image.map(|image| content_url(&image.url)),
+            read_more_url: article.read_more_link,
+        })
+        .collect())
+}
+
+async fn fetch_patch_notes() -> anyhow::Result<Vec<PatchNote>> {
+    Ok(get_feed::<MojangPatchNote>("/javaPatchNotes.json")
+        .await?
+        .into_iter()
+        .map(|note| PatchNote {
+            id: note.id,
+            title: note.title,
+            version: note.version,
+            kind: note.kind,
+            image_url: note.image.map(|image| content_url(&image.url)),
+            body: note.body,
+        })
+        .collect())
+}
+
+async fn fetch_launcher_releases() -> anyhow::Result<Vec<LauncherRelease>> {
+    Ok(self_update::fetch_releases()
+        .await?
+        .into_iter()
+        .filter(|release| !release.draft)
+        .map(|release| LauncherRelease {
+            version: release.tag_name,
+            name: release.name,
+            notes: release.body,
+            url: release.html_url,
+            date: release.published_at,
+            prerelease: release.prerelease,
+        })
+        .collect())
+}
+
+fn cache_path(app_handle: &tauri::AppHandle, name: &str) -> anyhow::Result<PathBuf> {
+    Ok(app_handle
+        .path_resolver()
+        .app_cache_dir()
+        .ok_or(anyhow!("Can't get app cache dir"))?
+        .join("news")
+        .join(format!("{}.json", name)))
+}
+
+/// Serves `name` from the cache while it is fresh, otherwise refetches it.
+/// If fetching fails the stale cache is used instead, so news still show up
+/// offline.
+async fn cached<T, F>(
+    app_handle: &tauri::AppHandle,
+    name: &str,
+    refresh: bool,
+    fetch: F,
+) -> anyhow::Result<Vec<T>>
+where
+    T: Serialize + DeserializeOwned,
+    F: Future<Output = anyhow::Result<Vec<T>>>,
+{
+    let path = cache_path(app_handle, name)?;
+    let age = tokio::fs::metadata(&path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok());
+    if let (false, Some(age)) = (refresh, age) {
+        if age < CACHE_TTL {
+            return Ok(serde_json::from_slice(&tokio::fs::read(&path).await?)?);
+        }
+    }
+    match fetch.await {
+        Ok(items) => {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&path, serde_json::to_vec(&items)?).await?;
+            Ok(items)
+        }
+        Err(e) if age.is_some() => {
+            warn!("Using cached {} after fetching failed: {:#}", name, e);
+            Ok(serde_json::from_slice(&tokio::fs::read(&path).await?)?)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[tauri::command]
+pub async fn get_news(
+    app_handle: tauri::AppHandle,
+    refresh: bool,
+) -> Result<Vec<NewsArticle>, CommandError> {
+    command_result(cached(&app_handle, "news", refresh, fetch_news()).await)
+}
+
+#[tauri::command]
+pub async fn get_patch_notes(
+    app_handle: tauri::AppHandle,
+    refresh: bool,
+) -> Result<Vec<PatchNote>, CommandError> {
+    command_result(cached(&app_handle, "patch-notes", refresh, fetch_patch_notes()).await)
+}
+
+#[tauri::command]
+pub async fn get_launcher_changelog(
+    app_handle: tauri::AppHandle,
+    refresh: bool,
+) -> Result<Vec<LauncherRelease>, CommandError> {
+    command_result(
+        cached(
+            &app_handle,
+            "launcher-releases",
+            refresh,
+            fetch_launcher_releases(),
+        )
+        .await,
+    )
+}
@@ -0,0 +1,284 @@
+//! Reading and writing the game's `options.txt`, and copying groups of
+//! settings between instances.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::{command_result, errors::CommandError, instance, process::ProcessManager};
+
+/// The contents of an `options.txt`, kept in file order so that writing it
+/// back doesn't shuffle the user's file around.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GameOptions {
+    pub entries: Vec<(String, String)>,
+}
+
+impl GameOptions {
+    pub fn parse(text: &str) -> Self {
+        GameOptions {
+            entries: text
+                .lines()
+                .filter_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    Some((key.to_string(), value.to_string()))
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(key, value)| format!("{}:{}\n", key, value))
+            .collect()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.entries.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key)?.parse().ok()
+    }
+
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        self.get(key)?.parse().ok()
+    }
+
+    pub fn get_f64(&self, key: &str) -> Option<f64> {
+        self.get(key)?.parse().ok()
+    }
+
+    /// The data version of the game that last wrote this file.
+    pub fn data_version(&self) -> Option<i64> {
+        self.get_i64("version")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OptionCategory {
+    Keybinds,
+    Video,
+    Chat,
+}
+
+const VIDEO_KEYS: &[&str] = &[
+    "ao",
+    "biomeBlendRadius",
+    "bobView",
+    "enableVsync",
+    "entityDistanceScaling",
+    "entityShadows",
+    "fancyGraphics",
+    "fov",
+    "fovEffectScale",
+    "fullscreen",
+    "gamma",
+    "graphicsMode",
+    "guiScale",
+    "maxFps",
+    "mipmapLevels",
+    "particles",
+    "prioritizeChunkUpdates",
+    "renderClouds",
+    "renderDistance",
+    "screenEffectScale",
+    "simulationDistance",
+];
+
+const CHAT_KEYS: &[&str] = &[
+    "backgroundForChatOnly",
+    "chatColors",
+    "chatHeightFocused",
+    "chatHeightUnfocused",
+    "chatLineSpacing",
+    "chatLinks",
+    "chatLinksPrompt",
+    "chatOpacity",
+    "chatScale",
+    "chatVisibility",
+    "chatWidth",
+    "textBackgroundOpacity",
+];
+
+impl OptionCategory {
+    pub fn contains(self, key: &str) -> bool {
+        match self {
+            OptionCategory::Keybinds => key.starts_with("key_"),
+            OptionCategory::Video => VIDEO_KEYS.contains(&key),
+            OptionCategory::Chat => CHAT_KEYS.contains(&key),
+        }
+    }
+}
+
+/// Data version of 1.13, which stores keybinds as names like
+/// `key.keyboard.w` instead of LWJGL key codes.
+const KEY_NAMES_DATA_VERSION: i64 = 1519;
+
+/// Whether keybinds can be copied between the files, going by the data
+/// versions or else by the binding of `key` in both.
+fn same_keybind_format(source: &GameOptions, target: &GameOptions, key: &str) -> bool {
+    match (source.data_version(), target.data_version()) {
+        (Some(a), Some(b)) => (a >= KEY_NAMES_DATA_VERSION) == (b >= KEY_NAMES_DATA_VERSION),
+        _ if target.get(key).is_none() => true,
+        _ => source.get_i64(key).is_some() == target.get_i64(key).is_some(),
+    }
+}
+
+/// Whether `key` is set the same in both files, comparing numbers and
+/// booleans by value so `1` and `1.0` don't count as a change.
+fn same_value(source: &GameOptions, target: &GameOptions, key: &str) -> bool {
+    source.get(key) == target.get(key)
+        || source
+            .get_bool(key)
+            .is_some_and(|value| target.get_bool(key) == Some(value))
+        || source
+            .get_f64(key)
+            .is_some_and(|value| target.get_f64(key) == Some(value))
+}
+
+/// Copies every option in `categories` from `source` into `target`,
+/// returning how many options were changed.
+pub fn copy_categories(
+    source: &GameOptions,
+    target: &mut GameOptions,
+    categories: &[OptionCategory],
+) -> usize {
+    let mut changed = 0;
+    for (key, value) in &source.entries {
+        if !categories.iter().any(|category| category.contains(key)) {
+            continue;
+        }
+        if same_value(source, target, key)
+            || (OptionCategory::Keybinds.contains(key) && !same_keybind_format(source, target, key))
+        {
+            continue;
+        }
+        target.set(key, value);
+        changed += 1;
+    }
+    changed
+}
+
+fn options_path(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<PathBuf> {
+    Ok(instance::game_dir(app_handle, instance_id)?.join("options.txt"))
+}
+
+pub async fn read_options(path: &Path) -> anyhow::Result<GameOptions> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(text) => Ok(GameOptions::parse(&text)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(GameOptions::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn write_options(path: &Path, options: &GameOptions) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp = path.with_extension("txt.tmp");
+    tokio::fs::write(&tmp, options.to_text()).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+/// The game rewrites options.txt when it exits, so editing it while the
+/// instance runs would just get reverted.
+fn check_not_running(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<()> {
+    if app_handle.state::<ProcessManager>().is_running(instance_id) {
+        return Err(anyhow!(
+            "Can't change options of {} while it is running",
+            instance_id
+        ));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_options(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+) -> Result<GameOptions, CommandError> {
+    let path = command_result(options_path(&app_handle, &instance_id))?;
+    command_result(read_options(&path).await)
+}
+
+#[tauri::command]
+pub async fn set_options(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    options: GameOptions,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            check_not_running(&app_handle, &instance_id)?;
+            instance::ensure_unlocked(&app_handle, &instance_id).await?;
+            write_options(&options_path(&app_handle, &instance_id)?, &options).await
+        }
+        .await,
+    )
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncResult {
+    pub instance_id: String,
+    pub changed: usize,
+    pub error: Option<String>,
+}
+
+async fn sync_one(
+    app_handle: &tauri::AppHandle,
+    source: &GameOptions,
+    target_id: &str,
+    categories: &[OptionCategory],
+) -> anyhow::Result<usize> {
+    check_not_running(app_handle, target_id)?;
+    instance::ensure_unlocked(app_handle, target_id).await?;
+    let path = options_path(app_handle, target_id)?;
+    let mut target = read_options(&path).await?;
+    let changed = copy_categories(source, &mut target, categories);
+    if changed > 0 {
+        write_options(&path, &target).await?;
+    }
+    Ok(changed)
+}
+
+/// Copies the selected option categories from one instance to others.
+#[tauri::command]
+pub async fn sync_options(
+    app_handle: tauri::AppHandle,
+    source_id: String,
+    target_ids: Vec<String>,
+    categories: Vec<OptionCategory>,
+) -> Result<Vec<SyncResult>, CommandError> {
+    let source_path = command_result(options_path(&app_handle, &source_id))?;
+    let source = command_result(read_options(&source_path).await)?;
+    let mut results = vec![];
+    for target_id in target_ids {
+        if target_id == source_id {
+            continue;
+        }
+        let result = sync_one(&app_handle, &source, &target_id, &categories).await;
+        results.push(SyncResult {
+            instance_id: target_id,
+            changed: *result.as_ref().unwrap_or(&0),
+            error: result.err().map(|e| format!("{}", e)),
+        });
+    }
+    Ok(results)
+}
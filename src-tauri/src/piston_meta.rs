@@ -0,0 +1,216 @@
+//! Mojang's own version manifest, used in place of the Prism meta when its
+//! server is down. It only has Minecraft itself, so vanilla instances can
+//! still be installed, and its version files are converted into the Prism
+//! format.
+
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::{
+    errors::{coded, ErrorCode},
+    prism_meta::{
+        AssetIndex, Download, IndexPackage, Library, MetaIndex, PackageIndex, PackageVersion,
+        Version, MINECRAFT_UID,
+    },
+    storage::{self, Expected},
+};
+
+const MANIFEST_URL: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+
+/// What the Prism meta orders Minecraft at, below everything else
+const MINECRAFT_ORDER: i32 = -2;
+/// For versions from before Mojang listed the Java version
+const DEFAULT_JAVA_MAJOR: u32 = 8;
+
+#[derive(Debug, Clone, Deserialize)]
+struct Latest {
+    release: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestVersion {
+    id: String,
+    #[serde(rename = "type")]
+    version_type: String,
+    url: String,
+    #[serde(with = "time::serde::iso8601")]
+    release_time: OffsetDateTime,
+    sha1: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Manifest {
+    latest: Latest,
+    versions: Vec<ManifestVersion>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JavaVersion {
+    major_version: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct VersionDownloads {
+    client: Download,
+}
+
+/// Newer versions have structured arguments instead of
+/// `minecraftArguments`, with rules for some of them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Argument {
+    Plain(String),
+    Conditional(serde_json::Value),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Arguments {
+    #[serde(default)]
+    game: Vec<Argument>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MojangVersion {
+    id: String,
+    main_class: String,
+    minecraft_arguments: Option<String>,
+    arguments: Option<Arguments>,
+    #[serde(default)]
+    libraries: Vec<Library>,
+    downloads: VersionDownloads,
+    asset_index: AssetIndex,
+    java_version: Option<JavaVersion>,
+}
+
+impl MojangVersion {
+    fn into_version(self) -> Version {
+        // The conditional ones are for demo mode and custom resolutions,
+        // which the Prism meta leaves out as well
+        let minecraft_arguments = self.minecraft_arguments.or_else(|| {
+            self.arguments.map(|arguments| {
+                arguments
+                    .game
+                    .into_iter()
+                    .filter_map(|argument| match argument {
+                        Argument::Plain(argument) => Some(argument),
+                        Argument::Conditional(_) => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+        });
+        Version {
+            format_version: 1,
+            order: MINECRAFT_ORDER,
+            name: "Minecraft".to_string(),
+            main_jar: Some(Library::with_artifact(
+                format!("com.mojang:minecraft:{}:client", self.id),
+                self.downloads.client,
+            )),
+            version: self.id,
+            applet_class: None,
+            tweakers: None,
+            traits: None,
+            jvm_args: None,
+            jar_mods: None,
+            libraries: Some(self.libraries),
+            add_libraries: None,
+            maven_files: None,
+            requires: vec![],
+            conflicts: vec![],
+            volatile: false,
+            asset_index: Some(self.asset_index),
+            compatible_java_majors: vec![self
+                .java_version
+                .map_or(DEFAULT_JAVA_MAJOR, |java| java.major_version)],
+            main_class: Some(self.main_class),
+            minecraft_arguments,
+        }
+    }
+}
+
+/// Mojang's version manifest, as a stand-in for the Prism meta.
+#[derive(Debug, Clone)]
+pub struct PistonMeta {
+    manifest: Manifest,
+    cache_dir: PathBuf,
+}
+
+impl PistonMeta {
+    /// Downloads the manifest. Version files are cached in `cache_dir`.
+    pub async fn fetch(cache_dir: PathBuf) -> anyhow::Result<PistonMeta> {
+        let path = cache_dir.join("version_manifest_v2.json");
+        storage::get_file(&path, MANIFEST_URL, true, &Expected::default()).await?;
+        let data = tokio::fs::read(&path).await?;
+        Ok(PistonMeta {
+            manifest: serde_json::from_slice(&data)?,
+            cache_dir,
+        })
+    }
+
+    /// A meta index with only Minecraft in it.
+    pub fn index(&self) -> MetaIndex {
+        MetaIndex {
+            format_version: 1,
+            packages: vec![IndexPackage {
+                name: "Minecraft".to_string(),
+                // Not from the Prism meta, so there's nothing to check
+                sha256: String::new(),
+                uid: MINECRAFT_UID.to_string(),
+            }],
+        }
+    }
+
+    /// The Minecraft versions, with the latest release recommended.
+    pub fn package(&self) -> PackageIndex {
+        PackageIndex {
+            format_version: 1,
+            name: "Minecraft".to_string(),
+            uid: MINECRAFT_UID.to_string(),
+            versions: self
+                .manifest
+                .versions
+                .iter()
+                .map(|version| PackageVersion {
+                    recommended: version.id == self.manifest.latest.release,
+                    release_time: version.release_time,
+                    requires: vec![],
+                    sha256: String::new(),
+                    version_type: Some(version.version_type.clone()),
+                    version: version.id.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Minecraft `version`, converted from Mojang's version file.
+    pub async fn version(&self, version: &str) -> anyhow::Result<Version> {
+        let listed = self
+            .manifest
+            .versions
+            .iter()
+            .find(|listed| listed.id == version)
+            .ok_or_else(|| {
+                coded(
+                    ErrorCode::NotFound,
+                    format!("No version {} of Minecraft in Mojang's manifest", version),
+                )
+            })?;
+        let path = self.cache_dir.join(format!("{}.json", listed.id));
+        storage::get_file(
+            &path,
+            &listed.url,
+            false,
+            &Expected::sha1(Some(&listed.sha1)),
+        )
+        .await?;
+        let data = tokio::fs::read(&path).await?;
+        let version: MojangVersion = serde_json::from_slice(&data)?;
+        Ok(version.into_version())
+    }
+}
@@ -0,0 +1,225 @@
+//! Play session history and the summaries the playtime dashboard is built
+//! from. Sessions are appended to `sessions.jsonl` in the launcher dir, one
+//! JSON object per line.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime, UtcOffset};
+use tokio::io::AsyncWriteExt;
+
+use crate::{command_result, errors::CommandError, instance};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub instance_id: String,
+    /// Unix timestamps
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Session {
+    pub fn seconds(&self) -> u64 {
+        (self.end - self.start).max(0) as u64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstancePlaytime {
+    pub instance_id: String,
+    /// `None` if the instance has been deleted since
+    pub name: Option<String>,
+    pub seconds: u64,
+    pub sessions: u64,
+    pub last_played: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeriodPlaytime {
+    /// First day of the period, as `YYYY-MM-DD`
+    pub start: String,
+    pub seconds: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaytimeSummary {
+    pub total_seconds: u64,
+    pub sessions: u64,
+    pub longest_session: Option<Session>,
+    /// Most played first
+    pub instances: Vec<InstancePlaytime>,
+    /// Only days that were played on, oldest first
+    pub days: Vec<PeriodPlaytime>,
+    /// Weeks start on Monday
+    pub weeks: Vec<PeriodPlaytime>,
+}
+
+lazy_static::lazy_static! {
+    static ref LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+fn sessions_path(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    Ok(instance::launcher_dir(app_handle)?.join("sessions.jsonl"))
+}
+
+/// Appends a finished session to the history.
+pub async fn record_session(app_handle: &tauri::AppHandle, session: Session) {
+    let result = async {
+        let _lock = LOCK.lock().await;
+        let path = sessions_path(app_handle)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut line = serde_json::to_vec(&session)?;
+        line.push(b'\n');
+        tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?
+            .write_all(&line)
+            .await?;
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        warn!("Failed to record play session: {:#}", e);
+    }
+}
+
+/// Every recorded session, skipping lines that can't be parsed.
+pub async fn read_sessions(app_handle: &tauri::AppHandle) -> anyhow::Result<Vec<Session>> {
+    let data = match tokio::fs::read_to_string(sessions_path(app_handle)?).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(data
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                warn!("Skipping broken session {:?}: {}", line, e);
+                None
+            }
+        })
+        .collect())
+}
+
+fn local_date(timestamp: i64, offset: UtcOffset) -> anyhow::Result<Date> {
+    Ok(OffsetDateTime::from_unix_timestamp(timestamp)?
+        .to_offset(offset)
+        .date())
+}
+
+/// Splits a session at local midnights, so sessions past midnight count
+/// towards both days.
+fn add_days(
+    days: &mut BTreeMap<Date, u64>,
+    session: &Session,
+    offset: UtcOffset,
+) -> anyhow::Result<()> {
+    let mut start = session.start;
+    while start < session.end {
+        let date = local_date(start, offset)?;
+        let next_midnight = date
+            .next_day()
+            .map(|next| next.midnight().assume_offset(offset).unix_timestamp())
+            .unwrap_or(session.end);
+        let end = next_midnight.min(session.end);
+        *days.entry(date).or_default() += (end - start) as u64;
+        start = end;
+    }
+    Ok(())
+}
+
+pub fn summarize(
+    sessions: &[Session],
+    names: &HashMap<String, String>,
+    offset: UtcOffset,
+) -> anyhow::Result<PlaytimeSummary> {
+    let mut instances: HashMap<&str, InstancePlaytime> = HashMap::new();
+    let mut days = BTreeMap::new();
+    for session in sessions {
+        let entry = instances
+            .entry(&session.instance_id)
+            .or_insert_with(|| InstancePlaytime {
+                instance_id: session.instance_id.clone(),
+                name: names.get(&session.instance_id).cloned(),
+                seconds: 0,
+                sessions: 0,
+                last_played: session.end,
+            });
+        entry.seconds += session.seconds();
+        entry.sessions += 1;
+        entry.last_played = entry.last_played.max(session.end);
+        add_days(&mut days, session, offset)?;
+    }
+
+    let mut weeks: BTreeMap<Date, u64> = BTreeMap::new();
+    for (date, seconds) in &days {
+        let monday = *date - time::Duration::days(date.weekday().number_days_from_monday().into());
+        *weeks.entry(monday).or_default() += seconds;
+    }
+    let periods = |periods: BTreeMap<Date, u64>| {
+        periods
+            .into_iter()
+            .map(|(start, seconds)| PeriodPlaytime {
+                start: start.to_string(),
+                seconds,
+            })
+            .collect()
+    };
+    let mut instances: Vec<_> = instances.into_values().collect();
+    instances.sort_by_key(|instance| std::cmp::Reverse(instance.seconds));
+    Ok(PlaytimeSummary {
+        total_seconds: sessions.iter().map(Session::seconds).sum(),
+        sessions: sessions.len() as u64,
+        longest_session: sessions.iter().max_by_key(|s| s.seconds()).cloned(),
+        instances,
+        days: periods(days),
+        weeks: periods(weeks),
+    })
+}
+
+/// Summarizes the sessions that started between `from` and `to` (unix
+/// timestamps, both optional). Days and weeks are split in the frontend's
+/// time zone, given as its offset from UTC in seconds.
+#[tauri::command]
+pub async fn get_playtime_summary(
+    app_handle: tauri::AppHandle,
+    from: Option<i64>,
+    to: Option<i64>,
+    utc_offset: i32,
+) -> Result<PlaytimeSummary, CommandError> {
+    command_result(
+        async {
+            let sessions: Vec<_> = read_sessions(&app_handle)
+                .await?
+                .into_iter()
+                .filter(|session| from.is_none_or(|from| session.start >= from))
+                .filter(|session| to.is_none_or(|to| session.start < to))
+                .collect();
+            let names = instance::list_instances(&app_handle)
+                .await?
+                .into_iter()
+                .map(|entry| (entry.id, entry.config.name))
+                .collect();
+            summarize(
+                &sessions,
+                &names,
+                UtcOffset::from_whole_seconds(utc_offset)?,
+            )
+        }
+        .await,
+    )
+}
@@ -0,0 +1,214 @@
+//! Rhai plugins loaded from the `plugins` directory.
+//!
+//! Each plugin is a directory containing a `plugin.json` manifest and a
+//! script. Scripts can define hook functions taking a single map argument:
+//!
+//! - `on_launch(event)`: `#{ instanceId, config }` when an instance starts
+//! - `on_instance_created(event)`: `#{ instanceId, config }`
+//! - `on_download(event)`: `#{ url, path }` after a file was downloaded
+//!
+//! Commands listed in the manifest are run through `run_plugin_command` and
+//! call the script function `command_<name>(args)`.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{command_result, errors::CommandError, instance};
+
+/// Bumped when a hook or script API changes incompatibly.
+pub const PLUGIN_API_VERSION: u32 = 1;
+/// Keeps a runaway script from hanging the thread it runs on
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginManifest {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub api_version: u32,
+    /// Script file, relative to the plugin directory
+    #[serde(default = "default_main")]
+    pub main: String,
+    #[serde(default)]
+    pub commands: Vec<String>,
+}
+
+fn default_main() -> String {
+    "main.rhai".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    Launch,
+    InstanceCreated,
+    Download,
+}
+
+impl Hook {
+    fn function_name(self) -> &'static str {
+        match self {
+            Hook::Launch => "on_launch",
+            Hook::InstanceCreated => "on_instance_created",
+            Hook::Download => "on_download",
+        }
+    }
+}
+
+struct LoadedPlugin {
+    manifest: PluginManifest,
+    ast: rhai::AST,
+}
+
+impl LoadedPlugin {
+    fn has_function(&self, name: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == name && function.params.len() == 1)
+    }
+
+    fn call(&self, name: &str, argument: &impl Serialize) -> anyhow::Result<serde_json::Value> {
+        let argument = rhai::serde::to_dynamic(argument).map_err(|e| anyhow!("{}", e))?;
+        let result: rhai::Dynamic = ENGINE
+            .call_fn(&mut rhai::Scope::new(), &self.ast, name, (argument,))
+            .map_err(|e| anyhow!("{} in plugin {}: {}", name, self.manifest.id, e))?;
+        rhai::serde::from_dynamic(&result).map_err(|e| anyhow!("{}", e))
+    }
+}
+
+fn create_engine() -> rhai::Engine {
+    let mut engine = rhai::Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.register_fn("log", |message: &str| info!("[plugin] {}", message));
+    engine.register_fn("launcher_version", || env!("CARGO_PKG_VERSION").to_string());
+    engine
+}
+
+lazy_static::lazy_static! {
+    static ref ENGINE: rhai::Engine = create_engine();
+    static ref PLUGINS: RwLock<Vec<Arc<LoadedPlugin>>> = RwLock::new(vec![]);
+}
+
+pub fn plugins_dir(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    Ok(instance::launcher_dir(app_handle)?.join("plugins"))
+}
+
+fn load_plugin(dir: PathBuf) -> anyhow::Result<LoadedPlugin> {
+    let manifest: PluginManifest =
+        serde_json::from_slice(&std::fs::read(dir.join("plugin.json"))?)?;
+    if manifest.api_version != PLUGIN_API_VERSION {
+        return Err(anyhow!(
+            "{} needs plugin API {}, this launcher has {}",
+            manifest.id,
+            manifest.api_version,
+            PLUGIN_API_VERSION
+        ));
+    }
+    let ast = ENGINE
+        .compile_file(instance::safe_join(&dir, &manifest.main)?)
+        .map_err(|e| anyhow!("Can't compile {}: {}", manifest.id, e))?;
+    Ok(LoadedPlugin { manifest, ast })
+}
+
+/// (Re)loads every plugin, skipping ones that fail to load.
+pub fn load(app_handle: &tauri::AppHandle) -> anyhow::Result<()> {
+    let mut plugins = vec![];
+    let entries = match std::fs::read_dir(plugins_dir(app_handle)?) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            *PLUGINS.write().unwrap() = plugins;
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let dir = entry?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        match load_plugin(dir.clone()) {
+            Ok(plugin) => {
+                info!(
+                    "Loaded plugin {} {}",
+                    plugin.manifest.id, plugin.manifest.version
+                );
+                plugins.push(Arc::new(plugin));
+            }
+            Err(e) => warn!("Skipping plugin in {:?}: {:#}", dir, e),
+        }
+    }
+    *PLUGINS.write().unwrap() = plugins;
+    Ok(())
+}
+
+/// Runs `hook` in every plugin that defines it, in the background.
+pub fn run_hook(hook: Hook, event: serde_json::Value) {
+    let plugins: Vec<_> = PLUGINS
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|plugin| plugin.has_function(hook.function_name()))
+        .cloned()
+        .collect();
+    if plugins.is_empty() {
+        return;
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        for plugin in plugins {
+            if let Err(e) = plugin.call(hook.function_name(), &event) {
+                warn!("{:#}", e);
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub fn list_plugins() -> Vec<PluginManifest> {
+    PLUGINS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|plugin| plugin.manifest.clone())
+        .collect()
+}
+
+#[tauri::command]
+pub fn reload_plugins(app_handle: tauri::AppHandle) -> Result<Vec<PluginManifest>, CommandError> {
+    command_result(load(&app_handle))?;
+    Ok(list_plugins())
+}
+
+/// Runs a command a plugin declared in its manifest, returning whatever the
+/// script returns.
+#[tauri::command]
+pub async fn run_plugin_command(
+    plugin_id: String,
+    command: String,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, CommandError> {
+    command_result(
+        async {
+            let plugin = PLUGINS
+                .read()
+                .unwrap()
+                .iter()
+                .find(|plugin| plugin.manifest.id == plugin_id)
+                .cloned()
+                .ok_or(anyhow!("No plugin {}", plugin_id))?;
+            if !plugin.manifest.commands.contains(&command) {
+                return Err(anyhow!("{} has no command {}", plugin_id, command));
+            }
+            tokio::task::spawn_blocking(move || plugin.call(&format!("command_{}", command), &args))
+                .await?
+        }
+        .await,
+    )
+}
@@ -1,12 +1,34 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use futures::{stream, StreamExt};
+use log::{error, trace, warn};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use tauri::api::http::{ClientBuilder, HttpRequestBuilder, ResponseType};
+use sha2::{Digest, Sha256};
+use tauri::Manager;
 use time::OffsetDateTime;
 
+use crate::{
+    command_result,
+    downloads::{self, DownloadJob},
+    errors::{coded, coded_message, CommandError, ErrorCode},
+    instance,
+    messages::Message,
+    mirrors,
+    network::{self, QueuedOperation},
+    piston_meta::PistonMeta,
+    proxy, settings,
+    storage::Expected,
+    tasks::{self, TaskKind},
+};
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MetaIndex {
     pub format_version: u8,
     pub packages: Vec<IndexPackage>,
@@ -34,6 +56,7 @@ pub struct PackageVersion {
     pub recommended: bool,
     #[serde(with = "time::serde::iso8601")]
     pub release_time: OffsetDateTime,
+    #[serde(default)]
     pub requires: Vec<Dependency>,
     pub sha256: String,
     #[serde(rename = "type")]
@@ -65,12 +88,21 @@ pub struct Version {
     pub jvm_args: Option<Vec<String>>,
     pub jar_mods: Option<Vec<Library>>,
     pub libraries: Option<Vec<Library>>,
+    /// Libraries added on top of the ones from components with a lower
+    /// `order`, in patches written for MultiMC
+    #[serde(rename = "+libraries")]
+    pub add_libraries: Option<Vec<Library>>,
     pub maven_files: Option<Vec<Library>>,
     pub main_jar: Option<Library>,
+    #[serde(default)]
     pub requires: Vec<Dependency>,
+    #[serde(default)]
     pub conflicts: Vec<Dependency>,
+    #[serde(default)]
     pub volatile: bool,
-    pub asset_index: AssetIndex,
+    /// Only set by Minecraft itself
+    pub asset_index: Option<AssetIndex>,
+    #[serde(default)]
     pub compatible_java_majors: Vec<u32>,
     pub main_class: Option<String>,
     pub minecraft_arguments: Option<String>,
@@ -86,12 +118,81 @@ pub struct Library {
     downloads: Option<LibraryDownloads>,
     #[serde(rename = "MMC-hint")]
     hint: Option<LibraryHint>,
+    /// File name of a local library, if not the one from its name
+    #[serde(rename = "MMC-filename")]
+    filename: Option<String>,
+}
+
+impl Library {
+    /// A library from the Minecraft libraries server.
+    pub(crate) fn from_name(name: &str) -> Library {
+        Library {
+            name: name.to_string(),
+            url: None,
+            extract: None,
+            natives: None,
+            rules: None,
+            downloads: None,
+            hint: None,
+            filename: None,
+        }
+    }
+
+    /// A library downloaded from where `artifact` says.
+    pub(crate) fn with_artifact(name: String, artifact: Download) -> Library {
+        Library {
+            name,
+            url: None,
+            extract: None,
+            natives: None,
+            rules: None,
+            downloads: Some(LibraryDownloads {
+                artifact: Some(artifact),
+                classifiers: None,
+            }),
+            hint: None,
+            filename: None,
+        }
+    }
+
+    /// The Maven coordinates without the version, which identify the
+    /// library across versions.
+    pub fn artifact_id(&self) -> String {
+        match LIBRARY_NAME_REGEX.captures(&self.name) {
+            Some(caps) => [
+                caps.name("group"),
+                caps.name("name"),
+                caps.name("classifier"),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|mat| mat.as_str())
+            .collect::<Vec<_>>()
+            .join(":"),
+            None => self.name.clone(),
+        }
+    }
+
+    /// The name of the file of a local library in its directory in the
+    /// instance. `None` for libraries that are downloaded.
+    pub fn local_file_name(&self) -> Option<String> {
+        if self.hint != Some(LibraryHint::Local) {
+            return None;
+        }
+        self.filename.clone().or_else(|| {
+            name_to_path(&self.name, None)
+                .and_then(|path| path.rsplit('/').next().map(str::to_string))
+        })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum LibraryHint {
     AlwaysStale,
+    /// Kept in the instance instead of being downloaded, like jar mods the
+    /// user added
+    Local,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -114,8 +215,10 @@ pub enum LibraryRuleAction {
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LibraryRuleOs {
-    name: String,
+    /// Either just the OS, or the OS and architecture like `osx-arm64`
+    name: Option<String>,
     version: Option<String>,
+    arch: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -134,6 +237,31 @@ pub struct AssetIndex {
     url: String,
 }
 
+impl AssetIndex {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Hex SHA-1 of the index file
+    pub fn sha1(&self) -> &str {
+        &self.sha1
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// What the index file should be like.
+    pub fn expected(&self) -> Expected {
+        Expected::sha1(Some(&self.sha1)).with_size(Some(self.size))
+    }
+
+    /// Size of all the assets together, in bytes
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Download {
@@ -142,45 +270,564 @@ pub struct Download {
     url: String,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Newest `formatVersion` of meta files this launcher understands
+const SUPPORTED_FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FormatVersion {
+    format_version: u8,
+}
+
+/// Parses a meta file, failing with [`ErrorCode::UpdateRequired`] for
+/// files in a newer format instead of whatever error parsing them gives.
+pub(crate) fn parse_meta<T: serde::de::DeserializeOwned>(data: &[u8]) -> anyhow::Result<T> {
+    if let Ok(FormatVersion { format_version }) = serde_json::from_slice(data) {
+        if format_version > SUPPORTED_FORMAT_VERSION {
+            return Err(coded_message(
+                ErrorCode::UpdateRequired,
+                Message::new("meta.formatTooNew"),
+            )
+            .context(format!(
+                "Meta format {} is newer than {}",
+                format_version, SUPPORTED_FORMAT_VERSION
+            )));
+        }
+    }
+    Ok(serde_json::from_slice(data)?)
+}
+
+/// What a meta server said identifies the version of a file, for
+/// conditional requests. Kept next to the cached file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Validators {
+    /// Only valid for requests to the same URL
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// The cached file at `path` with its validators, if it has both.
+async fn read_cached_with_validators(
+    path: &Path,
+    validators_path: &Path,
+) -> Option<(Vec<u8>, Validators)> {
+    let validators = serde_json::from_slice(&tokio::fs::read(validators_path).await.ok()?).ok()?;
+    let data = tokio::fs::read(path).await.ok()?;
+    Some((data, validators))
+}
+
+/// Where meta files come from and are cached.
+#[derive(Debug, Clone)]
+struct MetaServer {
+    client: reqwest::Client,
+    /// Ends in a slash
+    base_url: String,
+    cache_dir: PathBuf,
+}
+
+impl MetaServer {
+    fn new(app_handle: &tauri::AppHandle) -> anyhow::Result<MetaServer> {
+        Ok(MetaServer {
+            client: proxy::client(),
+            base_url: meta_url(app_handle),
+            cache_dir: meta_cache_dir(app_handle)?,
+        })
+    }
+
+    /// Downloads `path` below the base URL and caches it, returning the raw
+    /// file. With `sha256`, a file that doesn't match isn't cached and fails.
+    ///
+    /// Without `sha256`, the request is conditional on the cached file
+    /// having changed, so unchanged files cost a 304.
+    async fn download(&self, path: &str, sha256: Option<&str>) -> anyhow::Result<Vec<u8>> {
+        let url = format!("{}{}", self.base_url, path);
+        let cache_path = self.cache_dir.join(path);
+        let validators_path = cache_path.with_extension("json.validators");
+        let cached = match sha256 {
+            Some(_) => None,
+            None => read_cached_with_validators(&cache_path, &validators_path).await,
+        };
+        let cached = &cached;
+        let fetched = mirrors::with_fallback(&url, |url| async move {
+            let mut request = self.client.get(&url);
+            // Validators are only good for the URL they came from
+            let cached = cached
+                .as_ref()
+                .filter(|(_, validators)| validators.url == url);
+            if let Some((_, validators)) = cached {
+                if let Some(etag) = &validators.etag {
+                    request = request.header("If-None-Match", etag);
+                }
+                if let Some(last_modified) = &validators.last_modified {
+                    request = request.header("If-Modified-Since", last_modified);
+                }
+            }
+            let resp = request.send().await?;
+            match cached {
+                Some((data, _)) if resp.status() == reqwest::StatusCode::NOT_MODIFIED => {
+                    trace!("{} didn't change", url);
+                    Ok((data.clone(), None))
+                }
+                _ => self.read_response(&url, resp, sha256).await,
+            }
+        })
+        .await?;
+        let (data, validators) = fetched;
+        let validators = match validators {
+            Some(validators) => validators,
+            // Not modified, the cache is up to date already
+            None => return Ok(data),
+        };
+        if let Some(parent) = cache_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = cache_path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, &data).await?;
+        tokio::fs::rename(&tmp, &cache_path).await?;
+        if validators.etag.is_some() || validators.last_modified.is_some() {
+            tokio::fs::write(&validators_path, serde_json::to_vec(&validators)?).await?;
+        } else if let Err(e) = tokio::fs::remove_file(&validators_path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        Ok(data)
+    }
+
+    /// Reads a full response to a meta request, with the validators that
+    /// came with it.
+    async fn read_response(
+        &self,
+        url: &str,
+        resp: reqwest::Response,
+        sha256: Option<&str>,
+    ) -> anyhow::Result<(Vec<u8>, Option<Validators>)> {
+        let header = |name: &str| {
+            resp.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = Validators {
+            url: url.to_string(),
+            etag: header("ETag"),
+            last_modified: header("Last-Modified"),
+        };
+        if resp.status() != reqwest::StatusCode::OK {
+            return Err(coded(
+                ErrorCode::DownloadFailed,
+                format!("Downloading {} failed with {}", url, resp.status()),
+            ));
+        }
+        let data = resp.bytes().await?.to_vec();
+        if let Some(sha256) = sha256 {
+            let hash = hex::encode(Sha256::digest(&data));
+            if !hash.eq_ignore_ascii_case(sha256) {
+                return Err(coded(
+                    ErrorCode::DownloadHashMismatch,
+                    format!("{} has hash {} instead of {}", url, hash, sha256),
+                ));
+            }
+        }
+        Ok((data, Some(validators)))
+    }
+
+    /// Like [`download`](Self::download) with a hash, trying once more if
+    /// what arrived doesn't match, in case it was cut off on the way.
+    async fn download_verified(&self, path: &str, sha256: &str) -> anyhow::Result<Vec<u8>> {
+        match self.download(path, Some(sha256)).await {
+            Err(e) if ErrorCode::of(&e) == ErrorCode::DownloadHashMismatch => {
+                warn!("{:#}, downloading it again", e);
+                self.download(path, Some(sha256)).await
+            }
+            result => result,
+        }
+    }
+
+    /// The cached file at `path`, if it has the hash `sha256`.
+    async fn cached(&self, path: &str, sha256: &str) -> Option<Vec<u8>> {
+        let data = tokio::fs::read(self.cache_dir.join(path)).await.ok()?;
+        hex::encode(Sha256::digest(&data))
+            .eq_ignore_ascii_case(sha256)
+            .then_some(data)
+    }
+
+    /// The index of `package`, from the cache if it's still the version the
+    /// meta index lists. Returns whether it had to be downloaded with it.
+    async fn package(&self, package: &IndexPackage) -> anyhow::Result<(PackageIndex, bool)> {
+        let path = format!("{}/index.json", package.uid);
+        if let Some(cached) = self
+            .cached(&path, &package.sha256)
+            .await
+            .and_then(|data| serde_json::from_slice(&data).ok())
+        {
+            return Ok((cached, false));
+        }
+        let data = self.download_verified(&path, &package.sha256).await?;
+        Ok((parse_meta(&data)?, true))
+    }
+
+    /// The index from the last time it was downloaded.
+    async fn cached_index(&self) -> Option<MetaIndex> {
+        let data = tokio::fs::read(self.cache_dir.join("index.json"))
+            .await
+            .ok()?;
+        parse_meta(&data).ok()
+    }
+
+    async fn index(&self) -> anyhow::Result<MetaIndex> {
+        let data = self.download("index.json", None).await?;
+        match parse_meta(&data) {
+            Err(e) if ErrorCode::of(&e) != ErrorCode::UpdateRequired => Err(coded(
+                ErrorCode::InvalidData,
+                format!("{} isn't a meta server: {:#}", self.base_url, e),
+            )),
+            result => result,
+        }
+    }
+}
+
+/// The meta index. The package indexes it lists are loaded when they're
+/// first asked for, clones share the ones that were.
+#[derive(Debug, Clone)]
 pub struct DownloadedMetaIndex {
     pub index: MetaIndex,
-    pub packages: HashMap<String, PackageIndex>,
+    server: MetaServer,
+    /// Set when the meta server was down, with only Minecraft available
+    piston: Option<Arc<PistonMeta>>,
+    concurrency: usize,
+    packages: Arc<Mutex<HashMap<String, Arc<PackageIndex>>>>,
 }
 
-const META_API_BASE: &str = "https://meta.prismlauncher.org/v1/";
+impl DownloadedMetaIndex {
+    /// The index of package `uid`, from the cache if it's up to date and
+    /// downloaded otherwise.
+    pub async fn package(&self, uid: &str) -> anyhow::Result<Arc<PackageIndex>> {
+        if let Some(loaded) = self.packages.lock().unwrap().get(uid) {
+            return Ok(loaded.clone());
+        }
+        let package = self
+            .index
+            .packages
+            .iter()
+            .find(|package| package.uid == uid)
+            .ok_or_else(|| {
+                coded(
+                    ErrorCode::NotFound,
+                    format!("No package {} in the meta", uid),
+                )
+            })?;
+        if let Some(piston) = &self.piston {
+            let loaded = Arc::new(piston.package());
+            self.packages
+                .lock()
+                .unwrap()
+                .insert(uid.to_string(), loaded.clone());
+            return Ok(loaded);
+        }
+        let (loaded, downloaded) = self.server.package(package).await?;
+        trace!(
+            "Loaded package {} from {}",
+            uid,
+            if downloaded {
+                "the meta server"
+            } else {
+                "the cache"
+            }
+        );
+        let loaded = Arc::new(loaded);
+        self.packages
+            .lock()
+            .unwrap()
+            .insert(uid.to_string(), loaded.clone());
+        Ok(loaded)
+    }
 
-pub async fn fetch_meta() -> anyhow::Result<DownloadedMetaIndex> {
-    let client = ClientBuilder::new().build()?;
-    let index = client
-        .send(
-            HttpRequestBuilder::new("GET", format!("{}index.json", META_API_BASE))?
-                .response_type(ResponseType::Json),
-        )
-        .await?
-        .read()
-        .await?;
-    let index: MetaIndex = serde_json::from_value(index.data)?;
-
-    let mut packages = HashMap::new();
-
-    for package in &index.packages {
-        let downloaded_package = client
-            .send(
-                HttpRequestBuilder::new(
-                    "GET",
-                    format!("{}{}/index.json", META_API_BASE, package.uid),
-                )?
-                .response_type(ResponseType::Json),
-            )
-            .await?
-            .read()
-            .await?;
-        let downloaded_package: PackageIndex = serde_json::from_value(downloaded_package.data)?;
-        packages.insert(package.uid.clone(), downloaded_package);
+    /// Version `version` of package `uid`, from the cache if it's up to date
+    /// and downloaded otherwise. The file is checked against the hash in the
+    /// package index either way.
+    pub async fn fetch_version(&self, uid: &str, version: &str) -> anyhow::Result<Version> {
+        if let Some(piston) = &self.piston {
+            // Only has Minecraft, the package lookup fails for anything else
+            self.package(uid).await?;
+            return piston.version(version).await;
+        }
+        let package = self.package(uid).await?;
+        let listed = package
+            .versions
+            .iter()
+            .find(|listed| listed.version == version)
+            .ok_or_else(|| {
+                coded(
+                    ErrorCode::NotFound,
+                    format!("No version {} of {} in the meta", version, uid),
+                )
+            })?;
+        let path = format!("{}/{}.json", uid, version);
+        let data = match self.server.cached(&path, &listed.sha256).await {
+            Some(data) => data,
+            None => self.server.download_verified(&path, &listed.sha256).await?,
+        };
+        parse_meta(&data)
+    }
+}
+
+pub const DEFAULT_META_URL: &str = "https://meta.prismlauncher.org/v1/";
+
+fn with_trailing_slash(url: String) -> String {
+    if url.ends_with('/') {
+        url
+    } else {
+        format!("{}/", url)
     }
+}
+
+/// The meta server from the settings, with a trailing slash.
+pub(crate) fn meta_url(app_handle: &tauri::AppHandle) -> String {
+    with_trailing_slash(
+        settings::current(app_handle)
+            .meta_url
+            .unwrap_or_else(|| DEFAULT_META_URL.to_string()),
+    )
+}
 
-    Ok(DownloadedMetaIndex { index, packages })
+/// Where the meta files are cached, laid out like on the meta server
+fn meta_cache_dir(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    Ok(instance::launcher_dir(app_handle)?.join("meta"))
+}
+
+/// Downloads the meta index, as a task. Package indexes are loaded from it
+/// on demand. While offline, the cached index is used and a refresh is
+/// queued, so installed instances can still be launched.
+///
+/// If the meta server is down, Mojang's version manifest stands in for it,
+/// so vanilla instances can still be installed.
+pub async fn fetch_meta(app_handle: &tauri::AppHandle) -> anyhow::Result<DownloadedMetaIndex> {
+    let server = MetaServer::new(app_handle)?;
+    let concurrency = settings::current(app_handle).max_concurrent_downloads;
+    if !network::is_online(app_handle) {
+        let deferred = network::defer(app_handle, QueuedOperation::MetaSync);
+        // Packages and versions come from the cache as long as they are
+        // the ones this index lists
+        let index = server.cached_index().await.ok_or(deferred)?;
+        return Ok(DownloadedMetaIndex {
+            index,
+            server,
+            piston: None,
+            concurrency,
+            packages: Arc::default(),
+        });
+    }
+    tasks::run(
+        app_handle,
+        TaskKind::MetaSync,
+        Message::new("task.metaSync"),
+        |_| async move {
+            let (index, piston) = match server.index().await {
+                Ok(index) => (index, None),
+                Err(e) => {
+                    warn!("Meta server failed, using Mojang's manifest: {:#}", e);
+                    let piston = PistonMeta::fetch(server.cache_dir.join("piston"))
+                        .await
+                        // What's wrong with the meta server is more useful
+                        .map_err(|piston_error| {
+                            warn!("Mojang's manifest failed as well: {:#}", piston_error);
+                            e
+                        })?;
+                    (piston.index(), Some(Arc::new(piston)))
+                }
+            };
+            Ok(DownloadedMetaIndex {
+                index,
+                server,
+                piston,
+                concurrency,
+                packages: Arc::default(),
+            })
+        },
+    )
+    .await
+}
+
+/// Checks that `url` is a meta server before it goes into the settings.
+/// Returns how many packages it has.
+#[tauri::command]
+pub async fn check_meta_url(
+    app_handle: tauri::AppHandle,
+    url: String,
+) -> Result<usize, CommandError> {
+    command_result(
+        async {
+            let server = MetaServer {
+                base_url: with_trailing_slash(url),
+                ..MetaServer::new(&app_handle)?
+            };
+            Ok(server.index().await?.packages.len())
+        }
+        .await,
+    )
+}
+
+pub const MINECRAFT_UID: &str = "net.minecraft";
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Which versions `list_versions` returns. Everything by default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct VersionFilter {
+    /// Package to list, Minecraft if not set
+    pub uid: Option<String>,
+    /// `release`, `snapshot`, `old_beta`, `old_alpha`, any if empty
+    pub types: Vec<String>,
+    pub recommended_only: bool,
+    /// Part of the version, case insensitive
+    pub search: Option<String>,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionEntry {
+    pub version: String,
+    pub version_type: Option<String>,
+    pub recommended: bool,
+    /// Unix timestamp
+    pub release_time: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionPage {
+    pub versions: Vec<VersionEntry>,
+    /// How many versions match the filter, across all pages
+    pub total: usize,
+}
+
+fn filter_versions(package: &PackageIndex, filter: &VersionFilter) -> VersionPage {
+    let search = filter.search.as_deref().map(str::to_lowercase);
+    let mut matching: Vec<&PackageVersion> = package
+        .versions
+        .iter()
+        .filter(|version| {
+            filter.types.is_empty()
+                || version
+                    .version_type
+                    .as_ref()
+                    .is_some_and(|version_type| filter.types.contains(version_type))
+        })
+        .filter(|version| !filter.recommended_only || version.recommended)
+        .filter(|version| {
+            search
+                .as_deref()
+                .is_none_or(|search| version.version.to_lowercase().contains(search))
+        })
+        .collect();
+    matching.sort_by_key(|version| std::cmp::Reverse(version.release_time));
+    VersionPage {
+        total: matching.len(),
+        versions: matching
+            .into_iter()
+            .skip(filter.offset)
+            .take(filter.limit.unwrap_or(DEFAULT_PAGE_SIZE))
+            .map(|version| VersionEntry {
+                version: version.version.clone(),
+                version_type: version.version_type.clone(),
+                recommended: version.recommended,
+                release_time: version.release_time.unix_timestamp(),
+            })
+            .collect(),
+    }
+}
+
+/// A page of the versions of a package, newest first.
+#[tauri::command]
+pub async fn list_versions(
+    app_handle: tauri::AppHandle,
+    filter: Option<VersionFilter>,
+) -> Result<VersionPage, CommandError> {
+    command_result(
+        async {
+            let filter = filter.unwrap_or_default();
+            let meta = fetch_meta(&app_handle).await?;
+            let package = meta
+                .package(filter.uid.as_deref().unwrap_or(MINECRAFT_UID))
+                .await?;
+            Ok(filter_versions(&package, &filter))
+        }
+        .await,
+    )
+}
+
+/// The versions of one package, as `meta:versions` events.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageVersions {
+    pub uid: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub page: VersionPage,
+}
+
+fn emit_versions(app_handle: &tauri::AppHandle, package: &PackageIndex, filter: &VersionFilter) {
+    let event = PackageVersions {
+        uid: package.uid.clone(),
+        name: package.name.clone(),
+        page: filter_versions(package, filter),
+    };
+    if let Err(e) = app_handle.emit_all("meta:versions", event) {
+        error!("Failed to emit versions of {}: {}", package.uid, e);
+    }
+}
+
+/// Emits the versions of each package in `uids`, or of all packages, as a
+/// `meta:versions` event as soon as its index is loaded, so the version
+/// picker fills in without waiting for the slowest one. Minecraft comes
+/// first. The `uid` of `filter` is ignored.
+///
+/// Packages other than Minecraft that fail to load are skipped.
+#[tauri::command]
+pub async fn stream_versions(
+    app_handle: tauri::AppHandle,
+    uids: Option<Vec<String>>,
+    filter: Option<VersionFilter>,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            let filter = filter.unwrap_or_default();
+            let meta = fetch_meta(&app_handle).await?;
+            let mut uids = uids.unwrap_or_else(|| {
+                meta.index
+                    .packages
+                    .iter()
+                    .map(|package| package.uid.clone())
+                    .collect()
+            });
+            if let Some(i) = uids.iter().position(|uid| uid == MINECRAFT_UID) {
+                let uid = uids.remove(i);
+                emit_versions(&app_handle, &*meta.package(&uid).await?, &filter);
+            }
+            let mut loaded = stream::iter(uids)
+                .map(|uid| {
+                    let meta = meta.clone();
+                    async move {
+                        let package = meta.package(&uid).await;
+                        (uid, package)
+                    }
+                })
+                .buffer_unordered(meta.concurrency);
+            while let Some((uid, package)) = loaded.next().await {
+                match package {
+                    Ok(package) => emit_versions(&app_handle, &package, &filter),
+                    Err(e) => warn!("Skipping versions of {}: {:#}", uid, e),
+                }
+            }
+            Ok(())
+        }
+        .await,
+    )
 }
 
 const LIBRARY_BASE_URL: &str = "https://libraries.minecraft.net/";
@@ -239,6 +886,19 @@ fn cur_os() -> &'static str {
     }
 }
 
+fn is_arm() -> bool {
+    matches!(cur_arch(), "arm64" | "arm32")
+}
+
+/// What `${arch}` in natives classifiers stands for
+fn arch_bits() -> &'static str {
+    if cfg!(target_pointer_width = "64") {
+        "64"
+    } else {
+        "32"
+    }
+}
+
 fn os_arch() -> String {
     if cur_arch() == "x86" || cur_arch() == "x86_64" {
         cur_os().to_string()
@@ -247,79 +907,288 @@ fn os_arch() -> String {
     }
 }
 
-pub async fn download_library(
+async fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The OS version like Java's `os.version` has it, which library rules are
+/// written against: the kernel release on Linux, e.g. `10.5.8` on macOS and
+/// e.g. `10.0` on Windows.
+async fn os_version() -> Option<String> {
+    if cfg!(target_os = "linux") {
+        let release = tokio::fs::read_to_string("/proc/sys/kernel/osrelease")
+            .await
+            .ok()?;
+        Some(release.trim().to_string())
+    } else if cfg!(target_os = "macos") {
+        command_output("sw_vers", &["-productVersion"]).await
+    } else if cfg!(windows) {
+        // "Microsoft Windows [Version 10.0.19045.2965]"
+        let ver = command_output("cmd", &["/C", "ver"]).await?;
+        let version = ver.split("Version ").nth(1)?.trim_end_matches(']');
+        Some(version.split('.').take(2).collect::<Vec<_>>().join("."))
+    } else {
+        None
+    }
+}
+
+/// [`os_version`], only looked up once.
+async fn cached_os_version() -> Option<&'static str> {
+    static OS_VERSION: tokio::sync::OnceCell<Option<String>> = tokio::sync::OnceCell::const_new();
+    OS_VERSION.get_or_init(os_version).await.as_deref()
+}
+
+/// Whether a rule for `os` is about this system. Rules for just the OS
+/// apply to all architectures. Its `version` is a regex the whole OS
+/// version has to match.
+fn os_matches(os: &LibraryRuleOs, os_version: Option<&str>) -> bool {
+    let name_matches = os
+        .name
+        .as_deref()
+        .is_none_or(|name| name == os_arch() || name == cur_os());
+    if !name_matches || os.arch.as_deref().is_some_and(|arch| arch != cur_arch()) {
+        return false;
+    }
+    let pattern = match &os.version {
+        Some(pattern) => pattern,
+        None => return true,
+    };
+    let os_version = match os_version {
+        Some(os_version) => os_version,
+        None => {
+            warn!("Unknown OS version, skipping rule for {}", pattern);
+            return false;
+        }
+    };
+    match Regex::new(&format!("^(?:{})$", pattern)) {
+        Ok(regex) => regex.is_match(os_version),
+        Err(e) => {
+            warn!("Invalid OS version pattern {}: {}", pattern, e);
+            false
+        }
+    }
+}
+
+impl Library {
+    /// The classifier of the natives for this system, if the library has
+    /// any.
+    ///
+    /// Libraries without natives for this architecture may still have a
+    /// classifier for it like `natives-macos-arm64`, which is used over the
+    /// natives for the OS on other architectures.
+    fn native_classifier(&self) -> Option<String> {
+        let natives = self.natives.as_ref()?;
+        if let Some(classifier) = natives.get(&os_arch()) {
+            return Some(classifier.replace("${arch}", arch_bits()));
+        }
+        let classifier = natives.get(cur_os())?.replace("${arch}", arch_bits());
+        let classifiers = self
+            .downloads
+            .as_ref()
+            .and_then(|downloads| downloads.classifiers.as_ref());
+        if let (true, Some(classifiers)) = (is_arm(), classifiers) {
+            // LWJGL calls macOS `macos` in classifiers, but `osx` elsewhere
+            let arch_specific = [
+                format!("{}-{}", classifier, cur_arch()),
+                format!("{}-{}", classifier.replace("osx", "macos"), cur_arch()),
+            ];
+            if let Some(found) = arch_specific
+                .into_iter()
+                .find(|candidate| classifiers.contains_key(candidate))
+            {
+                return Some(found);
+            }
+        }
+        Some(classifier)
+    }
+}
+
+/// Whether `name` in a natives jar matches an `exclude` pattern: a path
+/// prefix like `META-INF/`, or a glob with `*`.
+fn excluded(name: &str, exclude: &[String]) -> bool {
+    exclude.iter().any(|pattern| {
+        if !pattern.contains('*') {
+            return name.starts_with(pattern.as_str());
+        }
+        let regex = pattern
+            .split('*')
+            .map(regex::escape)
+            .collect::<Vec<_>>()
+            .join(".*");
+        Regex::new(&format!("^{}", regex)).is_ok_and(|regex| regex.is_match(name))
+    })
+}
+
+/// Unzips the natives jar at `jar` into `natives_dir`, skipping what
+/// `extract` excludes. Returns how many files were extracted.
+fn extract_native_jar(
+    jar: &Path,
+    natives_dir: &Path,
+    extract: Option<&ExtractOptions>,
+) -> anyhow::Result<usize> {
+    let exclude = extract.map_or(&[][..], |extract| &extract.exclude[..]);
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(jar)?)?;
+    let mut count = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() || excluded(entry.name(), exclude) {
+            continue;
+        }
+        let path = instance::safe_join(natives_dir, entry.name())?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::io::copy(&mut entry, &mut std::fs::File::create(&path)?)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Extracts the natives of `libraries`, downloaded below `base_path`, into
+/// `natives_dir`, replacing whatever was there. The game can't start
+/// without them.
+pub async fn extract_natives(
     base_path: PathBuf,
-    library: Library,
-) -> anyhow::Result<Vec<PathBuf>> {
-    if let Some(rules) = library.rules {
+    libraries: Vec<Library>,
+    natives_dir: PathBuf,
+) -> anyhow::Result<usize> {
+    tokio::task::spawn_blocking(move || {
+        match std::fs::remove_dir_all(&natives_dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        std::fs::create_dir_all(&natives_dir)?;
+        let mut count = 0;
+        for library in &libraries {
+            let classifier = match library.native_classifier() {
+                Some(classifier) => classifier,
+                None => continue,
+            };
+            let classifier = classifier.as_str();
+            let jar = base_path.join(
+                name_to_path(&library.name, Some(classifier))
+                    .ok_or(anyhow!("Can't get path from name"))?,
+            );
+            count += extract_native_jar(&jar, &natives_dir, library.extract.as_ref())
+                .with_context(|| format!("Failed to extract natives of {}", library.name))?;
+        }
+        Ok(count)
+    })
+    .await?
+}
+
+impl Library {
+    /// Whether the rules of the library leave it in on this system.
+    pub async fn applies(&self) -> bool {
+        let Some(rules) = &self.rules else {
+            return true;
+        };
+        let os_version = cached_os_version().await;
         let mut allowed = false;
         for rule in rules {
-            if let Some(os) = rule.os {
-                if os.name == os_arch() {
-                    allowed = match rule.action {
-                        LibraryRuleAction::Allow => true,
-                        LibraryRuleAction::Disallow => false,
-                    };
-                }
-            } else {
+            if rule.os.as_ref().is_none_or(|os| os_matches(os, os_version)) {
                 allowed = match rule.action {
                     LibraryRuleAction::Allow => true,
                     LibraryRuleAction::Disallow => false,
                 };
             }
         }
-        if !allowed {
-            // We don't need the library
-            return Ok(vec![]);
+        allowed
+    }
+
+    /// The jar of the library below `base_path` that goes on the classpath.
+    /// `None` for libraries with nothing but natives.
+    pub fn classpath_entry(&self, base_path: &Path) -> anyhow::Result<Option<PathBuf>> {
+        let natives_only = self.natives.is_some()
+            && self
+                .downloads
+                .as_ref()
+                .is_none_or(|downloads| downloads.artifact.is_none());
+        if natives_only {
+            return Ok(None);
         }
+        let path = name_to_path(&self.name, None).ok_or(anyhow!("Can't get path from name"))?;
+        Ok(Some(base_path.join(path)))
+    }
+}
+
+/// The downloads `library` needs on this system, into the libraries below
+/// `base_path`. Nothing if its rules leave it out.
+pub async fn library_jobs(base_path: &Path, library: &Library) -> anyhow::Result<Vec<DownloadJob>> {
+    if !library.applies().await || library.hint == Some(LibraryHint::Local) {
+        // We don't need the library, or it can't be downloaded
+        return Ok(vec![]);
     }
-    let mut downloaded = vec![];
-    match library.downloads {
+    let mut jobs = vec![];
+    match &library.downloads {
         Some(downloads) => {
-            if let Some(artifact) = downloads.artifact {
-                let mut path = base_path.clone();
-                path.push(PathBuf::from(
+            if let Some(artifact) = &downloads.artifact {
+                let path = base_path.join(
                     name_to_path(&library.name, None).ok_or(anyhow!("Can't get path from name"))?,
+                );
+                jobs.push(DownloadJob::new(&artifact.url, path).with_expected(
+                    Expected::sha1(Some(&artifact.sha1)).with_size(Some(artifact.size)),
                 ));
-                crate::storage::get_file(&path, &artifact.url, false, Some(&artifact.sha1)).await?;
-                downloaded.push(path);
             }
-            if let Some(natives) = library.natives {
-                if let Some(native) = natives.get(&os_arch()) {
-                    let artifacts = downloads
-                        .classifiers
-                        .ok_or(anyhow!("Can't get classifiers"))?;
-                    let artifact = artifacts.get(native).ok_or(anyhow!("Can't get native"))?;
-                    let mut path = base_path.clone();
-                    path.push(PathBuf::from(
-                        name_to_path(&library.name, Some(native))
-                            .ok_or(anyhow!("Can't get path from name"))?,
-                    ));
-                    crate::storage::get_file(&path, &artifact.url, false, Some(&artifact.sha1))
-                        .await?;
-                    downloaded.push(path);
-                }
+            if let Some(native) = library.native_classifier() {
+                let native = native.as_str();
+                let artifacts = downloads
+                    .classifiers
+                    .as_ref()
+                    .ok_or(anyhow!("Can't get classifiers"))?;
+                let artifact = artifacts.get(native).ok_or(anyhow!("Can't get native"))?;
+                let path = base_path.join(
+                    name_to_path(&library.name, Some(native))
+                        .ok_or(anyhow!("Can't get path from name"))?,
+                );
+                jobs.push(DownloadJob::new(&artifact.url, path).with_expected(
+                    Expected::sha1(Some(&artifact.sha1)).with_size(Some(artifact.size)),
+                ));
             }
         }
         None => {
-            let mut url = library.url.map_or(LIBRARY_BASE_URL.to_string(), |url| url);
+            let mut url = library
+                .url
+                .clone()
+                .unwrap_or_else(|| LIBRARY_BASE_URL.to_string());
             if url.ends_with('/') {
                 url += &name_to_path(&library.name, None)
                     .ok_or(anyhow!("Can't get path from name"))?;
             }
-            let mut path = base_path.clone();
-            path.push(PathBuf::from(
+            let path = base_path.join(
                 name_to_path(&library.name, None).ok_or(anyhow!("Can't get path from name"))?,
-            ));
-            crate::storage::get_file(
-                &path,
-                &url,
-                library.hint == Some(LibraryHint::AlwaysStale),
-                None,
-            )
-            .await?;
-            downloaded.push(path);
+            );
+            jobs.push(
+                DownloadJob::new(url, path)
+                    .with_redownload(library.hint == Some(LibraryHint::AlwaysStale)),
+            );
         }
     }
-    Ok(downloaded)
+    Ok(jobs)
+}
+
+/// Downloads the `mavenFiles` of components into the libraries below
+/// `base_path`. They aren't on the classpath, but loader installers like
+/// Forge's expect to find them there.
+pub async fn download_maven_files(
+    app_handle: &tauri::AppHandle,
+    base_path: &Path,
+    maven_files: &[Library],
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut jobs = vec![];
+    for file in maven_files {
+        jobs.extend(library_jobs(base_path, file).await?);
+    }
+    downloads::download_all(app_handle, &jobs)
+        .await
+        .context("Failed to download Maven files")?;
+    Ok(jobs.into_iter().map(|job| job.path).collect())
 }
@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    process::Stdio,
+    sync::{Arc, Mutex},
+};
+
+use log::{error, info};
+use serde::Serialize;
+use serde_json::json;
+use tauri::Manager;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    process::{ChildStdin, Command},
+    sync::oneshot,
+};
+
+use crate::{
+    errors::{coded, ErrorCode},
+    plugins::Hook,
+    settings::{self, LaunchBehavior},
+};
+
+/// Records that an instance was started, runs the `Launch` plugin hooks and
+/// shows it on Discord. Returns the start time for [`record_exit`].
+pub(crate) async fn record_start(app_handle: &tauri::AppHandle, instance_id: &str) -> i64 {
+    match crate::instance::record_launch(app_handle, instance_id).await {
+        Ok(config) => crate::plugins::run_hook(
+            Hook::Launch,
+            json!({ "instanceId": instance_id, "config": config }),
+        ),
+        Err(e) => error!("{:#?}", e),
+    }
+    crate::usage_stats::record_launch(app_handle, instance_id).await;
+    crate::discord::game_started(app_handle, instance_id).await;
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// Records the play session of an instance started at `started` and runs
+/// its post-exit hooks.
+pub(crate) async fn record_exit(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    started: i64,
+    code: Option<i32>,
+) {
+    let session = crate::playtime::Session {
+        instance_id: instance_id.to_string(),
+        start: started,
+        end: time::OffsetDateTime::now_utc().unix_timestamp(),
+    };
+    crate::usage_stats::record_playtime(app_handle, instance_id, session.seconds()).await;
+    crate::playtime::record_session(app_handle, session).await;
+    crate::discord::game_exited(app_handle, instance_id).await;
+    crate::launch_hooks::post_exit(app_handle, instance_id, code).await;
+}
+
+/// Minimizes or hides the launcher window once a game has started, as set
+/// in the settings.
+fn apply_launch_behavior(app_handle: &tauri::AppHandle) {
+    let Some(window) = app_handle.get_window("main") else {
+        return;
+    };
+    let result = match settings::current(app_handle).launch_behavior {
+        LaunchBehavior::KeepOpen => Ok(()),
+        LaunchBehavior::Minimize => window.minimize(),
+        LaunchBehavior::Close => window.hide(),
+    };
+    if let Err(e) = result {
+        error!("{:#?}", e);
+    }
+}
+
+struct RunningProcess {
+    stdin: Option<Arc<tokio::sync::Mutex<ChildStdin>>>,
+    kill: Option<oneshot::Sender<()>>,
+}
+
+/// Keeps track of which instances currently have a game process running.
+#[derive(Default)]
+pub struct ProcessManager {
+    running: Mutex<HashMap<String, RunningProcess>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsoleLine {
+    pub instance_id: String,
+    pub line: String,
+    pub stderr: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessExited {
+    pub instance_id: String,
+    pub code: Option<i32>,
+}
+
+impl ProcessManager {
+    pub fn is_running(&self, instance_id: &str) -> bool {
+        self.running.lock().unwrap().contains_key(instance_id)
+    }
+
+    /// Starts `command` for an instance, streaming its output as
+    /// `instance:console` events and emitting `instance:exited` when it ends.
+    pub fn spawn(
+        &self,
+        app_handle: &tauri::AppHandle,
+        instance_id: &str,
+        mut command: Command,
+    ) -> anyhow::Result<()> {
+        let mut running = self.running.lock().unwrap();
+        if running.contains_key(instance_id) {
+            return Err(coded(
+                ErrorCode::InstanceRunning,
+                format!("{} is already running", instance_id),
+            ));
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        info!("Started {} with pid {:?}", instance_id, child.id());
+
+        if let Some(stdout) = child.stdout.take() {
+            stream_lines(app_handle.clone(), instance_id.to_string(), stdout, false);
+        }
+        if let Some(stderr) = child.stderr.take() {
+            stream_lines(app_handle.clone(), instance_id.to_string(), stderr, true);
+        }
+        let (kill_tx, kill_rx) = oneshot::channel();
+        running.insert(
+            instance_id.to_string(),
+            RunningProcess {
+                stdin: child
+                    .stdin
+                    .take()
+                    .map(|stdin| Arc::new(tokio::sync::Mutex::new(stdin))),
+                kill: Some(kill_tx),
+            },
+        );
+
+        drop(running);
+        crate::tray::refresh(app_handle);
+        apply_launch_behavior(app_handle);
+
+        let app_handle = app_handle.clone();
+        let instance_id = instance_id.to_string();
+        tauri::async_runtime::spawn(async move {
+            let started = record_start(&app_handle, &instance_id).await;
+            let status = tokio::select! {
+                status = child.wait() => status,
+                _ = kill_rx => {
+                    if let Err(e) = child.kill().await {
+                        error!("Failed to kill {}: {:?}", instance_id, e);
+                    }
+                    child.wait().await
+                }
+            };
+            let code = status.ok().and_then(|status| status.code());
+            info!("{} exited with {:?}", instance_id, code);
+            let any_running = {
+                let manager = app_handle.state::<ProcessManager>();
+                let mut running = manager.running.lock().unwrap();
+                running.remove(&instance_id);
+                !running.is_empty()
+            };
+            crate::tray::refresh(&app_handle);
+            // Bring back the window `apply_launch_behavior` put away
+            if !any_running
+                && settings::current(&app_handle).launch_behavior != LaunchBehavior::KeepOpen
+            {
+                crate::tray::show_main_window(&app_handle);
+            }
+            record_exit(&app_handle, &instance_id, started, code).await;
+            if let Err(e) =
+                app_handle.emit_all("instance:exited", ProcessExited { instance_id, code })
+            {
+                error!("{:#?}", e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Writes a line to the process' standard input.
+    pub async fn send_line(&self, instance_id: &str, line: &str) -> anyhow::Result<()> {
+        let stdin = self
+            .running
+            .lock()
+            .unwrap()
+            .get(instance_id)
+            .and_then(|process| process.stdin.clone())
+            .ok_or_else(|| {
+                coded(
+                    ErrorCode::InstanceNotRunning,
+                    format!("{} is not running", instance_id),
+                )
+            })?;
+        let mut stdin = stdin.lock().await;
+        stdin.write_all(line.as_bytes()).await?;
+        stdin.write_all(b"\n").await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    pub fn kill(&self, instance_id: &str) -> anyhow::Result<()> {
+        let kill = self
+            .running
+            .lock()
+            .unwrap()
+            .get_mut(instance_id)
+            .and_then(|process| process.kill.take())
+            .ok_or_else(|| {
+                coded(
+                    ErrorCode::InstanceNotRunning,
+                    format!("{} is not running", instance_id),
+                )
+            })?;
+        // The receiver only goes away once the process has exited anyway
+        let _ = kill.send(());
+        Ok(())
+    }
+}
+
+fn stream_lines(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    output: impl AsyncRead + Unpin + Send + 'static,
+    stderr: bool,
+) {
+    tauri::async_runtime::spawn(async move {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let event = ConsoleLine {
+                instance_id: instance_id.clone(),
+                line,
+                stderr,
+            };
+            if let Err(e) = app_handle.emit_all("instance:console", event) {
+                error!("{:#?}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,307 @@
+//! Source RCON client, as implemented by the vanilla server.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Mutex,
+    time::timeout,
+};
+
+use crate::{
+    command_result, errors::CommandError, instance, server_ping, storage::CredentialStore,
+};
+
+const DEFAULT_RCON_PORT: u16 = 25575;
+const RCON_TIMEOUT: Duration = Duration::from_secs(10);
+
+const TYPE_RESPONSE: i32 = 0;
+const TYPE_COMMAND: i32 = 2;
+const TYPE_LOGIN: i32 = 3;
+
+pub struct RconClient {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl RconClient {
+    pub async fn connect(host: &str, port: u16, password: &str) -> anyhow::Result<Self> {
+        let stream = timeout(RCON_TIMEOUT, TcpStream::connect((host, port)))
+            .await
+            .map_err(|_| anyhow!("Timed out connecting to {}:{}", host, port))??;
+        let mut client = RconClient { stream, next_id: 1 };
+        let id = client.send_packet(TYPE_LOGIN, password).await?;
+        let (response_id, _, _) = client.read_packet().await?;
+        if response_id == -1 {
+            return Err(anyhow!("RCON authentication failed, check the password"));
+        }
+        if response_id != id {
+            return Err(anyhow!("Unexpected RCON login response"));
+        }
+        Ok(client)
+    }
+
+    /// Runs a command and returns its output.
+    pub async fn command(&mut self, command: &str) -> anyhow::Result<String> {
+        let id = self.send_packet(TYPE_COMMAND, command).await?;
+        // Long responses are split over several packets with no end marker,
+        // so send a second packet and read until its response shows up
+        let end_id = self.send_packet(TYPE_RESPONSE, "").await?;
+        let mut output = String::new();
+        loop {
+            let (response_id, _, payload) = self.read_packet().await?;
+            if response_id == end_id {
+                break;
+            }
+            if response_id == id {
+                output.push_str(&payload);
+            }
+        }
+        Ok(output)
+    }
+
+    async fn send_packet(&mut self, kind: i32, payload: &str) -> anyhow::Result<i32> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let mut packet = Vec::with_capacity(payload.len() + 14);
+        packet.extend_from_slice(&(payload.len() as i32 + 10).to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.extend_from_slice(&kind.to_le_bytes());
+        packet.extend_from_slice(payload.as_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        self.stream.write_all(&packet).await?;
+        Ok(id)
+    }
+
+    async fn read_packet(&mut self) -> anyhow::Result<(i32, i32, String)> {
+        timeout(RCON_TIMEOUT, async {
+            let len = self.stream.read_i32_le().await?;
+            if !(10..=4096 + 10).contains(&len) {
+                return Err(anyhow!("Invalid RCON packet length {}", len));
+            }
+            let id = self.stream.read_i32_le().await?;
+            let kind = self.stream.read_i32_le().await?;
+            let mut payload = vec![0; len as usize - 8];
+            self.stream.read_exact(&mut payload).await?;
+            payload.truncate(payload.len() - 2);
+            Ok((id, kind, String::from_utf8_lossy(&payload).to_string()))
+        })
+        .await
+        .map_err(|_| anyhow!("Timed out waiting for RCON response"))?
+    }
+}
+
+/// Saved RCON settings for one of an instance's servers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RconProfile {
+    /// The server address as stored in `servers.dat`
+    pub address: String,
+    pub port: Option<u16>,
+    /// Only ever set coming from the frontend, it's kept in the credential
+    /// store instead of `rcon.json`. Leaving it empty keeps the stored one.
+    #[serde(default, skip_serializing)]
+    pub password: String,
+}
+
+fn credential_key(instance_id: &str, address: &str) -> String {
+    format!("rcon:{}:{}", instance_id, address)
+}
+
+fn profiles_path(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<PathBuf> {
+    Ok(instance::instance_dir(app_handle, instance_id)?.join("rcon.json"))
+}
+
+async fn read_profiles(path: &Path) -> anyhow::Result<Vec<RconProfile>> {
+    match tokio::fs::read(path).await {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn write_profiles(path: &Path, profiles: &[RconProfile]) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, serde_json::to_vec_pretty(profiles)?).await?;
+    Ok(())
+}
+
+/// Moves the passwords of `profiles` into the credential store, leaving
+/// them empty. Returns whether there were any.
+async fn store_passwords(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    profiles: &mut [RconProfile],
+) -> anyhow::Result<bool> {
+    let mut stored = false;
+    for profile in profiles
+        .iter_mut()
+        .filter(|profile| !profile.password.is_empty())
+    {
+        CredentialStore::open(app_handle)
+            .await?
+            .set(
+                &credential_key(instance_id, &profile.address),
+                &std::mem::take(&mut profile.password),
+            )
+            .await?;
+        stored = true;
+    }
+    Ok(stored)
+}
+
+/// Reads the profiles of an instance, moving passwords that older versions
+/// kept in `rcon.json` into the credential store.
+async fn load_profiles(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<Vec<RconProfile>> {
+    let path = profiles_path(app_handle, instance_id)?;
+    let mut profiles = read_profiles(&path).await?;
+    if store_passwords(app_handle, instance_id, &mut profiles).await? {
+        write_profiles(&path, &profiles).await?;
+    }
+    Ok(profiles)
+}
+
+/// Open RCON connections, keyed by a session id handed to the frontend.
+#[derive(Default)]
+pub struct RconSessions {
+    next_id: AtomicU32,
+    sessions: Mutex<HashMap<u32, Arc<Mutex<RconClient>>>>,
+}
+
+#[tauri::command]
+pub async fn list_rcon_profiles(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+) -> Result<Vec<RconProfile>, CommandError> {
+    command_result(load_profiles(&app_handle, &instance_id).await)
+}
+
+/// Adds or replaces the profile for `profile.address`.
+#[tauri::command]
+pub async fn save_rcon_profile(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    mut profile: RconProfile,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            let mut profiles = load_profiles(&app_handle, &instance_id).await?;
+            store_passwords(
+                &app_handle,
+                &instance_id,
+                std::slice::from_mut(&mut profile),
+            )
+            .await?;
+            profiles.retain(|existing| existing.address != profile.address);
+            profiles.push(profile);
+            write_profiles(&profiles_path(&app_handle, &instance_id)?, &profiles).await
+        }
+        .await,
+    )
+}
+
+#[tauri::command]
+pub async fn remove_rcon_profile(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    address: String,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            let mut profiles = load_profiles(&app_handle, &instance_id).await?;
+            profiles.retain(|existing| existing.address != address);
+            write_profiles(&profiles_path(&app_handle, &instance_id)?, &profiles).await?;
+            CredentialStore::open(&app_handle)
+                .await?
+                .delete(&credential_key(&instance_id, &address))
+                .await
+        }
+        .await,
+    )
+}
+
+/// Connects using the saved profile for `address`, returning a session id.
+#[tauri::command]
+pub async fn rcon_connect(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    address: String,
+) -> Result<u32, CommandError> {
+    command_result(
+        async {
+            let profiles = load_profiles(&app_handle, &instance_id).await?;
+            let profile = profiles
+                .iter()
+                .find(|profile| profile.address == address)
+                .ok_or(anyhow!("No RCON profile saved for {}", address))?;
+            let password = CredentialStore::open(&app_handle)
+                .await?
+                .get(&credential_key(&instance_id, &address))
+                .await?
+                .unwrap_or_default();
+            let (host, _) = server_ping::split_address(&profile.address)?;
+            let client =
+                RconClient::connect(&host, profile.port.unwrap_or(DEFAULT_RCON_PORT), &password)
+                    .await?;
+            let sessions = app_handle.state::<RconSessions>();
+            let id = sessions.next_id.fetch_add(1, Ordering::Relaxed);
+            sessions
+                .sessions
+                .lock()
+                .await
+                .insert(id, Arc::new(Mutex::new(client)));
+            Ok(id)
+        }
+        .await,
+    )
+}
+
+#[tauri::command]
+pub async fn rcon_command(
+    app_handle: tauri::AppHandle,
+    session: u32,
+    command: String,
+) -> Result<String, CommandError> {
+    command_result(
+        async {
+            let client = app_handle
+                .state::<RconSessions>()
+                .sessions
+                .lock()
+                .await
+                .get(&session)
+                .cloned()
+                .ok_or(anyhow!("No RCON session {}", session))?;
+            let mut client = client.lock().await;
+            client.command(&command).await
+        }
+        .await,
+    )
+}
+
+#[tauri::command]
+pub async fn rcon_disconnect(app_handle: tauri::AppHandle, session: u32) {
+    app_handle
+        .state::<RconSessions>()
+        .sessions
+        .lock()
+        .await
+        .remove(&session);
+}
@@ -0,0 +1,301 @@
+//! Opt-in JSON-RPC API on a local socket (a named pipe on Windows) so that
+//! scripts and tools like stream decks can drive the launcher.
+//!
+//! Requests and responses are JSON-RPC 2.0 objects, one per line. Every
+//! connection has to call `authenticate` with the token from the
+//! `api-token` file in the config dir before anything else.
+
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::Manager;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::{errors::ErrorCode, instance, process::ProcessManager, server_pack, settings, tray};
+
+#[cfg(windows)]
+const PIPE_NAME: &str = r"\\.\pipe\untitled-minecraft-launcher";
+
+const PARSE_ERROR: i32 = -32700;
+const UNAUTHORIZED: i32 = -32001;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const SERVER_ERROR: i32 = -32000;
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InstanceParams {
+    instance_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InstallParams {
+    instance_id: String,
+    pack_path: PathBuf,
+}
+
+fn config_dir(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    app_handle
+        .path_resolver()
+        .app_config_dir()
+        .ok_or(anyhow!("Can't get app config dir"))
+}
+
+/// Reads the API token, creating one readable only by the user on first use.
+async fn load_token(app_handle: &tauri::AppHandle) -> anyhow::Result<String> {
+    let path = config_dir(app_handle)?.join("api-token");
+    match tokio::fs::read_to_string(&path).await {
+        Ok(token) => return Ok(token.trim().to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // Created that way, so there's no moment anyone else could read it
+    #[cfg(unix)]
+    options.mode(0o600);
+    options
+        .open(&path)
+        .await?
+        .write_all(token.as_bytes())
+        .await?;
+    info!("Created API token at {:?}", path);
+    Ok(token)
+}
+
+/// Compares without stopping at the first difference, so how long it takes
+/// doesn't give away how much of a guessed token was right.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError {
+        code: INVALID_PARAMS,
+        message: e.to_string(),
+        data: None,
+    })
+}
+
+fn server_error(e: anyhow::Error) -> RpcError {
+    RpcError {
+        code: SERVER_ERROR,
+        data: Some(json!({ "errorCode": ErrorCode::of(&e) })),
+        message: format!("{:#}", e),
+    }
+}
+
+async fn call(
+    app_handle: &tauri::AppHandle,
+    method: &str,
+    params_value: Value,
+) -> Result<Value, RpcError> {
+    match method {
+        "listInstances" => {
+            let instances = instance::list_instances(app_handle)
+                .await
+                .map_err(server_error)?;
+            let processes = app_handle.state::<ProcessManager>();
+            Ok(instances
+                .into_iter()
+                .map(|entry| {
+                    json!({
+                        "id": entry.id,
+                        "running": processes.is_running(&entry.id),
+                        "config": entry.config,
+                    })
+                })
+                .collect())
+        }
+        "launch" => {
+            let InstanceParams { instance_id } = params(params_value)?;
+            tray::quick_launch(app_handle, &instance_id)
+                .await
+                .map_err(server_error)?;
+            Ok(Value::Null)
+        }
+        "kill" => {
+            let InstanceParams { instance_id } = params(params_value)?;
+            app_handle
+                .state::<ProcessManager>()
+                .kill(&instance_id)
+                .map_err(server_error)?;
+            Ok(Value::Null)
+        }
+        "installServerPack" => {
+            let InstallParams {
+                instance_id,
+                pack_path,
+            } = params(params_value)?;
+            let result =
+                server_pack::install_server_pack_inner(app_handle, &instance_id, pack_path)
+                    .await
+                    .map_err(server_error)?;
+            serde_json::to_value(result).map_err(|e| server_error(e.into()))
+        }
+        _ => Err(RpcError {
+            code: METHOD_NOT_FOUND,
+            message: format!("Unknown method {}", method),
+            data: None,
+        }),
+    }
+}
+
+async fn handle_connection(
+    app_handle: tauri::AppHandle,
+    token: String,
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+) -> anyhow::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut authenticated = false;
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, result) = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let result = if request.method == "authenticate" {
+                    authenticated = request.params["token"]
+                        .as_str()
+                        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()));
+                    if authenticated {
+                        Ok(Value::Bool(true))
+                    } else {
+                        Err(RpcError {
+                            code: UNAUTHORIZED,
+                            message: "Wrong token".to_string(),
+                            data: None,
+                        })
+                    }
+                } else if !authenticated {
+                    Err(RpcError {
+                        code: UNAUTHORIZED,
+                        message: "Call authenticate first".to_string(),
+                        data: None,
+                    })
+                } else {
+                    call(&app_handle, &request.method, request.params).await
+                };
+                (request.id, result)
+            }
+            Err(e) => (
+                Value::Null,
+                Err(RpcError {
+                    code: PARSE_ERROR,
+                    message: e.to_string(),
+                    data: None,
+                }),
+            ),
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+        };
+        let mut response = serde_json::to_vec(&response)?;
+        response.push(b'\n');
+        writer.write_all(&response).await?;
+    }
+    Ok(())
+}
+
+fn spawn_connection(
+    app_handle: &tauri::AppHandle,
+    token: &str,
+    stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+) {
+    let app_handle = app_handle.clone();
+    let token = token.to_string();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = handle_connection(app_handle, token, stream).await {
+            warn!("API connection failed: {:#}", e);
+        }
+    });
+}
+
+#[cfg(unix)]
+async fn serve(app_handle: tauri::AppHandle, token: String) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // The socket gets the permissions the umask leaves, so it lives in a
+    // directory only the user can enter
+    let dir = config_dir(&app_handle)?.join("api");
+    tokio::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .await?;
+    // In case it already existed with other permissions
+    tokio::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).await?;
+    let path = dir.join("api.sock");
+    // A socket left behind by a previous run would make binding fail
+    match tokio::fs::remove_file(&path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let listener = tokio::net::UnixListener::bind(&path)?;
+    info!("API listening on {:?}", path);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        spawn_connection(&app_handle, &token, stream);
+    }
+}
+
+#[cfg(windows)]
+async fn serve(app_handle: tauri::AppHandle, token: String) -> anyhow::Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(PIPE_NAME)?;
+    info!("API listening on {}", PIPE_NAME);
+    loop {
+        server.connect().await?;
+        let client = server;
+        server = ServerOptions::new().create(PIPE_NAME)?;
+        spawn_connection(&app_handle, &token, client);
+    }
+}
+
+/// Starts the API if it is enabled in the settings. Changing the setting
+/// takes effect on the next start.
+pub fn start(app_handle: &tauri::AppHandle) {
+    if !settings::current(app_handle).rpc_api_enabled {
+        return;
+    }
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let result = match load_token(&app_handle).await {
+            Ok(token) => serve(app_handle, token).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("API stopped: {:#}", e);
+        }
+    });
+}
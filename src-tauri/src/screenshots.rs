@@ -0,0 +1,245 @@
+use std::{borrow::Cow, path::PathBuf};
+
+use anyhow::anyhow;
+use log::info;
+use reqwest::multipart::{Form, Part};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use time::OffsetDateTime;
+
+use crate::{command_result, errors::CommandError, instance, proxy, usage_stats};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Screenshot {
+    pub file_name: String,
+    pub path: PathBuf,
+    #[serde(with = "time::serde::iso8601")]
+    pub taken: OffsetDateTime,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+}
+
+fn screenshots_dir(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<PathBuf> {
+    Ok(instance::game_dir(app_handle, instance_id)?.join("screenshots"))
+}
+
+fn screenshot_path(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    file_name: &str,
+) -> anyhow::Result<PathBuf> {
+    instance::check_name(file_name)?;
+    let path = screenshots_dir(app_handle, instance_id)?.join(file_name);
+    if !path.is_file() {
+        return Err(anyhow!("Screenshot {:?} does not exist", file_name));
+    }
+    Ok(path)
+}
+
+fn list_screenshots_blocking(dir: PathBuf) -> anyhow::Result<Vec<Screenshot>> {
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut screenshots = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension() != Some("png".as_ref()) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        // Skip files the game is still writing or that aren't valid images
+        let (width, height) = match image::image_dimensions(&path) {
+            Ok(dimensions) => dimensions,
+            Err(_) => continue,
+        };
+        screenshots.push(Screenshot {
+            file_name: entry.file_name().to_string_lossy().to_string(),
+            path,
+            taken: metadata.modified()?.into(),
+            width,
+            height,
+            size: metadata.len(),
+        });
+    }
+    screenshots.sort_by_key(|screenshot| std::cmp::Reverse(screenshot.taken));
+    Ok(screenshots)
+}
+
+/// Lists an instance's screenshots, newest first.
+#[tauri::command]
+pub async fn list_screenshots(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+) -> Result<Vec<Screenshot>, CommandError> {
+    let dir = command_result(screenshots_dir(&app_handle, &instance_id))?;
+    command_result(
+        tokio::task::spawn_blocking(move || list_screenshots_blocking(dir))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result),
+    )
+}
+
+#[tauri::command]
+pub fn open_screenshot(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    file_name: String,
+) -> Result<(), CommandError> {
+    command_result(
+        screenshot_path(&app_handle, &instance_id, &file_name)
+            .and_then(|path| Ok(open::that(path)?)),
+    )
+}
+
+#[tauri::command]
+pub async fn delete_screenshot(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    file_name: String,
+) -> Result<(), CommandError> {
+    command_result(delete_screenshot_inner(&app_handle, &instance_id, &file_name).await)
+}
+
+async fn delete_screenshot_inner(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    file_name: &str,
+) -> anyhow::Result<()> {
+    let path = screenshot_path(app_handle, instance_id, file_name)?;
+    instance::move_to_trash(app_handle, &path, instance_id).await?;
+    app_handle.emit_all("instance:screenshots:changed", instance_id)?;
+    Ok(())
+}
+
+/// Copies the screenshot image itself (not its path) to the clipboard.
+#[tauri::command]
+pub async fn copy_screenshot(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    file_name: String,
+) -> Result<(), CommandError> {
+    let result = async {
+        let path = screenshot_path(&app_handle, &instance_id, &file_name)?;
+        tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+            let image = image::open(path)?.to_rgba8();
+            let mut clipboard = arboard::Clipboard::new()?;
+            clipboard.set_image(arboard::ImageData {
+                width: image.width() as usize,
+                height: image.height() as usize,
+                bytes: Cow::Borrowed(image.as_raw()),
+            })?;
+            Ok(())
+        })
+        .await?
+    }
+    .await;
+    command_result(result)
+}
+
+const ZERO_X_ZERO_URL: &str = "https://0x0.st";
+
+/// Where screenshots get uploaded to when sharing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ImageHost {
+    /// The 0x0.st file host, which needs no account
+    ZeroXZero,
+    /// Any endpoint accepting a multipart POST with the image in `field`
+    #[serde(rename_all = "camelCase")]
+    Generic {
+        url: String,
+        field: String,
+        /// JSON pointer to the image URL in the response, e.g.
+        /// `/data/link`. If unset the whole response body is the URL.
+        url_pointer: Option<String>,
+    },
+}
+
+impl ImageHost {
+    fn upload_url(&self) -> &str {
+        match self {
+            ImageHost::ZeroXZero => ZERO_X_ZERO_URL,
+            ImageHost::Generic { url, .. } => url,
+        }
+    }
+}
+
+/// Uploads a screenshot after asking the user to confirm, then copies the
+/// resulting URL to the clipboard. Returns `None` if the user declined.
+#[tauri::command]
+pub async fn upload_screenshot(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    file_name: String,
+    host: ImageHost,
+) -> Result<Option<String>, CommandError> {
+    usage_stats::record_feature(&app_handle, "screenshotUpload");
+    command_result(upload_screenshot_inner(&app_handle, &instance_id, &file_name, &host).await)
+}
+
+async fn upload_screenshot_inner(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    file_name: &str,
+    host: &ImageHost,
+) -> anyhow::Result<Option<String>> {
+    let path = screenshot_path(app_handle, instance_id, file_name)?;
+
+    // The confirmation lives in the backend so that nothing can upload a
+    // screenshot without the user seeing this dialog
+    let window = app_handle.get_window("main");
+    let message = format!(
+        "{} will be uploaded publicly to {}. Continue?",
+        file_name,
+        host.upload_url()
+    );
+    let confirmed = tokio::task::spawn_blocking(move || {
+        tauri::api::dialog::blocking::ask(window.as_ref(), "Upload screenshot", message)
+    })
+    .await?;
+    if !confirmed {
+        return Ok(None);
+    }
+
+    let data = tokio::fs::read(&path).await?;
+    let field = match host {
+        ImageHost::ZeroXZero => "file",
+        ImageHost::Generic { field, .. } => field,
+    };
+    let file = Part::bytes(data)
+        .file_name(file_name.to_string())
+        .mime_str("image/png")?;
+    let resp = proxy::client()
+        .post(host.upload_url())
+        .multipart(Form::new().part(field.to_string(), file))
+        .send()
+        .await?;
+    let status = resp.status();
+    let body = resp.text().await?;
+    if status != reqwest::StatusCode::OK {
+        return Err(anyhow!("Image host returned {}: {}", status, body));
+    }
+    let url = match host {
+        ImageHost::Generic {
+            url_pointer: Some(pointer),
+            ..
+        } => serde_json::from_str::<serde_json::Value>(&body)?
+            .pointer(pointer)
+            .and_then(serde_json::Value::as_str)
+            .ok_or(anyhow!("Image host response has no URL at {}", pointer))?
+            .to_string(),
+        _ => body.trim().to_string(),
+    };
+    info!("Uploaded screenshot {:?} to {}", path, url);
+
+    let clipboard_url = url.clone();
+    tokio::task::spawn_blocking(move || arboard::Clipboard::new()?.set_text(clipboard_url))
+        .await??;
+    Ok(Some(url))
+}
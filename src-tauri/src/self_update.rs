@@ -0,0 +1,204 @@
+//! Updating the launcher itself from GitHub releases.
+//!
+//! Releases carry a `latest.json` manifest for the Tauri updater, which does
+//! the download and checks it against the public key in `tauri.conf.json`.
+//! Builds without a key have the updater turned off and only point to the
+//! releases page.
+//!
+//! Checking for updates goes through the proxy, but the updater downloads
+//! with its own client, which can't use it. Behind a proxy the releases page
+//! is opened instead, for the browser to download the update.
+
+use anyhow::anyhow;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::{
+    command_result,
+    errors::{coded, CommandError, ErrorCode},
+    proxy,
+    settings::{self, UpdateChannel},
+};
+
+const RELEASES_API_URL: &str =
+    "https://api.github.com/repos/vgskye/untitled-minecraft-launcher/releases";
+const STABLE_MANIFEST_URL: &str =
+    "https://github.com/vgskye/untitled-minecraft-launcher/releases/latest/download/latest.json";
+const MANIFEST_ASSET: &str = "latest.json";
+const RELEASES_PAGE_URL: &str = "https://github.com/vgskye/untitled-minecraft-launcher/releases";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GithubRelease {
+    pub tag_name: String,
+    pub name: Option<String>,
+    pub body: Option<String>,
+    pub html_url: String,
+    pub published_at: Option<String>,
+    pub draft: bool,
+    pub prerelease: bool,
+    pub assets: Vec<GithubAsset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GithubAsset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+/// The parts of an updater manifest the check needs.
+#[derive(Debug, Clone, Deserialize)]
+struct UpdateManifest {
+    version: String,
+    notes: Option<String>,
+    /// RFC 3339
+    pub_date: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateInfo {
+    pub version: String,
+    pub notes: Option<String>,
+    /// RFC 3339
+    pub date: Option<String>,
+}
+
+/// Lists the launcher's releases, newest first.
+pub async fn fetch_releases() -> anyhow::Result<Vec<GithubRelease>> {
+    let resp = proxy::client()
+        .get(RELEASES_API_URL)
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", "untitled-minecraft-launcher")
+        .send()
+        .await?;
+    if resp.status() != reqwest::StatusCode::OK {
+        return Err(anyhow!("Got status {} listing releases", resp.status()));
+    }
+    Ok(resp.json().await?)
+}
+
+/// Finds the updater manifest of the newest release, including pre-releases.
+async fn beta_manifest_url() -> anyhow::Result<String> {
+    fetch_releases()
+        .await?
+        .into_iter()
+        .filter(|release| !release.draft)
+        .find_map(|release| {
+            release
+                .assets
+                .into_iter()
+                .find(|asset| asset.name == MANIFEST_ASSET)
+        })
+        .map(|asset| asset.browser_download_url)
+        .ok_or(anyhow!("No release has an update manifest"))
+}
+
+async fn manifest_url(app_handle: &tauri::AppHandle) -> anyhow::Result<String> {
+    match settings::current(app_handle).update_channel {
+        UpdateChannel::Stable => Ok(STABLE_MANIFEST_URL.to_string()),
+        UpdateChannel::Beta => beta_manifest_url().await,
+    }
+}
+
+/// Whether updates can be installed, which needs the updater turned on
+/// with a key to verify them.
+fn updater_enabled(app_handle: &tauri::AppHandle) -> bool {
+    let updater = &app_handle.config().tauri.updater;
+    updater.active && !updater.pubkey.is_empty()
+}
+
+/// Whether `version` is newer than the running launcher.
+fn is_newer(app_handle: &tauri::AppHandle, version: &str) -> anyhow::Result<bool> {
+    let current = semver::Version::parse(&app_handle.package_info().version.to_string())?;
+    Ok(semver::Version::parse(version.trim_start_matches('v'))? > current)
+}
+
+async fn check(app_handle: &tauri::AppHandle) -> anyhow::Result<tauri::updater::UpdateResponse> {
+    Ok(tauri::updater::builder(app_handle.clone())
+        .endpoints(&[manifest_url(app_handle).await?])
+        .skip_events()
+        .check()
+        .await?)
+}
+
+/// Returns the update offered on the configured channel, if any. Nothing is
+/// checked while offline or if the updater is turned off.
+#[tauri::command]
+pub async fn check_for_update(
+    app_handle: tauri::AppHandle,
+) -> Result<Option<UpdateInfo>, CommandError> {
+    command_result(
+        async {
+            if !crate::network::is_online(&app_handle) || !updater_enabled(&app_handle) {
+                return Ok(None);
+            }
+            let resp = proxy::client()
+                .get(manifest_url(&app_handle).await?)
+                .send()
+                .await?;
+            if resp.status() != reqwest::StatusCode::OK {
+                return Err(anyhow!("Got status {} fetching the update", resp.status()));
+            }
+            let manifest: UpdateManifest = resp.json().await?;
+            if !is_newer(&app_handle, &manifest.version)? {
+                return Ok(None);
+            }
+            Ok(Some(UpdateInfo {
+                version: manifest.version,
+                notes: manifest.notes,
+                date: manifest.pub_date,
+            }))
+        }
+        .await,
+    )
+}
+
+/// Downloads and installs the update, then asks whether to restart into it.
+#[tauri::command]
+pub async fn install_update(app_handle: tauri::AppHandle) -> Result<(), CommandError> {
+    command_result(
+        async {
+            if !updater_enabled(&app_handle) {
+                return Err(coded(
+                    ErrorCode::Unsupported,
+                    "This build can't verify updates, download them from the releases page",
+                ));
+            }
+            if settings::current(&app_handle)
+                .proxy
+                .is_some_and(|proxy| !proxy.is_empty())
+            {
+                info!("Opening the releases page, the updater can't use the proxy");
+                open::that(RELEASES_PAGE_URL)?;
+                return Ok(());
+            }
+            let update = check(&app_handle).await?;
+            if !update.is_update_available() {
+                return Err(anyhow!("No update available"));
+            }
+            let version = update.latest_version().to_string();
+            app_handle.emit_all("update:installing", &version)?;
+            update.download_and_install().await?;
+            info!("Installed launcher update {}", version);
+
+            let window = app_handle.get_window("main");
+            let restart = tokio::task::spawn_blocking(move || {
+                tauri::api::dialog::blocking::ask(
+                    window.as_ref(),
+                    "Update installed",
+                    format!(
+                        "Version {} is installed. Restart the launcher now?",
+                        version
+                    ),
+                )
+            })
+            .await?;
+            if restart {
+                app_handle.restart();
+            }
+            Ok(())
+        }
+        .await,
+    )
+}
@@ -0,0 +1,455 @@
+//! Dedicated server instances: installing the server jar, the EULA, and
+//! running the server through the process manager.
+
+use std::path::Path;
+
+use anyhow::anyhow;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::process::Command;
+
+use crate::{
+    command_result, downloads,
+    errors::{coded, CommandError, ErrorCode},
+    install_progress::{InstallPhase, InstallTracker},
+    instance::{self, InstanceConfig, InstanceKind},
+    java, launch_hooks,
+    messages::Message,
+    process::ProcessManager,
+    storage::{self, Expected},
+    tasks::{self, TaskKind},
+};
+
+const VERSION_MANIFEST_URL: &str =
+    "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+const FABRIC_META_URL: &str = "https://meta.fabricmc.net/v2/versions/";
+const FORGE_MAVEN_URL: &str = "https://maven.minecraftforge.net/net/minecraftforge/forge/";
+const EULA_URL: &str = "https://aka.ms/MinecraftEULA";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ServerLoader {
+    Vanilla,
+    /// Uses the latest loader if no version is given
+    #[serde(rename_all = "camelCase")]
+    Fabric {
+        loader_version: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Forge {
+        forge_version: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionManifest {
+    versions: Vec<ManifestVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestVersion {
+    id: String,
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MojangVersion {
+    downloads: MojangDownloads,
+}
+
+#[derive(Debug, Deserialize)]
+struct MojangDownloads {
+    server: Option<MojangDownload>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MojangDownload {
+    sha1: String,
+    size: u64,
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct FabricLoaderEntry {
+    loader: FabricVersion,
+}
+
+#[derive(Debug, Deserialize)]
+struct FabricVersion {
+    version: String,
+    #[serde(default)]
+    stable: bool,
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(url: &str) -> anyhow::Result<T> {
+    let client = crate::proxy::client();
+    crate::mirrors::with_fallback(url, |url| {
+        let client = client.clone();
+        async move {
+            let resp = client.get(&url).send().await?;
+            if resp.status() != reqwest::StatusCode::OK {
+                return Err(anyhow!("Got status {} fetching {}", resp.status(), url));
+            }
+            Ok(resp.json().await?)
+        }
+    })
+    .await
+}
+
+async fn install_vanilla(
+    server_dir: &Path,
+    minecraft_version: &str,
+    progress: &InstallTracker,
+) -> anyhow::Result<()> {
+    progress.phase(InstallPhase::Meta, 2, 0);
+    let manifest: VersionManifest = get_json(VERSION_MANIFEST_URL).await?;
+    let version = manifest
+        .versions
+        .iter()
+        .find(|version| version.id == minecraft_version)
+        .ok_or(anyhow!("Unknown Minecraft version {}", minecraft_version))?;
+    progress.add(1, 0);
+    let version: MojangVersion = get_json(&version.url).await?;
+    progress.add(1, 0);
+    let server = version
+        .downloads
+        .server
+        .ok_or(anyhow!("{} has no server download", minecraft_version))?;
+    storage::ensure_free_space(server_dir, server.size).await?;
+    progress.phase(InstallPhase::Server, 1, server.size);
+    storage::get_file(
+        &server_dir.join("server.jar"),
+        &server.url,
+        false,
+        &Expected::sha1(Some(&server.sha1)).with_size(Some(server.size)),
+    )
+    .await?;
+    progress.add(1, server.size);
+    Ok(())
+}
+
+async fn install_fabric(
+    server_dir: &Path,
+    minecraft_version: &str,
+    loader_version: Option<&str>,
+    progress: &InstallTracker,
+) -> anyhow::Result<()> {
+    progress.phase(InstallPhase::Meta, 2, 0);
+    let loader_version = match loader_version {
+        Some(version) => version.to_string(),
+        None => {
+            let loaders: Vec<FabricLoaderEntry> =
+                get_json(&format!("{}loader/{}", FABRIC_META_URL, minecraft_version)).await?;
+            loaders
+                .into_iter()
+                .map(|entry| entry.loader)
+                .find(|loader| loader.stable)
+                .ok_or(anyhow!("No Fabric loader for {}", minecraft_version))?
+                .version
+        }
+    };
+    progress.add(1, 0);
+    let installers: Vec<FabricVersion> = get_json(&format!("{}installer", FABRIC_META_URL)).await?;
+    let installer = installers
+        .into_iter()
+        .find(|installer| installer.stable)
+        .ok_or(anyhow!("No stable Fabric installer"))?;
+    progress.add(1, 0);
+    progress.phase(InstallPhase::Server, 1, 0);
+    // The server launcher downloads the vanilla server itself on first start
+    storage::get_file(
+        &server_dir.join("server.jar"),
+        &format!(
+            "{}loader/{}/{}/{}/server/jar",
+            FABRIC_META_URL, minecraft_version, loader_version, installer.version
+        ),
+        true,
+        &Expected::default(),
+    )
+    .await?;
+    progress.add(1, 0);
+    Ok(())
+}
+
+async fn install_forge(
+    server_dir: &Path,
+    minecraft_version: &str,
+    forge_version: &str,
+    java: &Path,
+    progress: &InstallTracker,
+) -> anyhow::Result<()> {
+    // Downloading the installer, then running it
+    progress.phase(InstallPhase::Server, 2, 0);
+    let full_version = format!("{}-{}", minecraft_version, forge_version);
+    let installer = server_dir.join("forge-installer.jar");
+    storage::get_file(
+        &installer,
+        &format!(
+            "{}{}/forge-{}-installer.jar",
+            FORGE_MAVEN_URL, full_version, full_version
+        ),
+        false,
+        &Expected::default(),
+    )
+    .await?;
+    progress.add(1, 0);
+    let output = Command::new(java)
+        .arg("-jar")
+        .arg(&installer)
+        .arg("--installServer")
+        .current_dir(server_dir)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Forge installer failed: {}",
+            String::from_utf8_lossy(&output.stdout)
+        ));
+    }
+    tokio::fs::remove_file(&installer).await?;
+    progress.add(1, 0);
+    Ok(())
+}
+
+/// Downloads (or re-downloads) the server jar for a server instance.
+pub async fn install_server(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<()> {
+    instance::ensure_unlocked(app_handle, instance_id).await?;
+    tasks::run(
+        app_handle,
+        TaskKind::Install,
+        Message::new("task.installServer").with("instance", instance_id),
+        |task| async move {
+            let progress = InstallTracker::new(app_handle, &task);
+            install_server_files(app_handle, instance_id, &progress).await
+        },
+    )
+    .await
+}
+
+async fn install_server_files(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    progress: &InstallTracker,
+) -> anyhow::Result<()> {
+    let config = instance::read_config(app_handle, instance_id).await?;
+    let dir = instance::game_dir(app_handle, instance_id)?;
+    tokio::fs::create_dir_all(&dir).await?;
+    let minecraft_version = config
+        .minecraft_version
+        .as_deref()
+        .ok_or(anyhow!("Instance has no Minecraft version"))?;
+    match config
+        .server_loader
+        .as_ref()
+        .unwrap_or(&ServerLoader::Vanilla)
+    {
+        ServerLoader::Vanilla => install_vanilla(&dir, minecraft_version, progress).await?,
+        ServerLoader::Fabric { loader_version } => {
+            install_fabric(&dir, minecraft_version, loader_version.as_deref(), progress).await?
+        }
+        ServerLoader::Forge { forge_version } => {
+            // Which Java the server needs isn't known, any that runs
+            let java = java::select_for_instance(app_handle, &config, &[]).await?;
+            install_forge(&dir, minecraft_version, forge_version, &java.path, progress).await?
+        }
+    }
+    downloads::save_hash_cache().await;
+    info!("Installed server for {}", instance_id);
+    Ok(())
+}
+
+/// Works out the arguments after `java` needed to start the server.
+fn start_arguments(dir: &Path, config: &InstanceConfig) -> anyhow::Result<Vec<String>> {
+    let mut args = config.jvm_args.clone();
+    if let Some(ServerLoader::Forge { forge_version }) = &config.server_loader {
+        let minecraft_version = config.minecraft_version.as_deref().unwrap_or_default();
+        let full_version = format!("{}-{}", minecraft_version, forge_version);
+        // 1.17+ installers produce an argument file instead of a runnable jar
+        let args_file = format!(
+            "libraries/net/minecraftforge/forge/{}/{}",
+            full_version,
+            if cfg!(windows) {
+                "win_args.txt"
+            } else {
+                "unix_args.txt"
+            }
+        );
+        if dir.join(&args_file).is_file() {
+            args.push(format!("@{}", args_file));
+        } else {
+            let jar = [
+                format!("forge-{}.jar", full_version),
+                format!("forge-{}-universal.jar", full_version),
+            ]
+            .into_iter()
+            .find(|jar| dir.join(jar).is_file())
+            .ok_or(anyhow!("Can't find the Forge server jar, try reinstalling"))?;
+            args.push("-jar".to_string());
+            args.push(jar);
+        }
+    } else {
+        args.push("-jar".to_string());
+        args.push("server.jar".to_string());
+    }
+    args.push("nogui".to_string());
+    Ok(args)
+}
+
+pub async fn eula_accepted(dir: &Path) -> anyhow::Result<bool> {
+    match tokio::fs::read_to_string(dir.join("eula.txt")).await {
+        Ok(text) => Ok(text
+            .lines()
+            .any(|line| line.trim().eq_ignore_ascii_case("eula=true"))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Builds the command that runs a server instance, checking that it is
+/// ready to start.
+pub(crate) async fn server_command(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<Command> {
+    let config = instance::read_config(app_handle, instance_id).await?;
+    if config.kind != InstanceKind::Server {
+        return Err(anyhow!("{} is not a server instance", instance_id));
+    }
+    let dir = instance::game_dir(app_handle, instance_id)?;
+    if !eula_accepted(&dir).await? {
+        return Err(coded(
+            ErrorCode::EulaNotAccepted,
+            format!("The Minecraft EULA ({}) has to be accepted first", EULA_URL),
+        ));
+    }
+    let java = java::select_for_instance(app_handle, &config, &[]).await?;
+    let mut command = Command::new(&java.path);
+    command
+        .args(start_arguments(&dir, &config)?)
+        .current_dir(&dir);
+    Ok(command)
+}
+
+pub(crate) async fn start_server_inner(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<()> {
+    let command = server_command(app_handle, instance_id).await?;
+    launch_hooks::pre_launch(app_handle, instance_id).await?;
+    app_handle
+        .state::<ProcessManager>()
+        .spawn(app_handle, instance_id, command)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewServerInstance {
+    pub name: String,
+    pub minecraft_version: String,
+    pub loader: ServerLoader,
+}
+
+/// Creates a server instance and installs its server jar, returning its id.
+#[tauri::command]
+pub async fn create_server_instance(
+    app_handle: tauri::AppHandle,
+    server: NewServerInstance,
+) -> Result<String, CommandError> {
+    command_result(
+        async {
+            let config = InstanceConfig {
+                minecraft_version: Some(server.minecraft_version),
+                server_loader: Some(server.loader),
+                ..InstanceConfig::new(server.name, InstanceKind::Server)
+            };
+            let instance_id = instance::create_instance(&app_handle, &config).await?;
+            install_server(&app_handle, &instance_id).await?;
+            Ok(instance_id)
+        }
+        .await,
+    )
+}
+
+#[tauri::command]
+pub async fn get_eula_accepted(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+) -> Result<bool, CommandError> {
+    command_result(
+        async {
+            let dir = instance::game_dir(&app_handle, &instance_id)?;
+            eula_accepted(&dir).await
+        }
+        .await,
+    )
+}
+
+/// Records that the user accepted the EULA, after the frontend has shown it.
+#[tauri::command]
+pub async fn accept_eula(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            let dir = instance::game_dir(&app_handle, &instance_id)?;
+            let accepted_at = time::OffsetDateTime::now_utc()
+                .format(&time::format_description::well_known::Rfc3339)?;
+            tokio::fs::write(
+                dir.join("eula.txt"),
+                format!(
+                    "# Accepted through the launcher at {} ({})\neula=true\n",
+                    accepted_at, EULA_URL
+                ),
+            )
+            .await?;
+            Ok(())
+        }
+        .await,
+    )
+}
+
+#[tauri::command]
+pub async fn start_server(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+) -> Result<(), CommandError> {
+    command_result(start_server_inner(&app_handle, &instance_id).await)
+}
+
+/// Asks the server to shut down cleanly by sending `stop` to its console.
+#[tauri::command]
+pub async fn stop_server(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+) -> Result<(), CommandError> {
+    command_result(
+        app_handle
+            .state::<ProcessManager>()
+            .send_line(&instance_id, "stop")
+            .await,
+    )
+}
+
+#[tauri::command]
+pub fn kill_server(app_handle: tauri::AppHandle, instance_id: String) -> Result<(), CommandError> {
+    command_result(app_handle.state::<ProcessManager>().kill(&instance_id))
+}
+
+#[tauri::command]
+pub async fn send_server_command(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    command: String,
+) -> Result<(), CommandError> {
+    command_result(
+        app_handle
+            .state::<ProcessManager>()
+            .send_line(&instance_id, &command)
+            .await,
+    )
+}
@@ -0,0 +1,352 @@
+//! Installing Modrinth (`.mrpack`) and CurseForge server packs into server
+//! instances.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use zip::ZipArchive;
+
+use crate::{
+    command_result,
+    downloads::{self, DownloadJob},
+    errors::{coded, CommandError, ErrorCode},
+    history,
+    install_progress::{InstallPhase, InstallTracker},
+    instance::{self, InstanceKind},
+    messages::Message,
+    process::ProcessManager,
+    server_instance::{self, ServerLoader},
+    storage::{self, Expected},
+    tasks::{self, TaskHandle, TaskKind},
+    usage_stats,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MrpackIndex {
+    format_version: u32,
+    game: String,
+    name: String,
+    files: Vec<MrpackFile>,
+    dependencies: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MrpackFile {
+    path: String,
+    hashes: HashMap<String, String>,
+    file_size: Option<u64>,
+    env: Option<MrpackEnv>,
+    downloads: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MrpackEnv {
+    server: MrpackSide,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MrpackSide {
+    Required,
+    Optional,
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerPackResult {
+    pub name: Option<String>,
+    pub files_installed: usize,
+    /// Client-only files that were left out or disabled
+    pub skipped: Vec<String>,
+}
+
+/// Extracts every file below `prefix` in the archive into `dir`.
+fn extract_dir(archive: &mut ZipArchive<File>, prefix: &str, dir: &Path) -> anyhow::Result<usize> {
+    let mut count = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let relative = match entry.name().strip_prefix(prefix) {
+            Some(relative) if !relative.is_empty() => relative.to_string(),
+            _ => continue,
+        };
+        let path = instance::safe_join(dir, &relative)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::io::copy(&mut entry, &mut File::create(&path)?)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// If every entry sits in one top level folder, returns that folder so it
+/// can be stripped when extracting.
+fn common_root(archive: &mut ZipArchive<File>) -> Option<String> {
+    let mut root: Option<&str> = None;
+    for name in archive.file_names() {
+        let (first, _) = name.split_once('/')?;
+        match root {
+            Some(root) if root != first => return None,
+            _ => root = Some(first),
+        }
+    }
+    root.map(|root| format!("{}/", root))
+}
+
+/// Reads the Fabric/Quilt metadata of a mod jar and reports whether it
+/// declares itself client-only.
+fn is_client_only_mod(path: &Path) -> anyhow::Result<bool> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    for metadata in ["fabric.mod.json", "quilt.mod.json"] {
+        let mut json = String::new();
+        match archive.by_name(metadata) {
+            Ok(mut entry) => entry.read_to_string(&mut json)?,
+            Err(_) => continue,
+        };
+        let json: serde_json::Value = serde_json::from_str(&json)?;
+        let environment = json
+            .get("environment")
+            .or_else(|| json.pointer("/minecraft/environment"))
+            .and_then(serde_json::Value::as_str);
+        return Ok(environment == Some("client"));
+    }
+    Ok(false)
+}
+
+/// Renames client-only mods to `.jar.disabled` so the server doesn't crash
+/// trying to load them.
+fn disable_client_mods(server_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut disabled = vec![];
+    let entries = match std::fs::read_dir(server_dir.join("mods")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(disabled),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() != Some("jar".as_ref()) {
+            continue;
+        }
+        match is_client_only_mod(&path) {
+            Ok(true) => {
+                let mut disabled_path = path.clone().into_os_string();
+                disabled_path.push(".disabled");
+                std::fs::rename(&path, disabled_path)?;
+                disabled.push(
+                    path.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string(),
+                );
+            }
+            Ok(false) => {}
+            Err(e) => warn!("Can't read mod metadata of {:?}: {}", path, e),
+        }
+    }
+    Ok(disabled)
+}
+
+fn loader_from_dependencies(
+    dependencies: &HashMap<String, String>,
+) -> anyhow::Result<ServerLoader> {
+    if let Some(version) = dependencies.get("fabric-loader") {
+        Ok(ServerLoader::Fabric {
+            loader_version: Some(version.clone()),
+        })
+    } else if let Some(version) = dependencies.get("forge") {
+        Ok(ServerLoader::Forge {
+            forge_version: version.clone(),
+        })
+    } else if dependencies.contains_key("quilt-loader") {
+        Err(anyhow!("Quilt server packs are not supported yet"))
+    } else if dependencies.contains_key("neoforge") {
+        Err(anyhow!("NeoForge server packs are not supported yet"))
+    } else {
+        Ok(ServerLoader::Vanilla)
+    }
+}
+
+async fn install_mrpack(
+    app_handle: &tauri::AppHandle,
+    progress: &InstallTracker,
+    instance_id: &str,
+    pack: PathBuf,
+    server_dir: PathBuf,
+) -> anyhow::Result<ServerPackResult> {
+    let (index, mut archive) = {
+        let mut archive = ZipArchive::new(File::open(&pack)?)?;
+        let mut json = String::new();
+        archive
+            .by_name("modrinth.index.json")?
+            .read_to_string(&mut json)?;
+        let index: MrpackIndex = serde_json::from_str(&json)?;
+        (index, archive)
+    };
+    if index.format_version != 1 || index.game != "minecraft" {
+        return Err(anyhow!(
+            "Unsupported mrpack (format {}, game {})",
+            index.format_version,
+            index.game
+        ));
+    }
+
+    let mut result = ServerPackResult {
+        name: Some(index.name.clone()),
+        ..ServerPackResult::default()
+    };
+    let mut jobs = vec![];
+    for file in &index.files {
+        if file.env.as_ref().map(|env| env.server) == Some(MrpackSide::Unsupported) {
+            result.skipped.push(file.path.clone());
+            continue;
+        }
+        let url = file
+            .downloads
+            .first()
+            .ok_or(anyhow!("No download for {}", file.path))?;
+        jobs.push(
+            DownloadJob::new(url, instance::safe_join(&server_dir, &file.path)?)
+                .with_expected(
+                    Expected::sha1(file.hashes.get("sha1").map(String::as_str))
+                        .with_size(file.file_size),
+                )
+                .with_task(Some(progress.task().id())),
+        );
+    }
+    let needed = jobs.iter().filter_map(|job| job.expected.size).sum();
+    storage::ensure_free_space(&server_dir, needed).await?;
+    progress.phase(InstallPhase::Mods, jobs.len(), needed);
+    downloads::download_pool(app_handle, &jobs, |pool| {
+        progress.set(pool.files_done, pool.bytes_done)
+    })
+    .await?;
+    result.files_installed += jobs.len();
+
+    let dir = server_dir.clone();
+    result.files_installed += tokio::task::spawn_blocking(move || -> anyhow::Result<usize> {
+        Ok(extract_dir(&mut archive, "overrides/", &dir)?
+            + extract_dir(&mut archive, "server-overrides/", &dir)?)
+    })
+    .await??;
+
+    let mut config = instance::read_config(app_handle, instance_id).await?;
+    config.minecraft_version = Some(
+        index
+            .dependencies
+            .get("minecraft")
+            .ok_or(anyhow!("mrpack doesn't specify a Minecraft version"))?
+            .clone(),
+    );
+    config.server_loader = Some(loader_from_dependencies(&index.dependencies)?);
+    instance::write_config(app_handle, instance_id, &config).await?;
+    server_instance::install_server(app_handle, instance_id).await?;
+    Ok(result)
+}
+
+/// CurseForge server packs are a ready made server directory, usually
+/// wrapped in a single top level folder.
+fn install_server_zip(pack: PathBuf, server_dir: PathBuf) -> anyhow::Result<ServerPackResult> {
+    let mut archive = ZipArchive::new(File::open(pack)?)?;
+    let root = common_root(&mut archive).unwrap_or_default();
+    Ok(ServerPackResult {
+        files_installed: extract_dir(&mut archive, &root, &server_dir)?,
+        ..ServerPackResult::default()
+    })
+}
+
+pub(crate) async fn install_server_pack_inner(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    pack: PathBuf,
+) -> anyhow::Result<ServerPackResult> {
+    let title = Message::new("task.installServerPack")
+        .with(
+            "pack",
+            pack.file_name().unwrap_or_default().to_string_lossy(),
+        )
+        .with("instance", instance_id);
+    tasks::run(app_handle, TaskKind::Install, title, |task| {
+        install_server_pack_task(app_handle, task, instance_id, pack)
+    })
+    .await
+}
+
+async fn install_server_pack_task(
+    app_handle: &tauri::AppHandle,
+    task: TaskHandle,
+    instance_id: &str,
+    pack: PathBuf,
+) -> anyhow::Result<ServerPackResult> {
+    let config = instance::read_config(app_handle, instance_id).await?;
+    if config.kind != InstanceKind::Server {
+        return Err(anyhow!("{} is not a server instance", instance_id));
+    }
+    if app_handle.state::<ProcessManager>().is_running(instance_id) {
+        return Err(coded(
+            ErrorCode::InstanceRunning,
+            "Stop the server before installing a pack",
+        ));
+    }
+    instance::ensure_unlocked(app_handle, instance_id).await?;
+    history::auto_snapshot(
+        app_handle,
+        instance_id,
+        &format!(
+            "Before installing {}",
+            pack.file_name().unwrap_or_default().to_string_lossy()
+        ),
+    )
+    .await?;
+    let server_dir = instance::game_dir(app_handle, instance_id)?;
+    let is_mrpack = {
+        let pack = pack.clone();
+        tokio::task::spawn_blocking(move || -> anyhow::Result<bool> {
+            let mut archive = ZipArchive::new(File::open(pack)?)?;
+            let is_mrpack = archive.by_name("modrinth.index.json").is_ok();
+            Ok(is_mrpack)
+        })
+        .await??
+    };
+    let mut result = if is_mrpack {
+        let progress = InstallTracker::new(app_handle, &task);
+        install_mrpack(app_handle, &progress, instance_id, pack, server_dir.clone()).await?
+    } else {
+        let dir = server_dir.clone();
+        tokio::task::spawn_blocking(move || install_server_zip(pack, dir)).await??
+    };
+    let dir = server_dir.clone();
+    result
+        .skipped
+        .extend(tokio::task::spawn_blocking(move || disable_client_mods(&dir)).await??);
+    info!(
+        "Installed server pack into {}: {} files, skipped {:?}",
+        instance_id, result.files_installed, result.skipped
+    );
+    Ok(result)
+}
+
+/// Installs a local `.mrpack` or CurseForge server pack zip into a server
+/// instance, leaving out client-only mods.
+#[tauri::command]
+pub async fn install_server_pack(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    pack_path: PathBuf,
+) -> Result<ServerPackResult, CommandError> {
+    usage_stats::record_feature(&app_handle, "serverPackInstall");
+    command_result(install_server_pack_inner(&app_handle, &instance_id, pack_path).await)
+}
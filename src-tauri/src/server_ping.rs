@@ -0,0 +1,499 @@
+//! Server List Ping, both the modern (1.7+) JSON protocol and the legacy
+//! `0xFE` ping understood by older servers.
+
+use std::{
+    cmp::Reverse,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use log::trace;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::{command_result, errors::CommandError, instance, servers};
+
+const DEFAULT_PORT: u16 = 25565;
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+/// Protocol version sent in the handshake; servers answer status requests
+/// regardless of the version a client claims.
+const HANDSHAKE_PROTOCOL: i32 = -1;
+/// Largest packet accepted from a server, since the length comes from the
+/// server. Real status responses are a few KiB.
+const MAX_PACKET_LEN: usize = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatus {
+    pub version_name: String,
+    pub protocol: i32,
+    pub players_online: i64,
+    pub players_max: i64,
+    pub player_sample: Vec<PlayerSample>,
+    pub motd: Vec<MotdSegment>,
+    pub latency_ms: u64,
+    /// PNG bytes of the server icon
+    pub favicon: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerSample {
+    pub name: String,
+    pub id: String,
+}
+
+/// A run of MOTD text sharing the same formatting.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MotdSegment {
+    pub text: String,
+    pub color: Option<String>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underlined: bool,
+    pub strikethrough: bool,
+    pub obfuscated: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusResponse {
+    version: StatusVersion,
+    players: Option<StatusPlayers>,
+    description: Option<Value>,
+    favicon: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusVersion {
+    name: String,
+    protocol: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusPlayers {
+    max: i64,
+    online: i64,
+    sample: Option<Vec<PlayerSample>>,
+}
+
+/// Splits an address into host and port, handling `[v6]:port` literals.
+pub fn split_address(address: &str) -> anyhow::Result<(String, Option<u16>)> {
+    let address = address.trim();
+    if let Some(rest) = address.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or(anyhow!("Unterminated IPv6 literal in {:?}", address))?;
+        let port = match rest.strip_prefix(':') {
+            Some(port) => Some(port.parse()?),
+            None => None,
+        };
+        return Ok((host.to_string(), port));
+    }
+    match address.rsplit_once(':') {
+        // More than one colon is a bare IPv6 address
+        Some((host, port)) if !host.contains(':') => Ok((host.to_string(), Some(port.parse()?))),
+        _ => Ok((address.to_string(), None)),
+    }
+}
+
+/// Resolves an address to the host and port to connect to. Like the game,
+/// addresses without an explicit port go through the `_minecraft._tcp` SRV
+/// record first, falling back to the default port.
+pub async fn resolve_address(address: &str) -> anyhow::Result<(String, u16)> {
+    let (host, port) = split_address(address)?;
+    if let Some(port) = port {
+        return Ok((host, port));
+    }
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok((host, DEFAULT_PORT));
+    }
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+    match resolver
+        .srv_lookup(format!("_minecraft._tcp.{}.", host.trim_end_matches('.')))
+        .await
+    {
+        Ok(lookup) => {
+            let srv = lookup
+                .iter()
+                .min_by_key(|srv| (srv.priority(), Reverse(srv.weight())));
+            if let Some(srv) = srv {
+                let target = srv.target().to_utf8();
+                trace!("Resolved SRV for {} to {}:{}", host, target, srv.port());
+                return Ok((target.trim_end_matches('.').to_string(), srv.port()));
+            }
+        }
+        Err(e) => trace!("No SRV record for {}: {}", host, e),
+    }
+    Ok((host, DEFAULT_PORT))
+}
+
+pub async fn ping(address: &str) -> anyhow::Result<ServerStatus> {
+    let (host, port) = resolve_address(address).await?;
+    match timeout(PING_TIMEOUT, ping_modern(&host, port)).await {
+        Ok(Ok(status)) => Ok(status),
+        modern => {
+            trace!(
+                "Modern ping of {} failed ({:?}), trying legacy",
+                address,
+                modern
+            );
+            timeout(PING_TIMEOUT, ping_legacy(&host, port))
+                .await
+                .map_err(|_| anyhow!("Timed out pinging {}", address))?
+        }
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7f == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+async fn read_varint(reader: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<i32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let byte = reader.read_u8().await?;
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(anyhow!("VarInt too long"))
+}
+
+fn packet(id: i32, data: &[u8]) -> Vec<u8> {
+    let mut body = vec![];
+    write_varint(&mut body, id);
+    body.extend_from_slice(data);
+    let mut packet = vec![];
+    write_varint(&mut packet, body.len() as i32);
+    packet.extend(body);
+    packet
+}
+
+async fn read_packet(stream: &mut TcpStream) -> anyhow::Result<(i32, Vec<u8>)> {
+    let len = read_varint(stream).await?;
+    let len = usize::try_from(len).map_err(|_| anyhow!("Negative packet length"))?;
+    if len > MAX_PACKET_LEN {
+        return Err(anyhow!("Packet of {} bytes is too large", len));
+    }
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).await?;
+    let mut cursor = std::io::Cursor::new(body);
+    let id = read_varint(&mut cursor).await?;
+    let offset = cursor.position() as usize;
+    Ok((id, cursor.into_inner().split_off(offset)))
+}
+
+async fn ping_modern(host: &str, port: u16) -> anyhow::Result<ServerStatus> {
+    let mut stream = TcpStream::connect((host, port)).await?;
+
+    let mut handshake = vec![];
+    write_varint(&mut handshake, HANDSHAKE_PROTOCOL);
+    write_varint(&mut handshake, host.len() as i32);
+    handshake.extend_from_slice(host.as_bytes());
+    handshake.extend_from_slice(&port.to_be_bytes());
+    write_varint(&mut handshake, 1);
+    stream.write_all(&packet(0, &handshake)).await?;
+    let status_start = Instant::now();
+    stream.write_all(&packet(0, &[])).await?;
+
+    let (id, data) = read_packet(&mut stream).await?;
+    let status_latency = status_start.elapsed();
+    if id != 0 {
+        return Err(anyhow!(
+            "Unexpected packet {} instead of status response",
+            id
+        ));
+    }
+    let mut cursor = std::io::Cursor::new(data);
+    let json_len = usize::try_from(read_varint(&mut cursor).await?)
+        .map_err(|_| anyhow!("Negative status response length"))?;
+    let offset = cursor.position() as usize;
+    let data = cursor.into_inner();
+    let json = offset
+        .checked_add(json_len)
+        .and_then(|end| data.get(offset..end))
+        .ok_or(anyhow!("Truncated status response"))?;
+    let response: StatusResponse = serde_json::from_slice(json)?;
+
+    let start = Instant::now();
+    let payload = time::OffsetDateTime::now_utc().unix_timestamp();
+    stream.write_all(&packet(1, &payload.to_be_bytes())).await?;
+    // Some servers never answer the ping, in which case the status round
+    // trip is the best latency estimate we have
+    let latency = match timeout(Duration::from_secs(1), read_packet(&mut stream)).await {
+        Ok(Ok((1, _))) => start.elapsed(),
+        _ => status_latency,
+    };
+
+    let players = response.players.unwrap_or(StatusPlayers {
+        max: 0,
+        online: 0,
+        sample: None,
+    });
+    Ok(ServerStatus {
+        version_name: response.version.name,
+        protocol: response.version.protocol,
+        players_online: players.online,
+        players_max: players.max,
+        player_sample: players.sample.unwrap_or_default(),
+        motd: response
+            .description
+            .as_ref()
+            .map(parse_chat_component)
+            .unwrap_or_default(),
+        latency_ms: latency.as_millis() as u64,
+        favicon: match response.favicon {
+            Some(favicon) => Some(decode_favicon(&favicon)?),
+            None => None,
+        },
+    })
+}
+
+fn decode_favicon(favicon: &str) -> anyhow::Result<Vec<u8>> {
+    let data = favicon
+        .strip_prefix("data:image/png;base64,")
+        .ok_or(anyhow!("Favicon is not a PNG data URL"))?;
+    // The game wraps long base64 strings, so strip the newlines out
+    let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    Ok(base64::decode(data)?)
+}
+
+async fn ping_legacy(host: &str, port: u16) -> anyhow::Result<ServerStatus> {
+    let start = Instant::now();
+    let mut stream = TcpStream::connect((host, port)).await?;
+    stream.write_all(&[0xfe, 0x01]).await?;
+    if stream.read_u8().await? != 0xff {
+        return Err(anyhow!("Invalid legacy ping response"));
+    }
+    let len = stream.read_u16().await?;
+    let mut units = Vec::with_capacity(len.into());
+    for _ in 0..len {
+        units.push(stream.read_u16().await?);
+    }
+    let latency = start.elapsed();
+    let response = String::from_utf16(&units)?;
+
+    let (protocol, version_name, motd, online, max) =
+        if let Some(response) = response.strip_prefix("\u{a7}1\0") {
+            // 1.4 - 1.6: §1\0protocol\0version\0motd\0online\0max
+            let fields: Vec<&str> = response.split('\0').collect();
+            if fields.len() != 5 {
+                return Err(anyhow!("Invalid legacy ping response"));
+            }
+            (
+                fields[0].parse()?,
+                fields[1].to_string(),
+                fields[2].to_string(),
+                fields[3],
+                fields[4],
+            )
+        } else {
+            // Beta 1.8 - 1.3: motd§online§max
+            let mut fields = response.rsplitn(3, '\u{a7}');
+            let max = fields
+                .next()
+                .ok_or(anyhow!("Invalid legacy ping response"))?;
+            let online = fields
+                .next()
+                .ok_or(anyhow!("Invalid legacy ping response"))?;
+            let motd = fields
+                .next()
+                .ok_or(anyhow!("Invalid legacy ping response"))?;
+            (-1, String::new(), motd.to_string(), online, max)
+        };
+    Ok(ServerStatus {
+        version_name,
+        protocol,
+        players_online: online.parse()?,
+        players_max: max.parse()?,
+        player_sample: vec![],
+        motd: parse_formatting_codes(&motd, &MotdSegment::default()),
+        latency_ms: latency.as_millis() as u64,
+        favicon: None,
+    })
+}
+
+fn color_code(code: char) -> Option<&'static str> {
+    Some(match code {
+        '0' => "black",
+        '1' => "dark_blue",
+        '2' => "dark_green",
+        '3' => "dark_aqua",
+        '4' => "dark_red",
+        '5' => "dark_purple",
+        '6' => "gold",
+        '7' => "gray",
+        '8' => "dark_gray",
+        '9' => "blue",
+        'a' => "green",
+        'b' => "aqua",
+        'c' => "red",
+        'd' => "light_purple",
+        'e' => "yellow",
+        'f' => "white",
+        _ => return None,
+    })
+}
+
+/// Splits legacy `§`-formatted text into segments, starting from `base`.
+pub fn parse_formatting_codes(text: &str, base: &MotdSegment) -> Vec<MotdSegment> {
+    let mut segments = vec![];
+    let mut current = MotdSegment {
+        text: String::new(),
+        ..base.clone()
+    };
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\u{a7}' {
+            current.text.push(c);
+            continue;
+        }
+        let code = match chars.next() {
+            Some(code) => code.to_ascii_lowercase(),
+            None => break,
+        };
+        if !current.text.is_empty() {
+            segments.push(current.clone());
+            current.text.clear();
+        }
+        if let Some(color) = color_code(code) {
+            // Colors reset all other formatting
+            current = MotdSegment {
+                color: Some(color.to_string()),
+                ..MotdSegment::default()
+            };
+        } else {
+            match code {
+                'k' => current.obfuscated = true,
+                'l' => current.bold = true,
+                'm' => current.strikethrough = true,
+                'n' => current.underlined = true,
+                'o' => current.italic = true,
+                'r' => {
+                    current = MotdSegment {
+                        text: String::new(),
+                        ..base.clone()
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    if !current.text.is_empty() {
+        segments.push(current);
+    }
+    segments
+}
+
+/// Flattens a JSON chat component into formatted segments.
+pub fn parse_chat_component(component: &Value) -> Vec<MotdSegment> {
+    let mut segments = vec![];
+    flatten_component(component, &MotdSegment::default(), &mut segments);
+    segments
+}
+
+fn flatten_component(component: &Value, parent: &MotdSegment, out: &mut Vec<MotdSegment>) {
+    match component {
+        Value::String(text) => out.extend(parse_formatting_codes(text, parent)),
+        Value::Array(components) => {
+            for component in components {
+                flatten_component(component, parent, out);
+            }
+        }
+        Value::Object(object) => {
+            let flag = |name: &str, inherited: bool| {
+                object
+                    .get(name)
+                    .and_then(Value::as_bool)
+                    .unwrap_or(inherited)
+            };
+            let style = MotdSegment {
+                text: String::new(),
+                color: object
+                    .get("color")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .or_else(|| parent.color.clone()),
+                bold: flag("bold", parent.bold),
+                italic: flag("italic", parent.italic),
+                underlined: flag("underlined", parent.underlined),
+                strikethrough: flag("strikethrough", parent.strikethrough),
+                obfuscated: flag("obfuscated", parent.obfuscated),
+            };
+            if let Some(text) = object.get("text").and_then(Value::as_str) {
+                out.extend(parse_formatting_codes(text, &style));
+            }
+            if let Some(extra) = object.get("extra") {
+                flatten_component(extra, &style, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerPingResult {
+    pub address: String,
+    pub status: Option<ServerStatus>,
+    pub error: Option<CommandError>,
+}
+
+#[tauri::command]
+pub async fn ping_server(address: String) -> Result<ServerStatus, CommandError> {
+    command_result(ping(&address).await)
+}
+
+/// Pings every server saved in an instance's `servers.dat` concurrently.
+#[tauri::command]
+pub async fn ping_instance_servers(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+) -> Result<Vec<ServerPingResult>, CommandError> {
+    command_result(ping_instance_servers_inner(&app_handle, &instance_id).await)
+}
+
+async fn ping_instance_servers_inner(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<Vec<ServerPingResult>> {
+    let path = instance::game_dir(app_handle, instance_id)?.join("servers.dat");
+    let handles: Vec<_> = servers::read_servers(&path)
+        .await?
+        .into_iter()
+        .map(|server| tokio::spawn(async move { (ping(&server.ip).await, server.ip) }))
+        .collect();
+    let mut results = vec![];
+    for handle in handles {
+        let (status, address) = handle.await?;
+        results.push(match status {
+            Ok(status) => ServerPingResult {
+                address,
+                status: Some(status),
+                error: None,
+            },
+            Err(e) => ServerPingResult {
+                address,
+                status: None,
+                error: Some(e.into()),
+            },
+        });
+    }
+    Ok(results)
+}
@@ -0,0 +1,322 @@
+use std::{io::Cursor, path::Path};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::{
+    command_result,
+    errors::CommandError,
+    instance,
+    nbt::{self, Compound, Tag},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerEntry {
+    pub name: String,
+    pub ip: String,
+    /// Base64 encoded PNG, as stored by the game
+    pub icon: Option<String>,
+    /// `None` means the game will prompt for server resource packs
+    pub accept_textures: Option<bool>,
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+impl ServerEntry {
+    fn from_compound(compound: &Compound) -> anyhow::Result<Self> {
+        Ok(ServerEntry {
+            name: compound
+                .get("name")
+                .and_then(Tag::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            ip: compound
+                .get("ip")
+                .and_then(Tag::as_str)
+                .ok_or(anyhow!("Server entry has no ip"))?
+                .to_string(),
+            icon: compound
+                .get("icon")
+                .and_then(Tag::as_str)
+                .map(str::to_string),
+            accept_textures: compound
+                .get("acceptTextures")
+                .and_then(Tag::as_byte)
+                .map(|b| b != 0),
+            hidden: compound.get("hidden").and_then(Tag::as_byte).unwrap_or(0) != 0,
+        })
+    }
+
+    fn to_compound(&self) -> Compound {
+        let mut compound = Compound::new();
+        compound.insert("name".to_string(), Tag::String(self.name.clone()));
+        compound.insert("ip".to_string(), Tag::String(self.ip.clone()));
+        if let Some(icon) = &self.icon {
+            compound.insert("icon".to_string(), Tag::String(icon.clone()));
+        }
+        if let Some(accept_textures) = self.accept_textures {
+            compound.insert(
+                "acceptTextures".to_string(),
+                Tag::Byte(accept_textures.into()),
+            );
+        }
+        if self.hidden {
+            compound.insert("hidden".to_string(), Tag::Byte(1));
+        }
+        compound
+    }
+}
+
+pub fn parse_servers_dat(data: &[u8]) -> anyhow::Result<Vec<ServerEntry>> {
+    let (_, root) = nbt::read(&mut Cursor::new(data))?;
+    let root = root
+        .as_compound()
+        .ok_or(anyhow!("servers.dat root is not a compound"))?;
+    let servers = match root.get("servers") {
+        Some(servers) => servers
+            .as_list()
+            .ok_or(anyhow!("servers.dat `servers` is not a list"))?,
+        None => return Ok(vec![]),
+    };
+    servers
+        .iter()
+        .map(|server| {
+            ServerEntry::from_compound(
+                server
+                    .as_compound()
+                    .ok_or(anyhow!("Server entry is not a compound"))?,
+            )
+        })
+        .collect()
+}
+
+pub fn serialize_servers_dat(servers: &[ServerEntry]) -> anyhow::Result<Vec<u8>> {
+    let mut root = Compound::new();
+    root.insert(
+        "servers".to_string(),
+        Tag::List(
+            servers
+                .iter()
+                .map(|server| Tag::Compound(server.to_compound()))
+                .collect(),
+        ),
+    );
+    let mut data = vec![];
+    nbt::write(&mut data, "", &Tag::Compound(root))?;
+    Ok(data)
+}
+
+pub async fn read_servers(path: &Path) -> anyhow::Result<Vec<ServerEntry>> {
+    match tokio::fs::read(path).await {
+        Ok(data) => parse_servers_dat(&data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn write_servers(path: &Path, servers: &[ServerEntry]) -> anyhow::Result<()> {
+    let data = serialize_servers_dat(servers)?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp = path.with_extension("dat.tmp");
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+fn servers_dat_path(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<std::path::PathBuf> {
+    Ok(instance::game_dir(app_handle, instance_id)?.join("servers.dat"))
+}
+
+/// Reads the server list, applies `edit` to it and writes it back, unless
+/// the instance is locked.
+async fn modify_servers(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    edit: impl FnOnce(&mut Vec<ServerEntry>) -> anyhow::Result<()>,
+) -> anyhow::Result<Vec<ServerEntry>> {
+    instance::ensure_unlocked(app_handle, instance_id).await?;
+    let path = servers_dat_path(app_handle, instance_id)?;
+    let mut servers = read_servers(&path).await?;
+    edit(&mut servers)?;
+    write_servers(&path, &servers).await?;
+    app_handle.emit_all("instance:servers:changed", instance_id)?;
+    Ok(servers)
+}
+
+fn check_index(servers: &[ServerEntry], index: usize) -> anyhow::Result<()> {
+    if index >= servers.len() {
+        return Err(anyhow!(
+            "Server index {} out of range ({} servers)",
+            index,
+            servers.len()
+        ));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_servers(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+) -> Result<Vec<ServerEntry>, CommandError> {
+    let path = command_result(servers_dat_path(&app_handle, &instance_id))?;
+    command_result(read_servers(&path).await)
+}
+
+/// Inserts `server` at `index`, or appends it when no index is given.
+#[tauri::command]
+pub async fn add_server(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    server: ServerEntry,
+    index: Option<usize>,
+) -> Result<Vec<ServerEntry>, CommandError> {
+    command_result(
+        modify_servers(&app_handle, &instance_id, |servers| {
+            match index {
+                Some(index) if index <= servers.len() => servers.insert(index, server),
+                Some(index) => return Err(anyhow!("Server index {} out of range", index)),
+                None => servers.push(server),
+            }
+            Ok(())
+        })
+        .await,
+    )
+}
+
+#[tauri::command]
+pub async fn edit_server(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    index: usize,
+    server: ServerEntry,
+) -> Result<Vec<ServerEntry>, CommandError> {
+    command_result(
+        modify_servers(&app_handle, &instance_id, |servers| {
+            check_index(servers, index)?;
+            servers[index] = server;
+            Ok(())
+        })
+        .await,
+    )
+}
+
+#[tauri::command]
+pub async fn move_server(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    from: usize,
+    to: usize,
+) -> Result<Vec<ServerEntry>, CommandError> {
+    command_result(
+        modify_servers(&app_handle, &instance_id, |servers| {
+            check_index(servers, from)?;
+            check_index(servers, to)?;
+            let server = servers.remove(from);
+            servers.insert(to, server);
+            Ok(())
+        })
+        .await,
+    )
+}
+
+#[tauri::command]
+pub async fn remove_server(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    index: usize,
+) -> Result<Vec<ServerEntry>, CommandError> {
+    command_result(
+        modify_servers(&app_handle, &instance_id, |servers| {
+            check_index(servers, index)?;
+            servers.remove(index);
+            Ok(())
+        })
+        .await,
+    )
+}
+
+/// Normalizes an address for duplicate detection, so `Example.com` and
+/// `example.com:25565` count as the same server.
+fn address_key(address: &str) -> String {
+    let address = address.trim().to_ascii_lowercase();
+    match address.strip_suffix(":25565") {
+        Some(host) => host.to_string(),
+        None => address,
+    }
+}
+
+/// Appends the servers from `source` that `target` doesn't have yet,
+/// returning how many were added.
+pub fn merge_servers(target: &mut Vec<ServerEntry>, source: &[ServerEntry]) -> usize {
+    let mut added = 0;
+    for server in source {
+        let key = address_key(&server.ip);
+        if target
+            .iter()
+            .any(|existing| address_key(&existing.ip) == key)
+        {
+            continue;
+        }
+        target.push(server.clone());
+        added += 1;
+    }
+    added
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ServerSyncMode {
+    /// Add missing servers, keeping the target's own entries
+    Merge,
+    /// Make the target's list identical to the source's
+    Replace,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerSyncResult {
+    pub instance_id: String,
+    pub added: usize,
+    pub error: Option<String>,
+}
+
+/// Copies the server list of one instance into others.
+#[tauri::command]
+pub async fn sync_servers(
+    app_handle: tauri::AppHandle,
+    source_id: String,
+    target_ids: Vec<String>,
+    mode: ServerSyncMode,
+) -> Result<Vec<ServerSyncResult>, CommandError> {
+    let source_path = command_result(servers_dat_path(&app_handle, &source_id))?;
+    let source = command_result(read_servers(&source_path).await)?;
+    let mut results = vec![];
+    for target_id in target_ids {
+        if target_id == source_id {
+            continue;
+        }
+        let mut added = 0;
+        let result = modify_servers(&app_handle, &target_id, |servers| {
+            if mode == ServerSyncMode::Replace {
+                servers.clear();
+            }
+            added = merge_servers(servers, &source);
+            Ok(())
+        })
+        .await;
+        results.push(ServerSyncResult {
+            instance_id: target_id,
+            added,
+            error: result.err().map(|e| format!("{}", e)),
+        });
+    }
+    Ok(results)
+}
@@ -0,0 +1,47 @@
+//! Handling launches of the launcher while it is already running. The
+//! single instance plugin hands the new process' arguments to this one and
+//! exits the new process.
+
+use log::{error, info};
+use serde::Serialize;
+use tauri::Manager;
+
+use crate::{
+    cli::{self, CliCommand},
+    deep_link, tray,
+};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecondInstance {
+    /// Arguments without the executable path
+    pub args: Vec<String>,
+    pub cwd: String,
+}
+
+/// Focuses the existing window and runs `--launch` or deep links of the
+/// second invocation. Anything else is forwarded as a
+/// `launcher:second-instance` event.
+pub fn on_second_instance(app_handle: &tauri::AppHandle, argv: Vec<String>, cwd: String) {
+    info!("Launcher started again with {:?}", argv);
+    tray::show_main_window(app_handle);
+    let args: Vec<String> = argv.into_iter().skip(1).collect();
+    if let Ok(Some(CliCommand::Launch(instance_id))) = cli::parse(args.clone()) {
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = tray::quick_launch(&app_handle, &instance_id).await {
+                error!(
+                    "Failed to launch {} from the command line: {:#}",
+                    instance_id, e
+                );
+            }
+        });
+        return;
+    }
+    if deep_link::handle_args(app_handle, &args) {
+        return;
+    }
+    if let Err(e) = app_handle.emit_all("launcher:second-instance", SecondInstance { args, cwd }) {
+        error!("{:#?}", e);
+    }
+}
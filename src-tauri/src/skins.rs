@@ -0,0 +1,466 @@
+//! Changing the skin, cape and name of an account through the Minecraft
+//! profile API, and rendering the player heads shown in the account
+//! switcher.
+
+use std::{io::Cursor, path::PathBuf};
+
+use anyhow::anyhow;
+use image::{imageops, DynamicImage, ImageOutputFormat};
+use reqwest::multipart::{Form, Part};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    accounts::{self, AccountKind, Cape, Profile},
+    auth::{self, AuthClient, AuthResponse},
+    command_result,
+    errors::{coded, CommandError, ErrorCode},
+    proxy,
+};
+
+const SKINS_URL: &str = "https://api.minecraftservices.com/minecraft/profile/skins";
+const ACTIVE_SKIN_URL: &str = "https://api.minecraftservices.com/minecraft/profile/skins/active";
+const ACTIVE_CAPE_URL: &str = "https://api.minecraftservices.com/minecraft/profile/capes/active";
+const NAME_URL: &str = "https://api.minecraftservices.com/minecraft/profile/name";
+const NAME_CHANGE_URL: &str = "https://api.minecraftservices.com/minecraft/profile/namechange";
+/// How long after a name change the next one is allowed
+const NAME_CHANGE_COOLDOWN: time::Duration = time::Duration::days(30);
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// Where the face and the hat layer on top of it are in a skin
+const HEAD_POSITION: (u32, u32) = (8, 8);
+const HAT_POSITION: (u32, u32) = (40, 8);
+const HEAD_SIZE: u32 = 8;
+const DEFAULT_HEAD_SCALE: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SkinVariant {
+    Classic,
+    Slim,
+}
+
+impl SkinVariant {
+    fn as_str(self) -> &'static str {
+        match self {
+            SkinVariant::Classic => "classic",
+            SkinVariant::Slim => "slim",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum NameAvailability {
+    Available,
+    /// Someone else has it
+    Duplicate,
+    /// Blocked, e.g. for being offensive
+    NotAllowed,
+}
+
+#[derive(Debug, Deserialize)]
+struct NameAvailabilityResponse {
+    status: NameAvailability,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NameChangeResponse {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    changed_at: Option<OffsetDateTime>,
+    name_change_allowed: bool,
+}
+
+/// Whether the name of an account can be changed right now.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NameChangeInfo {
+    pub allowed: bool,
+    /// Unix timestamp of the last change, if the name was ever changed
+    pub changed_at: Option<i64>,
+    /// Unix timestamp when the cooldown after the last change ends
+    pub next_change_at: Option<i64>,
+}
+
+/// Checks that `data` is a PNG with the size of a skin, 64x64 or the legacy
+/// 64x32.
+fn check_skin(data: &[u8]) -> anyhow::Result<()> {
+    // The size is at the start of the IHDR chunk, right after the signature
+    if data.len() < 24 || !data.starts_with(PNG_SIGNATURE) {
+        return Err(coded(ErrorCode::InvalidData, "Skins have to be PNG images"));
+    }
+    let width = u32::from_be_bytes(data[16..20].try_into()?);
+    let height = u32::from_be_bytes(data[20..24].try_into()?);
+    if width != 64 || (height != 64 && height != 32) {
+        return Err(coded(
+            ErrorCode::InvalidData,
+            format!("Skins have to be 64x64 or 64x32, not {}x{}", width, height),
+        ));
+    }
+    Ok(())
+}
+
+/// The URL of the skin the account with `id`, or the active one, wears.
+async fn active_skin_url(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+) -> anyhow::Result<String> {
+    accounts::get(app_handle, id)
+        .await?
+        .profile
+        .ok_or_else(|| coded(ErrorCode::NotFound, "The account has no Minecraft profile"))?
+        .skins
+        .into_iter()
+        .find(|skin| skin.state == "ACTIVE")
+        .map(|skin| skin.url)
+        .ok_or_else(|| coded(ErrorCode::NotFound, "The account has no skin"))
+}
+
+/// Sends the request built by `request` to the profile API for the account
+/// with `id`, or the active one. Returns the id of the account with the
+/// response.
+async fn profile_api(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+    request: impl Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+) -> anyhow::Result<(String, AuthResponse)> {
+    let account = accounts::get(app_handle, id).await?;
+    if account.kind == AccountKind::Offline {
+        return Err(coded(
+            ErrorCode::Unsupported,
+            "Offline accounts have no profile to change",
+        ));
+    }
+    let token = auth::minecraft_access_token(app_handle, Some(&account.id)).await?;
+    let resp = AuthClient::new(app_handle)?
+        .send(|http| request(http).bearer_auth(&token))
+        .await?;
+    Ok((account.id, resp))
+}
+
+fn unexpected_status(resp: &AuthResponse) -> anyhow::Error {
+    coded(
+        ErrorCode::AuthFailed,
+        format!("The profile API answered {}: {}", resp.status, resp.data),
+    )
+}
+
+/// Sends the request built by `request` to the profile API for the account
+/// with `id`, or the active one, and stores the profile it answers with.
+pub async fn profile_request(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+    request: impl Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+) -> anyhow::Result<Profile> {
+    let (account_id, resp) = profile_api(app_handle, id, request).await?;
+    if resp.status != 200 {
+        return Err(unexpected_status(&resp));
+    }
+    let profile: Profile = serde_json::from_value(resp.data)?;
+    accounts::update_profile(app_handle, &account_id, profile.clone()).await?;
+    Ok(profile)
+}
+
+async fn upload_skin_inner(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+    path: PathBuf,
+    variant: SkinVariant,
+) -> anyhow::Result<Profile> {
+    let data = tokio::fs::read(&path).await?;
+    check_skin(&data)?;
+    let request = |http: &reqwest::Client| {
+        let file = Part::bytes(data.clone())
+            .file_name("skin.png")
+            .mime_str("image/png")
+            .expect("image/png is a valid MIME type");
+        http.post(SKINS_URL).multipart(
+            Form::new()
+                .text("variant", variant.as_str())
+                .part("file", file),
+        )
+    };
+    profile_request(app_handle, id, request).await
+}
+
+async fn set_skin_variant_inner(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+    variant: SkinVariant,
+) -> anyhow::Result<Profile> {
+    let skin_url = active_skin_url(app_handle, id).await?;
+    // Setting the current skin again by URL avoids uploading it
+    let request = |http: &reqwest::Client| {
+        http.post(SKINS_URL)
+            .json(&serde_json::json!({ "variant": variant.as_str(), "url": skin_url }))
+    };
+    profile_request(app_handle, id, request).await
+}
+
+/// Uploads a PNG skin for the account with `id`, or the active one.
+#[tauri::command]
+pub async fn upload_skin(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+    path: PathBuf,
+    variant: SkinVariant,
+) -> Result<Profile, CommandError> {
+    command_result(upload_skin_inner(&app_handle, id.as_deref(), path, variant).await)
+}
+
+/// Switches the current skin between the classic and slim arms.
+#[tauri::command]
+pub async fn set_skin_variant(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+    variant: SkinVariant,
+) -> Result<Profile, CommandError> {
+    command_result(set_skin_variant_inner(&app_handle, id.as_deref(), variant).await)
+}
+
+/// Goes back to the default skin.
+#[tauri::command]
+pub async fn reset_skin(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+) -> Result<Profile, CommandError> {
+    command_result(
+        async {
+            let request = |http: &reqwest::Client| http.delete(ACTIVE_SKIN_URL);
+            profile_request(&app_handle, id.as_deref(), request).await
+        }
+        .await,
+    )
+}
+
+/// The capes the account with `id`, or the active one, owns, fetched fresh
+/// since new ones can be granted at any time.
+#[tauri::command]
+pub async fn get_capes(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+) -> Result<Vec<Cape>, CommandError> {
+    command_result(
+        async {
+            let request = |http: &reqwest::Client| http.get(auth::PROFILE_URL);
+            Ok(profile_request(&app_handle, id.as_deref(), request)
+                .await?
+                .capes)
+        }
+        .await,
+    )
+}
+
+/// Shows the owned cape `cape_id`.
+#[tauri::command]
+pub async fn equip_cape(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+    cape_id: String,
+) -> Result<Profile, CommandError> {
+    command_result(
+        async {
+            let request = |http: &reqwest::Client| {
+                http.put(ACTIVE_CAPE_URL)
+                    .json(&serde_json::json!({ "capeId": cape_id }))
+            };
+            profile_request(&app_handle, id.as_deref(), request).await
+        }
+        .await,
+    )
+}
+
+/// Hides the cape.
+#[tauri::command]
+pub async fn disable_cape(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+) -> Result<Profile, CommandError> {
+    command_result(
+        async {
+            let request = |http: &reqwest::Client| http.delete(ACTIVE_CAPE_URL);
+            profile_request(&app_handle, id.as_deref(), request).await
+        }
+        .await,
+    )
+}
+
+/// Crops the face and hat out of a skin and scales them up without
+/// blurring the pixels.
+fn render_head(skin: &[u8], scale: u32) -> anyhow::Result<Vec<u8>> {
+    let skin = image::load_from_memory(skin)?.to_rgba8();
+    let mut head = imageops::crop_imm(
+        &skin,
+        HEAD_POSITION.0,
+        HEAD_POSITION.1,
+        HEAD_SIZE,
+        HEAD_SIZE,
+    )
+    .to_image();
+    let hat = imageops::crop_imm(&skin, HAT_POSITION.0, HAT_POSITION.1, HEAD_SIZE, HEAD_SIZE);
+    imageops::overlay(&mut head, &*hat, 0, 0);
+    let head = imageops::resize(
+        &head,
+        HEAD_SIZE * scale,
+        HEAD_SIZE * scale,
+        imageops::FilterType::Nearest,
+    );
+    let mut png = Cursor::new(vec![]);
+    DynamicImage::ImageRgba8(head).write_to(&mut png, ImageOutputFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+async fn player_head_inner(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+    scale: u32,
+) -> anyhow::Result<String> {
+    let skin_url = active_skin_url(app_handle, id).await?;
+    // Texture URLs end in the hash of the texture, so a changed skin gets a
+    // new file
+    let texture = skin_url.rsplit('/').next().unwrap_or_default();
+    let path = app_handle
+        .path_resolver()
+        .app_data_dir()
+        .ok_or(anyhow!("Can't get app data dir"))?
+        .join("heads")
+        .join(format!("{}-{}.png", texture, scale));
+    let png = match tokio::fs::read(&path).await {
+        Ok(png) => png,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let resp = proxy::client().get(&skin_url).send().await?;
+            if resp.status() != reqwest::StatusCode::OK {
+                return Err(coded(
+                    ErrorCode::DownloadFailed,
+                    format!("Downloading the skin failed with {}", resp.status()),
+                ));
+            }
+            let skin = resp.bytes().await?;
+            let png = tokio::task::spawn_blocking(move || render_head(&skin, scale)).await??;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&path, &png).await?;
+            png
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(format!("data:image/png;base64,{}", base64::encode(png)))
+}
+
+/// The face of the account with `id`, or the active one, as a PNG data URL.
+/// Every skin pixel becomes `scale` by `scale` pixels, 8 by default.
+#[tauri::command]
+pub async fn get_player_head(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+    scale: Option<u32>,
+) -> Result<String, CommandError> {
+    command_result(
+        player_head_inner(
+            &app_handle,
+            id.as_deref(),
+            scale.unwrap_or(DEFAULT_HEAD_SCALE).clamp(1, 64),
+        )
+        .await,
+    )
+}
+
+fn check_name(name: &str) -> anyhow::Result<()> {
+    if name.len() < 3
+        || name.len() > 16
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(coded(
+            ErrorCode::InvalidData,
+            "Names have to be 3 to 16 letters, digits or underscores",
+        ));
+    }
+    Ok(())
+}
+
+async fn name_available_inner(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+    name: &str,
+) -> anyhow::Result<NameAvailability> {
+    check_name(name)?;
+    let request = |http: &reqwest::Client| http.get(format!("{}/{}/available", NAME_URL, name));
+    let (_, resp) = profile_api(app_handle, id, request).await?;
+    if resp.status != 200 {
+        return Err(unexpected_status(&resp));
+    }
+    Ok(serde_json::from_value::<NameAvailabilityResponse>(resp.data)?.status)
+}
+
+async fn name_change_info_inner(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+) -> anyhow::Result<NameChangeInfo> {
+    let request = |http: &reqwest::Client| http.get(NAME_CHANGE_URL);
+    let (_, resp) = profile_api(app_handle, id, request).await?;
+    if resp.status != 200 {
+        return Err(unexpected_status(&resp));
+    }
+    let info: NameChangeResponse = serde_json::from_value(resp.data)?;
+    Ok(NameChangeInfo {
+        allowed: info.name_change_allowed,
+        changed_at: info.changed_at.map(OffsetDateTime::unix_timestamp),
+        next_change_at: info
+            .changed_at
+            .map(|changed_at| (changed_at + NAME_CHANGE_COOLDOWN).unix_timestamp())
+            .filter(|_| !info.name_change_allowed),
+    })
+}
+
+async fn change_name_inner(
+    app_handle: &tauri::AppHandle,
+    id: Option<&str>,
+    name: &str,
+) -> anyhow::Result<Profile> {
+    check_name(name)?;
+    let request = |http: &reqwest::Client| http.put(format!("{}/{}", NAME_URL, name));
+    let (account_id, resp) = profile_api(app_handle, id, request).await?;
+    match resp.status {
+        200 => {
+            let profile: Profile = serde_json::from_value(resp.data)?;
+            accounts::update_profile(app_handle, &account_id, profile.clone()).await?;
+            Ok(profile)
+        }
+        403 => Err(coded(
+            ErrorCode::AuthFailed,
+            "The name was changed less than 30 days ago",
+        )),
+        409 => Err(coded(ErrorCode::AuthFailed, "The name is taken")),
+        _ => Err(unexpected_status(&resp)),
+    }
+}
+
+/// Whether `name` could be taken by the account with `id`, or the active
+/// one.
+#[tauri::command]
+pub async fn check_name_available(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+    name: String,
+) -> Result<NameAvailability, CommandError> {
+    command_result(name_available_inner(&app_handle, id.as_deref(), &name).await)
+}
+
+/// Whether the name can be changed now, and when it can be otherwise.
+#[tauri::command]
+pub async fn get_name_change_info(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+) -> Result<NameChangeInfo, CommandError> {
+    command_result(name_change_info_inner(&app_handle, id.as_deref()).await)
+}
+
+#[tauri::command]
+pub async fn change_name(
+    app_handle: tauri::AppHandle,
+    id: Option<String>,
+    name: String,
+) -> Result<Profile, CommandError> {
+    command_result(change_name_inner(&app_handle, id.as_deref(), &name).await)
+}
@@ -1,39 +1,646 @@
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
+use anyhow::anyhow;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use log::{info, warn};
 use sha1::Digest;
-use tauri::api::http::{ClientBuilder, HttpRequestBuilder, ResponseType};
+use sha2::Sha256;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use crate::{
+    errors::{coded, coded_message, ErrorCode},
+    hash_cache,
+    messages::Message,
+};
+
+/// Service name the credentials are stored under in the OS keychain
+const KEYRING_SERVICE: &str = "vg.skye.uml";
+const NONCE_SIZE: usize = 12;
+
+/// Size of the chunks files are hashed in
+const HASH_CHUNK_SIZE: usize = 64 * 1024;
+/// Kept free on top of what an install needs, for everything else on the
+/// disk
+const FREE_SPACE_MARGIN: u64 = 128 * 1024 * 1024;
+
+/// Hex digest of the file at `path`, read in chunks.
+async fn digest_file<D: Digest>(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = D::new();
+    let mut buf = vec![0; HASH_CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// A hash a file should have, in hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileHash {
+    Sha1(String),
+    Sha256(String),
+}
+
+impl FileHash {
+    /// The same kind of hash of the file at `path`.
+    pub async fn of_file(&self, path: &Path) -> std::io::Result<FileHash> {
+        Ok(match self {
+            FileHash::Sha1(_) => FileHash::Sha1(digest_file::<::sha1::Sha1>(path).await?),
+            FileHash::Sha256(_) => FileHash::Sha256(digest_file::<Sha256>(path).await?),
+        })
+    }
+
+    pub fn hex(&self) -> &str {
+        match self {
+            FileHash::Sha1(hex) | FileHash::Sha256(hex) => hex,
+        }
+    }
+}
+
+impl std::fmt::Display for FileHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileHash::Sha1(hex) => write!(f, "SHA-1 {}", hex),
+            FileHash::Sha256(hex) => write!(f, "SHA-256 {}", hex),
+        }
+    }
+}
+
+/// A downloaded file that isn't what was [`Expected`], most likely because
+/// it was cut off or corrupted on the way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityError {
+    pub url: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} arrived with {} instead of {}",
+            self.url, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+/// What's known about a file before downloading it, to check it against.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Expected {
+    pub hash: Option<FileHash>,
+    /// In bytes
+    pub size: Option<u64>,
+}
+
+impl Expected {
+    pub fn sha1(sha1: Option<&str>) -> Expected {
+        Expected {
+            hash: sha1.map(|sha1| FileHash::Sha1(sha1.to_string())),
+            size: None,
+        }
+    }
+
+    pub fn sha256(sha256: Option<&str>) -> Expected {
+        Expected {
+            hash: sha256.map(|sha256| FileHash::Sha256(sha256.to_string())),
+            size: None,
+        }
+    }
+
+    pub fn with_size(mut self, size: Option<u64>) -> Expected {
+        self.size = size;
+        self
+    }
+
+    /// Whether the file at `path` exists and is as expected. The size is
+    /// checked first, so files that are obviously wrong aren't hashed, and
+    /// the hash is taken from the [`hash_cache`] if the file didn't change,
+    /// unless `full_verify`.
+    pub async fn matches_file(&self, path: &Path, full_verify: bool) -> bool {
+        let metadata = match tokio::fs::metadata(path).await {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+        if self.size.is_some_and(|size| size != metadata.len()) {
+            return false;
+        }
+        let hash = match &self.hash {
+            Some(hash) => hash,
+            None => return true,
+        };
+        let cached = match full_verify {
+            true => None,
+            false => hash_cache::lookup(path, &metadata, hash),
+        };
+        let actual = match cached {
+            Some(actual) => actual,
+            None => match hash.of_file(path).await {
+                Ok(actual) => {
+                    hash_cache::store(path, &metadata, &actual);
+                    actual
+                }
+                Err(_) => return false,
+            },
+        };
+        actual.hex().eq_ignore_ascii_case(hash.hex())
+    }
+
+    /// Remembers the hash of the file at `path`, just downloaded and
+    /// verified, so it isn't hashed again next time.
+    pub async fn remember(&self, path: &Path) {
+        if let (Some(hash), Ok(metadata)) = (&self.hash, tokio::fs::metadata(path).await) {
+            hash_cache::store(path, &metadata, hash);
+        }
+    }
+
+    /// Checks the file at `path`, just downloaded from `url`, failing with
+    /// an [`IntegrityError`] if it isn't as expected.
+    pub async fn verify(&self, url: &str, path: &Path) -> anyhow::Result<()> {
+        let mismatch = |expected: String, actual: String| IntegrityError {
+            url: url.to_string(),
+            expected,
+            actual,
+        };
+        let len = tokio::fs::metadata(path).await?.len();
+        if let Some(size) = self.size.filter(|size| *size != len) {
+            return Err(mismatch(format!("{} bytes", size), format!("{} bytes", len)).into());
+        }
+        if let Some(hash) = &self.hash {
+            let actual = hash.of_file(path).await?;
+            if !actual.hex().eq_ignore_ascii_case(hash.hex()) {
+                return Err(mismatch(hash.to_string(), actual.to_string()).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `download` again if what it got wasn't as expected, once, in case
+/// that was a fluke on the way.
+pub async fn retry_on_mismatch<F, Fut>(url: &str, download: F) -> anyhow::Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<()>>,
+{
+    match download().await {
+        Err(e) if e.is::<IntegrityError>() => {
+            warn!("{:#}, downloading {} again", e, url);
+            download().await
+        }
+        result => result,
+    }
+}
+
+/// Where a download to `path` is written until it's complete. It's next to
+/// it, so it can be renamed into place, and a crash halfway through never
+/// leaves a broken file at `path`. What's there is kept when a download is
+/// interrupted, so the next one can pick up from there.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// A download answered with an error status
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusError {
+    pub url: String,
+    pub status: u16,
+}
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Got status {} instead of 200 for {}",
+            self.status, self.url
+        )
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// A download started into a `.part` file.
+pub struct PartDownload {
+    pub resp: reqwest::Response,
+    /// Open for appending what arrives
+    pub file: tokio::fs::File,
+    /// Bytes from an earlier download that are in the file already
+    pub offset: u64,
+}
+
+impl PartDownload {
+    /// Size of the whole file, if the server said.
+    pub fn total(&self) -> Option<u64> {
+        self.resp.content_length().map(|len| self.offset + len)
+    }
+}
+
+/// Whether `resp` to a range request starts at `offset`.
+fn resumes_at(resp: &reqwest::Response, offset: u64) -> bool {
+    resp.headers()
+        .get(reqwest::header::CONTENT_RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.strip_prefix("bytes "))
+        .and_then(|range| range.split('-').next())
+        .and_then(|start| start.parse::<u64>().ok())
+        == Some(offset)
+}
+
+/// Starts downloading `url` into `part`. If `resume` and there's something
+/// in `part` already, only the rest is requested with a `Range` header. When
+/// the server doesn't support that, the whole file is downloaded again.
+///
+/// Only resume downloads that are verified afterwards, as the file may have
+/// changed on the server since.
+pub async fn start_download(
+    client: &reqwest::Client,
+    url: &str,
+    part: &Path,
+    resume: bool,
+) -> anyhow::Result<PartDownload> {
+    let offset = match resume {
+        true => tokio::fs::metadata(part).await.map_or(0, |meta| meta.len()),
+        false => 0,
+    };
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+    }
+    let resp = request.send().await?;
+    let status = resp.status();
+    if offset > 0 && status == reqwest::StatusCode::PARTIAL_CONTENT && resumes_at(&resp, offset) {
+        info!("Resuming {} from byte {}", url, offset);
+        let file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(part)
+            .await?;
+        return Ok(PartDownload { resp, file, offset });
+    }
+    if offset > 0
+        && (status == reqwest::StatusCode::PARTIAL_CONTENT
+            || status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE)
+    {
+        // What's there doesn't fit the file on the server
+        remove_part(part).await;
+        return Box::pin(start_download(client, url, part, false)).await;
+    }
+    if status != reqwest::StatusCode::OK {
+        return Err(StatusError {
+            url: url.to_string(),
+            status: status.as_u16(),
+        }
+        .into());
+    }
+    // Without a range, or one the server ignored
+    let file = tokio::fs::File::create(part).await?;
+    Ok(PartDownload {
+        resp,
+        file,
+        offset: 0,
+    })
+}
+
+/// Downloads `url` to `path`, writing it as it arrives instead of holding
+/// all of it in memory. It's only moved to `path` if it's as `expected`.
+async fn download_to(
+    client: &reqwest::Client,
+    url: &str,
+    path: &Path,
+    expected: &Expected,
+) -> anyhow::Result<()> {
+    let part = part_path(path);
+    let PartDownload {
+        mut resp, mut file, ..
+    } = start_download(client, url, &part, expected.hash.is_some()).await?;
+    while let Some(chunk) = resp.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+    drop(file);
+    if let Err(e) = expected.verify(url, &part).await {
+        remove_part(&part).await;
+        return Err(e);
+    }
+    tokio::fs::rename(&part, path).await?;
+    expected.remember(path).await;
+    Ok(())
+}
+
+/// Deletes a partial download that's of no use, if it's there.
+pub async fn remove_part(part: &Path) {
+    match tokio::fs::remove_file(part).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            warn!("Failed to remove {:?}: {}", part, e);
+        }
+        _ => {}
+    }
+}
+
+/// Makes sure the file from `url` is at `path`, downloading it unless it's
+/// there already as `expected`. Without a hash or size, any file there will
+/// do unless `redownload`. A download that isn't as expected is tried once
+/// more before failing with an [`IntegrityError`].
 pub async fn get_file(
     path: &Path,
     url: &str,
     redownload: bool,
-    sha1: Option<&str>,
-) -> anyhow::Result<Vec<u8>> {
-    if !redownload {
-        if let Ok(file) = tokio::fs::read(path).await {
-            if let Some(sha1) = sha1 {
-                let sha1 = hex::decode(sha1)?;
-                let hash = ::sha1::Sha1::digest(&file);
-                if sha1 == hash.as_slice() {
-                    return Ok(file);
+    expected: &Expected,
+) -> anyhow::Result<()> {
+    if !redownload && expected.matches_file(path, false).await {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let client = crate::proxy::client();
+    retry_on_mismatch(url, || {
+        crate::mirrors::with_fallback(url, |url| {
+            let client = client.clone();
+            async move { download_to(&client, &url, path, expected).await }
+        })
+    })
+    .await?;
+    crate::plugins::run_hook(
+        crate::plugins::Hook::Download,
+        serde_json::json!({ "url": url, "path": path }),
+    );
+    Ok(())
+}
+
+/// Fails with [`ErrorCode::DiskFull`] if there isn't room for `needed` more
+/// bytes in `dir`, which doesn't have to exist yet. Checked before installs,
+/// so they don't run out of space halfway through.
+pub async fn ensure_free_space(dir: &Path, needed: u64) -> anyhow::Result<()> {
+    if needed == 0 {
+        return Ok(());
+    }
+    let existing = dir
+        .ancestors()
+        .find(|dir| dir.exists())
+        .ok_or(anyhow!("None of {:?} exists", dir))?
+        .to_path_buf();
+    let available = tokio::task::spawn_blocking(move || fs2::available_space(existing)).await??;
+    if available < needed.saturating_add(FREE_SPACE_MARGIN) {
+        const MB: u64 = 1024 * 1024;
+        return Err(coded_message(
+            ErrorCode::DiskFull,
+            Message::new("disk.notEnoughSpace")
+                .with("needed", needed.div_ceil(MB))
+                .with("available", available / MB),
+        ));
+    }
+    Ok(())
+}
+
+/// Where secrets like account tokens are kept: the OS keychain (Windows
+/// Credential Manager, macOS Keychain or the Secret Service on Linux), or
+/// `credentials.enc` in the launcher dir where there is none.
+///
+/// The file is encrypted with a key derived from the machine id and a random
+/// salt next to it, so a copy of the launcher dir is useless on another
+/// machine. It's no match for someone with access to this one, which is
+/// what the keychain is for. A file that can't be decrypted is moved aside
+/// instead of being written over.
+#[derive(Debug, Clone)]
+pub enum CredentialStore {
+    Keyring,
+    File(PathBuf),
+}
+
+lazy_static::lazy_static! {
+    /// Serializes access to the fallback file
+    static ref CREDENTIALS_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+impl CredentialStore {
+    /// Uses the keychain if it works, the file otherwise.
+    pub async fn open(app_handle: &tauri::AppHandle) -> anyhow::Result<CredentialStore> {
+        let available = tokio::task::spawn_blocking(|| {
+            // Looking up something that doesn't exist tells whether the
+            // keychain can be reached at all
+            match keyring::Entry::new(KEYRING_SERVICE, "probe")
+                .and_then(|entry| entry.get_password())
+            {
+                Ok(_) | Err(keyring::Error::NoEntry) => true,
+                Err(e) => {
+                    warn!(
+                        "OS keychain unavailable, storing credentials in a file: {}",
+                        e
+                    );
+                    false
                 }
-            } else {
-                return Ok(file);
             }
+        })
+        .await?;
+        if available {
+            Ok(CredentialStore::Keyring)
+        } else {
+            Ok(CredentialStore::File(
+                crate::instance::launcher_dir(app_handle)?.join("credentials.enc"),
+            ))
         }
     }
-    let client = ClientBuilder::new().build()?;
-    let file = client
-        .send(HttpRequestBuilder::new("GET", url)?.response_type(ResponseType::Binary))
-        .await?
-        .bytes()
-        .await?;
-    if file.status != 200 {
-        return Err(anyhow::anyhow!("Got status {} instead of 200", file.status));
+
+    /// Plain `credentials.json` written by older versions
+    fn legacy_path(path: &Path) -> PathBuf {
+        path.with_file_name("credentials.json")
     }
-    if let Some(parent) = path.parent() {
-        tokio::fs::create_dir_all(parent).await?;
+
+    async fn write_private(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        tokio::fs::write(&tmp, data).await?;
+        // Keep other users out
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600)).await?;
+        }
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    async fn file_cipher(path: &Path) -> anyhow::Result<ChaCha20Poly1305> {
+        let salt_path = path.with_extension("salt");
+        let salt = match tokio::fs::read(&salt_path).await {
+            Ok(salt) => salt,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut salt = vec![0; 16];
+                OsRng.fill_bytes(&mut salt);
+                Self::write_private(&salt_path, &salt).await?;
+                salt
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // Without a machine id the key would only depend on the salt next
+        // to the file, which is as good as no encryption
+        let machine_id = tokio::task::spawn_blocking(|| machine_uid::get().ok())
+            .await?
+            .filter(|machine_id| !machine_id.trim().is_empty())
+            .ok_or_else(|| {
+                coded(
+                    ErrorCode::Unsupported,
+                    "There's no machine id to encrypt the credentials with",
+                )
+            })?;
+        let key = Sha256::new()
+            .chain_update(b"uml credentials\0")
+            .chain_update(machine_id)
+            .chain_update(&salt)
+            .finalize();
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    async fn read_file(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+        let data = match tokio::fs::read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return match tokio::fs::read(Self::legacy_path(path)).await {
+                    Ok(data) => Ok(serde_json::from_slice(&data)?),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+                    Err(e) => Err(e.into()),
+                };
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let plaintext = match data.split_at_checked(NONCE_SIZE) {
+            Some((nonce, ciphertext)) => Self::file_cipher(path)
+                .await?
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .ok(),
+            None => None,
+        };
+        match plaintext {
+            Some(plaintext) => Ok(serde_json::from_slice(&plaintext)?),
+            // Damaged, or copied from another machine. It's kept instead of
+            // being written over, in case it can still be recovered.
+            None => {
+                let aside = Self::move_aside(path).await?;
+                Err(coded(
+                    ErrorCode::InvalidData,
+                    format!(
+                        "The credentials file can't be decrypted and was moved to {:?}, accounts have to be signed into again",
+                        aside
+                    ),
+                ))
+            }
+        }
+    }
+
+    /// Renames the file and its salt to `<name>.<timestamp>.unreadable`,
+    /// returning the new path of the file.
+    async fn move_aside(path: &Path) -> anyhow::Result<PathBuf> {
+        let timestamp = time::OffsetDateTime::now_utc().unix_timestamp();
+        let aside = |path: &Path| {
+            let mut aside = path.as_os_str().to_owned();
+            aside.push(format!(".{}.unreadable", timestamp));
+            PathBuf::from(aside)
+        };
+        let salt_path = path.with_extension("salt");
+        match tokio::fs::rename(&salt_path, aside(&salt_path)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let target = aside(path);
+        tokio::fs::rename(path, &target).await?;
+        warn!("Moved the unreadable credentials file to {:?}", target);
+        Ok(target)
+    }
+
+    async fn write_file(path: &Path, credentials: &HashMap<String, String>) -> anyhow::Result<()> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = Self::file_cipher(path)
+            .await?
+            .encrypt(&nonce, serde_json::to_vec(credentials)?.as_slice())
+            .map_err(|_| anyhow!("Encrypting the credentials failed"))?;
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        Self::write_private(path, &data).await?;
+        match tokio::fs::remove_file(Self::legacy_path(path)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        match self {
+            CredentialStore::Keyring => {
+                let key = key.to_string();
+                tokio::task::spawn_blocking(move || {
+                    match keyring::Entry::new(KEYRING_SERVICE, &key)?.get_password() {
+                        Ok(value) => Ok(Some(value)),
+                        Err(keyring::Error::NoEntry) => Ok(None),
+                        Err(e) => Err(e.into()),
+                    }
+                })
+                .await?
+            }
+            CredentialStore::File(path) => {
+                let _lock = CREDENTIALS_LOCK.lock().await;
+                Ok(Self::read_file(path).await?.remove(key))
+            }
+        }
+    }
+
+    pub async fn set(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        match self {
+            CredentialStore::Keyring => {
+                let (key, value) = (key.to_string(), value.to_string());
+                tokio::task::spawn_blocking(move || {
+                    keyring::Entry::new(KEYRING_SERVICE, &key)?.set_password(&value)
+                })
+                .await??;
+            }
+            CredentialStore::File(path) => {
+                let _lock = CREDENTIALS_LOCK.lock().await;
+                let mut credentials = Self::read_file(path).await?;
+                credentials.insert(key.to_string(), value.to_string());
+                Self::write_file(path, &credentials).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a credential, doing nothing if it doesn't exist.
+    pub async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match self {
+            CredentialStore::Keyring => {
+                let key = key.to_string();
+                tokio::task::spawn_blocking(move || {
+                    match keyring::Entry::new(KEYRING_SERVICE, &key)?.delete_password() {
+                        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+                        Err(e) => Err(anyhow::Error::from(e)),
+                    }
+                })
+                .await??;
+            }
+            CredentialStore::File(path) => {
+                let _lock = CREDENTIALS_LOCK.lock().await;
+                let mut credentials = Self::read_file(path).await?;
+                if credentials.remove(key).is_some() {
+                    Self::write_file(path, &credentials).await?;
+                }
+            }
+        }
+        info!("Deleted credential {}", key);
+        Ok(())
     }
-    tokio::fs::write(path, &file.data).await?;
-    Ok(file.data)
 }
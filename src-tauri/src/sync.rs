@@ -0,0 +1,674 @@
+//! Syncing instances between machines through a WebDAV server or an
+//! S3-compatible bucket the user configures.
+//!
+//! Each instance is stored under `instances/<id>/` as its `instance.json`,
+//! a `mods.json` listing the mod files and their hashes, `options.txt` and,
+//! if enabled, a `saves.zip`. A `manifest.json` next to them holds the SHA-1
+//! of every file. The hashes from the last sync are kept locally in
+//! `sync-state.json`, so a file that changed on both sides since then is
+//! reported as a conflict instead of being overwritten.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Write},
+    path::Path,
+};
+
+use anyhow::anyhow;
+use hmac::{Hmac, Mac};
+use log::info;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha1::Digest;
+use sha2::Sha256;
+use tauri::Manager;
+
+use crate::{
+    command_result,
+    errors::{coded, CommandError, ErrorCode},
+    instance,
+    messages::Message,
+    network::{self, QueuedOperation},
+    process::ProcessManager,
+    proxy, settings,
+    storage::CredentialStore,
+    tasks::{self, TaskKind},
+};
+
+const INSTANCE_FILE: &str = "instance.json";
+const MODS_FILE: &str = "mods.json";
+const OPTIONS_FILE: &str = "options.txt";
+const SAVES_FILE: &str = "saves.zip";
+const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SyncBackend {
+    #[serde(rename_all = "camelCase")]
+    WebDav {
+        /// Directory everything is stored in, e.g.
+        /// `https://cloud.example.com/remote.php/dav/files/me/minecraft`
+        url: String,
+        username: Option<String>,
+        /// Only ever set coming from the frontend, it's kept in the
+        /// credential store instead of the settings
+        #[serde(default, skip_serializing)]
+        password: Option<String>,
+    },
+    /// Any S3-compatible storage, addressed path-style
+    #[serde(rename_all = "camelCase")]
+    S3 {
+        /// e.g. `https://s3.eu-central-1.amazonaws.com`
+        endpoint: String,
+        region: String,
+        bucket: String,
+        access_key_id: String,
+        /// Kept in the credential store like the WebDAV password
+        #[serde(default, skip_serializing)]
+        secret_access_key: String,
+    },
+}
+
+/// File name to SHA-1, used both for the remote manifest and the local
+/// record of the last sync.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct SyncManifest {
+    files: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModFile {
+    pub file_name: String,
+    pub sha1: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncResult {
+    /// Files that were uploaded or downloaded
+    pub transferred: Vec<String>,
+    /// Files that changed on both sides and were left alone
+    pub conflicts: Vec<String>,
+    /// Mods listed remotely that aren't installed here, after a pull
+    pub missing_mods: Vec<String>,
+}
+
+fn sha1_hex(data: &[u8]) -> String {
+    hex::encode(sha1::Sha1::digest(data))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters and `/`, which is
+/// what both WebDAV servers and S3 signatures expect.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+impl SyncBackend {
+    /// Key of the password or secret key in the credential store. It names
+    /// the server and user, so a secret never goes to another backend.
+    fn credential_key(&self) -> String {
+        match self {
+            SyncBackend::WebDav { url, username, .. } => format!(
+                "sync:webdav:{}:{}",
+                url,
+                username.as_deref().unwrap_or_default()
+            ),
+            SyncBackend::S3 {
+                endpoint,
+                bucket,
+                access_key_id,
+                ..
+            } => format!("sync:s3:{}:{}:{}", endpoint, bucket, access_key_id),
+        }
+    }
+
+    /// Removes the password or secret key, if one is set.
+    fn take_secret(&mut self) -> Option<String> {
+        match self {
+            SyncBackend::WebDav { password, .. } => password.take(),
+            SyncBackend::S3 {
+                secret_access_key, ..
+            } => Some(std::mem::take(secret_access_key)),
+        }
+        .filter(|secret| !secret.is_empty())
+    }
+
+    fn set_secret(&mut self, secret: String) {
+        match self {
+            SyncBackend::WebDav { password, .. } => *password = Some(secret),
+            SyncBackend::S3 {
+                secret_access_key, ..
+            } => *secret_access_key = secret,
+        }
+    }
+
+    fn request(
+        &self,
+        client: &Client,
+        method: &str,
+        key: &str,
+        body: &[u8],
+    ) -> anyhow::Result<RequestBuilder> {
+        let http_method = Method::from_bytes(method.as_bytes())?;
+        match self {
+            SyncBackend::WebDav {
+                url,
+                username,
+                password,
+            } => {
+                let mut request = client.request(
+                    http_method,
+                    format!("{}/{}", url.trim_end_matches('/'), encode_path(key)),
+                );
+                if let Some(username) = username {
+                    request = request.basic_auth(username, Some(password.as_deref().unwrap_or("")));
+                }
+                Ok(request)
+            }
+            SyncBackend::S3 {
+                endpoint,
+                region,
+                bucket,
+                access_key_id,
+                secret_access_key,
+            } => {
+                let endpoint = endpoint.trim_end_matches('/');
+                let host = endpoint
+                    .split_once("://")
+                    .map_or(endpoint, |(_, rest)| rest)
+                    .split('/')
+                    .next()
+                    .unwrap_or_default();
+                let uri = encode_path(&format!("/{}/{}", bucket, key));
+                let now = time::OffsetDateTime::now_utc();
+                let amz_date = format!(
+                    "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+                    now.year(),
+                    u8::from(now.month()),
+                    now.day(),
+                    now.hour(),
+                    now.minute(),
+                    now.second()
+                );
+                let date = &amz_date[..8];
+                let payload_hash = sha256_hex(body);
+
+                // AWS Signature Version 4
+                let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+                let canonical_request = format!(
+                    "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+                    method, uri, host, payload_hash, amz_date, signed_headers, payload_hash
+                );
+                let scope = format!("{}/{}/s3/aws4_request", date, region);
+                let string_to_sign = format!(
+                    "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+                    amz_date,
+                    scope,
+                    sha256_hex(canonical_request.as_bytes())
+                );
+                let mut key = format!("AWS4{}", secret_access_key).into_bytes();
+                for part in [date, region.as_str(), "s3", "aws4_request"] {
+                    key = hmac_sha256(&key, part);
+                }
+                let signature = hex::encode(hmac_sha256(&key, &string_to_sign));
+
+                Ok(client
+                    .request(http_method, format!("{}{}", endpoint, uri))
+                    .header("x-amz-date", &amz_date)
+                    .header("x-amz-content-sha256", &payload_hash)
+                    .header(
+                        "Authorization",
+                        format!(
+                            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                            access_key_id, scope, signed_headers, signature
+                        ),
+                    ))
+            }
+        }
+    }
+
+    async fn get(&self, client: &Client, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let response = self.request(client, "GET", key, &[])?.send().await?;
+        match response.status() {
+            StatusCode::OK => Ok(Some(response.bytes().await?.to_vec())),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => Err(anyhow!("Sync server returned {} for {}", status, key)),
+        }
+    }
+
+    async fn put(&self, client: &Client, key: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        if let SyncBackend::WebDav { .. } = self {
+            // WebDAV doesn't create parent collections by itself. Existing
+            // ones make MKCOL fail, which is fine.
+            let mut parent = String::new();
+            for segment in key
+                .split('/')
+                .rev()
+                .skip(1)
+                .collect::<Vec<_>>()
+                .iter()
+                .rev()
+            {
+                parent.push_str(segment);
+                parent.push('/');
+                self.request(client, "MKCOL", &parent, &[])?.send().await?;
+            }
+        }
+        let response = self
+            .request(client, "PUT", key, &data)?
+            .body(data)
+            .send()
+            .await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(anyhow!("Sync server returned {} for {}", status, key)),
+        }
+    }
+}
+
+/// The files in `mods/`, sorted by name.
+pub fn mod_list(game_dir: &Path) -> anyhow::Result<Vec<ModFile>> {
+    let mut mods = vec![];
+    let entries = match std::fs::read_dir(game_dir.join("mods")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(mods),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() {
+            mods.push(ModFile {
+                file_name: path.file_name().unwrap().to_string_lossy().to_string(),
+                sha1: sha1_hex(&std::fs::read(&path)?),
+            });
+        }
+    }
+    mods.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    Ok(mods)
+}
+
+fn zip_saves(saves_dir: &Path) -> anyhow::Result<Vec<u8>> {
+    fn add_dir(
+        zip: &mut zip::ZipWriter<std::io::Cursor<Vec<u8>>>,
+        dir: &Path,
+        prefix: &str,
+    ) -> anyhow::Result<()> {
+        let mut entries: Vec<_> = std::fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+        // A stable order keeps the hash the same as long as nothing changed
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            let path = entry.path();
+            if path.is_dir() {
+                add_dir(zip, &path, &format!("{}/", name))?;
+            } else if name.ends_with("session.lock") {
+                continue;
+            } else {
+                zip.start_file(
+                    name,
+                    zip::write::FileOptions::default()
+                        .compression_method(zip::CompressionMethod::Deflated),
+                )?;
+                zip.write_all(&std::fs::read(&path)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    if saves_dir.is_dir() {
+        add_dir(&mut zip, saves_dir, "")?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+fn unzip_saves(data: Vec<u8>, saves_dir: &Path) -> anyhow::Result<()> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let path = instance::safe_join(saves_dir, entry.name())?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut data = vec![];
+        entry.read_to_end(&mut data)?;
+        std::fs::write(path, data)?;
+    }
+    Ok(())
+}
+
+/// The synced files of an instance as they are on this machine.
+async fn local_files(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<HashMap<String, Vec<u8>>> {
+    let instance_dir = instance::instance_dir(app_handle, instance_id)?;
+    let game_dir = instance::game_dir(app_handle, instance_id)?;
+    let include_saves = settings::current(app_handle).sync_saves;
+    tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+        let mut files = HashMap::new();
+        files.insert(
+            INSTANCE_FILE.to_string(),
+            std::fs::read(instance_dir.join(INSTANCE_FILE))?,
+        );
+        files.insert(
+            MODS_FILE.to_string(),
+            serde_json::to_vec_pretty(&mod_list(&game_dir)?)?,
+        );
+        match std::fs::read(game_dir.join(OPTIONS_FILE)) {
+            Ok(options) => {
+                files.insert(OPTIONS_FILE.to_string(), options);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        if include_saves {
+            files.insert(SAVES_FILE.to_string(), zip_saves(&game_dir.join("saves"))?);
+        }
+        Ok(files)
+    })
+    .await?
+}
+
+async fn read_state(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+) -> anyhow::Result<SyncManifest> {
+    let path = instance::instance_dir(app_handle, instance_id)?.join("sync-state.json");
+    match tokio::fs::read(path).await {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SyncManifest::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn write_state(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    state: &SyncManifest,
+) -> anyhow::Result<()> {
+    let path = instance::instance_dir(app_handle, instance_id)?.join("sync-state.json");
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(state)?).await?;
+    tokio::fs::rename(&tmp, &path).await?;
+    Ok(())
+}
+
+/// Moves the password or secret key of `backend` into the credential store.
+/// Returns whether it had one.
+pub async fn store_secret(
+    app_handle: &tauri::AppHandle,
+    backend: &mut SyncBackend,
+) -> anyhow::Result<bool> {
+    match backend.take_secret() {
+        Some(secret) => {
+            CredentialStore::open(app_handle)
+                .await?
+                .set(&backend.credential_key(), &secret)
+                .await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Deletes the stored secret of a backend that was replaced by `current`,
+/// unless `current` uses the same one.
+pub async fn forget_secret(
+    app_handle: &tauri::AppHandle,
+    previous: &SyncBackend,
+    current: Option<&SyncBackend>,
+) -> anyhow::Result<()> {
+    let key = previous.credential_key();
+    if current.is_some_and(|current| current.credential_key() == key) {
+        return Ok(());
+    }
+    CredentialStore::open(app_handle).await?.delete(&key).await
+}
+
+/// The configured backend with its secret from the credential store.
+async fn backend(app_handle: &tauri::AppHandle) -> anyhow::Result<SyncBackend> {
+    let mut backend = settings::current(app_handle)
+        .sync_backend
+        .ok_or_else(|| coded(ErrorCode::Unsupported, "No sync backend is configured"))?;
+    if let Some(secret) = CredentialStore::open(app_handle)
+        .await?
+        .get(&backend.credential_key())
+        .await?
+    {
+        backend.set_secret(secret);
+    }
+    Ok(backend)
+}
+
+async fn remote_manifest(
+    backend: &SyncBackend,
+    client: &Client,
+    prefix: &str,
+) -> anyhow::Result<SyncManifest> {
+    match backend
+        .get(client, &format!("{}{}", prefix, MANIFEST_FILE))
+        .await?
+    {
+        Some(data) => Ok(serde_json::from_slice(&data)?),
+        None => Ok(SyncManifest::default()),
+    }
+}
+
+async fn push(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    force: bool,
+) -> anyhow::Result<SyncResult> {
+    let backend = backend(app_handle).await?;
+    let client = proxy::client();
+    let prefix = format!("instances/{}/", instance_id);
+    let mut remote = remote_manifest(&backend, &client, &prefix).await?;
+    let mut state = read_state(app_handle, instance_id).await?;
+    let mut result = SyncResult::default();
+
+    for (name, data) in local_files(app_handle, instance_id).await? {
+        let hash = sha1_hex(&data);
+        let remote_hash = remote.files.get(&name);
+        if remote_hash == Some(&hash) {
+            state.files.insert(name, hash);
+            continue;
+        }
+        // Someone else pushed a change we haven't pulled yet
+        if !force && remote_hash.is_some() && remote_hash != state.files.get(&name) {
+            result.conflicts.push(name);
+            continue;
+        }
+        backend
+            .put(&client, &format!("{}{}", prefix, name), data)
+            .await?;
+        remote.files.insert(name.clone(), hash.clone());
+        state.files.insert(name.clone(), hash);
+        result.transferred.push(name);
+    }
+
+    if !result.transferred.is_empty() {
+        backend
+            .put(
+                &client,
+                &format!("{}{}", prefix, MANIFEST_FILE),
+                serde_json::to_vec_pretty(&remote)?,
+            )
+            .await?;
+    }
+    write_state(app_handle, instance_id, &state).await?;
+    Ok(result)
+}
+
+async fn apply(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    name: &str,
+    data: Vec<u8>,
+    result: &mut SyncResult,
+) -> anyhow::Result<()> {
+    let instance_dir = instance::instance_dir(app_handle, instance_id)?;
+    let game_dir = instance::game_dir(app_handle, instance_id)?;
+    match name {
+        INSTANCE_FILE => {
+            // Make sure it parses before replacing the local config
+            serde_json::from_slice::<instance::InstanceConfig>(&data)?;
+            tokio::fs::write(instance_dir.join(INSTANCE_FILE), data).await?;
+        }
+        OPTIONS_FILE => {
+            tokio::fs::create_dir_all(&game_dir).await?;
+            tokio::fs::write(game_dir.join(OPTIONS_FILE), data).await?;
+        }
+        MODS_FILE => {
+            let remote: Vec<ModFile> = serde_json::from_slice(&data)?;
+            let local = {
+                let game_dir = game_dir.clone();
+                tokio::task::spawn_blocking(move || mod_list(&game_dir)).await??
+            };
+            result.missing_mods = remote
+                .into_iter()
+                .filter(|remote| !local.contains(remote))
+                .map(|remote| remote.file_name)
+                .collect();
+        }
+        SAVES_FILE => {
+            let saves_dir = game_dir.join("saves");
+            tokio::task::spawn_blocking(move || unzip_saves(data, &saves_dir)).await??;
+        }
+        _ => info!("Ignoring unknown synced file {}", name),
+    }
+    Ok(())
+}
+
+async fn pull(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    force: bool,
+) -> anyhow::Result<SyncResult> {
+    if app_handle.state::<ProcessManager>().is_running(instance_id) {
+        return Err(coded(
+            ErrorCode::InstanceRunning,
+            "Stop the instance before pulling changes",
+        ));
+    }
+    instance::ensure_unlocked(app_handle, instance_id).await?;
+    let backend = backend(app_handle).await?;
+    let client = proxy::client();
+    let prefix = format!("instances/{}/", instance_id);
+    let remote = remote_manifest(&backend, &client, &prefix).await?;
+    let mut state = read_state(app_handle, instance_id).await?;
+    let local: HashMap<_, _> = local_files(app_handle, instance_id)
+        .await?
+        .into_iter()
+        .map(|(name, data)| (name, sha1_hex(&data)))
+        .collect();
+    let mut result = SyncResult::default();
+
+    for (name, remote_hash) in &remote.files {
+        let local_hash = local.get(name);
+        if local_hash == Some(remote_hash) {
+            state.files.insert(name.clone(), remote_hash.clone());
+            continue;
+        }
+        // Changed here since the last sync, pulling would lose that
+        if !force && local_hash.is_some() && local_hash != state.files.get(name) {
+            result.conflicts.push(name.clone());
+            continue;
+        }
+        let data = backend
+            .get(&client, &format!("{}{}", prefix, name))
+            .await?
+            .ok_or(anyhow!("{} is in the manifest but not on the server", name))?;
+        if &sha1_hex(&data) != remote_hash {
+            return Err(coded(
+                ErrorCode::DownloadHashMismatch,
+                format!("{} doesn't match the manifest", name),
+            ));
+        }
+        apply(app_handle, instance_id, name, data, &mut result).await?;
+        state.files.insert(name.clone(), remote_hash.clone());
+        result.transferred.push(name.clone());
+    }
+
+    write_state(app_handle, instance_id, &state).await?;
+    Ok(result)
+}
+
+/// Pushes an instance as a task. While offline, the push is queued instead.
+pub async fn push_instance(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    force: bool,
+) -> anyhow::Result<SyncResult> {
+    if !network::is_online(app_handle) {
+        return Err(network::defer(
+            app_handle,
+            QueuedOperation::SyncPush {
+                instance_id: instance_id.to_string(),
+            },
+        ));
+    }
+    tasks::run(
+        app_handle,
+        TaskKind::Sync,
+        Message::new("task.syncPush").with("instance", instance_id),
+        |_| push(app_handle, instance_id, force),
+    )
+    .await
+}
+
+/// Uploads the instance's files that changed since the last sync. Files
+/// that were also changed remotely are reported as conflicts unless `force`
+/// is set.
+#[tauri::command]
+pub async fn sync_push(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    force: bool,
+) -> Result<SyncResult, CommandError> {
+    command_result(push_instance(&app_handle, &instance_id, force).await)
+}
+
+/// Downloads the instance's files that changed remotely. Files that were
+/// also changed here are reported as conflicts unless `force` is set. Mods
+/// themselves aren't synced, missing ones are listed in the result.
+#[tauri::command]
+pub async fn sync_pull(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    force: bool,
+) -> Result<SyncResult, CommandError> {
+    command_result(
+        tasks::run(
+            &app_handle,
+            TaskKind::Sync,
+            Message::new("task.syncPull").with("instance", instance_id.as_str()),
+            |_| pull(&app_handle, &instance_id, force),
+        )
+        .await,
+    )
+}
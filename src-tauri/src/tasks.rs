@@ -0,0 +1,216 @@
+//! Registry of long-running operations, so the frontend can show them all in
+//! one place and cancel them.
+//!
+//! Every change to a task is emitted as `task:updated` with its
+//! [`TaskInfo`]. Finished tasks are kept until `clear_finished_tasks`.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use log::{info, warn};
+use serde::Serialize;
+use tauri::Manager;
+use tokio::sync::Notify;
+
+use crate::{errors::ErrorCode, messages::Message};
+
+pub type TaskId = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskKind {
+    MetaSync,
+    Download,
+    Install,
+    Backup,
+    Verify,
+    Sync,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum TaskState {
+    Running,
+    Completed,
+    Failed { code: ErrorCode, error: String },
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub kind: TaskKind,
+    pub title: Message,
+    #[serde(flatten)]
+    pub state: TaskState,
+    /// Between 0 and 1, if the task knows how far along it is
+    pub progress: Option<f64>,
+    /// What the task is doing right now
+    pub message: Option<String>,
+}
+
+/// Returned by [`run`] when the task was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+struct Task {
+    info: TaskInfo,
+    cancel: Arc<Notify>,
+}
+
+#[derive(Default)]
+pub struct TaskManager {
+    next_id: AtomicU32,
+    tasks: Mutex<HashMap<TaskId, Task>>,
+}
+
+impl TaskManager {
+    pub fn list(&self) -> Vec<TaskInfo> {
+        let mut tasks: Vec<_> = self
+            .tasks
+            .lock()
+            .unwrap()
+            .values()
+            .map(|task| task.info.clone())
+            .collect();
+        tasks.sort_by_key(|task| task.id);
+        tasks
+    }
+
+    pub fn cancel(&self, id: TaskId) -> bool {
+        match self.tasks.lock().unwrap().get(&id) {
+            Some(task) if task.info.state == TaskState::Running => {
+                task.cancel.notify_one();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Lets a running task report how it is doing.
+#[derive(Clone)]
+pub struct TaskHandle {
+    app_handle: tauri::AppHandle,
+    id: TaskId,
+}
+
+impl TaskHandle {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn update(&self, f: impl FnOnce(&mut TaskInfo)) {
+        let manager = self.app_handle.state::<TaskManager>();
+        let mut tasks = manager.tasks.lock().unwrap();
+        if let Some(task) = tasks.get_mut(&self.id) {
+            f(&mut task.info);
+            if let Err(e) = self.app_handle.emit_all("task:updated", &task.info) {
+                warn!("Failed to emit task update: {}", e);
+            }
+        }
+    }
+
+    pub fn set_progress(&self, done: u64, total: u64) {
+        self.update(|info| {
+            info.progress = (total > 0).then(|| done as f64 / total as f64);
+        });
+    }
+
+    pub fn set_message(&self, message: impl Into<String>) {
+        let message = message.into();
+        self.update(|info| info.message = Some(message));
+    }
+}
+
+/// Runs `f` as a registered task. Cancelling the task drops the future and
+/// makes this return [`Cancelled`].
+pub async fn run<T, F, Fut>(
+    app_handle: &tauri::AppHandle,
+    kind: TaskKind,
+    title: Message,
+    f: F,
+) -> anyhow::Result<T>
+where
+    F: FnOnce(TaskHandle) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let manager = app_handle.state::<TaskManager>();
+    let id = manager.next_id.fetch_add(1, Ordering::Relaxed);
+    let cancel = Arc::new(Notify::new());
+    manager.tasks.lock().unwrap().insert(
+        id,
+        Task {
+            info: TaskInfo {
+                id,
+                kind,
+                title,
+                state: TaskState::Running,
+                progress: None,
+                message: None,
+            },
+            cancel: cancel.clone(),
+        },
+    );
+    let handle = TaskHandle {
+        app_handle: app_handle.clone(),
+        id,
+    };
+    handle.update(|_| {});
+
+    let result = tokio::select! {
+        result = f(handle.clone()) => result,
+        _ = cancel.notified() => Err(Cancelled.into()),
+    };
+    handle.update(|info| {
+        info.state = match &result {
+            Ok(_) => TaskState::Completed,
+            Err(e) if e.is::<Cancelled>() => TaskState::Cancelled,
+            Err(e) => TaskState::Failed {
+                code: ErrorCode::of(e),
+                error: format!("{:#}", e),
+            },
+        };
+        if info.state == TaskState::Completed {
+            info.progress = Some(1.0);
+        }
+        info!("Task {} ({}) finished: {:?}", id, info.title, info.state);
+    });
+    result
+}
+
+#[tauri::command]
+pub fn get_tasks(app_handle: tauri::AppHandle) -> Vec<TaskInfo> {
+    app_handle.state::<TaskManager>().list()
+}
+
+/// Returns whether there was a running task to cancel.
+#[tauri::command]
+pub fn cancel_task(app_handle: tauri::AppHandle, id: TaskId) -> bool {
+    app_handle.state::<TaskManager>().cancel(id)
+}
+
+#[tauri::command]
+pub fn clear_finished_tasks(app_handle: tauri::AppHandle) {
+    app_handle
+        .state::<TaskManager>()
+        .tasks
+        .lock()
+        .unwrap()
+        .retain(|_, task| task.info.state == TaskState::Running);
+}
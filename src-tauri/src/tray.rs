@@ -0,0 +1,121 @@
+//! The system tray icon and its quick launch menu.
+
+use log::{error, warn};
+use tauri::{
+    CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem,
+};
+
+use crate::{
+    client_instance,
+    instance::{self, InstanceEntry, InstanceKind},
+    process::ProcessManager,
+    server_instance,
+};
+
+const RECENT_INSTANCES: usize = 5;
+const LAUNCH_PREFIX: &str = "launch:";
+
+fn add_launcher_items(menu: SystemTrayMenu) -> SystemTrayMenu {
+    menu.add_item(CustomMenuItem::new("show", "Show launcher"))
+        .add_item(CustomMenuItem::new("quit", "Quit"))
+}
+
+pub fn build() -> SystemTray {
+    SystemTray::new()
+        .with_tooltip("untitled-minecraft-launcher")
+        .with_menu(add_launcher_items(SystemTrayMenu::new()))
+}
+
+fn build_menu(app_handle: &tauri::AppHandle, mut instances: Vec<InstanceEntry>) -> SystemTrayMenu {
+    let processes = app_handle.state::<ProcessManager>();
+    instances.sort_by_key(|entry| std::cmp::Reverse(entry.config.last_played));
+    let (pinned, recent): (Vec<_>, Vec<_>) =
+        instances.into_iter().partition(|entry| entry.config.pinned);
+    let recent = recent
+        .into_iter()
+        .filter(|entry| entry.config.last_played.is_some())
+        .take(RECENT_INSTANCES);
+
+    let mut menu = SystemTrayMenu::new();
+    let mut any = false;
+    for entry in pinned.into_iter().chain(recent) {
+        let mut item = CustomMenuItem::new(
+            format!("{}{}", LAUNCH_PREFIX, entry.id),
+            entry.config.name.clone(),
+        );
+        // Running instances get a checkmark
+        if processes.is_running(&entry.id) {
+            item = item.selected();
+        }
+        menu = menu.add_item(item);
+        any = true;
+    }
+    if any {
+        menu = menu.add_native_item(SystemTrayMenuItem::Separator);
+    }
+    add_launcher_items(menu)
+}
+
+/// Rebuilds the tray menu, e.g. after an instance started or stopped.
+pub fn refresh(app_handle: &tauri::AppHandle) {
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let instances = match instance::list_instances(&app_handle).await {
+            Ok(instances) => instances,
+            Err(e) => {
+                warn!("Can't list instances for the tray: {:#}", e);
+                vec![]
+            }
+        };
+        let menu = build_menu(&app_handle, instances);
+        if let Err(e) = app_handle.tray_handle().set_menu(menu) {
+            error!("{:#?}", e);
+        }
+    });
+}
+
+pub fn show_main_window(app_handle: &tauri::AppHandle) {
+    if let Some(window) = app_handle.get_window("main") {
+        let result = window
+            .show()
+            .and_then(|_| window.unminimize())
+            .and_then(|_| window.set_focus());
+        if let Err(e) = result {
+            error!("{:#?}", e);
+        }
+    }
+}
+
+/// Launches an instance from outside the launcher window, clients as the
+/// active account.
+pub async fn quick_launch(app_handle: &tauri::AppHandle, instance_id: &str) -> anyhow::Result<()> {
+    let config = instance::read_config(app_handle, instance_id).await?;
+    match config.kind {
+        InstanceKind::Server => server_instance::start_server_inner(app_handle, instance_id).await,
+        InstanceKind::Client => {
+            client_instance::launch_client_inner(app_handle, instance_id, None, false).await
+        }
+    }
+}
+
+pub fn handle_event(app_handle: &tauri::AppHandle, event: SystemTrayEvent) {
+    match event {
+        SystemTrayEvent::LeftClick { .. } => show_main_window(app_handle),
+        SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
+            "show" => show_main_window(app_handle),
+            "quit" => app_handle.exit(0),
+            _ => {
+                if let Some(instance_id) = id.strip_prefix(LAUNCH_PREFIX) {
+                    let app_handle = app_handle.clone();
+                    let instance_id = instance_id.to_string();
+                    tauri::async_runtime::spawn(async move {
+                        if let Err(e) = quick_launch(&app_handle, &instance_id).await {
+                            error!("Failed to launch {} from the tray: {:#}", instance_id, e);
+                        }
+                    });
+                }
+            }
+        },
+        _ => {}
+    }
+}
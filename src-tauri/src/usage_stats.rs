@@ -0,0 +1,127 @@
+//! Opt-in usage statistics. Only counters are kept, in `stats.json` in the
+//! launcher dir, and nothing is ever sent anywhere.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{command_result, errors::CommandError, instance, settings};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UsageStats {
+    /// Unix timestamp of when collecting started
+    pub since: Option<i64>,
+    /// Keyed by instance id
+    pub launches: HashMap<String, u64>,
+    /// Seconds, keyed by instance id
+    pub playtime: HashMap<String, u64>,
+    /// How often each feature was used, keyed by a name like `logUpload`
+    pub features: HashMap<String, u64>,
+}
+
+lazy_static::lazy_static! {
+    /// Keeps concurrent updates from losing each other's counts
+    static ref LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+fn stats_path(app_handle: &tauri::AppHandle) -> anyhow::Result<PathBuf> {
+    Ok(instance::launcher_dir(app_handle)?.join("stats.json"))
+}
+
+async fn read_stats(app_handle: &tauri::AppHandle) -> anyhow::Result<UsageStats> {
+    match tokio::fs::read(stats_path(app_handle)?).await {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(UsageStats::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn update(app_handle: &tauri::AppHandle, f: impl FnOnce(&mut UsageStats)) {
+    if !settings::current(app_handle).usage_statistics {
+        return;
+    }
+    let result = async {
+        let _lock = LOCK.lock().await;
+        let mut stats = read_stats(app_handle).await?;
+        stats
+            .since
+            .get_or_insert_with(|| time::OffsetDateTime::now_utc().unix_timestamp());
+        f(&mut stats);
+        let path = stats_path(app_handle)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(&stats)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        warn!("Failed to update usage statistics: {:#}", e);
+    }
+}
+
+pub async fn record_launch(app_handle: &tauri::AppHandle, instance_id: &str) {
+    update(app_handle, |stats| {
+        *stats.launches.entry(instance_id.to_string()).or_default() += 1;
+    })
+    .await;
+}
+
+pub async fn record_playtime(app_handle: &tauri::AppHandle, instance_id: &str, seconds: u64) {
+    update(app_handle, |stats| {
+        *stats.playtime.entry(instance_id.to_string()).or_default() += seconds;
+    })
+    .await;
+}
+
+/// Counts a use of `feature` in the background.
+pub fn record_feature(app_handle: &tauri::AppHandle, feature: &'static str) {
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        update(&app_handle, |stats| {
+            *stats.features.entry(feature.to_string()).or_default() += 1;
+        })
+        .await;
+    });
+}
+
+#[tauri::command]
+pub async fn get_usage_stats(app_handle: tauri::AppHandle) -> Result<UsageStats, CommandError> {
+    command_result(read_stats(&app_handle).await)
+}
+
+/// Writes the collected statistics to `path` as JSON.
+#[tauri::command]
+pub async fn export_usage_stats(
+    app_handle: tauri::AppHandle,
+    path: PathBuf,
+) -> Result<(), CommandError> {
+    command_result(
+        async {
+            let stats = read_stats(&app_handle).await?;
+            tokio::fs::write(&path, serde_json::to_vec_pretty(&stats)?).await?;
+            Ok(())
+        }
+        .await,
+    )
+}
+
+/// Deletes everything collected so far. Collection continues if it is still
+/// enabled in the settings.
+#[tauri::command]
+pub async fn delete_usage_stats(app_handle: tauri::AppHandle) -> Result<(), CommandError> {
+    command_result(
+        async {
+            let _lock = LOCK.lock().await;
+            match tokio::fs::remove_file(stats_path(&app_handle)?).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        }
+        .await,
+    )
+}
@@ -0,0 +1,44 @@
+use anyhow::anyhow;
+use log::info;
+use tauri::Manager;
+
+use crate::{
+    command_result,
+    errors::{coded, CommandError, ErrorCode},
+    instance,
+    process::ProcessManager,
+};
+
+#[tauri::command]
+pub async fn delete_world(
+    app_handle: tauri::AppHandle,
+    instance_id: String,
+    world: String,
+) -> Result<(), CommandError> {
+    command_result(delete_world_inner(&app_handle, &instance_id, &world).await)
+}
+
+async fn delete_world_inner(
+    app_handle: &tauri::AppHandle,
+    instance_id: &str,
+    world: &str,
+) -> anyhow::Result<()> {
+    if app_handle.state::<ProcessManager>().is_running(instance_id) {
+        return Err(coded(
+            ErrorCode::InstanceRunning,
+            "Can't delete a world while the instance is running",
+        ));
+    }
+    instance::ensure_unlocked(app_handle, instance_id).await?;
+    instance::check_name(world)?;
+    let path = instance::game_dir(app_handle, instance_id)?
+        .join("saves")
+        .join(world);
+    if !tokio::fs::metadata(&path).await?.is_dir() {
+        return Err(anyhow!("World {:?} does not exist", world));
+    }
+    let trashed = instance::move_to_trash(app_handle, &path, instance_id).await?;
+    info!("Moved world {:?} to {:?}", path, trashed);
+    app_handle.emit_all("instance:worlds:changed", instance_id)?;
+    Ok(())
+}